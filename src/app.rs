@@ -161,6 +161,8 @@ pub struct App<'a> {
     pub keybinds: Keybinds,
     pub settings: crate::settings::Settings,
     pub history: HistoryManager,
+    /// seed for new dungeon sessions, from `--seed`
+    pub seed: Option<u64>,
 
     pub uid: String,
 
@@ -177,7 +179,7 @@ pub struct App<'a> {
 
 
 impl<'a> App<'a> {
-    pub fn new(shaders: Shaders, font_handler: FontHandler, window_width: u32, window_height: u32, window: &'a mut Window, monitors: Vec<(i32, i32, u32, u32)>) -> App<'a> {
+    pub fn new(shaders: Shaders, font_handler: FontHandler, window_width: u32, window_height: u32, window: &'a mut Window, monitors: Vec<(i32, i32, u32, u32)>, seed: Option<u64>) -> App<'a> {
 
         let mut tex_atlas = TextureAtlas::new();
 
@@ -214,6 +216,7 @@ impl<'a> App<'a> {
             keybinds: Keybinds::new(&settings),
             settings,
             history: HistoryManager::new(),
+            seed,
             uid: "App".to_string(),
             path: Vec::new(),
             toasts: ToastSystem::blank(),
@@ -258,6 +261,11 @@ impl<'a> App<'a> {
         self.events.clear();
     }
 
+    /// shows a short message in a toast with the default duration
+    pub fn toast(&mut self, text: impl ToString) {
+        self.toasts.push(text, Vec::new(), None, None);
+    }
+
     pub fn update(&mut self) {
         
         let dt = time::Instant::now();
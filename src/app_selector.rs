@@ -24,7 +24,7 @@ impl AppSelector {
 
 
         Self {
-            game_app: GameApp::new().systemize(&mut app.component_system),
+            game_app: GameApp::new(app.seed).systemize(&mut app.component_system),
             editor_app: EditorApp::new(app).systemize(&mut app.component_system),
 
             selected_app: 0,
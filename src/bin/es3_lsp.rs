@@ -0,0 +1,431 @@
+//! Language server for ES3 scripts, speaking LSP over stdin/stdout so external editors get the same
+//! diagnostics, highlighting, hover, completion, go-to-definition and formatting as the in-app editor.
+//! The project objects `<namespace:path>` references are checked against can be passed as
+//! `initializationOptions.objects`, a list of ids
+
+// the compiler modules are shared with the game, which uses parts of them this server doesn't
+#![allow(dead_code)]
+
+#[path = "../es3.rs"]
+mod es3;
+#[path = "../es3_bytecode.rs"]
+mod es3_bytecode;
+#[path = "../es3_completion.rs"]
+mod es3_completion;
+#[path = "../es3_diagnostics.rs"]
+mod es3_diagnostics;
+#[path = "../es3_format.rs"]
+mod es3_format;
+#[path = "../es3_header.rs"]
+mod es3_header;
+#[path = "../es3_interpreter.rs"]
+mod es3_interpreter;
+#[path = "../es3_lexer.rs"]
+mod es3_lexer;
+#[path = "../es3_strings.rs"]
+mod es3_strings;
+#[path = "../es3_symbols.rs"]
+mod es3_symbols;
+#[path = "../es3_types.rs"]
+mod es3_types;
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use serde_json::{json, Value};
+use es3::{format, ES3Compiler, PositionedToken, Token};
+use es3_completion::CompletionKind;
+use es3_diagnostics::{Diagnostic, Severity};
+use es3_interpreter::BUILTINS;
+use es3_symbols::SymbolKind;
+use es3_types::{engine_schema, entity_schema, ScriptType};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+/// semantic token types, indexed by `token_type`
+const TOKEN_TYPES: [&str; 12] = [
+    "keyword", "string", "number", "comment", "decorator", "macro", "variable", "parameter", "function", "property", "type", "operator"
+];
+/// semantic token modifiers, bit `n` is `style_flags` bit `n`
+const TOKEN_MODIFIERS: [&str; 7] = ["bold", "italic", "underline", "strikethrough", "error", "warning", "faded"];
+
+fn main() {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout().lock();
+    let mut server = Server::new();
+
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("es3_lsp: failed to read a message: {}", e);
+                break
+            }
+        };
+        let replies = match serde_json::from_str::<Value>(&message) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![error_response(Value::Null, PARSE_ERROR, &e.to_string())]
+        };
+        for reply in replies {
+            if let Err(e) = write_message(&mut output, &reply) {
+                eprintln!("es3_lsp: failed to write a message: {}", e);
+                return
+            }
+        }
+        if server.exited {
+            break
+        }
+    }
+    std::process::exit(if server.shut_down { 0 } else { 1 })
+}
+
+/// Reads one `Content-Length` framed message, `None` once the input ends
+fn read_message(input: &mut impl BufRead) -> std::io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None)
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "a message is missing its `Content-Length` header"))
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> std::io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// An open script and its compiler, kept up to date with every change
+struct Document {
+    compiler: ES3Compiler
+}
+
+struct Server {
+    documents: HashMap<String, Document>,
+    objects: Option<Vec<String>>,
+    initialized: bool,
+    shut_down: bool,
+    exited: bool
+}
+
+impl Server {
+    fn new() -> Self {
+        Self { documents: HashMap::new(), objects: None, initialized: false, shut_down: false, exited: false }
+    }
+
+    /// Handles a request or notification, returning the messages to send back
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let Some(method) = message["method"].as_str() else {
+            // responses to requests we never make
+            return Vec::new()
+        };
+        let params = &message["params"];
+        let Some(id) = message.get("id").cloned() else {
+            return self.notify(method, params)
+        };
+
+        if !self.initialized && method != "initialize" {
+            return vec![error_response(id, SERVER_NOT_INITIALIZED, "the server has not been initialized")]
+        }
+        if self.shut_down {
+            return vec![error_response(id, INVALID_REQUEST, "the server is shutting down")]
+        }
+        let result = match method {
+            "initialize" => self.initialize(params),
+            "shutdown" => {
+                self.shut_down = true;
+                Value::Null
+            }
+            "textDocument/hover" => self.with_document(params, hover),
+            "textDocument/completion" => self.with_document(params, completion),
+            "textDocument/definition" => self.with_document(params, definition),
+            "textDocument/formatting" => self.with_document(params, |uri, document, _| formatting(uri, document)),
+            "textDocument/semanticTokens/full" => self.with_document(params, |_, document, _| semantic_tokens(document)),
+            _ => return vec![error_response(id, METHOD_NOT_FOUND, &format!("unknown method `{}`", method))]
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })]
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        match method {
+            "exit" => {
+                self.exited = true;
+                Vec::new()
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.update(uri, text)
+            }
+            // documents are synced in full, so the last change holds the whole text
+            "textDocument/didChange" => match params["contentChanges"].as_array().and_then(|c| c.last()) {
+                Some(change) => self.update(uri, change["text"].as_str().unwrap_or("")),
+                None => Vec::new()
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, "", &[])]
+            }
+            _ => Vec::new()
+        }
+    }
+
+    fn initialize(&mut self, params: &Value) -> Value {
+        self.initialized = true;
+        self.objects = params["initializationOptions"]["objects"].as_array()
+            .map(|ids| ids.iter().filter_map(|id| id.as_str().map(str::to_string)).collect());
+
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "hoverProvider": true,
+                "completionProvider": { "triggerCharacters": [".", "#", "<", "$"] },
+                "definitionProvider": true,
+                "documentFormattingProvider": true,
+                "semanticTokensProvider": {
+                    "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": TOKEN_MODIFIERS },
+                    "full": true
+                }
+            },
+            "serverInfo": { "name": "es3_lsp", "version": env!("CARGO_PKG_VERSION") }
+        })
+    }
+
+    /// recompiles a document with its new text and publishes its diagnostics
+    fn update(&mut self, uri: String, text: &str) -> Vec<Value> {
+        let document = self.documents.entry(uri.clone()).or_insert_with(|| {
+            let mut compiler = ES3Compiler::new();
+            compiler.set_type_checking(true);
+            if let Some(objects) = &self.objects {
+                compiler.set_objects(objects);
+            }
+            Document { compiler }
+        });
+        document.compiler.tokenize(text);
+        let _ = document.compiler.parse();
+        vec![publish_diagnostics(&uri, text, &document.compiler.diagnostics)]
+    }
+
+    /// runs `f` on the document a request names, with the byte index of its position if it has one
+    fn with_document(&self, params: &Value, f: impl Fn(&str, &Document, usize) -> Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        match self.documents.get(uri) {
+            Some(document) => f(uri, document, offset(&document.compiler.source, &params["position"])),
+            None => Value::Null
+        }
+    }
+}
+
+/// LSP position (line and UTF-16 column, both from 0) of byte `index` in `text`
+fn position(text: &str, index: usize) -> Value {
+    let before = &text[..index];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    json!({ "line": before.matches('\n').count(), "character": before[line_start..].encode_utf16().count() })
+}
+
+/// byte index of an LSP position in `text`, clamped to the end of its line
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let line_start: usize = text.split_inclusive('\n').take(line).map(str::len).sum();
+    let line_text = text[line_start..].split('\n').next().unwrap_or("");
+
+    let mut units = 0;
+    for (i, c) in line_text.char_indices() {
+        if units >= character {
+            return line_start + i
+        }
+        units += c.len_utf16();
+    }
+    line_start + line_text.len()
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position(text, start), "end": position(text, end) })
+}
+
+fn publish_diagnostics(uri: &str, text: &str, diagnostics: &[Diagnostic]) -> Value {
+    let diagnostics: Vec<Value> = diagnostics.iter().map(|d| {
+        let severity = match d.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2
+        };
+        let related: Vec<Value> = d.related.iter().map(|(span, label)| json!({
+            "location": { "uri": uri, "range": range(text, span.start, span.end) },
+            "message": label
+        })).collect();
+        json!({
+            "range": range(text, d.span.start, d.span.end),
+            "severity": severity,
+            "code": d.code,
+            "source": "es3",
+            "message": d.message,
+            "relatedInformation": related
+        })
+    }).collect();
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics }
+    })
+}
+
+fn token_text<'a>(source: &'a str, token: &PositionedToken) -> &'a str {
+    &source[token.index()..token.index() + token.length()]
+}
+
+/// markdown list of an engine object's attributes
+fn describe_schema(schema: Vec<(&str, ScriptType)>) -> String {
+    schema.iter().map(|(name, t)| format!("\n- `{}`: {}", name, t)).collect()
+}
+
+fn hover(_: &str, document: &Document, index: usize) -> Value {
+    let compiler = &document.compiler;
+    let Some(token) = compiler.token_at(index) else {
+        return Value::Null
+    };
+    let source = &compiler.source;
+    let text = token_text(source, token);
+    let symbol = token.links().get("symbol").and_then(|s| s.parse::<usize>().ok()).map(|s| &compiler.symbols.symbols[s]);
+
+    let contents = if let Some(symbol) = symbol {
+        let scope = symbol.scope.as_ref().map(|s| format!(" in `{}`", s)).unwrap_or_default();
+        let defined = match symbol.definition {
+            Some(span) => format!(", defined on line {}", span.start_line),
+            None => String::new()
+        };
+        format!("{} `{}`{}{}", symbol.kind.name(), symbol.name, scope, defined)
+    } else {
+        match token.token() {
+            Token::Object(_) => match token.links().get("object") {
+                Some(id) => format!("object `<{}>`{}", id, engine_schema(id).map(describe_schema).unwrap_or_default()),
+                None => return Value::Null
+            },
+            Token::Word(w) if source[..token.index()].ends_with('#') => match entity_schema(&format!("engine:{}", w)) {
+                Some(schema) => format!("context `#{}`, the `<engine:{}>` entity{}", w, w, describe_schema(schema)),
+                None => return Value::Null
+            },
+            Token::Word(w) if BUILTINS.contains(&w.as_str()) => format!("builtin `{}`", w),
+            Token::Keyword(_) | Token::Command(_) => format!("keyword `{}`", text),
+            _ => return Value::Null
+        }
+    };
+    json!({
+        "contents": { "kind": "markdown", "value": contents },
+        "range": range(source, token.index(), token.index() + token.length())
+    })
+}
+
+fn completion(_: &str, document: &Document, index: usize) -> Value {
+    let source = &document.compiler.source;
+    let items: Vec<Value> = document.compiler.complete(index).into_iter().enumerate().map(|(rank, c)| {
+        // https://microsoft.github.io/language-server-protocol/specification#completionItemKind
+        let kind = match c.kind {
+            CompletionKind::Parameter | CompletionKind::Variable => 6,
+            CompletionKind::Member => 5,
+            CompletionKind::Macro | CompletionKind::Builtin => 3,
+            CompletionKind::Context => 21,
+            CompletionKind::Object => 18,
+            CompletionKind::Keyword => 14
+        };
+        json!({
+            "label": c.label,
+            "kind": kind,
+            "detail": if c.detail.is_empty() { c.kind.name().to_string() } else { c.detail },
+            "sortText": format!("{:04}", rank),
+            "filterText": source[c.range.clone()].to_string() + &c.label,
+            "textEdit": { "range": range(source, c.range.start, c.range.end), "newText": c.insert_text }
+        })
+    }).collect();
+    json!({ "isIncomplete": false, "items": items })
+}
+
+fn definition(uri: &str, document: &Document, index: usize) -> Value {
+    match document.compiler.definition(index) {
+        Some(span) => json!({ "uri": uri, "range": range(&document.compiler.source, span.start, span.end) }),
+        None => Value::Null
+    }
+}
+
+fn formatting(_: &str, document: &Document) -> Value {
+    let source = &document.compiler.source;
+    let formatted = format(source);
+    if formatted == *source {
+        return json!([])
+    }
+    json!([{ "range": range(source, 0, source.len()), "newText": formatted }])
+}
+
+/// index into `TOKEN_TYPES` for a token, `None` for those left to the editor's own highlighting
+fn token_type(document: &Document, token: &PositionedToken) -> Option<usize> {
+    let compiler = &document.compiler;
+    let symbol = token.links().get("symbol").and_then(|s| s.parse::<usize>().ok()).map(|s| compiler.symbols.symbols[s].kind);
+    let name = match (token.token(), symbol) {
+        (_, Some(SymbolKind::Variable)) => "variable",
+        (_, Some(SymbolKind::Parameter)) => "parameter",
+        (_, Some(SymbolKind::Macro)) => "macro",
+        (Token::Keyword(_) | Token::Command(_) | Token::Boolean(_), _) => "keyword",
+        (Token::String(_), _) => "string",
+        (Token::Integer(_) | Token::Float(_), _) => "number",
+        (Token::Comment(_), _) => "comment",
+        (Token::Context(_), _) => "decorator",
+        (Token::Macro(_), _) => "macro",
+        (Token::Object(_), _) => "type",
+        (Token::Tag(_), _) => "property",
+        (Token::Comparison(_), _) => "operator",
+        (Token::Word(w), _) if BUILTINS.contains(&w.as_str()) => "function",
+        (Token::Word(_), _) if compiler.source[..token.index()].ends_with('.') => "property",
+        (Token::Word(_), _) => "variable",
+        _ => return None
+    };
+    TOKEN_TYPES.iter().position(|t| *t == name)
+}
+
+/// Every token, encoded as the relative positions LSP expects.
+/// Tokens spanning lines (strings and block comments) are split into one token per line
+fn semantic_tokens(document: &Document) -> Value {
+    let source = &document.compiler.source;
+    let mut data = Vec::new();
+    let (mut last_line, mut last_column) = (0, 0);
+
+    for token in &document.compiler.tokens {
+        let Some(token_type) = token_type(document, token) else {
+            continue
+        };
+        let modifiers = (token.style().get_flags() as usize) & ((1 << TOKEN_MODIFIERS.len()) - 1);
+        let line_start = token.index() - token.column();
+
+        for (i, segment) in token_text(source, token).split('\n').enumerate() {
+            if segment.is_empty() {
+                continue
+            }
+            let line = token.line() - 1 + i;
+            let column = if i == 0 { source[line_start..token.index()].encode_utf16().count() } else { 0 };
+            let delta_column = if line == last_line { column - last_column } else { column };
+            data.extend([line - last_line, delta_column, segment.encode_utf16().count(), token_type, modifiers]);
+            (last_line, last_column) = (line, column);
+        }
+    }
+    json!({ "data": data })
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::{json, Map, Value};
+use crate::es3_interpreter::{Entity, Random, ScriptValue, ScriptWorld};
+
+/// One play-through of a dungeon.
+/// Everything random in it comes from the world's generator, which is seeded once when the session starts,
+/// so a session (and any bug in it) can be reproduced from its seed
+pub struct DungeonSession {
+    pub world: ScriptWorld
+}
+
+impl DungeonSession {
+    /// starts a session from `seed`, or from the clock if there isn't one
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default()
+        });
+
+        Self {
+            world: ScriptWorld::new(seed)
+        }
+    }
+
+    /// adds a player and moves it into `room`, which makes the room's enter scripts run
+    pub fn spawn_player(&mut self, uid: &str, room: &str) -> Result<(), String> {
+        self.world.add_entity(Entity::new(uid, "engine:player"));
+        self.world.move_entity(uid, room)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.world.random.seed()
+    }
+
+    /// Serializes the session.
+    /// Both the seed and the generator's current state are stored, so a loaded session keeps drawing
+    /// the same numbers it would have if it had never been saved
+    pub fn to_save(&self) -> Value {
+        let mut entities = Map::new();
+        for (uid, entity) in &self.world.entities {
+            let values = |values: &BTreeMap<String, ScriptValue>| Value::Object(values.iter().map(|(k, v)| (k.clone(), v.to_json())).collect());
+            entities.insert(uid.clone(), json!({
+                "object": entity.object,
+                "room": entity.room,
+                "tags": values(&entity.tags),
+                "attributes": values(&entity.attributes)
+            }));
+        }
+
+        json!({
+            "seed": self.world.random.seed(),
+            "random_state": self.world.random.state(),
+            "dungeon": self.world.dungeon,
+            "entities": entities
+        })
+    }
+
+    pub fn from_save(save: &Value) -> Result<Self, String> {
+        let number = |key: &str| save[key].as_u64().ok_or(format!("save is missing `{}`", key));
+        let values = |values: &Value| -> Result<BTreeMap<String, ScriptValue>, String> {
+            values.as_object().into_iter().flatten().map(|(k, v)| Ok((k.clone(), ScriptValue::from_json(v)?))).collect()
+        };
+
+        let mut world = ScriptWorld::new(0);
+        world.random = Random::restore(number("seed")?, number("random_state")?);
+        world.dungeon = save["dungeon"].as_str().ok_or("save is missing `dungeon`")?.to_string();
+        world.entities.clear();
+
+        for (uid, data) in save["entities"].as_object().ok_or("save is missing `entities`")? {
+            let mut entity = Entity::new(uid, data["object"].as_str().unwrap_or_default());
+            entity.room = data["room"].as_str().map(str::to_string);
+            entity.tags = values(&data["tags"])?;
+            entity.attributes = values(&data["attributes"])?;
+            world.add_entity(entity);
+        }
+
+        Ok(Self {
+            world
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, self.to_save().to_string()).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let save = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_save(&serde_json::from_str(&save).map_err(|e| e.to_string())?)
+    }
+}
+
+
+#[cfg(test)]
+mod session_tests {
+    use crate::dungeon_session::DungeonSession;
+    use crate::es3::ES3Compiler;
+    use crate::es3_interpreter::{ES3Interpreter, Entity};
+
+    const SCRIPT: &str = r#"
+captain = random.choice("Mara", "Osk", "Lin", "Teodor")
+gold = random.range(9, 11)
+rolls = []
+for i in [1, 2, 3, 4, 5, 6] {
+    rolls.append(random.range(1, 100))
+}
+output(captain, gold, rolls, random.range(0.0, 1.0))
+"#;
+
+    /// runs `SCRIPT` to the end, returning its output
+    fn run(session: &mut DungeonSession) -> Vec<String> {
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize(SCRIPT);
+        compiler.parse().unwrap();
+
+        session.world.add_entity(Entity::new("p1", "engine:player"));
+        let mut script = ES3Interpreter::new(&compiler.compile(), "p1", None).unwrap();
+        while !script.is_finished() {
+            script.resume(&mut session.world, 0.0).unwrap();
+        }
+        session.world.output.drain(..).collect()
+    }
+
+    #[test]
+    pub fn test_same_seed_same_output() {
+        let first = run(&mut DungeonSession::new(Some(42)));
+        assert_eq!(first, run(&mut DungeonSession::new(Some(42))));
+        assert_ne!(first, run(&mut DungeonSession::new(Some(43))));
+    }
+
+    #[test]
+    pub fn test_save_keeps_seed() {
+        let mut session = DungeonSession::new(Some(42));
+        run(&mut session);
+        session.world.entities.get_mut("p1").unwrap().room = Some("emberhollow:rooms/docks".to_string());
+
+        let mut loaded = DungeonSession::from_save(&session.to_save()).unwrap();
+        assert_eq!(loaded.seed(), 42);
+        assert_eq!(loaded.world.entities["p1"].room.as_deref(), Some("emberhollow:rooms/docks"));
+        assert_eq!(loaded.to_save(), session.to_save());
+
+        // the loaded session continues the sequence instead of starting over
+        assert_eq!(run(&mut loaded), run(&mut session));
+    }
+}
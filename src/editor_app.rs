@@ -1,5 +1,10 @@
+use serde_json::{json, Map, Value};
 use crate::{canvas::Canvas, component::Component, rectangle::Rectangle};
 use crate::app::App;
+use crate::component_system::{CompRef, SystematicComponent};
+use crate::editor_tile::{ObjectTile, StringAttribute};
+use crate::es3_text_editor::ES3TextEditor;
+use crate::project::{self, PROJECT_PATH, SCRIPTS_GROUP};
 use crate::text_box::Textbox;
 use crate::visibility_toggle::VisibilityToggle;
 
@@ -7,6 +12,13 @@ pub struct EditorApp {
     canvas: Canvas,
     visibility_toggles: Vec<VisibilityToggle>,
     vis_toggle_bg: Rectangle,
+    script_editor: ES3TextEditor,
+    /// the project's objects with the group each is in
+    objects: Vec<(String, CompRef<ObjectTile>)>,
+    /// the object in the scripts group whose script is in `script_editor`
+    script: Option<usize>,
+    /// the open script as it was last written back to its object
+    script_source: String,
     children: Vec<Box<dyn Component>>,
 }
 
@@ -18,6 +30,10 @@ impl EditorApp {
             canvas: Canvas::new(0, 0, 0, 0, 0, 0.0, (0, 0, 0, 0)),
             visibility_toggles: Vec::new(),
             vis_toggle_bg: Rectangle::new(0, 0, 0, 0, (0, 0, 0, 0), 0.0),
+            script_editor: ES3TextEditor::new((0, 0), (0, 0), "", 0.0),
+            objects: Vec::new(),
+            script: None,
+            script_source: String::new(),
             children: Vec::new(),
         }
     }
@@ -70,12 +86,123 @@ impl EditorApp {
 
         canvas.children.push(Box::new(text_box));
 
-        Self {
+        let objects = match project::load(PROJECT_PATH) {
+            Ok(project) => project::objects(&project).filter_map(|(group, object)| match ObjectTile::from_json(object) {
+                Ok(tile) => Some((group.clone(), tile.systemize(&mut app.component_system))),
+                Err(e) => {
+                    eprintln!("Error: skipping an object in `{}`: {}", group, e);
+                    None
+                }
+            }).collect(),
+            Err(e) => {
+                eprintln!("Error: could not load the project: {}", e);
+                Vec::new()
+            }
+        };
+
+        let mut editor = Self {
             canvas,
             visibility_toggles,
             vis_toggle_bg: Rectangle::new(0, 0, 1, 35, (24, 24, 24, 255), 0.99).with_shader(app.shaders.prox_fade),
+            script_editor: ES3TextEditor::new((0, 0), (350, 500), "", 0.97),
+            objects,
+            script: None,
+            script_source: String::new(),
             children,
+        };
+        let ids: Vec<String> = (0..editor.objects.len()).filter_map(|i| editor.object_id(app, i)).collect();
+        editor.script_editor.set_objects(ids);
+        if let Some(first) = editor.objects.iter().position(|(group, _)| group == SCRIPTS_GROUP) {
+            editor.open_script(app, first);
+        }
+        editor
+    }
+
+    fn object_id(&self, app: &mut App, index: usize) -> Option<String> {
+        let tile = self.objects[index].1.get(&mut app.component_system)?;
+        let id = tile.id.clone();
+        self.objects[index].1.restore(&mut app.component_system, tile);
+        Some(id)
+    }
+
+    /// goes to the object with `id`, opening it in the script editor if it's a script or centering the canvas on it
+    fn go_to_object(&mut self, app: &mut App, id: &str) {
+        let Some(index) = (0..self.objects.len()).find(|&i| self.object_id(app, i).as_deref() == Some(id)) else {
+            return
+        };
+        if self.objects[index].0 == SCRIPTS_GROUP {
+            if self.script != Some(index) {
+                self.open_script(app, index);
+            }
+        }
+        else if let Some(tile) = self.objects[index].1.get(&mut app.component_system) {
+            self.canvas.scroll_offset = (
+                self.canvas.size.0 as i64 / 2 - tile.position.0 as i64,
+                self.canvas.size.1 as i64 / 2 - tile.position.1 as i64
+            );
+            self.objects[index].1.restore(&mut app.component_system, tile);
+        }
+    }
+
+    /// shows the script of `objects[index]` in the script editor
+    fn open_script(&mut self, app: &mut App, index: usize) {
+        let Some(tile) = self.objects[index].1.get(&mut app.component_system) else {
+            return
+        };
+        self.script_source = tile.attribute("source").and_then(|s| s.as_str().map(str::to_string)).unwrap_or_default();
+        self.objects[index].1.restore(&mut app.component_system, tile);
+
+        self.script_editor.set_content(&self.script_source);
+        self.script = Some(index);
+    }
+
+    /// opens the script after the open one in the project, or the one before it, wrapping around
+    fn cycle_script(&mut self, app: &mut App, forward: bool) {
+        let scripts: Vec<usize> = (0..self.objects.len()).filter(|&i| self.objects[i].0 == SCRIPTS_GROUP).collect();
+        if scripts.is_empty() {
+            return
         }
+        let next = match (self.script.and_then(|open| scripts.iter().position(|&i| i == open)), forward) {
+            (Some(current), true) => (current + 1) % scripts.len(),
+            (Some(current), false) => (current + scripts.len() - 1) % scripts.len(),
+            (None, _) => 0
+        };
+        self.open_script(app, scripts[next]);
+    }
+
+    /// writes any edit in the script editor back to the object the script was opened from
+    fn write_back_script(&mut self, app: &mut App) {
+        let Some(index) = self.script else {
+            return
+        };
+        if self.script_editor.content() == self.script_source {
+            return
+        }
+        self.script_source = self.script_editor.content().to_string();
+
+        if let Some(mut tile) = self.objects[index].1.get(&mut app.component_system) {
+            if !tile.set_attribute("source", Value::String(self.script_source.clone())) {
+                tile.attributes.push(Box::new(StringAttribute { key: "source".to_string(), value: self.script_source.clone() }));
+            }
+            self.objects[index].1.restore(&mut app.component_system, tile);
+        }
+    }
+
+    /// writes every object to the project file, in its group
+    fn save_project(&self, app: &mut App) -> Result<(), String> {
+        let mut project = Map::new();
+        for (group, tile_ref) in &self.objects {
+            let Some(tile) = tile_ref.get(&mut app.component_system) else {
+                continue
+            };
+            let object = tile.to_json();
+            tile_ref.restore(&mut app.component_system, tile);
+
+            if let Some(objects) = project.entry(group.clone()).or_insert_with(|| json!([])).as_array_mut() {
+                objects.push(object?);
+            }
+        }
+        project::save(PROJECT_PATH, project)
     }
 }
 
@@ -101,6 +228,32 @@ impl Component for EditorApp {
             toggle.update(app);
             dx += toggle.width as i32;
         }
+
+        // the script editor sits in the side panel while scripts are shown
+        if self.script.is_some() && self.visibility_toggles.iter().any(|t| t.group == SCRIPTS_GROUP && t.visible) {
+            if app.keybinds.check_binding("Next-Script") {
+                app.keybinds.accept(&app.keybinds.last("Next-Script").unwrap().clone());
+                self.cycle_script(app, true);
+            }
+            else if app.keybinds.check_binding("Previous-Script") {
+                app.keybinds.accept(&app.keybinds.last("Previous-Script").unwrap().clone());
+                self.cycle_script(app, false);
+            }
+            self.script_editor.position = (self.canvas.size.0 as i32 + 5, 0);
+            self.script_editor.size = (350, self.canvas.size.1);
+            self.script_editor.update(app);
+            self.write_back_script(app);
+            if let Some(id) = self.script_editor.take_followed_object() {
+                self.go_to_object(app, &id);
+            }
+        }
+
+        if app.keybinds.check_binding("Save") {
+            app.keybinds.accept(&app.keybinds.last("Save").unwrap().clone());
+            if let Err(e) = self.save_project(app) {
+                eprintln!("Error: could not save the project: {}", e);
+            }
+        }
         self.vis_toggle_bg.size.0 = dx as u32;
         self.vis_toggle_bg.position = (0, dy+50);
         self.vis_toggle_bg.update(app);
@@ -1,4 +1,4 @@
-use serde_json::{Number, Value};
+use serde_json::{json, Number, Value};
 use crate::app::App;
 use crate::component::Component;
 use crate::component_system::{CompRef, SystematicComponent};
@@ -159,6 +159,68 @@ impl ObjectTile {
             attributes,
         }.systemize(&mut app.component_system)
     }
+
+    /// Reads an object saved by `to_json`. Keys other than `id`, `name`, `description` and `position`
+    /// become attributes, by the type of their value
+    pub fn from_json(object: &Value) -> Result<Self, String> {
+        let Some(map) = object.as_object() else {
+            return Err("object is not a JSON object".to_string())
+        };
+        let text = |key: &str| map.get(key).and_then(Value::as_str).unwrap_or_default().to_string();
+        let id = map.get("id").and_then(Value::as_str).ok_or("object is missing `id`")?.to_string();
+        let position = match map.get("position").and_then(Value::as_array).map(Vec::as_slice) {
+            Some([x, y]) => (x.as_i64().unwrap_or_default() as i32, y.as_i64().unwrap_or_default() as i32),
+            _ => (0, 0)
+        };
+
+        let mut attributes: Vec<Box<dyn DataAttribute>> = Vec::new();
+        for (key, value) in map.iter().filter(|(k, _)| !["id", "name", "description", "position"].contains(&k.as_str())) {
+            let key = key.clone();
+            attributes.push(match value {
+                Value::Bool(b) => Box::new(BooleanAttribute { key, value: *b }),
+                Value::String(s) => Box::new(StringAttribute { key, value: s.clone() }),
+                Value::Number(n) if n.is_i64() => Box::new(I64Attribute { key, value: n.as_i64().unwrap() }),
+                Value::Number(n) => Box::new(F64Attribute { key, value: n.as_f64().unwrap_or_default() }),
+                _ => return Err(format!("attribute `{}` of `{}` has an unsupported type", key, id))
+            });
+        }
+
+        Ok(Self {
+            position,
+            name: text("name"),
+            description: text("description"),
+            id,
+            attributes,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<Value, String> {
+        let mut object = json!({
+            "id": self.id,
+            "name": self.name,
+            "description": self.description,
+            "position": [self.position.0, self.position.1]
+        });
+        for attribute in &self.attributes {
+            attribute.write(&mut object)?;
+        }
+        Ok(object)
+    }
+
+    /// value of the attribute stored under `key`
+    pub fn attribute(&self, key: &str) -> Option<Value> {
+        let mut object = json!({});
+        for attribute in &self.attributes {
+            attribute.write(&mut object).ok()?;
+        }
+        object.get(key).cloned()
+    }
+
+    /// sets the attribute stored under `key`, returning whether there is one that takes `value`
+    pub fn set_attribute(&mut self, key: &str, value: Value) -> bool {
+        let object = json!({ key: value });
+        self.attributes.iter_mut().any(|attribute| attribute.read(&object).is_ok())
+    }
 }
 
 
@@ -167,4 +229,36 @@ impl Component for ObjectTile {
         
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tile_tests {
+    use serde_json::json;
+    use crate::editor_tile::ObjectTile;
+
+    #[test]
+    pub fn test_json_round_trip() {
+        let object = json!({
+            "id": "emberhollow:scripts/boat",
+            "name": "Boat",
+            "description": "",
+            "position": [4, -2],
+            "source": "output(1)",
+            "locked": false,
+            "gold": 3,
+            "weight": 1.5
+        });
+        let mut tile = ObjectTile::from_json(&object).unwrap();
+        assert_eq!(tile.position, (4, -2));
+        assert_eq!(tile.attribute("source"), Some(json!("output(1)")));
+        assert_eq!(tile.to_json().unwrap(), object);
+
+        assert!(tile.set_attribute("source", json!("wait(2)")));
+        assert_eq!(tile.attribute("source"), Some(json!("wait(2)")));
+        assert!(!tile.set_attribute("gold", json!("lots")));
+        assert!(!tile.set_attribute("missing", json!(1)));
+
+        assert!(ObjectTile::from_json(&json!({"name": "no id"})).is_err());
+        assert!(ObjectTile::from_json(&json!({"id": "a", "tags": []})).is_err());
+    }
+}
@@ -1,8 +1,21 @@
-use std::collections::{HashMap};
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
-use serde_json::{Map, Value};
-
-#[derive(Debug)]
+use std::ops::Range;
+use serde_json::{json, Map, Number, Value};
+use crate::es3_bytecode::{self, Chunk};
+use crate::es3_completion::{complete, Completion};
+use crate::es3_diagnostics::{codes, Diagnostic, Span};
+use crate::es3_header::ScriptHeader;
+use crate::es3_strings::{check_strings, decode_string};
+use crate::es3_symbols::{SymbolTable, TextEdit};
+use crate::es3_types::check_types;
+use crate::es3_lexer::Lexer;
+
+pub use crate::es3_format::format;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Newline,
     String(String),
@@ -26,19 +39,145 @@ pub enum Token {
     Error(String)
 }
 
-pub struct CompileContext {}
+/// Object types provided by the engine itself, always valid in `<...>` references
+pub const ENGINE_OBJECTS: [&str; 6] = [
+    "engine:currency",
+    "engine:dungeon",
+    "engine:item",
+    "engine:npc",
+    "engine:player",
+    "engine:room"
+];
+
+/// Macros visible to the code being compiled, and the expansions currently in progress.
+/// Macro calls are expanded while compiling, each call compiles a fresh copy of the macro's body with the
+/// parameters substituted for the arguments
+pub struct CompileContext<'a> {
+    macros: HashMap<String, &'a MacroDefNode>,
+    expansions: Vec<Expansion>,
+    /// number of expansions so far, used to give each expansion's local variables unique names
+    expansion_count: usize,
+    /// whether each statement is tagged with the line it starts on, as `"#line": n`, and each macro call is kept as
+    /// a statement of its own, see `ES3Compiler::compile_debug`
+    pub debug_info: bool,
+    /// problems found while expanding macros
+    pub diagnostics: Vec<Diagnostic>
+}
 
-pub trait Node {
-    fn compile(&self, compile_context: &mut CompileContext) -> Value {
-        Value::Null
+/// a macro being expanded, by name, with the compiled argument for each parameter and the new names of its locals
+struct Expansion {
+    name: String,
+    params: HashMap<String, Value>,
+    locals: HashMap<String, String>
+}
+
+impl<'a> CompileContext<'a> {
+    pub fn new() -> Self {
+        Self {
+            macros: HashMap::new(),
+            expansions: Vec::new(),
+            expansion_count: 0,
+            debug_info: false,
+            diagnostics: Vec::new()
+        }
+    }
+
+    /// registers every macro definition in `node`, so macros can be called before they are defined
+    pub fn define_macros(&mut self, node: &'a dyn Node) {
+        if let Some(definition) = node.as_any().downcast_ref::<MacroDefNode>() {
+            self.macros.insert(definition.name.clone(), definition);
+        }
+        node.for_each_child(&mut |child| self.define_macros(child));
+    }
+
+    /// the name a variable compiles to, macro locals are renamed so they can't clash with the caller's variables
+    fn variable_name(&self, name: &str) -> String {
+        self.expansions.last().and_then(|e| e.locals.get(name)).cloned().unwrap_or_else(|| name.to_string())
+    }
+}
+
+pub trait NodeToAny: 'static {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: 'static + Node> NodeToAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Walks the fields of a node, `node!` implements this for every node from its field list
+pub trait NodeChildren {
+    fn for_each_child<'a>(&'a self, f: &mut dyn FnMut(&'a dyn Node));
+
+    /// moves every span in the node and its children, see `Span::shift`
+    fn shift_spans(&mut self, bytes: isize, lines: isize);
+}
+
+/// a type that can be a field of a node
+trait NodeField {
+    fn visit<'a>(&'a self, _f: &mut dyn FnMut(&'a dyn Node)) {}
+    fn shift(&mut self, _bytes: isize, _lines: isize) {}
+}
+
+impl NodeField for Box<dyn Node> {
+    fn visit<'a>(&'a self, f: &mut dyn FnMut(&'a dyn Node)) {
+        f(self.as_ref())
+    }
+    fn shift(&mut self, bytes: isize, lines: isize) {
+        self.shift_spans(bytes, lines)
+    }
+}
+
+impl<T: NodeField> NodeField for Vec<T> {
+    fn visit<'a>(&'a self, f: &mut dyn FnMut(&'a dyn Node)) {
+        self.iter().for_each(|item| item.visit(f))
+    }
+    fn shift(&mut self, bytes: isize, lines: isize) {
+        self.iter_mut().for_each(|item| item.shift(bytes, lines))
     }
 }
 
+impl<T: NodeField> NodeField for Option<T> {
+    fn visit<'a>(&'a self, f: &mut dyn FnMut(&'a dyn Node)) {
+        self.iter().for_each(|item| item.visit(f))
+    }
+    fn shift(&mut self, bytes: isize, lines: isize) {
+        self.iter_mut().for_each(|item| item.shift(bytes, lines))
+    }
+}
+
+impl<A: NodeField, B: NodeField> NodeField for (A, B) {
+    fn visit<'a>(&'a self, f: &mut dyn FnMut(&'a dyn Node)) {
+        self.0.visit(f);
+        self.1.visit(f);
+    }
+    fn shift(&mut self, bytes: isize, lines: isize) {
+        self.0.shift(bytes, lines);
+        self.1.shift(bytes, lines);
+    }
+}
+
+impl NodeField for Span {
+    fn shift(&mut self, bytes: isize, lines: isize) {
+        Span::shift(self, bytes, lines)
+    }
+}
+
+impl NodeField for String {}
+impl NodeField for i64 {}
+impl NodeField for f64 {}
+impl NodeField for bool {}
+
+pub trait Node: NodeToAny + NodeChildren {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value;
+}
+
 
 macro_rules! node {
-    ( $cls:tt => [ $( $v:vis $name:ident : $tp:ty ),* ]) => {
-        struct $cls {
-            $( $v $name: $tp ),*
+    ( $cls:tt => [ $( $name:ident : $tp:ty ),* ]) => {
+        pub struct $cls {
+            $( pub $name: $tp ),*
         }
         impl $cls {
             pub fn new( $( $name : $tp ),* ) -> Self {
@@ -48,24 +187,46 @@ macro_rules! node {
                 }
             }
         }
+        impl NodeChildren for $cls {
+            #[allow(unused_variables)]
+            fn for_each_child<'a>(&'a self, f: &mut dyn FnMut(&'a dyn Node)) {
+                $( self.$name.visit(f); )*
+            }
+            #[allow(unused_variables)]
+            fn shift_spans(&mut self, bytes: isize, lines: isize) {
+                $( self.$name.shift(bytes, lines); )*
+            }
+        }
     };
 }
 
 
-node!(StatementsNode => [ nodes: Vec<Box<dyn Node>> ]);
+// `spans` has the span of each statement in `nodes`
+node!(StatementsNode => [ nodes: Vec<Box<dyn Node>>, spans: Vec<Span> ]);
 impl Node for StatementsNode {
-    fn compile(&self, compile_context: &mut CompileContext) -> Value {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
         let mut out = Vec::new();
-        for node in &self.nodes {
-            out.push(node.compile(compile_context));
+        for (node, span) in self.nodes.iter().zip(&self.spans) {
+            // definitions are only used through expansions, which are spliced in place of their call
+            if node.as_any().is::<MacroDefNode>() {
+                continue
+            }
+            match node.compile(compile_context) {
+                Value::Array(expansion) if node.as_any().is::<MacroCallNode>() => out.extend(expansion),
+                Value::Object(mut statement) if compile_context.debug_info => {
+                    statement.insert("#line".to_string(), json!(span.start_line));
+                    out.push(Value::Object(statement))
+                }
+                value => out.push(value)
+            }
         }
         Value::Array(out)
     }
 }
 
-node!(CompareNode => [ left: Box<dyn Node>, op: String, right: Box<dyn Node> ]);
+node!(CompareNode => [ left: Box<dyn Node>, op: String, right: Box<dyn Node>, span: Span ]);
 impl Node for CompareNode {
-    fn compile(&self, compile_context: &mut CompileContext) -> Value {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
         let mut out = Map::new();
         out.insert("left".to_string(), self.left.compile(compile_context));
         out.insert("op".to_string(), Value::String(self.op.clone()));
@@ -76,7 +237,7 @@ impl Node for CompareNode {
 
 node!(IfNode => [ condition: Box<dyn Node>, body: Box<dyn Node>, else_node: Option<Box<dyn Node>> ]);
 impl Node for IfNode {
-    fn compile(&self, compile_context: &mut CompileContext) -> Value {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
         let mut out = Map::new();
         out.insert("#check".to_string(), self.condition.compile(compile_context));
         out.insert("true".to_string(), self.body.compile(compile_context));
@@ -89,6 +250,385 @@ impl Node for IfNode {
     }
 }
 
+fn compile_all<'a>(nodes: &'a [Box<dyn Node>], compile_context: &mut CompileContext<'a>) -> Value {
+    Value::Array(nodes.iter().map(|n| n.compile(compile_context)).collect())
+}
+
+fn compile_entries<'a>(entries: &'a [(String, Box<dyn Node>)], compile_context: &mut CompileContext<'a>) -> Value {
+    let mut out = Map::new();
+    for (key, value) in entries {
+        out.insert(key.clone(), value.compile(compile_context));
+    }
+    Value::Object(out)
+}
+
+node!(AssignNode => [ target: Box<dyn Node>, value: Box<dyn Node>, span: Span ]);
+impl Node for AssignNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#store".to_string(), self.target.compile(compile_context));
+        out.insert("value".to_string(), self.value.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+node!(CallNode => [ callee: Box<dyn Node>, args: Vec<Box<dyn Node>>, kwargs: Vec<(String, Box<dyn Node>)>, span: Span ]);
+impl Node for CallNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#call".to_string(), self.callee.compile(compile_context));
+        out.insert("args".to_string(), compile_all(&self.args, compile_context));
+        out.insert("kwargs".to_string(), compile_entries(&self.kwargs, compile_context));
+        Value::Object(out)
+    }
+}
+
+node!(MemberNode => [ object: Box<dyn Node>, name: String, span: Span ]);
+impl Node for MemberNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#attr".to_string(), Value::String(self.name.clone()));
+        out.insert("of".to_string(), self.object.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+node!(IndexNode => [ object: Box<dyn Node>, index: Box<dyn Node>, span: Span ]);
+impl Node for IndexNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#index".to_string(), self.index.compile(compile_context));
+        out.insert("of".to_string(), self.object.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+// `obj.tag$[name]` reads a tag, `obj.tag$[name = value]` sets it.
+// `object` is None for bare `tag$[...]` and `=>name` tags, which refer to the script's owner
+node!(TagNode => [ object: Option<Box<dyn Node>>, name: String, value: Option<Box<dyn Node>> ]);
+impl Node for TagNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#tag".to_string(), Value::String(self.name.clone()));
+        if let Some(object) = &self.object {
+            out.insert("of".to_string(), object.compile(compile_context));
+        } else {
+            out.insert("of".to_string(), Value::Null);
+        }
+        if let Some(value) = &self.value {
+            out.insert("value".to_string(), value.compile(compile_context));
+        }
+        Value::Object(out)
+    }
+}
+
+// arithmetic (`+ - * / %`) and concatenation (`.. ::`)
+node!(BinaryOpNode => [ left: Box<dyn Node>, op: String, right: Box<dyn Node>, span: Span ]);
+impl Node for BinaryOpNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("left".to_string(), self.left.compile(compile_context));
+        out.insert("op".to_string(), Value::String(self.op.clone()));
+        out.insert("right".to_string(), self.right.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+node!(UnaryOpNode => [ op: String, value: Box<dyn Node>, span: Span ]);
+impl Node for UnaryOpNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("op".to_string(), Value::String(self.op.clone()));
+        out.insert("value".to_string(), self.value.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+// `and` / `or`
+node!(LogicNode => [ left: Box<dyn Node>, op: String, right: Box<dyn Node> ]);
+impl Node for LogicNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("left".to_string(), self.left.compile(compile_context));
+        out.insert("op".to_string(), Value::String(self.op.clone()));
+        out.insert("right".to_string(), self.right.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+node!(NotNode => [ value: Box<dyn Node> ]);
+impl Node for NotNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("op".to_string(), Value::String("not".to_string()));
+        out.insert("value".to_string(), self.value.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+node!(ListNode => [ items: Vec<Box<dyn Node>> ]);
+impl Node for ListNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#list".to_string(), compile_all(&self.items, compile_context));
+        Value::Object(out)
+    }
+}
+
+node!(MapNode => [ entries: Vec<(String, Box<dyn Node>)> ]);
+impl Node for MapNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#map".to_string(), compile_entries(&self.entries, compile_context));
+        Value::Object(out)
+    }
+}
+
+node!(WhileNode => [ condition: Box<dyn Node>, body: Box<dyn Node> ]);
+impl Node for WhileNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#while".to_string(), self.condition.compile(compile_context));
+        out.insert("body".to_string(), self.body.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+// `span` covers the iterable
+node!(ForNode => [ variable: String, variable_span: Span, iterable: Box<dyn Node>, body: Box<dyn Node>, span: Span ]);
+impl Node for ForNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#for".to_string(), Value::String(compile_context.variable_name(&self.variable)));
+        out.insert("in".to_string(), self.iterable.compile(compile_context));
+        out.insert("body".to_string(), self.body.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+node!(MatchNode => [ value: Box<dyn Node>, cases: Vec<(Box<dyn Node>, Box<dyn Node>)>, default: Option<Box<dyn Node>> ]);
+impl Node for MatchNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#match".to_string(), self.value.compile(compile_context));
+
+        let mut cases = Vec::new();
+        for (case, body) in &self.cases {
+            let mut c = Map::new();
+            c.insert("case".to_string(), case.compile(compile_context));
+            c.insert("body".to_string(), body.compile(compile_context));
+            cases.push(Value::Object(c));
+        }
+        out.insert("cases".to_string(), Value::Array(cases));
+
+        if let Some(default) = &self.default {
+            out.insert("default".to_string(), default.compile(compile_context));
+        } else {
+            out.insert("default".to_string(), Value::Null);
+        }
+        Value::Object(out)
+    }
+}
+
+node!(BreakNode => [ ]);
+impl Node for BreakNode {
+    fn compile<'a>(&'a self, _compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#break".to_string(), Value::Null);
+        Value::Object(out)
+    }
+}
+
+node!(ContinueNode => [ ]);
+impl Node for ContinueNode {
+    fn compile<'a>(&'a self, _compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#continue".to_string(), Value::Null);
+        Value::Object(out)
+    }
+}
+
+// `span` covers the name and parameter list
+node!(MacroDefNode => [ name: String, params: Vec<String>, param_spans: Vec<Span>, body: Box<dyn Node>, span: Span ]);
+impl Node for MacroDefNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#macro".to_string(), Value::String(self.name.clone()));
+        out.insert("params".to_string(), Value::Array(self.params.iter().map(|p| Value::String(p.clone())).collect()));
+        out.insert("body".to_string(), self.body.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+impl MacroDefNode {
+    /// `$name` variables assigned in the body, which are local to each expansion
+    pub fn locals(&self) -> Vec<String> {
+        fn collect(node: &dyn Node, out: &mut Vec<String>) {
+            let any = node.as_any();
+            let assigned = if let Some(assign) = any.downcast_ref::<AssignNode>() {
+                assign.target.as_any().downcast_ref::<VariableNode>().map(|v| v.name.clone())
+            } else {
+                any.downcast_ref::<ForNode>().map(|f| f.variable.clone())
+            };
+            if let Some(name) = assigned.filter(|n| n.starts_with('$') && !out.contains(n)) {
+                out.push(name);
+            }
+            node.for_each_child(&mut |child| collect(child, out));
+        }
+
+        let mut out = Vec::new();
+        collect(self.body.as_ref(), &mut out);
+        out.retain(|name| !self.params.contains(name));
+        out
+    }
+}
+
+// Compiles to the statements of the macro's body, with each `$param` replaced by its argument.
+// Arguments are compiled where the macro is called, so they can't see the macro's own variables,
+// and variables the body assigns are renamed per expansion so they can't touch the caller's
+node!(MacroCallNode => [ name: String, args: Vec<Box<dyn Node>>, span: Span ]);
+impl Node for MacroCallNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let Some(definition) = compile_context.macros.get(&self.name).copied() else {
+            let message = format!("cannot find macro `{}`", self.name);
+            compile_context.diagnostics.push(Diagnostic::error(codes::UNDEFINED_MACRO, message, self.span));
+            return Value::Array(Vec::new())
+        };
+
+        if compile_context.expansions.iter().any(|e| e.name == self.name) {
+            let message = format!("macro `{}` expands into itself", self.name);
+            let diagnostic = Diagnostic::error(codes::RECURSIVE_MACRO, message, self.span)
+                .with_related(definition.span, format!("`{}` defined here", self.name));
+            compile_context.diagnostics.push(diagnostic);
+            return Value::Array(Vec::new())
+        }
+
+        if self.args.len() != definition.params.len() {
+            let message = format!(
+                "macro `{}` takes {} argument{} but {} {} given",
+                self.name, definition.params.len(), if definition.params.len() == 1 { "" } else { "s" },
+                self.args.len(), if self.args.len() == 1 { "was" } else { "were" }
+            );
+            let diagnostic = Diagnostic::error(codes::MACRO_ARGUMENTS, message, self.span)
+                .with_related(definition.span, format!("`{}` defined here", self.name));
+            compile_context.diagnostics.push(diagnostic);
+            return Value::Array(Vec::new())
+        }
+
+        let args: Vec<Value> = self.args.iter().map(|a| a.compile(compile_context)).collect();
+
+        compile_context.expansion_count += 1;
+        let count = compile_context.expansion_count;
+        compile_context.expansions.push(Expansion {
+            name: self.name.clone(),
+            params: definition.params.iter().cloned().zip(args).collect(),
+            locals: definition.locals().into_iter().map(|l| (l.clone(), format!("{}@{}", l, count))).collect()
+        });
+        let body = definition.body.compile(compile_context);
+        let expansion = compile_context.expansions.pop().unwrap();
+
+        if compile_context.debug_info {
+            return json!({ "#expand": self.name, "params": expansion.params, "locals": expansion.locals, "body": body })
+        }
+        body
+    }
+}
+
+// `new: <namespace:object> { ... }`
+node!(NewNode => [ object: String, data: Option<Box<dyn Node>>, span: Span ]);
+impl Node for NewNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#new".to_string(), Value::String(self.object.clone()));
+        if let Some(data) = &self.data {
+            out.insert("data".to_string(), data.compile(compile_context));
+        } else {
+            out.insert("data".to_string(), Value::Null);
+        }
+        Value::Object(out)
+    }
+}
+
+// `move: subject -> destination`
+node!(MoveNode => [ subject: Box<dyn Node>, destination: Box<dyn Node> ]);
+impl Node for MoveNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#move".to_string(), self.subject.compile(compile_context));
+        out.insert("to".to_string(), self.destination.compile(compile_context));
+        Value::Object(out)
+    }
+}
+
+// plain variables (`captain`) as well as macro variables (`$listening`)
+node!(VariableNode => [ name: String, span: Span ]);
+impl Node for VariableNode {
+    fn compile<'a>(&'a self, compile_context: &mut CompileContext<'a>) -> Value {
+        if let Some(arg) = compile_context.expansions.last().and_then(|e| e.params.get(&self.name)) {
+            return arg.clone()
+        }
+        let mut out = Map::new();
+        out.insert("#ref".to_string(), Value::String(compile_context.variable_name(&self.name)));
+        Value::Object(out)
+    }
+}
+
+// `#player`, `#dungeon`, etc...
+node!(ContextRefNode => [ name: String ]);
+impl Node for ContextRefNode {
+    fn compile<'a>(&'a self, _compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#context".to_string(), Value::String(self.name.clone()));
+        Value::Object(out)
+    }
+}
+
+// `<namespace:path>`, stored without the angle brackets
+node!(ObjectNode => [ id: String ]);
+impl Node for ObjectNode {
+    fn compile<'a>(&'a self, _compile_context: &mut CompileContext<'a>) -> Value {
+        let mut out = Map::new();
+        out.insert("#object".to_string(), Value::String(self.id.clone()));
+        Value::Object(out)
+    }
+}
+
+// `raw` is the string as written, including its quotes, and compiles to its decoded value
+node!(StringNode => [ raw: String, span: Span ]);
+impl Node for StringNode {
+    fn compile<'a>(&'a self, _compile_context: &mut CompileContext<'a>) -> Value {
+        Value::String(decode_string(&self.raw).0)
+    }
+}
+
+node!(IntegerNode => [ value: i64 ]);
+impl Node for IntegerNode {
+    fn compile<'a>(&'a self, _compile_context: &mut CompileContext<'a>) -> Value {
+        Value::Number(Number::from(self.value))
+    }
+}
+
+node!(FloatNode => [ value: f64 ]);
+impl Node for FloatNode {
+    fn compile<'a>(&'a self, _compile_context: &mut CompileContext<'a>) -> Value {
+        Number::from_f64(self.value).map(Value::Number).unwrap_or(Value::Null)
+    }
+}
+
+node!(BooleanNode => [ value: bool ]);
+impl Node for BooleanNode {
+    fn compile<'a>(&'a self, _compile_context: &mut CompileContext<'a>) -> Value {
+        Value::Bool(self.value)
+    }
+}
+
+node!(NoneNode => [ ]);
+impl Node for NoneNode {
+    fn compile<'a>(&'a self, _compile_context: &mut CompileContext<'a>) -> Value {
+        Value::Null
+    }
+}
+
 pub mod style_flags {
     pub const BOLD: u8          = 0b0000_0001;
     pub const ITALIC: u8        = 0b0000_0010;
@@ -101,7 +641,7 @@ pub mod style_flags {
 }
 
 #[derive(Debug)]
-struct TokenStyle {
+pub struct TokenStyle {
     flags: u8
 }
 impl TokenStyle {
@@ -163,6 +703,9 @@ impl TokenStyle {
     pub fn set_flags(&mut self, flags: u8) {
         self.flags = flags;
     }
+    pub fn get_flags(&self) -> u8 {
+        self.flags
+    }
 
 }
 
@@ -172,10 +715,77 @@ pub struct PositionedToken {
     index: usize,
     line: usize,
     column: usize,
+    length: usize,
     style: TokenStyle,
     links: HashMap<String, String>
 }
 
+impl PositionedToken {
+    pub fn new(token: Token, index: usize, line: usize, column: usize, length: usize) -> Self {
+        Self {
+            token,
+            index,
+            line,
+            column,
+            length,
+            style: TokenStyle::new(0),
+            links: HashMap::new()
+        }
+    }
+
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    /// byte index of the token in the source
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// byte column of the token on its first line
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// length of the token in bytes
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn style(&self) -> &TokenStyle {
+        &self.style
+    }
+
+    pub fn style_mut(&mut self) -> &mut TokenStyle {
+        &mut self.style
+    }
+
+    /// what the token refers to, by kind: `"object"` for the id an object reference resolved to,
+    /// `"symbol"` for the id of a variable, macro or parameter in `ES3Compiler::symbols`, and `"definition"` for the
+    /// byte index its definition starts at
+    pub fn links(&self) -> &HashMap<String, String> {
+        &self.links
+    }
+
+    pub fn span(&self) -> Span {
+        let text = match &self.token {
+            Token::String(s) | Token::Comment(s) => s.as_str(),
+            _ => ""
+        };
+        let extra_lines = text.matches('\n').count();
+        let end_column = if extra_lines > 0 {
+            text.rsplit_once('\n').unwrap().1.len()
+        } else {
+            self.column + self.length
+        };
+        Span::new(self.index, self.index + self.length, self.line, self.column, self.line + extra_lines, end_column)
+    }
+}
+
 impl Display for PositionedToken {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Tok[{:?}] @ Idx {} Ln {} Col {}", self.token, self.index, self.line, self.column)
@@ -183,195 +793,1292 @@ impl Display for PositionedToken {
 }
 
 
-macro_rules! tok {
-    ( $tk:expr => $idx:expr, $line:expr, $col:expr ) => {
-        PositionedToken {
-            token: $tk,
-            index: $idx,
-            line: $line,
-            column: $col,
-            style: TokenStyle::new(0),
-            links: HashMap::new()
-        }
-    };
-}
-
 pub struct ES3Compiler {
     pub tokens: Vec<PositionedToken>,
-    pub body: Box<dyn Node>,
-    patterns: Vec<(&'static str, &'static str)>
+    pub body: StatementsNode,
+    /// owner and trigger read from the `#!` context headers
+    pub header: ScriptHeader,
+    /// variables, macros and macro parameters, each use of one is linked to it
+    pub symbols: SymbolTable,
+    /// diagnostics from the last call to `tokenize` and `parse`
+    pub diagnostics: Vec<Diagnostic>,
+    /// the source the current tokens were lexed from
+    pub source: String,
+    /// token range of each statement in `body`, used to re-parse only what an edit touched
+    statement_ranges: Vec<Range<usize>>,
+    /// token index each statement's parse looked ahead to, an edit before it can change the statement
+    statement_reach: Vec<usize>,
+    parse_diagnostics: Vec<Diagnostic>,
+    /// diagnostics from macro expansion, the context headers and object resolution
+    analysis_diagnostics: Vec<Diagnostic>,
+    /// ids of the objects in the project, see `set_objects`
+    objects: Option<HashSet<String>>,
+    /// whether diagnostics include the type checker's, see `set_type_checking`
+    type_checking: bool
 }
 
 impl ES3Compiler {
 
     pub fn new() -> Self {
 
-        let patterns = vec![
-            (r"\/\/.*", "COMMENT"),
-            (r"(?<!\/)\/\*(\*[^/]|[^*])+\*\/", "COMMENT"),
-            (r"\#\![^\n;]*;?", "CONTEXT"),
-            ("(\"(\\\\.|[^\"\\\\])*\"|\'(\\\\.|[^\'\\\\])*\')", "STRING"),
-            (r"=>[^ ]*", "TAG"),
-            (r"\$[a-zA-Z_][a-zA-Z0-9_]*", "MACRO"),
-            (r"\b(true|false)\b", "BOOLEAN"),
-            (r"(<[^\<\> ]+>)", "OBJECT"),
-            (r"(<=|>=|<|>|==|!=)", "COMP"),
-            (r"(\.\.|::)", "CONCAT"),
-            (r"\b(new|move)\b", "COMMAND"),
-            (r"\b(if|elif|else|while|for|in|and|not|or|none|match|case|class|def|break|continue)\b", "KEYWORD"),
-            (r"[a-zA-Z_][a-zA-Z0-9_]*", "WORD"),
-            (r"(\d+(\.\d+)?|\.\d+)", "NUMBER"),
-            (r"[=\-+*/()&\[\]{},#%:|^\.\$;~`]", "LITERAL"),
-            (r"\n+", "NEWLINE"),
-            (r"[\t ]+", "ignore"),
-            (r".", "ERROR")
-        ];
-
-
         Self {
             tokens: Vec::new(),
-            body: Box::new(StatementsNode::new(Vec::new())),
-            patterns
+            body: StatementsNode::new(Vec::new(), Vec::new()),
+            header: ScriptHeader::default(),
+            symbols: SymbolTable::default(),
+            diagnostics: Vec::new(),
+            source: String::new(),
+            statement_ranges: Vec::new(),
+            statement_reach: Vec::new(),
+            parse_diagnostics: Vec::new(),
+            analysis_diagnostics: Vec::new(),
+            objects: None,
+            type_checking: false
         }
     }
 
     pub fn tokenize(&mut self, input: &str) {
+        self.tokens = Lexer::new(input).collect();
+        self.source = input.to_string();
+        self.diagnostics = self.lexer_diagnostics();
+    }
 
-        let mut pat = "(".to_string();
-
-        for (key, _) in &self.patterns {
-            pat += &(key.to_string() + "|");
+    /// Re-lexes after an edit that only touched lines `first_line..=last_line` of `input` (the full, edited source),
+    /// reusing the tokens before and after them.
+    /// Lexing restarts at the first edited line (or earlier, at a token spanning into it or an unterminated
+    /// string/comment that the edit may close) and stops once it lines up with an old token past the edit.
+    /// Returns the range of indices in `tokens` that were replaced
+    pub fn retokenize_lines(&mut self, input: &str, first_line: usize, last_line: usize) -> Range<usize> {
+        let byte_delta = input.len() as isize - self.source.len() as isize;
+        let line_delta = input.matches('\n').count() as isize - self.source.matches('\n').count() as isize;
+
+        let edit_start = line_start_index(input, first_line);
+        let edit_end = line_start_index(input, last_line + 1);
+
+        let mut first = self.tokens.partition_point(|t| t.index + t.length <= edit_start);
+        if let Some(opener) = self.tokens[..first].iter().position(|t| {
+            matches!(&t.token, Token::Error(e) if e == "\"" || e == "'")
+                || (matches!(&t.token, Token::Literal(l) if l == "/") && self.source[t.index + 1..].starts_with('*'))
+        }) {
+            first = opener;
         }
 
-        pat.remove(pat.len()-1); // remove trailing '|'
-        pat += ")";
-
-
-        let pattern = fancy_regex::Regex::new(&pat).unwrap();
+        let (start, line, column) = match self.tokens.get(first) {
+            Some(t) if t.index < edit_start => (t.index, t.line, t.column),
+            _ => (edit_start, first_line, 0)
+        };
+
+        // old tokens past the edit, which the new ones are checked against
+        let old_end = (edit_end as isize - byte_delta) as usize;
+        let mut resync = self.tokens.partition_point(|t| t.index < old_end).max(first);
+
+        let mut fresh = Vec::new();
+        let mut synced = false;
+        for token in Lexer::starting_at(input, start, line, column) {
+            if token.index >= edit_end {
+                while resync < self.tokens.len() && (self.tokens[resync].index as isize + byte_delta) < token.index as isize {
+                    resync += 1;
+                }
+                if let Some(old) = self.tokens.get(resync) {
+                    if old.index as isize + byte_delta == token.index as isize && old.length == token.length && old.token == token.token {
+                        synced = true;
+                        break
+                    }
+                }
+            }
+            fresh.push(token);
+        }
+        // lexing ran to the end of the input (e.g. a token swallowed the rest of it), so no old token survives
+        if !synced {
+            resync = self.tokens.len();
+        }
 
-        let mut tokens_out: Vec<PositionedToken> = Vec::new();
-        let mut idx: usize = 0;
-        let mut line: usize = 1;
-        let mut column: usize = 0;
+        for token in &mut self.tokens[resync..] {
+            token.index = (token.index as isize + byte_delta) as usize;
+            token.line = (token.line as isize + line_delta) as usize;
+        }
 
-        for r_mat in pattern.find_iter(input) {
-            let mat = r_mat.unwrap();
-            let sz = mat.end()-mat.start();
-            'pattern_loop: for (key, tp) in &self.patterns {
-                let sub_pattern = fancy_regex::Regex::new(key).unwrap();
-                if sub_pattern.find(mat.as_str()).unwrap().is_some() {
-                    let str_val = input[mat.range()].to_string();
-                    
-                    if *tp == "ignore" {
-                        column += sz;
-                        idx += sz;
-                        break 'pattern_loop
-                    }
-                    else if *tp == "NEWLINE" {
-                        column = 0;
-                        line += sz;
-                        idx += sz;
-                        break 'pattern_loop
-                    }
-                    else if *tp == "STRING" {
-                        tokens_out.push(tok!(Token::String(str_val.clone()) => idx, line, column));
-                        let li = str_val.split("\n").count() - 1;
-                        line += li;
-                        if li > 0 {
-                            column = str_val.rsplit_once("\n").unwrap().1.len();
-                        } else {
-                            column += str_val.len();
-                        }
-                        idx += sz;
+        let changed = first..first + fresh.len();
+        self.tokens.splice(first..resync, fresh);
+        self.source = input.to_string();
+        self.diagnostics = self.lexer_diagnostics();
 
-                        break 'pattern_loop
-                    }
+        changed
+    }
 
-                    
+    fn lexer_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut out = Vec::new();
+        for token in &self.tokens {
+            match &token.token {
+                Token::Error(e) if e.starts_with(|c: char| c.is_ascii_digit()) => {
+                    out.push(Diagnostic::error(codes::INTEGER_TOO_LARGE, format!("integer literal `{}` is too large", e), token.span()));
+                }
+                Token::Error(e) => {
+                    out.push(Diagnostic::error(codes::UNKNOWN_CHARACTER, format!("unknown character `{}`", e), token.span()));
+                }
+                _ => {}
+            }
+        }
+        out
+    }
 
+    /// Attempts to parse the current token vec into an AST.
+    /// The parser will attempt to recover from errors, and will collect errors to be returned at the end of parsing.
+    /// This function will also update the tokens with more informed highlighting, and create links for
+    /// definitions, uses, etc...
+    /// All diagnostics (including warnings and lexer errors) end up in `self.diagnostics`, only errors are returned
+    pub fn parse(&mut self) -> Result<(), Vec<Diagnostic>> {
 
-                    if *tp == "CONTEXT" {
-                        tokens_out.push(tok!(Token::Context(str_val) => idx, line, column));
-                    }
-                    else if *tp == "COMMENT" {
-                        tokens_out.push(tok!(Token::Comment(str_val) => idx, line, column));
-                    }
-                    else if *tp == "TAG" {
-                        tokens_out.push(tok!(Token::Tag(str_val) => idx, line, column));
-                    }
-                    else if *tp == "MACRO" {
-                        tokens_out.push(tok!(Token::Macro(str_val) => idx, line, column));
-                    }
-                    else if *tp == "BOOLEAN" {
-                        tokens_out.push(tok!(Token::Boolean(str_val.to_lowercase().parse::<bool>().unwrap()) => idx, line, column));
-                    }
-                    else if *tp == "OBJECT" {
-                        tokens_out.push(tok!(Token::Object(str_val) => idx, line, column));
-                    }
-                    else if *tp == "COMP" {
-                        tokens_out.push(tok!(Token::Comparison(str_val) => idx, line, column));
-                    }
-                    else if *tp == "CONCAT" {
-                        tokens_out.push(tok!(Token::Literal(str_val) => idx, line, column));
-                    }
-                    else if *tp == "COMMAND" {
-                        tokens_out.push(tok!(Token::Command(str_val) => idx, line, column));
+        let count = self.tokens.len();
+        let mut parser = Parser::new(&mut self.tokens, 0..count);
+
+        self.body = parser.parse_program();
+        self.statement_ranges = parser.statement_ranges;
+        self.statement_reach = parser.statement_reach;
+        self.parse_diagnostics = parser.diagnostics;
+
+        self.update_diagnostics(0..self.tokens.len());
+
+        let errors: Vec<Diagnostic> = self.diagnostics.iter().filter(|d| d.is_error()).cloned().collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Replaces the bytes in `range` of the source with `text`, then re-lexes the edited lines and re-parses
+    /// the top level statements around them (more if the edit leaves a block unclosed).
+    /// Tokens, AST and diagnostics end up the same as a full `tokenize` + `parse` of the new source.
+    /// Returns the range of indices in `tokens` whose token or style may have changed
+    pub fn apply_edit(&mut self, range: Range<usize>, text: &str) -> Range<usize> {
+        let mut source = self.source.clone();
+        source.replace_range(range.clone(), text);
+
+        let byte_delta = source.len() as isize - self.source.len() as isize;
+        let line_delta = text.matches('\n').count() as isize - self.source[range.clone()].matches('\n').count() as isize;
+        let old_token_count = self.tokens.len();
+
+        let first_line = self.source[..range.start].matches('\n').count() + 1;
+        let last_line = first_line + text.matches('\n').count();
+        let lexed = self.retokenize_lines(&source, first_line, last_line);
+
+        let token_delta = self.tokens.len() as isize - old_token_count as isize;
+        let shift = |i: usize| (i as isize + token_delta) as usize;
+        let lexed_old_end = (lexed.end as isize - token_delta) as usize;
+
+        // statements touching the re-lexed tokens, plus one on either side since an edit can join or split them,
+        // and any earlier ones that looked ahead into them
+        let first = self.statement_ranges.partition_point(|r| r.end < lexed.start).saturating_sub(1)
+            .min(self.statement_reach.iter().position(|&r| r > lexed.start).unwrap_or(usize::MAX));
+        let mut last = (self.statement_ranges.partition_point(|r| r.start <= lexed_old_end) + 1).min(self.statement_ranges.len()).max(first);
+
+        // re-parse everything between the statements that are kept, including tokens of broken statements and any
+        // that looking ahead needed
+        let start = if first > 0 { self.statement_ranges[first - 1].end } else { 0 };
+        let (body, ranges, reach, diagnostics, end) = loop {
+            let end = self.statement_ranges.get(last).map_or(self.tokens.len(), |r| shift(r.start));
+
+            let mut parser = Parser::new(&mut self.tokens, start..end);
+            let body = parser.parse_program();
+
+            if (parser.hit_end || parser.looked_past_end()) && last < self.statement_ranges.len() {
+                last += 1;
+                continue
+            }
+            break (body, parser.statement_ranges, parser.statement_reach, parser.diagnostics, end)
+        };
+
+        // swap the parse diagnostics of the re-parsed bytes for the new ones, and move the ones after them
+        let start_byte = if start > 0 { self.tokens[start - 1].index + self.tokens[start - 1].length } else { 0 };
+        let end_byte = self.tokens.get(end).map_or(source.len(), |t| t.index);
+        let old_end_byte = (end_byte as isize - byte_delta) as usize;
+        let mut kept = Vec::new();
+        let mut after = Vec::new();
+        for mut diagnostic in self.parse_diagnostics.drain(..) {
+            if diagnostic.span.start < start_byte {
+                kept.push(diagnostic);
+            } else if diagnostic.span.start >= old_end_byte && end < self.tokens.len() {
+                diagnostic.shift(byte_delta, line_delta);
+                after.push(diagnostic);
+            }
+        }
+        kept.extend(diagnostics);
+        kept.extend(after);
+        self.parse_diagnostics = kept;
+
+        for r in &mut self.statement_ranges[last..] {
+            *r = shift(r.start)..shift(r.end);
+        }
+        for r in &mut self.statement_reach[last..] {
+            *r = shift(*r);
+        }
+        for node in &mut self.body.nodes[last..] {
+            node.shift_spans(byte_delta, line_delta);
+        }
+        for span in &mut self.body.spans[last..] {
+            span.shift(byte_delta, line_delta);
+        }
+        self.statement_ranges.splice(first..last, ranges);
+        self.statement_reach.splice(first..last, reach);
+        self.body.nodes.splice(first..last, body.nodes);
+        self.body.spans.splice(first..last, body.spans);
+
+        self.update_diagnostics(start..end)
+    }
+
+    /// Sets the ids of the objects (`ObjectTile::id`) that `<namespace:path>` references may name, and re-checks them.
+    /// Until this is called only `engine:` references are checked, since there is no project to check the others against
+    pub fn set_objects(&mut self, ids: impl IntoIterator<Item = impl ToString>) {
+        self.objects = Some(ids.into_iter().map(|id| id.to_string()).collect());
+        self.update_diagnostics(0..self.tokens.len());
+    }
+
+    /// Turns the type checker on or off, and updates the diagnostics to match.
+    /// It's off by default since it can't see everything the runtime can, like attributes added to entities by the game
+    pub fn set_type_checking(&mut self, enabled: bool) {
+        self.type_checking = enabled;
+        self.update_diagnostics(0..self.tokens.len());
+    }
+
+    /// links every object reference to the id it names, returning a diagnostic for each one that names nothing
+    fn resolve_objects(&mut self) -> Vec<Diagnostic> {
+        let mut out = Vec::new();
+        for token in &mut self.tokens {
+            let Token::Object(text) = &token.token else {
+                continue
+            };
+            let id = &text[1..text.len() - 1];
+            let known = if id.starts_with("engine:") {
+                ENGINE_OBJECTS.contains(&id)
+            } else {
+                self.objects.as_ref().is_none_or(|objects| objects.contains(id))
+            };
+
+            if known {
+                token.links.insert("object".to_string(), id.to_string());
+            } else {
+                token.links.remove("object");
+                out.push(Diagnostic::error(codes::UNKNOWN_OBJECT, format!("unknown object `{}`", text), token.span()));
+            }
+        }
+        out
+    }
+
+    /// rebuilds the symbol table, and links every token naming a symbol to it and its definition
+    fn link_symbols(&mut self) {
+        self.symbols = SymbolTable::build(&self.body);
+        for token in &mut self.tokens {
+            token.links.remove("symbol");
+            token.links.remove("definition");
+        }
+        for (id, symbol) in self.symbols.symbols.iter().enumerate() {
+            for reference in &symbol.references {
+                let i = self.tokens.partition_point(|t| t.index < reference.start);
+                let Some(token) = self.tokens.get_mut(i).filter(|t| t.index == reference.start) else {
+                    continue
+                };
+                token.links.insert("symbol".to_string(), id.to_string());
+                if let Some(definition) = symbol.definition {
+                    token.links.insert("definition".to_string(), definition.start.to_string());
+                }
+            }
+        }
+    }
+
+    /// the token covering byte `index` of the source, a cursor right after a token counts as on it
+    pub fn token_at(&self, index: usize) -> Option<&PositionedToken> {
+        let i = self.tokens.partition_point(|t| t.index + t.length <= index);
+        match self.tokens.get(i).filter(|t| t.index <= index) {
+            Some(token) => Some(token),
+            None => i.checked_sub(1).map(|i| &self.tokens[i]).filter(|t| t.index + t.length == index)
+        }
+    }
+
+    /// the project's object ids, `None` until `set_objects` is called
+    pub fn objects(&self) -> Option<&HashSet<String>> {
+        self.objects.as_ref()
+    }
+
+    /// suggestions for the word being typed at byte `index`, best first, see `es3_completion::complete`
+    pub fn complete(&self, index: usize) -> Vec<Completion> {
+        complete(self, index)
+    }
+
+    /// where the variable, macro or parameter at byte `index` is defined
+    pub fn definition(&self, index: usize) -> Option<Span> {
+        let symbol = self.token_at(index)?.links.get("symbol")?.parse::<usize>().ok()?;
+        self.symbols.symbols[symbol].definition
+    }
+
+    /// every use of the variable, macro or parameter at byte `index`, including its definition
+    pub fn references(&self, index: usize) -> Vec<Span> {
+        let symbol = self.token_at(index).and_then(|t| t.links.get("symbol")).and_then(|s| s.parse::<usize>().ok());
+        symbol.map(|s| self.symbols.symbols[s].references.clone()).unwrap_or_default()
+    }
+
+    /// edits that rename the variable, macro or parameter at byte `index` everywhere it's used
+    pub fn rename(&self, index: usize, new_name: &str) -> Result<Vec<TextEdit>, String> {
+        let symbol = self.token_at(index).and_then(|t| t.links.get("symbol")).ok_or("there is no symbol to rename here")?;
+        self.symbols.rename(symbol.parse::<usize>().map_err(|e| e.to_string())?, new_name)
+    }
+
+    /// Rebuilds `diagnostics` from the lexer, parser, macro expansion, context header, object, string and type ones, and sets the
+    /// error/warning style flags on every token in `restyle` covered by one.
+    /// Those after parsing can show up far from an edit (at every call of an edited macro), so all tokens are
+    /// restyled when those change. Returns the range that was restyled
+    fn update_diagnostics(&mut self, restyle: Range<usize>) -> Range<usize> {
+        let (header, header_diagnostics) = ScriptHeader::parse(&self.tokens);
+        let (_, mut analysis_diagnostics) = self.expand(false);
+        analysis_diagnostics.extend(header_diagnostics);
+        analysis_diagnostics.extend(self.resolve_objects());
+        self.link_symbols();
+        analysis_diagnostics.extend(check_strings(&self.body));
+        if self.type_checking {
+            analysis_diagnostics.extend(check_types(&self.body));
+        }
+        analysis_diagnostics.sort_by_key(|d| d.span.start);
+        self.header = header;
+
+        let mut restyle = restyle;
+        if analysis_diagnostics != self.analysis_diagnostics {
+            restyle = 0..self.tokens.len();
+            for token in &mut self.tokens {
+                token.style.set_flag(style_flags::ERROR | style_flags::WARNING, false);
+            }
+            self.analysis_diagnostics = analysis_diagnostics;
+        }
+
+        self.diagnostics = self.lexer_diagnostics();
+        self.diagnostics.extend(self.parse_diagnostics.iter().cloned());
+        self.diagnostics.extend(self.analysis_diagnostics.iter().cloned());
+
+        for diagnostic in &self.diagnostics {
+            let flag = diagnostic.severity.style_flag();
+            if flag == 0 {
+                continue
+            }
+            for token in &mut self.tokens[restyle.clone()] {
+                if diagnostic.span.overlaps(token.index, token.index + token.length) {
+                    token.style.set_flag(flag, true);
+                }
+            }
+        }
+        restyle
+    }
+
+    /// Compiles the parsed AST into the JSON structure consumed by the game runtime,
+    /// `{"header": {"owner", "trigger", "flags"}, "body": [statements]}`
+    pub fn compile(&self) -> Value {
+        json!({
+            "header": self.header.to_json(),
+            "body": self.expand(false).0
+        })
+    }
+
+    /// Compiles like `compile`, keeping what a debugger needs: each statement is tagged with the line it starts on,
+    /// for statements from a macro that is the line in the macro's body, and each macro call becomes
+    /// `{"#expand": name, "params": {param: argument}, "locals": {local: renamed}, "body": [statements]}`
+    pub fn compile_debug(&self) -> Value {
+        json!({
+            "header": self.header.to_json(),
+            "body": self.expand(true).0
+        })
+    }
+
+    /// Compiles to bytecode instead of JSON, see `es3_bytecode`. Statements keep the line they came from
+    pub fn compile_bytecode(&self) -> Result<Chunk, String> {
+        es3_bytecode::compile(&self.compile_debug())
+    }
+
+    /// compiles the AST with every macro call expanded, returning any problems the expansion ran into.
+    /// With `debug_info` set the output is tagged for debugging, see `compile_debug`
+    fn expand(&self, debug_info: bool) -> (Value, Vec<Diagnostic>) {
+        let mut compile_context = CompileContext::new();
+        compile_context.debug_info = debug_info;
+        compile_context.define_macros(&self.body);
+        let out = self.body.compile(&mut compile_context);
+
+        // expand each definition once on its own, so problems in macros that are never called still show up
+        let mut definitions: Vec<&MacroDefNode> = compile_context.macros.values().copied().collect();
+        definitions.sort_by_key(|d| d.span.start);
+        for definition in definitions {
+            let params = definition.params.iter().map(|p| (p.clone(), json!({ "#ref": p }))).collect();
+            compile_context.expansions.push(Expansion { name: definition.name.clone(), params, locals: HashMap::new() });
+            definition.body.compile(&mut compile_context);
+            compile_context.expansions.pop();
+        }
+
+        // a problem inside a macro is found again at every expansion, only report it once
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        for diagnostic in compile_context.diagnostics {
+            if !diagnostics.iter().any(|d| d.code == diagnostic.code && d.span == diagnostic.span) {
+                diagnostics.push(diagnostic);
+            }
+        }
+        diagnostics.sort_by_key(|d| d.span.start);
+
+        (out, diagnostics)
+    }
+
+
+}
+
+/// Recursive-descent parser over a token vec.
+/// Comments and context headers are skipped, everything else is addressed by its position in `significant`
+struct Parser<'a> {
+    tokens: &'a mut Vec<PositionedToken>,
+    significant: Vec<usize>,
+    pos: usize,
+    diagnostics: Vec<Diagnostic>,
+    /// token range of each top level statement
+    statement_ranges: Vec<Range<usize>>,
+    /// whether error recovery ran into the end of the tokens, so the tokens after them may have been part of the broken statement
+    hit_end: bool,
+    /// token index each top level statement's parse looked ahead to (exclusive), see `lookahead`
+    statement_reach: Vec<usize>,
+    /// furthest position in `significant` looked at so far (exclusive), past its length when looking ahead went past
+    /// the end of the tokens, so the tokens after them could have changed what was parsed
+    lookahead: Cell<usize>,
+    /// end of the token range being parsed
+    end: usize
+}
+
+type ParseResult = Result<Box<dyn Node>, Diagnostic>;
+type Arguments = (Vec<Box<dyn Node>>, Vec<(String, Box<dyn Node>)>);
+
+impl<'a> Parser<'a> {
+
+    /// parser over `tokens[range]`, resetting the style of every token in it
+    fn new(tokens: &'a mut Vec<PositionedToken>, range: Range<usize>) -> Self {
+        let mut significant = Vec::new();
+
+        for (i, token) in tokens.iter_mut().enumerate().take(range.end).skip(range.start) {
+            token.style.clear_flags();
+            match token.token {
+                Token::Comment(_) => {
+                    token.style.set_flags(style_flags::ITALIC | style_flags::FADED);
+                }
+                Token::Context(_) => {
+                    token.style.set_flags(style_flags::ITALIC);
+                }
+                Token::Error(_) => {
+                    // already reported by the lexer
+                    token.style.set_flags(style_flags::ERROR);
+                }
+                Token::Keyword(_) | Token::Command(_) => {
+                    token.style.set_flags(style_flags::BOLD);
+                    significant.push(i);
+                }
+                _ => {
+                    significant.push(i);
+                }
+            }
+        }
+
+        Self {
+            tokens,
+            significant,
+            pos: 0,
+            diagnostics: Vec::new(),
+            statement_ranges: Vec::new(),
+            statement_reach: Vec::new(),
+            hit_end: false,
+            lookahead: Cell::new(0),
+            end: range.end
+        }
+    }
+
+    fn token_at(&self, pos: usize) -> Option<&PositionedToken> {
+        self.tokens.get(*self.significant.get(pos)?)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.token_at(self.pos).map(|t| &t.token)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.note_lookahead(offset);
+        self.token_at(self.pos + offset).map(|t| &t.token)
+    }
+
+    /// remembers how far looking `offset` tokens ahead of the current token reaches
+    fn note_lookahead(&self, offset: usize) {
+        if self.pos < self.significant.len() {
+            self.lookahead.set(self.lookahead.get().max(self.pos + offset + 1));
+        }
+    }
+
+    fn looked_past_end(&self) -> bool {
+        self.lookahead.get() > self.significant.len()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.significant.len()
+    }
+
+    fn is_literal(&self, lit: &str) -> bool {
+        matches!(self.peek(), Some(Token::Literal(l)) if l == lit)
+    }
+
+    fn is_literal_at(&self, offset: usize, lit: &str) -> bool {
+        matches!(self.peek_at(offset), Some(Token::Literal(l)) if l == lit)
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        matches!(self.peek(), Some(Token::Keyword(k)) if k == kw)
+    }
+
+    /// whether the current token starts on the same line that the previous token ends on
+    fn on_same_line(&self) -> bool {
+        if self.pos == 0 {
+            return false
+        }
+        match (self.token_at(self.pos - 1), self.token_at(self.pos)) {
+            (Some(prev), Some(cur)) => {
+                let extra_lines = match &prev.token {
+                    Token::String(s) => s.matches('\n').count(),
+                    _ => 0
+                };
+                prev.line + extra_lines == cur.line
+            }
+            _ => false
+        }
+    }
+
+    /// whether the current token is directly followed by the next one, with no whitespace between them
+    fn is_adjacent(&self) -> bool {
+        self.note_lookahead(1);
+        match (self.token_at(self.pos), self.token_at(self.pos + 1)) {
+            (Some(a), Some(b)) => a.index + describe_token(&a.token).len() == b.index,
+            _ => false
+        }
+    }
+
+    /// span of the significant token at `pos`, or an empty span at the end of the script
+    fn span_at(&self, pos: usize) -> Span {
+        if let Some(token) = self.token_at(pos) {
+            token.span()
+        } else if let Some(token) = self.significant.last().map(|&i| &self.tokens[i]) {
+            let end = token.span();
+            Span::new(end.end, end.end, end.end_line, end.end_column, end.end_line, end.end_column)
+        } else {
+            Span::new(0, 0, 1, 0, 1, 0)
+        }
+    }
+
+    /// span from the token at `start` up to the last consumed token
+    fn span_from(&self, start: usize) -> Span {
+        self.span_at(start).to(&self.span_at(self.pos.max(start + 1) - 1))
+    }
+
+    /// builds an error at the current token, `message` is followed by what was found instead
+    fn error(&self, code: &'static str, message: &str) -> Diagnostic {
+        let found = match self.peek() {
+            Some(token) => format!("`{}`", describe_token(token)),
+            None => "end of script".to_string()
+        };
+        Diagnostic::error(code, format!("{}, found {}", message, found), self.span_at(self.pos))
+    }
+
+    /// error for a missing closing delimiter, pointing back at where it was opened
+    fn unclosed(&self, close: &str, opened_at: usize, what: &str) -> Diagnostic {
+        self.error(codes::UNCLOSED_DELIMITER, &format!("expected `{}` to close {}", close, what))
+            .with_related(self.span_at(opened_at), format!("{} opened here", what))
+    }
+
+    fn expect_literal(&mut self, lit: &str) -> Result<(), Diagnostic> {
+        if self.is_literal(lit) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(codes::EXPECTED_TOKEN, &format!("expected `{}`", lit)))
+        }
+    }
+
+    fn expect_word(&mut self) -> Result<String, Diagnostic> {
+        if let Some(Token::Word(w)) = self.peek() {
+            let w = w.clone();
+            self.pos += 1;
+            Ok(w)
+        } else {
+            Err(self.error(codes::EXPECTED_NAME, "expected a name"))
+        }
+    }
+
+    /// Skips ahead from the start of a broken statement to the start of the next one.
+    /// Stops at the first token from `error_pos` onwards that begins a new line outside any brackets
+    /// (or any new line indented no further than the broken statement, in case of unclosed brackets),
+    /// or at a `}` that closes the enclosing block
+    fn synchronize(&mut self, start: usize, error_pos: usize) {
+        self.pos = start;
+        let start_column = self.token_at(start).map(|t| t.column).unwrap_or(0);
+        let mut depth: i32 = 0;
+
+        while !self.at_end() {
+            if self.pos >= error_pos && self.pos > start && !self.on_same_line()
+                && (depth == 0 || self.token_at(self.pos).unwrap().column <= start_column) {
+                return
+            }
+
+            if self.is_literal("(") || self.is_literal("[") || self.is_literal("{") {
+                depth += 1;
+            } else if self.is_literal(")") || self.is_literal("]") || self.is_literal("}") {
+                if depth == 0 {
+                    if self.is_literal("}") {
+                        if self.pos <= error_pos {
+                            self.pos += 1;
+                            continue
+                        }
+                        return
                     }
-                    else if *tp == "KEYWORD" {
-                        tokens_out.push(tok!(Token::Keyword(str_val) => idx, line, column));
+                } else {
+                    depth -= 1;
+                }
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn parse_program(&mut self) -> StatementsNode {
+        let mut nodes = Vec::new();
+        let mut spans = Vec::new();
+
+        while !self.at_end() {
+            if self.is_literal("}") {
+                let err = Diagnostic::error(codes::UNEXPECTED_TOKEN, "unexpected `}`", self.span_at(self.pos));
+                self.diagnostics.push(err);
+                self.pos += 1;
+                continue
+            }
+            let start = self.pos;
+            if let Some(node) = self.parse_statement_recovering() {
+                nodes.push(node);
+                spans.push(self.span_from(start));
+                let end = self.significant[self.pos - 1] + 1;
+                let reach = match self.lookahead.get() {
+                    0 => end,
+                    p => self.significant.get(p - 1).map_or(self.end + 1, |&i| i + 1).max(end)
+                };
+                self.statement_ranges.push(self.significant[start]..end);
+                self.statement_reach.push(reach);
+            }
+        }
+
+        StatementsNode::new(nodes, spans)
+    }
+
+    fn parse_statement_recovering(&mut self) -> Option<Box<dyn Node>> {
+        let start = self.pos;
+        match self.parse_statement() {
+            Ok(node) => Some(node),
+            Err(err) => {
+                self.diagnostics.push(err);
+                let error_pos = self.pos;
+                self.synchronize(start, error_pos);
+                self.hit_end |= self.at_end();
+                None
+            }
+        }
+    }
+
+    fn parse_block(&mut self) -> ParseResult {
+        let open = self.pos;
+        self.expect_literal("{")?;
+        let mut nodes = Vec::new();
+        let mut spans = Vec::new();
+        let mut unreachable = false;
+
+        loop {
+            if self.at_end() {
+                return Err(self.unclosed("}", open, "block"))
+            }
+            if self.is_literal("}") {
+                self.pos += 1;
+                break
+            }
+            let start = self.pos;
+            if let Some(node) = self.parse_statement_recovering() {
+                if unreachable {
+                    // only the first unreachable statement is reported
+                    self.diagnostics.push(Diagnostic::warning(codes::UNREACHABLE_CODE, "unreachable statement", self.span_from(start)));
+                    unreachable = false;
+                } else if node.as_any().is::<BreakNode>() || node.as_any().is::<ContinueNode>() {
+                    unreachable = true;
+                }
+                nodes.push(node);
+                spans.push(self.span_from(start));
+            }
+        }
+
+        Ok(Box::new(StatementsNode::new(nodes, spans)))
+    }
+
+    fn parse_statement(&mut self) -> ParseResult {
+        while self.is_literal(";") {
+            self.pos += 1;
+        }
+
+        let node: Box<dyn Node> = match self.peek() {
+            Some(Token::Keyword(kw)) => {
+                let kw = kw.clone();
+                match kw.as_str() {
+                    "if" => self.parse_if()?,
+                    "while" => self.parse_while()?,
+                    "for" => self.parse_for()?,
+                    "match" => self.parse_match()?,
+                    "break" => {
+                        self.pos += 1;
+                        Box::new(BreakNode::new())
                     }
-                    else if *tp == "WORD" {
-                        tokens_out.push(tok!(Token::Word(str_val) => idx, line, column));
+                    "continue" => {
+                        self.pos += 1;
+                        Box::new(ContinueNode::new())
                     }
-                    else if *tp == "NUMBER" {
-                        if str_val.contains(".") {
-                            tokens_out.push(tok!(Token::Float(str_val.parse::<f64>().unwrap()) => idx, line, column));
-                        } else {
-                            tokens_out.push(tok!(Token::Integer(str_val.parse::<i64>().unwrap()) => idx, line, column));
-                        }
+                    "class" | "def" => {
+                        return Err(self.error(codes::UNSUPPORTED_SYNTAX, &format!("`{}` is not supported, use a `$macro(...) {{ }}` definition instead", kw)))
                     }
-                    else if *tp == "LITERAL" {
-                        tokens_out.push(tok!(Token::Literal(str_val) => idx, line, column));
+                    "elif" | "else" | "case" => {
+                        return Err(self.error(codes::UNEXPECTED_TOKEN, &format!("`{}` without a matching statement", kw)))
                     }
-                    else if *tp == "ERROR" {
-                        let mut token = tok!(Token::Error(str_val) => idx, line, column);
-                        token.style.set_flag(style_flags::ERROR, true);
-                        tokens_out.push(token);
+                    _ => self.parse_expression_statement()?
+                }
+            }
+            Some(Token::Macro(_)) if self.is_literal_at(1, "(") && self.is_macro_definition() => {
+                self.parse_macro_definition()?
+            }
+            None => return Err(self.error(codes::EXPECTED_EXPRESSION, "expected a statement")),
+            _ => self.parse_expression_statement()?
+        };
+
+        while self.is_literal(";") {
+            self.pos += 1;
+        }
+
+        Ok(node)
+    }
+
+    /// looks past the parenthesized list after a macro name to see if a body follows it
+    fn is_macro_definition(&self) -> bool {
+        let mut offset = 1;
+        let mut depth = 0;
+        while let Some(token) = self.peek_at(offset) {
+            if let Token::Literal(l) = token {
+                if l == "(" {
+                    depth += 1;
+                } else if l == ")" {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.is_literal_at(offset + 1, "{")
                     }
+                }
+            }
+            offset += 1;
+        }
+        false
+    }
+
+    fn parse_macro_definition(&mut self) -> ParseResult {
+        let start = self.pos;
+        let name = if let Some(Token::Macro(m)) = self.peek() { m.clone() } else { unreachable!() };
+        let i = self.significant[self.pos];
+        self.tokens[i].style.set_flag(style_flags::BOLD, true);
+        self.pos += 1;
+
+        self.expect_literal("(")?;
+        let mut params = Vec::new();
+        let mut param_spans = Vec::new();
+        while !self.is_literal(")") {
+            match self.peek() {
+                Some(Token::Macro(m)) | Some(Token::Word(m)) => {
+                    params.push(m.clone());
+                    param_spans.push(self.span_at(self.pos));
+                    let i = self.significant[self.pos];
+                    self.tokens[i].style.set_flag(style_flags::ITALIC, true);
+                    self.pos += 1;
+                }
+                _ => return Err(self.error(codes::EXPECTED_NAME, "expected a macro parameter name"))
+            }
+            if !self.is_literal(")") {
+                self.expect_literal(",")?;
+            }
+        }
+        self.pos += 1;
+        let span = self.span_from(start);
+
+        let body = self.parse_block()?;
+
+        Ok(Box::new(MacroDefNode::new(name, params, param_spans, body, span)))
+    }
+
+    fn parse_if(&mut self) -> ParseResult {
+        self.pos += 1; // `if` or `elif`
+        let condition = self.parse_expression()?;
+        let body = self.parse_block()?;
+
+        let else_node = if self.is_keyword("elif") {
+            Some(self.parse_if()?)
+        } else if self.is_keyword("else") {
+            self.pos += 1;
+            if self.is_keyword("if") {
+                Some(self.parse_if()?)
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
 
-                    column += sz;
-                    idx += sz;
+        Ok(Box::new(IfNode::new(condition, body, else_node)))
+    }
+
+    fn parse_while(&mut self) -> ParseResult {
+        self.pos += 1;
+        let condition = self.parse_expression()?;
+        let body = self.parse_block()?;
+        Ok(Box::new(WhileNode::new(condition, body)))
+    }
 
-                    break 'pattern_loop;
+    fn parse_for(&mut self) -> ParseResult {
+        self.pos += 1;
+        let variable_span = self.span_at(self.pos);
+        let variable = match self.peek() {
+            Some(Token::Word(w)) | Some(Token::Macro(w)) => w.clone(),
+            _ => return Err(self.error(codes::EXPECTED_NAME, "expected a loop variable after `for`"))
+        };
+        self.pos += 1;
+        if !self.is_keyword("in") {
+            return Err(self.error(codes::EXPECTED_TOKEN, "expected `in`"))
+        }
+        self.pos += 1;
+        let start = self.pos;
+        let iterable = self.parse_expression()?;
+        let span = self.span_from(start);
+        let body = self.parse_block()?;
+        Ok(Box::new(ForNode::new(variable, variable_span, iterable, body, span)))
+    }
+
+    fn parse_match(&mut self) -> ParseResult {
+        self.pos += 1;
+        let value = self.parse_expression()?;
+        let open = self.pos;
+        self.expect_literal("{")?;
+
+        let mut cases = Vec::new();
+        let mut default = None;
+
+        loop {
+            if self.at_end() {
+                return Err(self.unclosed("}", open, "match"))
+            }
+            if self.is_literal("}") {
+                self.pos += 1;
+                break
+            }
+            if self.is_keyword("case") {
+                self.pos += 1;
+                let case = self.parse_expression()?;
+                let body = self.parse_block()?;
+                cases.push((case, body));
+            } else if self.is_keyword("else") {
+                if default.is_some() {
+                    return Err(self.error(codes::UNEXPECTED_TOKEN, "match can only have one `else` case"))
                 }
+                self.pos += 1;
+                default = Some(self.parse_block()?);
+            } else {
+                return Err(self.error(codes::EXPECTED_TOKEN, "expected `case` or `else`"))
             }
         }
 
-        self.tokens = tokens_out;
+        Ok(Box::new(MatchNode::new(value, cases, default)))
+    }
+
+    fn parse_expression_statement(&mut self) -> ParseResult {
+        let start = self.pos;
+        let expr = self.parse_expression()?;
+
+        if self.is_literal("=") {
+            self.pos += 1;
+            let value = self.parse_expression()?;
+            return Ok(Box::new(AssignNode::new(expr, value, self.span_from(start))))
+        }
+
+        if !has_effect(expr.as_ref()) {
+            self.diagnostics.push(Diagnostic::warning(codes::UNUSED_EXPRESSION, "expression has no effect", self.span_from(start)));
+        }
 
+        Ok(expr)
     }
 
-    /// Attempts to parse the current token vec into an AST.
-    /// The parser will attempt to recover from errors, and will collect errors to be returned at the end of parsing.
-    /// This function will also update the tokens with more informed highlighting, and create links for
-    /// definitions, uses, etc...
-    pub fn parse(&mut self) -> Result<(), Vec<String>> {
+    fn parse_expression(&mut self) -> ParseResult {
+        self.parse_or()
+    }
 
+    fn parse_or(&mut self) -> ParseResult {
+        let mut left = self.parse_and()?;
+        while self.is_keyword("or") {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = Box::new(LogicNode::new(left, "or".to_string(), right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> ParseResult {
+        let mut left = self.parse_not()?;
+        while self.is_keyword("and") {
+            self.pos += 1;
+            let right = self.parse_not()?;
+            left = Box::new(LogicNode::new(left, "and".to_string(), right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> ParseResult {
+        if self.is_keyword("not") {
+            self.pos += 1;
+            let value = self.parse_not()?;
+            return Ok(Box::new(NotNode::new(value)))
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> ParseResult {
+        let start = self.pos;
+        let mut left = self.parse_concat()?;
+        while let Some(Token::Comparison(op)) = self.peek() {
+            let op = op.clone();
+            self.pos += 1;
+            let right = self.parse_concat()?;
+            left = Box::new(CompareNode::new(left, op, right, self.span_from(start)));
+        }
+        Ok(left)
+    }
+
+    fn parse_concat(&mut self) -> ParseResult {
+        let start = self.pos;
+        let mut left = self.parse_additive()?;
+        while self.is_literal("..") || self.is_literal("::") {
+            let op = describe_token(self.peek().unwrap());
+            self.pos += 1;
+            let right = self.parse_additive()?;
+            left = Box::new(BinaryOpNode::new(left, op, right, self.span_from(start)));
+        }
+        Ok(left)
+    }
+
+    fn parse_additive(&mut self) -> ParseResult {
+        let start = self.pos;
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            if self.is_literal("-") && self.is_adjacent() && matches!(self.peek_at(1), Some(Token::Comparison(c)) if c == ">") {
+                // `->` belongs to a move command
+                break
+            }
+            if self.is_literal("+") || self.is_literal("-") {
+                let op = describe_token(self.peek().unwrap());
+                self.pos += 1;
+                let right = self.parse_multiplicative()?;
+                left = Box::new(BinaryOpNode::new(left, op, right, self.span_from(start)));
+            } else {
+                break
+            }
+        }
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> ParseResult {
+        let start = self.pos;
+        let mut left = self.parse_unary()?;
+        while self.is_literal("*") || self.is_literal("/") || self.is_literal("%") {
+            let op = describe_token(self.peek().unwrap());
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = Box::new(BinaryOpNode::new(left, op, right, self.span_from(start)));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> ParseResult {
+        let start = self.pos;
+        if self.is_literal("-") {
+            self.pos += 1;
+            let value = self.parse_unary()?;
+            return Ok(Box::new(UnaryOpNode::new("-".to_string(), value, self.span_from(start))))
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> ParseResult {
+        let start = self.pos;
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            if self.is_literal(".") {
+                self.pos += 1;
+                let name = self.expect_word()?;
+                if name == "tag" && self.is_literal("$") && self.is_literal_at(1, "[") {
+                    expr = self.parse_tag(Some(expr))?;
+                } else {
+                    expr = Box::new(MemberNode::new(expr, name, self.span_from(start)));
+                }
+            }
+            else if self.is_literal("(") && self.on_same_line() {
+                let (args, kwargs) = self.parse_arguments()?;
+                expr = Box::new(CallNode::new(expr, args, kwargs, self.span_from(start)));
+            }
+            else if self.is_literal("[") && self.on_same_line() {
+                self.pos += 1;
+                let index = self.parse_expression()?;
+                self.expect_literal("]")?;
+                expr = Box::new(IndexNode::new(expr, index, self.span_from(start)));
+            }
+            else {
+                break
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// parses `$[name]` or `$[name = value]`, the `tag` word before it has already been consumed
+    fn parse_tag(&mut self, object: Option<Box<dyn Node>>) -> ParseResult {
+        self.expect_literal("$")?;
+        self.expect_literal("[")?;
+        let name = match self.peek() {
+            Some(Token::Word(w)) => w.clone(),
+            Some(Token::String(s)) => decode_string(s).0,
+            _ => return Err(self.error(codes::EXPECTED_NAME, "expected a tag name"))
+        };
+        self.pos += 1;
+
+        let value = if self.is_literal("=") {
+            self.pos += 1;
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        self.expect_literal("]")?;
+
+        Ok(Box::new(TagNode::new(object, name, value)))
+    }
+
+    /// parses `(a, b, key: c)`. Keyword arguments must come after positional ones
+    fn parse_arguments(&mut self) -> Result<Arguments, Diagnostic> {
+        let open = self.pos;
+        self.expect_literal("(")?;
+        let mut args = Vec::new();
+        let mut kwargs = Vec::new();
+
+        while !self.is_literal(")") {
+            if self.at_end() {
+                return Err(self.unclosed(")", open, "argument list"))
+            }
+            if matches!(self.peek(), Some(Token::Word(_))) && self.is_literal_at(1, ":") {
+                let key = self.expect_word()?;
+                self.pos += 1;
+                kwargs.push((key, self.parse_expression()?));
+            } else {
+                if !kwargs.is_empty() {
+                    return Err(self.error(codes::INVALID_ARGUMENTS, "positional arguments cannot follow keyword arguments"))
+                }
+                args.push(self.parse_expression()?);
+            }
+            if !self.is_literal(")") && !self.is_literal(",") {
+                return Err(self.unclosed(")", open, "argument list"))
+            }
+            if self.is_literal(",") {
+                self.pos += 1;
+            }
+        }
+        self.pos += 1;
+
+        Ok((args, kwargs))
+    }
+
+    fn parse_primary(&mut self) -> ParseResult {
+        let token = match self.peek() {
+            Some(t) => t,
+            None => return Err(self.error(codes::EXPECTED_EXPRESSION, "expected an expression"))
+        };
+
+        let node: Box<dyn Node> = match token {
+            Token::Integer(i) => Box::new(IntegerNode::new(*i)),
+            Token::Float(f) => Box::new(FloatNode::new(*f)),
+            Token::Boolean(b) => Box::new(BooleanNode::new(*b)),
+            Token::String(s) => Box::new(StringNode::new(s.clone(), self.span_at(self.pos))),
+            Token::Object(o) => Box::new(ObjectNode::new(o[1..o.len()-1].to_string())),
+            Token::Tag(t) => Box::new(TagNode::new(None, t[2..].to_string(), None)),
+            Token::Word(w) => {
+                let w = w.clone();
+                if w == "tag" && self.is_literal_at(1, "$") && self.is_literal_at(2, "[") {
+                    self.pos += 1;
+                    return self.parse_tag(None)
+                }
+                Box::new(VariableNode::new(w, self.span_at(self.pos)))
+            }
+            Token::Macro(m) => {
+                let m = m.clone();
+                let start = self.pos;
+                self.pos += 1;
+                if self.is_literal("(") && self.on_same_line() {
+                    let (args, kwargs) = self.parse_arguments()?;
+                    if !kwargs.is_empty() {
+                        return Err(Diagnostic::error(codes::INVALID_ARGUMENTS, "macros do not take keyword arguments", self.span_from(start)))
+                    }
+                    return Ok(Box::new(MacroCallNode::new(m, args, self.span_from(start))))
+                }
+                return Ok(Box::new(VariableNode::new(m, self.span_at(start))))
+            }
+            Token::Keyword(k) if k == "none" => Box::new(NoneNode::new()),
+            Token::Command(c) => {
+                let c = c.clone();
+                return self.parse_command(&c)
+            }
+            Token::Literal(l) => {
+                match l.as_str() {
+                    "(" => {
+                        self.pos += 1;
+                        let expr = self.parse_expression()?;
+                        self.expect_literal(")")?;
+                        return Ok(expr)
+                    }
+                    "[" => return self.parse_list(),
+                    "{" => return self.parse_map(),
+                    "#" => {
+                        self.pos += 1;
+                        let name = self.expect_word()?;
+                        return Ok(Box::new(ContextRefNode::new(name)))
+                    }
+                    _ => return Err(self.error(codes::EXPECTED_EXPRESSION, "expected an expression"))
+                }
+            }
+            _ => return Err(self.error(codes::EXPECTED_EXPRESSION, "expected an expression"))
+        };
+
+        self.pos += 1;
+        Ok(node)
+    }
+
+    fn parse_list(&mut self) -> ParseResult {
+        let open = self.pos;
+        self.expect_literal("[")?;
+        let mut items = Vec::new();
+        while !self.is_literal("]") {
+            if self.at_end() {
+                return Err(self.unclosed("]", open, "list"))
+            }
+            items.push(self.parse_expression()?);
+            if !self.is_literal("]") && !self.is_literal(",") {
+                return Err(self.unclosed("]", open, "list"))
+            }
+            if self.is_literal(",") {
+                self.pos += 1;
+            }
+        }
+        self.pos += 1;
+        Ok(Box::new(ListNode::new(items)))
+    }
 
+    fn parse_map(&mut self) -> ParseResult {
+        let open = self.pos;
+        self.expect_literal("{")?;
+        let mut entries = Vec::new();
+        while !self.is_literal("}") {
+            if self.at_end() {
+                return Err(self.unclosed("}", open, "object literal"))
+            }
+            let key = match self.peek() {
+                Some(Token::Word(w)) => w.clone(),
+                Some(Token::String(s)) => decode_string(s).0,
+                _ => return Err(self.error(codes::EXPECTED_NAME, "expected a key"))
+            };
+            self.pos += 1;
+            self.expect_literal(":")?;
+            entries.push((key, self.parse_expression()?));
+            if !self.is_literal("}") && !self.is_literal(",") {
+                return Err(self.unclosed("}", open, "object literal"))
+            }
+            if self.is_literal(",") {
+                self.pos += 1;
+            }
+        }
+        self.pos += 1;
+        Ok(Box::new(MapNode::new(entries)))
+    }
 
-        Ok(())
+    /// `new: <namespace:object> { ... }` and `move: subject -> destination`
+    fn parse_command(&mut self, command: &str) -> ParseResult {
+        let start = self.pos;
+        self.pos += 1;
+        self.expect_literal(":")?;
+
+        if command == "new" {
+            let object = match self.peek() {
+                Some(Token::Object(o)) => o[1..o.len()-1].to_string(),
+                _ => return Err(self.error(codes::EXPECTED_NAME, "expected an object type like `<engine:currency>`"))
+            };
+            self.pos += 1;
+            let data = if self.is_literal("{") {
+                Some(self.parse_map()?)
+            } else {
+                None
+            };
+            Ok(Box::new(NewNode::new(object, data, self.span_from(start))))
+        } else {
+            let subject = self.parse_expression()?;
+            if !(self.is_literal("-") && matches!(self.peek_at(1), Some(Token::Comparison(c)) if c == ">")) {
+                return Err(self.error(codes::EXPECTED_TOKEN, "expected `->`"))
+            }
+            self.pos += 2;
+            let destination = self.parse_expression()?;
+            Ok(Box::new(MoveNode::new(subject, destination)))
+        }
     }
 
+}
+
+/// whether an expression used as a statement does anything
+fn has_effect(node: &dyn Node) -> bool {
+    let node = node.as_any();
+    node.is::<CallNode>() || node.is::<MacroCallNode>() || node.is::<NewNode>() || node.is::<MoveNode>()
+        || node.downcast_ref::<TagNode>().is_some_and(|tag| tag.value.is_some())
+}
 
+/// byte index of the start of `line` (1-based), or the end of `input` if it has fewer lines
+fn line_start_index(input: &str, line: usize) -> usize {
+    if line <= 1 {
+        return 0
+    }
+    input.match_indices('\n').nth(line - 2).map(|(i, _)| i + 1).unwrap_or(input.len())
+}
+
+/// source text of a token, used for error messages
+fn describe_token(token: &Token) -> String {
+    match token {
+        Token::Newline => "\n".to_string(),
+        Token::Integer(i) => i.to_string(),
+        Token::Float(f) => f.to_string(),
+        Token::Boolean(b) => b.to_string(),
+        Token::String(s) | Token::Context(s) | Token::Literal(s) | Token::Word(s) |
+        Token::Keyword(s) | Token::Macro(s) | Token::Tag(s) | Token::Object(s) |
+        Token::Command(s) | Token::Comparison(s) | Token::Comment(s) |
+        Token::JSONKey(s) | Token::JSONValue(s) | Token::Error(s) => s.clone()
+    }
 }
 
 
 
 #[cfg(test)]
 pub mod es3_tests {
-    use crate::es3::ES3Compiler;
+    use serde_json::Value;
+    use crate::es3::{ES3Compiler, Token};
+    use crate::es3_diagnostics::{codes, Diagnostic, Severity};
+    use crate::es3_header::Trigger;
 
-    const SCRIPT: &str = r##"
+    pub const SCRIPT: &str = r##"
 #!emberhollow/rooms/boats/spawn_boat
 #!enter-script
 
@@ -447,10 +2154,316 @@ move: #player -> <emberhollow:rooms/docks/roads/road_4>
 
     }
 
-}
+    /// re-lexes `edited` lines of `after` incrementally and checks the result against lexing it from scratch
+    fn check_retokenize(before: &str, after: &str, first_line: usize, last_line: usize) -> std::ops::Range<usize> {
+        let dump = |c: &ES3Compiler| c.tokens.iter().map(|t| format!("{} Len {}", t, t.length)).collect::<Vec<String>>();
 
+        let mut fresh = ES3Compiler::new();
+        fresh.tokenize(after);
+
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize(before);
+        let changed = compiler.retokenize_lines(after, first_line, last_line);
+
+        assert_eq!(dump(&compiler), dump(&fresh));
+        changed
+    }
+
+    #[test]
+    pub fn test_retokenize() {
+        let edited = SCRIPT.replace("    wait(2)\n}", "    wait(2, \"a\nb\")\n}");
+        let line = SCRIPT[..SCRIPT.find("    wait(2)").unwrap()].matches('\n').count() + 1;
+        let changed = check_retokenize(SCRIPT, &edited, line, line + 1);
+        assert_eq!(changed.len(), 6);
 
+        // inserted line, everything after it moves down
+        assert_eq!(check_retokenize("a = 1\nb = 2\nc = 3", "a = 1\nb = 2\nq = 9\nc = 3", 3, 3), 6..9);
 
+        // closing a string opened on an earlier line
+        check_retokenize("s = 'a\nt = 1\nu = 2", "s = 'a\nt = 1\nu = 2'", 3, 3);
+
+        // deleted lines
+        check_retokenize(SCRIPT, &SCRIPT.replace("if ($listening) {\n    output(\"...\")\n", ""), 19, 19);
+    }
+
+    /// applies an edit incrementally and checks tokens, output and diagnostics against a full re-parse
+    fn check_edit(before: &str, range: std::ops::Range<usize>, text: &str) -> std::ops::Range<usize> {
+        let mut after = before.to_string();
+        after.replace_range(range.clone(), text);
+
+        let mut fresh = ES3Compiler::new();
+        fresh.tokenize(&after);
+        let _ = fresh.parse();
+
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize(before);
+        let _ = compiler.parse();
+        let changed = compiler.apply_edit(range, text);
+
+        let dump = |c: &ES3Compiler| c.tokens.iter().map(|t| format!("{} Len {} {:?}", t, t.length, t.style)).collect::<Vec<String>>();
+        assert_eq!(dump(&compiler), dump(&fresh));
+        assert_eq!(compiler.compile(), fresh.compile());
+        assert_eq!(compiler.compile_bytecode(), fresh.compile_bytecode());
+        assert_eq!(compiler.diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>(), fresh.diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>());
+        changed
+    }
+
+    #[test]
+    pub fn test_apply_edit() {
+        let at = |needle: &str| SCRIPT.find(needle).unwrap();
+
+        // only the edited statement and its neighbours are re-parsed
+        let changed = check_edit(SCRIPT, at("wait(2)") + 5..at("wait(2)") + 6, "3");
+        assert!(changed.start > 0 && changed.end < 200, "{:?}", changed);
+
+        // continuing a statement onto a new one
+        check_edit(SCRIPT, at("\n\noutput(\"say")..at("\n\noutput(\"say"), "\n    + 1");
+
+        // unclosed block swallows everything after it, closing it again restores the statements
+        let unclosed = check_edit(SCRIPT, at("    wait(2)\n}") + 12..at("    wait(2)\n}") + 13, "");
+        assert_eq!(unclosed.end, 219);
+        check_edit(&SCRIPT.replacen("    wait(2)\n}", "    wait(2)\n", 1), at("    wait(2)\n}") + 12..at("    wait(2)\n}") + 12, "}");
+
+        // errors are fixed and added
+        check_edit("a = 1\nb = (2 +\nc = 3\n", 13..13, ")");
+        check_edit("a = 1\nb = (2 + 1)\nc = 3\n", 8..9, "");
+        check_edit("a = 1\nb = 2 @\nc = 3\n", 12..13, "");
+
+        // new lines and statements
+        check_edit("a = 1\n\nc = 3\n", 6..6, "b = [1,\n2]\nwhile b {\n    break\n    x = 1\n}");
+        check_edit("a = 1\nb = 2\nc = 3\nd = 4", 6..18, "");
+
+        // macro calls that look ahead past the re-parsed statements
+        let args = at("gold: random.range(9, 11)") + 20;
+        check_edit(SCRIPT, args..args + 2, "$m(");
+        check_edit(SCRIPT, args..args + 1, "");
+        check_edit("/* a\nb */\nx = 1\ny = 2", 17..17, "]");
+    }
+
+    #[test]
+    pub fn test_apply_edit_random() {
+        const FRAGMENTS: [&str; 16] = ["", "$m(", ",", ")", "(", "{", "}", "\n", " ", "/* a\nb */", "/*", "\"", "x = ", "[", "99999999999999999999", "$out(1, 2)"];
+
+        // xorshift, so every run makes the same edits
+        let mut state = 0x2545f4914f6cdd1d_u64;
+        let mut random = |below: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % below as u64) as usize
+        };
+
+        let mut source = SCRIPT.to_string();
+        for _ in 0..300 {
+            let start = random(source.len() + 1);
+            let end = (start + random(4)).min(source.len());
+            let text = FRAGMENTS[random(FRAGMENTS.len())];
+            check_edit(&source, start..end, text);
+            source.replace_range(start..end, text);
+        }
+    }
+
+    #[test]
+    pub fn test_parser() {
+        let mut compiler = ES3Compiler::new();
+
+        compiler.tokenize(SCRIPT);
+
+        assert!(compiler.parse().is_ok());
+
+        assert_eq!(compiler.body.nodes.len(), 13);
+        // the two macro definitions are expanded away
+        assert_eq!(compiler.compile()["body"].as_array().unwrap().len(), 11);
+        assert_eq!(compiler.header.owner.as_deref(), Some("emberhollow:rooms/boats/spawn_boat"));
+        assert_eq!(compiler.header.trigger, Some(Trigger::Enter));
+    }
+
+    #[test]
+    pub fn test_parser_recovery() {
+        let mut compiler = ES3Compiler::new();
 
+        compiler.tokenize("a = (1 +\nb = 2\nif (b) {\n    c = ]\n    d = 4\n}\ne = 5");
 
+        let errors = compiler.parse().unwrap_err();
 
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, codes::EXPECTED_TOKEN);
+        assert_eq!((errors[0].span.start_line, errors[0].span.start_column), (2, 2));
+        assert_eq!(errors[1].code, codes::EXPECTED_EXPRESSION);
+        assert_eq!((errors[1].span.start, errors[1].span.end), (32, 33));
+        assert_eq!(errors[1].message, "expected an expression, found `]`");
+
+        // `b` gets swallowed by the unclosed parenthesis, but the if statement and `e = 5` survive
+        let out = compiler.compile();
+        assert_eq!(out["body"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    pub fn test_diagnostics() {
+        let mut compiler = ES3Compiler::new();
+
+        compiler.tokenize("x = [1, 2\nwhile x {\n    break\n    x = 1\n}\n#player.name\ny = 3 @");
+
+        assert_eq!(compiler.diagnostics.len(), 1);
+        assert_eq!(compiler.diagnostics[0].code, codes::UNKNOWN_CHARACTER);
+
+        let errors = compiler.parse().unwrap_err();
+        assert_eq!(errors.iter().map(|d| d.code).collect::<Vec<_>>(), vec![codes::UNKNOWN_CHARACTER, codes::UNCLOSED_DELIMITER]);
+        assert_eq!(errors[1].related[0].1, "list opened here");
+        assert_eq!(errors[1].related[0].0.start, 4);
+
+        let warnings: Vec<&Diagnostic> = compiler.diagnostics.iter().filter(|d| d.severity == Severity::Warning).collect();
+        assert_eq!(warnings.iter().map(|d| d.code).collect::<Vec<_>>(), vec![codes::UNREACHABLE_CODE, codes::UNUSED_EXPRESSION]);
+        assert_eq!((warnings[0].span.start_line, warnings[0].span.end_column), (4, 9));
+        assert_eq!(warnings[1].span.start_line, 6);
+
+        // squiggles end up on the tokens
+        let error_token = compiler.tokens.iter().find(|t| matches!(t.token, Token::Error(_))).unwrap();
+        assert!(error_token.style.is_error());
+
+        // lines after a block comment spanning lines
+        compiler.tokenize("/* a\nb */\ny = ]");
+        let errors = compiler.parse().unwrap_err();
+        assert_eq!(errors[0].to_string(), "error[E0102] @ Ln 3 Col 4: expected an expression, found `]`");
+
+        compiler.tokenize("x = 99999999999999999999");
+        let errors = compiler.parse().unwrap_err();
+        assert_eq!(errors[0].code, codes::INTEGER_TOO_LARGE);
+        assert_eq!(errors[0].message, "integer literal `99999999999999999999` is too large");
+    }
+
+    #[test]
+    pub fn test_fixture_types() {
+        let mut compiler = ES3Compiler::new();
+        compiler.set_type_checking(true);
+        compiler.tokenize(SCRIPT);
+        compiler.parse().unwrap();
+        assert!(compiler.diagnostics.iter().all(|d| !d.code.starts_with("E05") && !d.code.starts_with("W05")), "{:?}", compiler.diagnostics);
+    }
+
+    #[test]
+    pub fn test_object_resolution() {
+        let mut compiler = ES3Compiler::new();
+
+        compiler.tokenize("a = new: <engine:currency> {}\nmove: #player -> <emberhollow:rooms/docks>\nb = <engine:curency>");
+        let errors = compiler.parse().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].code, errors[0].message.as_str()), (codes::UNKNOWN_OBJECT, "unknown object `<engine:curency>`"));
+
+        // other namespaces are only checked once the project's objects are known
+        compiler.set_objects(["emberhollow:rooms/boats"]);
+        let errors: Vec<&Diagnostic> = compiler.diagnostics.iter().filter(|d| d.code == codes::UNKNOWN_OBJECT).collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].span.start_line, 2);
+
+        compiler.set_objects(["emberhollow:rooms/docks"]);
+        let objects: Vec<Option<&String>> = compiler.tokens.iter()
+            .filter(|t| matches!(t.token, Token::Object(_)))
+            .map(|t| t.links().get("object"))
+            .collect();
+        assert_eq!(objects, vec![Some(&"engine:currency".to_string()), Some(&"emberhollow:rooms/docks".to_string()), None]);
+        assert!(compiler.tokens.last().unwrap().style.is_error());
+    }
+
+    /// Compares the compiled fixture against `tests/golden/es3_script.json`.
+    /// Run with `UPDATE_GOLDEN=1` to rewrite the file after an intentional change to the output
+    #[test]
+    pub fn test_compile_golden() {
+        const GOLDEN_PATH: &str = "tests/golden/es3_script.json";
+
+        let mut compiler = ES3Compiler::new();
+
+        compiler.tokenize(SCRIPT);
+        compiler.parse().unwrap();
+
+        let out = compiler.compile();
+
+        if std::env::var("UPDATE_GOLDEN").is_ok() {
+            std::fs::write(GOLDEN_PATH, serde_json::to_string_pretty(&out).unwrap() + "\n").unwrap();
+        }
+
+        let golden: Value = serde_json::from_str(&std::fs::read_to_string(GOLDEN_PATH).unwrap()).unwrap();
+
+        assert_eq!(out, golden, "compiled output:\n{}", serde_json::to_string_pretty(&out).unwrap());
+    }
+
+    #[test]
+    pub fn test_compile_constructs() {
+        let mut compiler = ES3Compiler::new();
+
+        compiler.tokenize(r#"
+while not done and count < 3 {
+    count = count + -1 * 2
+    if count % 2 == 0 { continue } else { break }
+}
+for item in [1, 2.5, "three", none] {
+    totals[item] = item .. "!"
+}
+match x {
+    case 1 { y = {a: 1} }
+    else { y = #player.inventory }
+}
+"#);
+        compiler.parse().unwrap();
+
+        let out = compiler.compile();
+        let body = out["body"].as_array().unwrap();
+
+        assert_eq!(body.len(), 3);
+        assert_eq!(body[0]["#while"]["op"], "and");
+        assert_eq!(body[0]["#while"]["left"]["op"], "not");
+        assert_eq!(body[0]["body"][0]["value"]["right"]["left"]["op"], "-");
+        assert_eq!(body[0]["body"][1]["true"][0]["#continue"], Value::Null);
+        assert_eq!(body[1]["#for"], "item");
+        assert_eq!(body[1]["in"]["#list"][1], 2.5);
+        assert_eq!(body[1]["body"][0]["#store"]["#index"]["#ref"], "item");
+        assert_eq!(body[1]["body"][0]["value"]["op"], "..");
+        assert_eq!(body[2]["cases"][0]["body"][0]["value"]["#map"]["a"], 1);
+        assert_eq!(body[2]["default"][0]["value"]["#attr"], "inventory");
+    }
+
+    #[test]
+    pub fn test_macro_expansion() {
+        let mut compiler = ES3Compiler::new();
+
+        compiler.tokenize(r#"
+$say($text) { $shown = $text
+output($shown) }
+$twice($text) { $say($text)
+$say($text .. "!") }
+$shown = 1
+$twice($shown)
+"#);
+        compiler.parse().unwrap();
+
+        let out = compiler.compile();
+        let body = out["body"].as_array().unwrap();
+        assert_eq!(body.len(), 5);
+        // arguments see the caller's `$shown`, the macro's own `$shown` is renamed per expansion
+        assert_eq!(body[1]["#store"]["#ref"], "$shown@2");
+        assert_eq!(body[1]["value"]["#ref"], "$shown");
+        assert_eq!(body[2]["args"][0]["#ref"], "$shown@2");
+        assert_eq!(body[3]["#store"]["#ref"], "$shown@3");
+        assert_eq!(body[3]["value"]["op"], "..");
+
+        compiler.tokenize("$a($x) { $b($x) }
+$b($x) { $a($x) }
+$a(1, 2)
+$c()");
+        let errors = compiler.parse().unwrap_err();
+        let found: Vec<(&str, &str)> = errors.iter().map(|e| (e.code, e.message.as_str())).collect();
+        assert_eq!(found, vec![
+            (codes::RECURSIVE_MACRO, "macro `$b` expands into itself"),
+            (codes::RECURSIVE_MACRO, "macro `$a` expands into itself"),
+            (codes::MACRO_ARGUMENTS, "macro `$a` takes 1 argument but 2 were given"),
+            (codes::UNDEFINED_MACRO, "cannot find macro `$c`")
+        ]);
+
+        // the arity error points at the call, and at the definition
+        assert_eq!(&compiler.source[errors[2].span.start..errors[2].span.end], "$a(1, 2)");
+        let (definition, label) = &errors[2].related[0];
+        assert_eq!(&compiler.source[definition.start..definition.end], "$a($x)");
+        assert_eq!(label, "`$a` defined here");
+    }
+
+}
@@ -0,0 +1,1276 @@
+//! Compact bytecode for compiled ES3 scripts, an alternative to the JSON `ES3Compiler::compile` produces.
+//!
+//! A `Chunk` is a constant pool, a flat list of instructions for a stack machine and a table of the
+//! statements they came from. `if`, `while`, `for` and `match` become jumps, names and literals are
+//! stored once in the pool and referred to by index. `ES3VirtualMachine` runs a chunk against the same
+//! `ScriptWorld` as `ES3Interpreter`, with the same results, and `disassemble` prints one for debugging.
+//!
+//! Serialized, a chunk is `ES3B`, a version byte, then the header, constants, instructions and
+//! statements, with every number written as a LEB128 varint
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::rc::Rc;
+use serde_json::Value;
+use crate::es3_header::ScriptHeader;
+use crate::es3_interpreter::{
+    append, call_builtin, call_method, context, get_attribute, get_index, lookup, loop_items, move_value, place_mut, binary_op,
+    unary_op, ES3Interpreter, Place, PlaceRoot, ScriptRunner, ScriptState, ScriptValue, ScriptWorld
+};
+
+/// first bytes of a serialized chunk
+pub const MAGIC: &[u8; 4] = b"ES3B";
+const VERSION: u8 = 1;
+
+/// how many instructions a script may run per `resume` before yielding, so a busy loop can't freeze the game
+const INSTRUCTION_LIMIT: usize = 100_000;
+
+/// One instruction. Operands named `u32` are indices into the constant pool unless they are a jump target,
+/// which is the index of an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// pushes a constant
+    Constant(u32),
+    /// pushes the variable named by the constant, or the builtin of that name
+    Load(u32),
+    /// pops a value into the variable named by the constant
+    Store(u32),
+    /// pushes the entity of a `#context`
+    Context(u32),
+    Pop,
+    /// pops that many values into a list
+    List(u32),
+    /// pops a value for each name in the constant (a list of names) into a map
+    Map(u32),
+    /// pops a map of fields into an instance of the object type in the constant
+    New(u32),
+    /// replaces the top value with its attribute
+    Attribute(u32),
+    /// pops an index and the value it indexes, pushing the value at that index
+    Index,
+    /// replaces the top entity with its tag
+    GetTag(u32),
+    /// pushes the tag of the script's owner
+    GetOwnTag(u32),
+    /// pops a value and an entity, setting the entity's tag and pushing `none`
+    SetTag(u32),
+    /// pops a value into the tag of the script's owner and pushes `none`
+    SetOwnTag(u32),
+    /// replaces the top value by applying the operator in the constant to it
+    Unary(u32),
+    /// pops the right and left side, pushing the result of the operator in the constant
+    Binary(u32),
+    /// pops the left side of an `and`, jumping with `false` pushed if it is falsy
+    And(u32),
+    /// pops the left side of an `or`, jumping with `true` pushed if it is truthy
+    Or(u32),
+    /// replaces the top value with whether it is truthy
+    Truthy,
+    Jump(u32),
+    /// pops a value, jumping if it is falsy
+    JumpIfFalse(u32),
+    /// pops a `match` case, jumping if it doesn't equal the matched value under it
+    JumpIfNotEqual(u32),
+    /// pops a value and starts a `for` loop over its items
+    Iterate,
+    /// stores the next item of the innermost loop in the variable named by `variable`, or ends the loop and jumps to `exit`
+    Next { variable: u32, exit: u32 },
+    /// ends the innermost loop early, for `break`
+    EndLoop,
+    /// pops the callee, a value for each name in `kwargs` (a list of names) and `args` positional arguments, then calls it
+    Call { args: u32, kwargs: u32 },
+    /// like `Call` for the method `name` of the popped receiver. `append` also pops a place, where the list is stored
+    CallMethod { name: u32, args: u32, kwargs: u32 },
+    /// pushes the variable named by the constant onto the place stack
+    PlaceVariable(u32),
+    /// pushes something that can't be assigned to onto the place stack
+    PlaceInvalid,
+    /// pops a value and the key into it, then replaces the top place with that key of it.
+    /// When the value is an entity the place becomes its attribute instead
+    PlaceKey,
+    /// pops a place and a value, writing the value to the place
+    StorePlace,
+    /// pops a destination and an entity, moving the entity there
+    Move,
+    /// stops the script with the error in the constant
+    Fail(u32)
+}
+
+impl Op {
+    /// mnemonic used by `disassemble`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Constant(_) => "CONSTANT",
+            Op::Load(_) => "LOAD",
+            Op::Store(_) => "STORE",
+            Op::Context(_) => "CONTEXT",
+            Op::Pop => "POP",
+            Op::List(_) => "LIST",
+            Op::Map(_) => "MAP",
+            Op::New(_) => "NEW",
+            Op::Attribute(_) => "ATTRIBUTE",
+            Op::Index => "INDEX",
+            Op::GetTag(_) => "GET_TAG",
+            Op::GetOwnTag(_) => "GET_OWN_TAG",
+            Op::SetTag(_) => "SET_TAG",
+            Op::SetOwnTag(_) => "SET_OWN_TAG",
+            Op::Unary(_) => "UNARY",
+            Op::Binary(_) => "BINARY",
+            Op::And(_) => "AND",
+            Op::Or(_) => "OR",
+            Op::Truthy => "TRUTHY",
+            Op::Jump(_) => "JUMP",
+            Op::JumpIfFalse(_) => "JUMP_IF_FALSE",
+            Op::JumpIfNotEqual(_) => "JUMP_IF_NOT_EQUAL",
+            Op::Iterate => "ITERATE",
+            Op::Next { .. } => "NEXT",
+            Op::EndLoop => "END_LOOP",
+            Op::Call { .. } => "CALL",
+            Op::CallMethod { .. } => "CALL_METHOD",
+            Op::PlaceVariable(_) => "PLACE_VARIABLE",
+            Op::PlaceInvalid => "PLACE_INVALID",
+            Op::PlaceKey => "PLACE_KEY",
+            Op::StorePlace => "STORE_PLACE",
+            Op::Move => "MOVE",
+            Op::Fail(_) => "FAIL"
+        }
+    }
+
+    fn opcode(&self) -> u8 {
+        match self {
+            Op::Constant(_) => 0,
+            Op::Load(_) => 1,
+            Op::Store(_) => 2,
+            Op::Context(_) => 3,
+            Op::Pop => 4,
+            Op::List(_) => 5,
+            Op::Map(_) => 6,
+            Op::New(_) => 7,
+            Op::Attribute(_) => 8,
+            Op::Index => 9,
+            Op::GetTag(_) => 10,
+            Op::GetOwnTag(_) => 11,
+            Op::SetTag(_) => 12,
+            Op::SetOwnTag(_) => 13,
+            Op::Unary(_) => 14,
+            Op::Binary(_) => 15,
+            Op::And(_) => 16,
+            Op::Or(_) => 17,
+            Op::Truthy => 18,
+            Op::Jump(_) => 19,
+            Op::JumpIfFalse(_) => 20,
+            Op::JumpIfNotEqual(_) => 21,
+            Op::Iterate => 22,
+            Op::Next { .. } => 23,
+            Op::EndLoop => 24,
+            Op::Call { .. } => 25,
+            Op::CallMethod { .. } => 26,
+            Op::PlaceVariable(_) => 27,
+            Op::PlaceInvalid => 28,
+            Op::PlaceKey => 29,
+            Op::StorePlace => 30,
+            Op::Move => 31,
+            Op::Fail(_) => 32
+        }
+    }
+
+    /// the constants the instruction refers to
+    fn constants(&self) -> Vec<u32> {
+        match *self {
+            Op::Constant(i) | Op::Load(i) | Op::Store(i) | Op::Context(i) | Op::Map(i) | Op::New(i) | Op::Attribute(i)
+            | Op::GetTag(i) | Op::GetOwnTag(i) | Op::SetTag(i) | Op::SetOwnTag(i) | Op::Unary(i) | Op::Binary(i)
+            | Op::PlaceVariable(i) | Op::Fail(i) => vec![i],
+            Op::Next { variable, .. } => vec![variable],
+            Op::Call { kwargs, .. } => vec![kwargs],
+            Op::CallMethod { name, kwargs, .. } => vec![name, kwargs],
+            _ => Vec::new()
+        }
+    }
+
+    /// the instruction the op may jump to
+    fn target(&self) -> Option<u32> {
+        match *self {
+            Op::And(target) | Op::Or(target) | Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfNotEqual(target) => Some(target),
+            Op::Next { exit, .. } => Some(exit),
+            _ => None
+        }
+    }
+
+    fn set_target(&mut self, to: u32) {
+        match self {
+            Op::And(target) | Op::Or(target) | Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfNotEqual(target) => *target = to,
+            Op::Next { exit, .. } => *exit = to,
+            _ => {}
+        }
+    }
+
+    /// every operand in order, as written to bytes and shown by `disassemble`
+    fn operands(&self) -> Vec<u32> {
+        match *self {
+            Op::List(n) => vec![n],
+            Op::Next { variable, exit } => vec![variable, exit],
+            Op::Call { args, kwargs } => vec![args, kwargs],
+            Op::CallMethod { name, args, kwargs } => vec![name, args, kwargs],
+            op => op.constants().into_iter().chain(op.target()).collect()
+        }
+    }
+
+    fn from_parts(opcode: u8, operands: &mut impl FnMut() -> Result<u32, String>) -> Result<Op, String> {
+        Ok(match opcode {
+            0 => Op::Constant(operands()?),
+            1 => Op::Load(operands()?),
+            2 => Op::Store(operands()?),
+            3 => Op::Context(operands()?),
+            4 => Op::Pop,
+            5 => Op::List(operands()?),
+            6 => Op::Map(operands()?),
+            7 => Op::New(operands()?),
+            8 => Op::Attribute(operands()?),
+            9 => Op::Index,
+            10 => Op::GetTag(operands()?),
+            11 => Op::GetOwnTag(operands()?),
+            12 => Op::SetTag(operands()?),
+            13 => Op::SetOwnTag(operands()?),
+            14 => Op::Unary(operands()?),
+            15 => Op::Binary(operands()?),
+            16 => Op::And(operands()?),
+            17 => Op::Or(operands()?),
+            18 => Op::Truthy,
+            19 => Op::Jump(operands()?),
+            20 => Op::JumpIfFalse(operands()?),
+            21 => Op::JumpIfNotEqual(operands()?),
+            22 => Op::Iterate,
+            23 => Op::Next { variable: operands()?, exit: operands()? },
+            24 => Op::EndLoop,
+            25 => Op::Call { args: operands()?, kwargs: operands()? },
+            26 => Op::CallMethod { name: operands()?, args: operands()?, kwargs: operands()? },
+            27 => Op::PlaceVariable(operands()?),
+            28 => Op::PlaceInvalid,
+            29 => Op::PlaceKey,
+            30 => Op::StorePlace,
+            31 => Op::Move,
+            32 => Op::Fail(operands()?),
+            other => return Err(format!("unknown opcode {}", other))
+        })
+    }
+}
+
+/// The instructions compiled from one statement, `start..end`.
+/// Statements nest, an `if` covers the statements in its branches
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub start: u32,
+    pub end: u32,
+    /// the line of the source the statement starts on, 0 when the compiled JSON didn't say
+    pub line: u32
+}
+
+/// A compiled script
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    /// the script's context headers, as `ScriptHeader::to_json` writes them
+    pub header: Value,
+    pub constants: Vec<ScriptValue>,
+    pub code: Vec<Op>,
+    /// every statement, ordered by where it starts
+    pub statements: Vec<Statement>
+}
+
+impl Chunk {
+    /// the line of the innermost statement instruction `pc` belongs to
+    pub fn line_at(&self, pc: usize) -> Option<u32> {
+        let pc = pc as u32;
+        self.statements.iter()
+            .filter(|s| s.start <= pc && pc < s.end && s.line > 0)
+            .max_by_key(|s| s.start)
+            .map(|s| s.line)
+    }
+
+    /// whether a statement starts or ends right before instruction `pc`, the points where a script can pause
+    pub fn is_statement_boundary(&self, pc: usize) -> bool {
+        let pc = pc as u32;
+        pc as usize >= self.code.len() || self.statements.iter().any(|s| s.start == pc || s.end == pc)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        write_string(&mut out, &self.header.to_string());
+
+        write_varint(&mut out, self.constants.len() as u64);
+        for constant in &self.constants {
+            write_constant(&mut out, constant);
+        }
+
+        write_varint(&mut out, self.code.len() as u64);
+        for op in &self.code {
+            out.push(op.opcode());
+            for operand in op.operands() {
+                write_varint(&mut out, operand as u64);
+            }
+        }
+
+        write_varint(&mut out, self.statements.len() as u64);
+        let mut last_start = 0;
+        for statement in &self.statements {
+            write_varint(&mut out, (statement.start - last_start) as u64);
+            write_varint(&mut out, (statement.end - statement.start) as u64);
+            write_varint(&mut out, statement.line as u64);
+            last_start = statement.start;
+        }
+        out
+    }
+
+    /// reads a chunk written by `to_bytes`, checking that every instruction only refers to constants and instructions that exist
+    pub fn from_bytes(bytes: &[u8]) -> Result<Chunk, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("not an ES3 bytecode file".to_string())
+        }
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(format!("bytecode version {} is not supported, expected {}", version, VERSION))
+        }
+        let header = serde_json::from_str(&reader.string()?).map_err(|e| format!("bad header: {}", e))?;
+
+        let count = reader.count()?;
+        let mut constants = Vec::with_capacity(count);
+        for _ in 0..count {
+            constants.push(reader.constant()?);
+        }
+
+        let count = reader.count()?;
+        let mut code = Vec::with_capacity(count);
+        for _ in 0..count {
+            let opcode = reader.byte()?;
+            code.push(Op::from_parts(opcode, &mut || reader.u32())?);
+        }
+
+        let count = reader.count()?;
+        let mut statements = Vec::with_capacity(count);
+        let mut start = 0u32;
+        for _ in 0..count {
+            start = start.checked_add(reader.u32()?).ok_or("statement table overflows")?;
+            let end = start.checked_add(reader.u32()?).ok_or("statement table overflows")?;
+            statements.push(Statement { start, end, line: reader.u32()? });
+        }
+        if reader.pos != bytes.len() {
+            return Err("unexpected bytes after the end of the chunk".to_string())
+        }
+
+        for (pc, op) in code.iter().enumerate() {
+            if op.constants().iter().any(|&i| i as usize >= constants.len()) {
+                return Err(format!("instruction {} refers to a constant that doesn't exist", pc))
+            }
+            if op.target().is_some_and(|t| t as usize > code.len()) {
+                return Err(format!("instruction {} jumps outside the code", pc))
+            }
+        }
+        if statements.iter().any(|s| s.end as usize > code.len()) {
+            return Err("a statement ends outside the code".to_string())
+        }
+        Ok(Chunk { header, constants, code, statements })
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_string(out: &mut Vec<u8>, text: &str) {
+    write_varint(out, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+/// constants are a tag byte followed by their value, integers are zigzag encoded so small negatives stay short
+fn write_constant(out: &mut Vec<u8>, constant: &ScriptValue) {
+    match constant {
+        ScriptValue::None => out.push(0),
+        ScriptValue::Boolean(b) => out.push(1 + *b as u8),
+        ScriptValue::Integer(i) => {
+            out.push(3);
+            write_varint(out, ((i << 1) ^ (i >> 63)) as u64);
+        }
+        ScriptValue::Float(f) => {
+            out.push(4);
+            out.extend_from_slice(&f.to_le_bytes());
+        }
+        ScriptValue::String(s) => {
+            out.push(5);
+            write_string(out, s);
+        }
+        ScriptValue::Object(id) => {
+            out.push(6);
+            write_string(out, id);
+        }
+        ScriptValue::List(items) => {
+            out.push(7);
+            write_varint(out, items.len() as u64);
+            for item in items {
+                write_constant(out, item);
+            }
+        }
+        // only literals are put in the pool, instances, entities and builtins are made while running
+        _ => out.push(0)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self.bytes.get(self.pos..self.pos.saturating_add(length)).ok_or("unexpected end of the bytecode")?;
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value)
+            }
+        }
+        Err("varint is too long".to_string())
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        u32::try_from(self.varint()?).map_err(|_| "operand is too large".to_string())
+    }
+
+    /// a length, which can't be more than the bytes left since every item takes at least one
+    fn count(&mut self) -> Result<usize, String> {
+        let count = self.varint()? as usize;
+        if count > self.bytes.len() - self.pos {
+            return Err("unexpected end of the bytecode".to_string())
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.count()?;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| "string is not valid UTF-8".to_string())
+    }
+
+    fn constant(&mut self) -> Result<ScriptValue, String> {
+        Ok(match self.byte()? {
+            0 => ScriptValue::None,
+            1 => ScriptValue::Boolean(false),
+            2 => ScriptValue::Boolean(true),
+            3 => {
+                let zigzag = self.varint()?;
+                ScriptValue::Integer((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64))
+            }
+            4 => ScriptValue::Float(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            5 => ScriptValue::String(self.string()?),
+            6 => ScriptValue::Object(self.string()?),
+            7 => {
+                let count = self.count()?;
+                let mut items = Vec::with_capacity(count);
+                for _ in 0..count {
+                    items.push(self.constant()?);
+                }
+                ScriptValue::List(items)
+            }
+            other => return Err(format!("unknown constant tag {}", other))
+        })
+    }
+}
+
+/// jumps waiting for the end of the loop they leave, and where `continue` goes
+struct Loop {
+    continue_to: u32,
+    breaks: Vec<usize>,
+    /// `for` loops have an iterator to drop when left with `break`
+    is_for: bool
+}
+
+/// Turns the JSON of a compiled program into a chunk, see `compile`
+struct BytecodeCompiler {
+    chunk: Chunk,
+    /// index of each constant, keyed by its debug form so `1` and `"1"` stay apart
+    constant_indices: HashMap<String, u32>,
+    loops: Vec<Loop>
+}
+
+/// Compiles a program produced by `ES3Compiler::compile` (or its tagged version, `compile_debug`) to bytecode.
+/// Statements tagged with `"#line"` keep their line in the chunk
+pub fn compile(program: &Value) -> Result<Chunk, String> {
+    if !program["body"].is_array() {
+        return Err("a compiled program must have a list of statements as its body".to_string())
+    }
+    let mut compiler = BytecodeCompiler {
+        chunk: Chunk { header: program["header"].clone(), constants: Vec::new(), code: Vec::new(), statements: Vec::new() },
+        constant_indices: HashMap::new(),
+        loops: Vec::new()
+    };
+    compiler.block(&program["body"]);
+    compiler.chunk.statements.sort_by_key(|s| s.start);
+    Ok(compiler.chunk)
+}
+
+impl BytecodeCompiler {
+    fn constant(&mut self, value: ScriptValue) -> u32 {
+        let key = format!("{:?}", value);
+        if let Some(&i) = self.constant_indices.get(&key) {
+            return i
+        }
+        let i = self.chunk.constants.len() as u32;
+        self.chunk.constants.push(value);
+        self.constant_indices.insert(key, i);
+        i
+    }
+
+    fn text(&mut self, text: &str) -> u32 {
+        self.constant(ScriptValue::String(text.to_string()))
+    }
+
+    fn names<'a>(&mut self, names: impl Iterator<Item = &'a String>) -> u32 {
+        self.constant(ScriptValue::List(names.map(|n| ScriptValue::String(n.clone())).collect()))
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        self.chunk.code.len() as u32
+    }
+
+    /// points the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let here = self.here();
+        self.chunk.code[at].set_target(here);
+    }
+
+    fn fail(&mut self, message: &str) {
+        let message = self.text(message);
+        self.emit(Op::Fail(message));
+    }
+
+    /// a block is a list of statements, nothing, or an `elif` compiled to a single nested `if`
+    fn block(&mut self, body: &Value) {
+        match body {
+            Value::Array(statements) => statements.iter().for_each(|s| self.statement(s)),
+            Value::Null => {}
+            other => self.statement(other)
+        }
+    }
+
+    fn statement(&mut self, statement: &Value) {
+        let index = self.chunk.statements.len();
+        let line = statement.get("#line").and_then(|l| l.as_u64()).unwrap_or(0) as u32;
+        self.chunk.statements.push(Statement { start: self.here(), end: 0, line });
+
+        match statement.as_object() {
+            // a macro call, the bytecode only needs its body
+            Some(map) if map.contains_key("#expand") => self.block(&map["body"]),
+            Some(map) if map.contains_key("#check") => {
+                self.expression(&map["#check"]);
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.block(&statement["true"]);
+                if statement["false"].is_null() {
+                    self.patch(to_else);
+                } else {
+                    let to_end = self.emit(Op::Jump(0));
+                    self.patch(to_else);
+                    self.block(&statement["false"]);
+                    self.patch(to_end);
+                }
+            }
+            Some(map) if map.contains_key("#while") => {
+                let start = self.here();
+                self.expression(&map["#while"]);
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.loop_body(&statement["body"], Loop { continue_to: start, breaks: vec![exit], is_for: false });
+            }
+            Some(map) if map.contains_key("#for") => {
+                self.expression(&statement["in"]);
+                self.emit(Op::Iterate);
+                let start = self.here();
+                let variable = self.text(map["#for"].as_str().unwrap_or_default());
+                let exit = self.emit(Op::Next { variable, exit: 0 });
+                self.loop_body(&statement["body"], Loop { continue_to: start, breaks: vec![exit], is_for: true });
+            }
+            Some(map) if map.contains_key("#match") => {
+                self.expression(&map["#match"]);
+                let mut to_end = Vec::new();
+                for case in statement["cases"].as_array().into_iter().flatten() {
+                    self.expression(&case["case"]);
+                    let to_next = self.emit(Op::JumpIfNotEqual(0));
+                    self.emit(Op::Pop);
+                    self.block(&case["body"]);
+                    to_end.push(self.emit(Op::Jump(0)));
+                    self.patch(to_next);
+                }
+                self.emit(Op::Pop);
+                self.block(&statement["default"]);
+                to_end.into_iter().for_each(|at| self.patch(at));
+            }
+            Some(map) if map.contains_key("#break") || map.contains_key("#continue") => {
+                let is_break = map.contains_key("#break");
+                match self.loops.last() {
+                    Some(current) if is_break => {
+                        if current.is_for {
+                            self.emit(Op::EndLoop);
+                        }
+                        let jump = self.emit(Op::Jump(0));
+                        self.loops.last_mut().unwrap().breaks.push(jump);
+                    }
+                    Some(current) => {
+                        let start = current.continue_to;
+                        self.emit(Op::Jump(start));
+                    }
+                    None => self.fail(&format!("`{}` outside of a loop", if is_break { "break" } else { "continue" }))
+                }
+            }
+            Some(map) if map.contains_key("#store") => {
+                let target = &map["#store"];
+                self.expression(&statement["value"]);
+                if let Some(name) = target.get("#ref").and_then(|n| n.as_str()) {
+                    let name = self.text(name);
+                    self.emit(Op::Store(name));
+                } else if target.get("#tag").is_some() {
+                    self.fail("tags are set with `tag$[name = value]`");
+                } else {
+                    self.place(target);
+                    self.emit(Op::StorePlace);
+                }
+            }
+            Some(map) if map.contains_key("#move") => {
+                self.expression(&map["#move"]);
+                self.expression(&statement["to"]);
+                self.emit(Op::Move);
+            }
+            _ => {
+                self.expression(statement);
+                self.emit(Op::Pop);
+            }
+        }
+
+        self.chunk.statements[index].end = self.here();
+    }
+
+    /// the body of a loop that starts at `current.continue_to`, jumping back there at the end of each pass
+    fn loop_body(&mut self, body: &Value, current: Loop) {
+        let start = current.continue_to;
+        self.loops.push(current);
+        self.block(body);
+        self.emit(Op::Jump(start));
+        for at in self.loops.pop().unwrap().breaks {
+            self.patch(at);
+        }
+    }
+
+    /// pushes where an assignment or `append` writes to onto the place stack, see `ES3Interpreter::place`
+    fn place(&mut self, target: &Value) {
+        if let Some(name) = target.get("#ref").and_then(|n| n.as_str()) {
+            let name = self.text(name);
+            self.emit(Op::PlaceVariable(name));
+            return
+        }
+
+        if let Some(name) = target.get("#attr").and_then(|n| n.as_str()) {
+            let name = self.text(name);
+            self.emit(Op::Constant(name));
+        } else if let Some(index) = target.get("#index") {
+            self.expression(index);
+        } else {
+            self.emit(Op::PlaceInvalid);
+            return
+        }
+        self.expression(&target["of"]);
+        self.place(&target["of"]);
+        self.emit(Op::PlaceKey);
+    }
+
+    fn entries(&mut self, entries: &Value) -> u32 {
+        let entries = entries.as_object().cloned().unwrap_or_default();
+        for value in entries.values() {
+            self.expression(value);
+        }
+        self.names(entries.keys())
+    }
+
+    fn expression(&mut self, expr: &Value) {
+        let Some(map) = expr.as_object() else {
+            match ScriptValue::from_literal(expr) {
+                Some(value) => {
+                    let value = self.constant(value);
+                    self.emit(Op::Constant(value));
+                }
+                None => self.fail("blocks cannot be used as values")
+            }
+            return
+        };
+        let text = |key: &str| map[key].as_str().unwrap_or_default().to_string();
+
+        if map.contains_key("#ref") {
+            let name = self.text(&text("#ref"));
+            self.emit(Op::Load(name));
+        } else if map.contains_key("#context") {
+            let name = self.text(&text("#context"));
+            self.emit(Op::Context(name));
+        } else if map.contains_key("#object") {
+            let id = self.constant(ScriptValue::Object(text("#object")));
+            self.emit(Op::Constant(id));
+        } else if let Some(items) = map.get("#list") {
+            let items = items.as_array().cloned().unwrap_or_default();
+            items.iter().for_each(|item| self.expression(item));
+            self.emit(Op::List(items.len() as u32));
+        } else if let Some(entries) = map.get("#map") {
+            let names = self.entries(entries);
+            self.emit(Op::Map(names));
+        } else if map.contains_key("#new") {
+            let names = self.entries(&expr["data"]["#map"]);
+            self.emit(Op::Map(names));
+            let object = self.text(&text("#new"));
+            self.emit(Op::New(object));
+        } else if map.contains_key("#attr") {
+            self.expression(&expr["of"]);
+            let name = self.text(&text("#attr"));
+            self.emit(Op::Attribute(name));
+        } else if let Some(index) = map.get("#index") {
+            self.expression(&expr["of"]);
+            self.expression(index);
+            self.emit(Op::Index);
+        } else if map.contains_key("#tag") {
+            let name = self.text(&text("#tag"));
+            let owned = expr["of"].is_null();
+            if !owned {
+                self.expression(&expr["of"]);
+            }
+            let op = match map.get("value") {
+                Some(value) => {
+                    self.expression(value);
+                    if owned { Op::SetOwnTag(name) } else { Op::SetTag(name) }
+                }
+                None if owned => Op::GetOwnTag(name),
+                None => Op::GetTag(name)
+            };
+            self.emit(op);
+        } else if map.contains_key("#call") {
+            self.call(expr);
+        } else if let (Some(left), Some(op)) = (map.get("left"), map.get("op").and_then(|op| op.as_str())) {
+            self.expression(left);
+            if op == "and" || op == "or" {
+                let jump = self.emit(if op == "and" { Op::And(0) } else { Op::Or(0) });
+                self.expression(&expr["right"]);
+                self.emit(Op::Truthy);
+                self.patch(jump);
+            } else {
+                self.expression(&expr["right"]);
+                let op = self.text(op);
+                self.emit(Op::Binary(op));
+            }
+        } else if let Some(op) = map.get("op").and_then(|op| op.as_str()) {
+            self.expression(&expr["value"]);
+            let op = self.text(op);
+            self.emit(Op::Unary(op));
+        } else {
+            self.fail(&format!("unknown expression `{}`", expr));
+        }
+    }
+
+    fn call(&mut self, expr: &Value) {
+        let callee = &expr["#call"];
+        let args = expr["args"].as_array().cloned().unwrap_or_default();
+        args.iter().for_each(|arg| self.expression(arg));
+        let kwargs = self.entries(&expr["kwargs"]);
+        let args = args.len() as u32;
+
+        if let Some(method) = callee.get("#attr").and_then(|m| m.as_str()) {
+            if method == "append" {
+                self.place(&callee["of"]);
+            }
+            self.expression(&callee["of"]);
+            let name = self.text(method);
+            self.emit(Op::CallMethod { name, args, kwargs });
+        } else {
+            self.expression(callee);
+            self.emit(Op::Call { args, kwargs });
+        }
+    }
+}
+
+/// Runs a chunk, the bytecode counterpart of `ES3Interpreter`. `wait` suspends the script at the next statement boundary,
+/// the same point the interpreter would suspend it at
+pub struct ES3VirtualMachine {
+    chunk: Rc<Chunk>,
+    /// index of the next instruction
+    pc: usize,
+    stack: Vec<ScriptValue>,
+    places: Vec<Result<Place, String>>,
+    /// items left in each `for` loop being run, innermost last
+    loops: Vec<std::vec::IntoIter<ScriptValue>>,
+    variables: HashMap<String, ScriptValue>,
+    /// uid of the entity `#player` refers to
+    pub player: String,
+    /// uid of the entity running the script, which bare `tag$[...]` refers to
+    pub owner: Option<String>,
+    pub state: ScriptState,
+    pending_wait: Option<f64>
+}
+
+impl ES3VirtualMachine {
+    pub fn new(chunk: Rc<Chunk>, player: impl ToString, owner: Option<String>) -> Self {
+        Self {
+            chunk,
+            pc: 0,
+            stack: Vec::new(),
+            places: Vec::new(),
+            loops: Vec::new(),
+            variables: HashMap::new(),
+            player: player.to_string(),
+            owner,
+            state: ScriptState::Running,
+            pending_wait: None
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == ScriptState::Finished
+    }
+
+    pub fn variable(&self, name: &str) -> Option<&ScriptValue> {
+        self.variables.get(name)
+    }
+
+    /// the line of the statement that runs next
+    pub fn line(&self) -> Option<u32> {
+        self.chunk.line_at(self.pc)
+    }
+
+    /// Runs the script until it waits, finishes or hits the instruction limit, see `ES3Interpreter::resume`
+    pub fn resume(&mut self, world: &mut ScriptWorld, delta: f64) -> Result<&ScriptState, String> {
+        if let ScriptState::Waiting(left) = self.state {
+            if left - delta > 0.0 {
+                self.state = ScriptState::Waiting(left - delta);
+                return Ok(&self.state)
+            }
+            self.state = ScriptState::Running;
+        }
+
+        for _ in 0..INSTRUCTION_LIMIT {
+            if self.pc >= self.chunk.code.len() {
+                self.state = ScriptState::Finished;
+                break
+            }
+            if let Err(e) = self.step(world) {
+                self.state = ScriptState::Finished;
+                return Err(e)
+            }
+            if self.pending_wait.is_some() && self.chunk.is_statement_boundary(self.pc) {
+                self.state = ScriptState::Waiting(self.pending_wait.take().unwrap());
+                break
+            }
+        }
+
+        Ok(&self.state)
+    }
+
+    fn pop(&mut self) -> Result<ScriptValue, String> {
+        self.stack.pop().ok_or_else(|| "the bytecode popped an empty stack".to_string())
+    }
+
+    /// pops `count` values, in the order they were pushed
+    fn pop_many(&mut self, count: u32) -> Result<Vec<ScriptValue>, String> {
+        let start = self.stack.len().checked_sub(count as usize).ok_or("the bytecode popped an empty stack")?;
+        Ok(self.stack.split_off(start))
+    }
+
+    /// pops a value for each name in the list constant `names`
+    fn pop_entries(&mut self, names: u32) -> Result<BTreeMap<String, ScriptValue>, String> {
+        let names = match &self.chunk.constants[names as usize] {
+            ScriptValue::List(names) => names.iter().map(|n| n.to_string()).collect(),
+            _ => Vec::new()
+        };
+        let values = self.pop_many(names.len() as u32)?;
+        Ok(names.into_iter().zip(values).collect())
+    }
+
+    fn text(&self, constant: u32) -> String {
+        self.chunk.constants[constant as usize].to_string()
+    }
+
+    /// the entity whose tag to read or set, a tag's name may be quoted
+    fn tag_owner(&mut self, name: &str, owned: bool) -> Result<String, String> {
+        if owned {
+            return self.owner.clone().ok_or_else(|| format!("tag `{}` has no owner to read from", name))
+        }
+        match self.pop()? {
+            ScriptValue::Entity(uid) => Ok(uid),
+            other => Err(format!("a value of type {} has no tags", other.type_name()))
+        }
+    }
+
+    fn step(&mut self, world: &mut ScriptWorld) -> Result<(), String> {
+        let op = self.chunk.code[self.pc];
+        self.pc += 1;
+
+        match op {
+            Op::Constant(i) => self.stack.push(self.chunk.constants[i as usize].clone()),
+            Op::Load(name) => {
+                let value = lookup(&self.variables, &self.text(name))?;
+                self.stack.push(value);
+            }
+            Op::Store(name) => {
+                let value = self.pop()?;
+                self.variables.insert(self.text(name), value);
+            }
+            Op::Context(name) => {
+                let value = context(&self.text(name), &self.player, self.owner.as_deref(), world)?;
+                self.stack.push(value);
+            }
+            Op::Pop => {
+                self.pop()?;
+            }
+            Op::List(count) => {
+                let items = self.pop_many(count)?;
+                self.stack.push(ScriptValue::List(items));
+            }
+            Op::Map(names) => {
+                let entries = self.pop_entries(names)?;
+                self.stack.push(ScriptValue::Map(entries));
+            }
+            Op::New(object) => {
+                let fields = match self.pop()? {
+                    ScriptValue::Map(fields) => fields,
+                    _ => BTreeMap::new()
+                };
+                self.stack.push(ScriptValue::Instance(self.text(object), fields));
+            }
+            Op::Attribute(name) => {
+                let object = self.pop()?;
+                self.stack.push(get_attribute(&object, &self.text(name), world)?);
+            }
+            Op::Index => {
+                let index = self.pop()?;
+                let object = self.pop()?;
+                self.stack.push(get_index(&object, &index, world)?);
+            }
+            Op::GetTag(name) | Op::GetOwnTag(name) => {
+                let name = self.text(name);
+                let uid = self.tag_owner(&name, matches!(op, Op::GetOwnTag(_)))?;
+                let value = world.entity(&uid)?.tags.get(&name).cloned();
+                self.stack.push(value.unwrap_or(ScriptValue::None));
+            }
+            Op::SetTag(name) | Op::SetOwnTag(name) => {
+                let name = self.text(name);
+                let value = self.pop()?;
+                let uid = self.tag_owner(&name, matches!(op, Op::SetOwnTag(_)))?;
+                world.entity_mut(&uid)?.tags.insert(name, value);
+                self.stack.push(ScriptValue::None);
+            }
+            Op::Unary(op) => {
+                let value = self.pop()?;
+                self.stack.push(unary_op(&self.text(op), value)?);
+            }
+            Op::Binary(op) => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+                self.stack.push(binary_op(&self.text(op), left, right)?);
+            }
+            Op::And(target) | Op::Or(target) => {
+                let is_and = matches!(op, Op::And(_));
+                if self.pop()?.is_truthy() != is_and {
+                    self.stack.push(ScriptValue::Boolean(!is_and));
+                    self.pc = target as usize;
+                }
+            }
+            Op::Truthy => {
+                let value = self.pop()?;
+                self.stack.push(ScriptValue::Boolean(value.is_truthy()));
+            }
+            Op::Jump(target) => self.pc = target as usize,
+            Op::JumpIfFalse(target) => {
+                if !self.pop()?.is_truthy() {
+                    self.pc = target as usize;
+                }
+            }
+            Op::JumpIfNotEqual(target) => {
+                let case = self.pop()?;
+                if !case.equals(self.stack.last().ok_or("the bytecode popped an empty stack")?) {
+                    self.pc = target as usize;
+                }
+            }
+            Op::Iterate => {
+                let items = loop_items(self.pop()?)?;
+                self.loops.push(items.into_iter());
+            }
+            Op::Next { variable, exit } => {
+                match self.loops.last_mut().and_then(|items| items.next()) {
+                    Some(item) => {
+                        self.variables.insert(self.text(variable), item);
+                    }
+                    None => {
+                        self.loops.pop();
+                        self.pc = exit as usize;
+                    }
+                }
+            }
+            Op::EndLoop => {
+                self.loops.pop();
+            }
+            Op::Call { args, kwargs } => {
+                let callee = self.pop()?;
+                let kwargs = self.pop_entries(kwargs)?;
+                let args = self.pop_many(args)?;
+                let value = match callee {
+                    ScriptValue::Builtin(name) => call_builtin(&name, args, kwargs, world, &mut self.pending_wait)?,
+                    other => return Err(format!("a value of type {} cannot be called", other.type_name()))
+                };
+                self.stack.push(value);
+            }
+            Op::CallMethod { name, args, kwargs } => {
+                let receiver = self.pop()?;
+                let kwargs = self.pop_entries(kwargs)?;
+                let args = self.pop_many(args)?;
+                let method = self.text(name);
+                let place = if method == "append" { Some(self.places.pop().ok_or("the bytecode popped an empty place stack")?) } else { None };
+
+                let value = match (receiver, place) {
+                    (ScriptValue::Builtin(namespace), _) => match get_attribute(&ScriptValue::Builtin(namespace), &method, world)? {
+                        ScriptValue::Builtin(name) => call_builtin(&name, args, kwargs, world, &mut self.pending_wait)?,
+                        other => return Err(format!("a value of type {} cannot be called", other.type_name()))
+                    },
+                    (ScriptValue::List(_), Some(place)) => append(place_mut(&place?, &mut self.variables, world, false)?, args)?,
+                    (receiver, _) => call_method(receiver, &method, args, world)?
+                };
+                self.stack.push(value);
+            }
+            Op::PlaceVariable(name) => self.places.push(Ok(Place { root: PlaceRoot::Variable(self.text(name)), path: Vec::new() })),
+            Op::PlaceInvalid => self.places.push(Err("can only assign to variables, attributes and indices".to_string())),
+            Op::PlaceKey => {
+                let of = self.pop()?;
+                let key = self.pop()?;
+                let parent = self.places.pop().ok_or("the bytecode popped an empty place stack")?;
+                let place = match (of, key) {
+                    (ScriptValue::Entity(uid), ScriptValue::String(name)) => Ok(Place { root: PlaceRoot::Attribute(uid, name), path: Vec::new() }),
+                    (ScriptValue::Entity(_), _) => Err("entity attributes must be named by strings".to_string()),
+                    (_, key) => parent.map(|mut place| {
+                        place.path.push(key);
+                        place
+                    })
+                };
+                self.places.push(place);
+            }
+            Op::StorePlace => {
+                let place = self.places.pop().ok_or("the bytecode popped an empty place stack")??;
+                let value = self.pop()?;
+                *place_mut(&place, &mut self.variables, world, true)? = value;
+            }
+            Op::Move => {
+                let destination = self.pop()?;
+                let subject = self.pop()?;
+                move_value(subject, destination, world)?;
+            }
+            Op::Fail(message) => return Err(self.text(message))
+        }
+        Ok(())
+    }
+}
+
+impl ScriptRunner for ES3VirtualMachine {
+    fn resume(&mut self, world: &mut ScriptWorld, delta: f64) -> Result<&ScriptState, String> {
+        ES3VirtualMachine::resume(self, world, delta)
+    }
+
+    fn is_finished(&self) -> bool {
+        ES3VirtualMachine::is_finished(self)
+    }
+}
+
+/// A compiled script in either format, the game runs both
+#[derive(Debug, Clone)]
+pub enum CompiledScript {
+    Json(Value),
+    Bytecode(Rc<Chunk>)
+}
+
+impl CompiledScript {
+    /// reads a compiled script file, bytecode if it starts with `MAGIC` and JSON otherwise
+    pub fn from_bytes(bytes: &[u8]) -> Result<CompiledScript, String> {
+        if bytes.starts_with(MAGIC) {
+            return Chunk::from_bytes(bytes).map(|chunk| CompiledScript::Bytecode(Rc::new(chunk)))
+        }
+        serde_json::from_slice(bytes).map(CompiledScript::Json).map_err(|e| format!("not a compiled script: {}", e))
+    }
+
+    pub fn header(&self) -> ScriptHeader {
+        match self {
+            CompiledScript::Json(program) => ScriptHeader::from_json(&program["header"]),
+            CompiledScript::Bytecode(chunk) => ScriptHeader::from_json(&chunk.header)
+        }
+    }
+
+    /// starts running the script for `player`
+    pub fn start(&self, player: &str, owner: Option<String>) -> Result<Box<dyn ScriptRunner>, String> {
+        Ok(match self {
+            CompiledScript::Json(program) => Box::new(ES3Interpreter::new(program, player, owner)?),
+            CompiledScript::Bytecode(chunk) => Box::new(ES3VirtualMachine::new(chunk.clone(), player, owner))
+        })
+    }
+}
+
+/// Prints a chunk, one instruction per line with its operands and the constants they refer to.
+/// Each statement is preceded by its line number and, when `source` is given, the text of that line
+pub fn disassemble(chunk: &Chunk, source: Option<&str>) -> String {
+    let lines: Vec<&str> = source.map(|s| s.lines().collect()).unwrap_or_default();
+    let mut out = format!("header: {}\n", chunk.header);
+
+    out += "constants:\n";
+    for (i, constant) in chunk.constants.iter().enumerate() {
+        let _ = writeln!(out, "{:>6}  {}", i, describe_constant(constant));
+    }
+
+    out += "code:\n";
+    let mut last_line = None;
+    for (pc, op) in chunk.code.iter().enumerate() {
+        let line = chunk.line_at(pc);
+        let starts_statement = chunk.statements.iter().any(|s| s.start == pc as u32 && s.line > 0);
+        if starts_statement && line != last_line {
+            if let Some(line) = line {
+                let text = lines.get(line as usize - 1).map_or(String::new(), |l| format!("  {}", l.trim()));
+                let _ = writeln!(out, "  ; line {}{}", line, text);
+            }
+            last_line = line;
+        }
+
+        let operands: Vec<String> = op.operands().iter().map(|o| o.to_string()).collect();
+        let mut text = format!("{:>6}  {:<18}{}", pc, op.name(), operands.join(" "));
+        let constants: Vec<String> = op.constants().iter().map(|&i| describe_constant(&chunk.constants[i as usize])).collect();
+        if !constants.is_empty() {
+            text = format!("{:<40}; {}", text, constants.join(", "));
+        } else if let Some(target) = op.target() {
+            text = format!("{:<40}; -> {}", text, target);
+        }
+        out += text.trim_end();
+        out += "\n";
+    }
+    out
+}
+
+/// a constant as it would be written in a script
+pub fn describe_constant(constant: &ScriptValue) -> String {
+    match constant {
+        ScriptValue::String(s) => format!("{:?}", s),
+        ScriptValue::List(items) => format!("[{}]", items.iter().map(describe_constant).collect::<Vec<_>>().join(", ")),
+        other => other.to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod bytecode_tests {
+    use std::rc::Rc;
+    use crate::es3::ES3Compiler;
+    use crate::es3_bytecode::{disassemble, Chunk, CompiledScript, ES3VirtualMachine, Op};
+    use crate::es3_interpreter::{ES3Interpreter, Entity, ScriptState, ScriptValue, ScriptWorld};
+
+    const SOURCE: &str = r#"#!enter-script
+$greet($who) {
+    output(format("hi {0}", $who))
+    wait(1)
+}
+total = 0
+for i in [1, 2, 3, 4, 5] {
+    if i == 2 { continue }
+    if i == 5 { break }
+    total = total + i
+}
+n = 0
+while n < 3 and total > 0 { n = n + 1 }
+match n {
+    case 2 { output("two") }
+    case 3 { output("three") }
+    else { output("other") }
+}
+$greet("you")
+seen = {count: 0}
+seen.count = seen["count"] + 1
+#player.tag$[met = true]
+#player.tag$["'quoted'" = 1]
+#dungeon.player_ids.append(#player.uid)
+output(total, n, seen.count, #player.tag$[met], length(#dungeon.player_ids), not false or x)
+"#;
+
+    fn compile(source: &str) -> ES3Compiler {
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize(source);
+        compiler.parse().unwrap();
+        compiler
+    }
+
+    fn world() -> ScriptWorld {
+        let mut world = ScriptWorld::new(3);
+        world.add_entity(Entity::new("p1", "engine:player"));
+        world
+    }
+
+    #[test]
+    pub fn test_run_bytecode() {
+        let compiler = compile(SOURCE);
+        let chunk = compiler.compile_bytecode().unwrap();
+        assert!(chunk.code.iter().any(|op| matches!(op, Op::JumpIfNotEqual(_))));
+
+        // the bytes read back to the same chunk, and are smaller than the JSON
+        let bytes = chunk.to_bytes();
+        assert_eq!(Chunk::from_bytes(&bytes), Ok(chunk.clone()));
+        assert!(bytes.len() < compiler.compile().to_string().len() / 2);
+        assert!(Chunk::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(matches!(CompiledScript::from_bytes(&bytes), Ok(CompiledScript::Bytecode(_))));
+
+        let (mut tree_world, mut vm_world) = (world(), world());
+        let mut tree = ES3Interpreter::new(&compiler.compile(), "p1", None).unwrap();
+        let mut vm = ES3VirtualMachine::new(Rc::new(chunk), "p1", None);
+
+        assert_eq!(vm.resume(&mut vm_world, 0.0), Ok(&ScriptState::Waiting(1.0)));
+        assert_eq!(vm.line(), Some(20));
+        assert_eq!(vm_world.output, ["three", "hi you"]);
+        assert_eq!(vm.resume(&mut vm_world, 1.0), Ok(&ScriptState::Finished));
+
+        tree.resume(&mut tree_world, 0.0).unwrap();
+        tree.resume(&mut tree_world, 1.0).unwrap();
+        assert_eq!(vm_world.output, tree_world.output);
+        assert_eq!(vm_world.output[2], "8 3 1 true 1 true");
+        for name in ["total", "n", "seen", "i"] {
+            assert_eq!(vm.variable(name), tree.variable(name));
+        }
+        assert_eq!(vm_world.entities["dungeon"].attributes, tree_world.entities["dungeon"].attributes);
+        assert_eq!(vm_world.entities["p1"].tags, tree_world.entities["p1"].tags);
+        assert_eq!(vm_world.entities["p1"].tags["'quoted'"], ScriptValue::Integer(1));
+
+        let mut vm = ES3VirtualMachine::new(Rc::new(compile("x = [1]\nx[3] = 2").compile_bytecode().unwrap()), "p1", None);
+        assert_eq!(vm.resume(&mut vm_world, 0.0), Err("index 3 is out of range for a list of length 1".to_string()));
+        assert!(vm.is_finished());
+        assert_eq!(vm.variable("x"), Some(&ScriptValue::List(vec![ScriptValue::Integer(1)])));
+    }
+
+    #[test]
+    pub fn test_disassemble() {
+        let source = "x = 1\nif x > 0 {\n    output(\"positive\")\n}";
+        let chunk = compile(source).compile_bytecode().unwrap();
+        let text = disassemble(&chunk, Some(source));
+
+        assert!(text.contains("  ; line 1  x = 1\n"));
+        assert!(text.contains("  ; line 3  output(\"positive\")\n"));
+        assert!(text.contains("JUMP_IF_FALSE     10"));
+        assert!(text.contains("; \"positive\""));
+        assert_eq!(chunk.line_at(chunk.code.len() - 1), Some(3));
+    }
+}
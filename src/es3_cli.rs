@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::es3::ES3Compiler;
+use crate::es3_bytecode::{self, disassemble, CompiledScript};
+
+/// scripts found when a directory is given
+const EXTENSION: &str = "es3";
+
+const USAGE: &str = "usage: IDNHRust es3 compile [options] <files or directories...>
+       IDNHRust es3 disassemble <files...>
+
+Compiles ES3 scripts without opening a window. Directories are searched for `.es3` files.
+Each script compiles to a `.json` file next to it (`.es3b` with `--bytecode`), unless it has errors.
+`disassemble` prints the bytecode of `.es3` scripts, next to their source, or of compiled `.es3b` and `.json` files.
+
+options:
+    -o, --out-dir <dir>     write the compiled scripts here instead, keeping their paths under any directory given
+    --objects <file>        check `<namespace:path>` references against the object ids in <file>, one per line
+    --type-check            report type errors as well
+    --check                 only report diagnostics, don't write anything
+    --bytecode              write compact bytecode instead of JSON
+
+exits with 1 if any script has errors, and 2 if the arguments or files are bad";
+
+struct Options {
+    out_dir: Option<PathBuf>,
+    objects: Option<Vec<String>>,
+    type_check: bool,
+    check: bool,
+    bytecode: bool,
+    /// each script with the path its output is written to under `out_dir`
+    scripts: Vec<(PathBuf, PathBuf)>
+}
+
+/// Runs `IDNHRust es3 <args>`, returning the process exit code
+pub fn run(args: Vec<String>) -> i32 {
+    let mut args = VecDeque::from(args);
+    match args.pop_front().as_deref() {
+        Some("compile") => {}
+        Some("disassemble") if args.is_empty() => {
+            eprintln!("error: no files to disassemble\n\n{}", USAGE);
+            return 2
+        }
+        Some("disassemble") => return disassemble_all(args),
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            return 0
+        }
+        Some(command) => {
+            eprintln!("error: unknown es3 command `{}`\n\n{}", command, USAGE);
+            return 2
+        }
+        None => {
+            eprintln!("{}", USAGE);
+            return 2
+        }
+    }
+
+    match parse_options(args) {
+        Ok(options) => compile_all(&options),
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            2
+        }
+    }
+}
+
+fn parse_options(mut args: VecDeque<String>) -> Result<Options, String> {
+    let mut options = Options { out_dir: None, objects: None, type_check: false, check: false, bytecode: false, scripts: Vec::new() };
+
+    while let Some(arg) = args.pop_front() {
+        match arg.as_str() {
+            "-o" | "--out-dir" => {
+                options.out_dir = Some(args.pop_front().ok_or(format!("`{}` needs a directory after it", arg))?.into());
+            }
+            "--objects" => {
+                let path = args.pop_front().ok_or("`--objects` needs a file after it")?;
+                let ids = fs::read_to_string(&path).map_err(|e| format!("cannot read `{}`: {}", path, e))?;
+                options.objects = Some(ids.lines().map(str::trim).filter(|id| !id.is_empty()).map(str::to_string).collect());
+            }
+            "--type-check" => options.type_check = true,
+            "--check" => options.check = true,
+            "--bytecode" => options.bytecode = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+            _ => {
+                let path = PathBuf::from(&arg);
+                if path.is_dir() {
+                    find_scripts(&path, &path, &mut options.scripts)?;
+                } else if path.is_file() {
+                    let name = path.file_name().map(PathBuf::from).unwrap_or_default();
+                    options.scripts.push((path, name));
+                } else {
+                    return Err(format!("`{}` does not exist", arg))
+                }
+            }
+        }
+    }
+
+    if options.scripts.is_empty() {
+        return Err("no scripts to compile".to_string())
+    }
+    Ok(options)
+}
+
+/// adds every script under `dir` to `out`, along with its path relative to `root`, in a stable order
+fn find_scripts(root: &Path, dir: &Path, out: &mut Vec<(PathBuf, PathBuf)>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("cannot read `{}`: {}", dir.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            find_scripts(root, &path, out)?;
+        } else if path.extension().is_some_and(|e| e == EXTENSION) {
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            out.push((path, relative));
+        }
+    }
+    Ok(())
+}
+
+/// compiles every script, printing its diagnostics, and returns the exit code
+fn compile_all(options: &Options) -> i32 {
+    let (mut failed, mut errors, mut warnings) = (0, 0, 0);
+
+    for (path, relative) in &options.scripts {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("error: cannot read `{}`: {}", path.display(), e);
+                return 2
+            }
+        };
+
+        let mut compiler = ES3Compiler::new();
+        compiler.set_type_checking(options.type_check);
+        if let Some(objects) = &options.objects {
+            compiler.set_objects(objects);
+        }
+        compiler.tokenize(&source);
+        let _ = compiler.parse();
+
+        let name = path.display().to_string();
+        for diagnostic in &compiler.diagnostics {
+            eprintln!("{}", diagnostic.render(&source, &name));
+            if diagnostic.is_error() {
+                errors += 1;
+            } else {
+                warnings += 1;
+            }
+        }
+        if compiler.diagnostics.iter().any(|d| d.is_error()) {
+            failed += 1;
+            continue
+        }
+        if options.check {
+            continue
+        }
+
+        let out_path = match &options.out_dir {
+            Some(dir) => dir.join(relative),
+            None => path.clone()
+        }.with_extension(if options.bytecode { "es3b" } else { "json" });
+        let output = if options.bytecode {
+            match compiler.compile_bytecode() {
+                Ok(chunk) => chunk.to_bytes(),
+                Err(e) => {
+                    eprintln!("error: cannot compile `{}` to bytecode: {}", path.display(), e);
+                    return 2
+                }
+            }
+        } else {
+            (serde_json::to_string_pretty(&compiler.compile()).unwrap() + "\n").into_bytes()
+        };
+        let written = out_path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(&out_path, output));
+        if let Err(e) = written {
+            eprintln!("error: cannot write `{}`: {}", out_path.display(), e);
+            return 2
+        }
+    }
+
+    let compiled = options.scripts.len() - failed;
+    eprintln!("compiled {} of {} scripts, {} errors, {} warnings", compiled, options.scripts.len(), errors, warnings);
+    if failed > 0 { 1 } else { 0 }
+}
+
+/// prints the bytecode of each file, returning the exit code
+fn disassemble_all(paths: VecDeque<String>) -> i32 {
+    for path in paths {
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("error: cannot read `{}`: {}", path, e);
+                return 2
+            }
+        };
+
+        let (chunk, source) = if path.ends_with(&format!(".{}", EXTENSION)) {
+            let source = String::from_utf8_lossy(&bytes).to_string();
+            let mut compiler = ES3Compiler::new();
+            compiler.tokenize(&source);
+            if compiler.parse().is_err() {
+                for diagnostic in compiler.diagnostics.iter().filter(|d| d.is_error()) {
+                    eprintln!("{}", diagnostic.render(&source, &path));
+                }
+                return 1
+            }
+            (compiler.compile_bytecode(), Some(source))
+        } else {
+            let chunk = match CompiledScript::from_bytes(&bytes) {
+                Ok(CompiledScript::Bytecode(chunk)) => Ok(chunk.as_ref().clone()),
+                Ok(CompiledScript::Json(program)) => es3_bytecode::compile(&program),
+                Err(e) => Err(e)
+            };
+            (chunk, None)
+        };
+
+        match chunk {
+            Ok(chunk) => println!("== {} ==\n{}", path, disassemble(&chunk, source.as_deref())),
+            Err(e) => {
+                eprintln!("error: cannot disassemble `{}`: {}", path, e);
+                return 2
+            }
+        }
+    }
+    0
+}
+
+
+#[cfg(test)]
+mod cli_tests {
+    use std::fs;
+    use crate::es3_cli::run;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    pub fn test_compile() {
+        let dir = std::env::temp_dir().join(format!("es3_cli_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("scripts/rooms")).unwrap();
+        fs::write(dir.join("scripts/rooms/docks.es3"), "#!enter-script\noutput(\"hi\")\n").unwrap();
+        fs::write(dir.join("scripts/notes.txt"), "not a script").unwrap();
+        fs::write(dir.join("objects.txt"), "emberhollow:rooms/docks\n").unwrap();
+        let scripts = dir.join("scripts").display().to_string();
+        let out = dir.join("out").display().to_string();
+
+        // next to the source
+        assert_eq!(run(args(&["compile", &scripts])), 0);
+        let compiled: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join("scripts/rooms/docks.json")).unwrap()).unwrap();
+        assert_eq!(compiled["header"]["trigger"], "enter");
+        assert!(!dir.join("scripts/notes.json").exists());
+
+        // into an output directory, keeping the path under the directory given
+        assert_eq!(run(args(&["compile", "-o", &out, &scripts])), 0);
+        assert!(dir.join("out/rooms/docks.json").exists());
+
+        let broken = dir.join("broken.es3");
+        fs::write(&broken, "x = <emberhollow:rooms/attic>\n").unwrap();
+        let objects = dir.join("objects.txt").display().to_string();
+        assert_eq!(run(args(&["compile", "--check", &broken.display().to_string()])), 0);
+        assert_eq!(run(args(&["compile", "--objects", &objects, &broken.display().to_string()])), 1);
+        assert!(!dir.join("broken.json").exists());
+
+        // bytecode, which disassembles from the script or the compiled file
+        assert_eq!(run(args(&["compile", "--bytecode", "-o", &out, &scripts])), 0);
+        let compiled = dir.join("out/rooms/docks.es3b");
+        assert!(fs::read(&compiled).unwrap().starts_with(b"ES3B"));
+        assert_eq!(run(args(&["disassemble", &compiled.display().to_string()])), 0);
+        assert_eq!(run(args(&["disassemble", &dir.join("scripts/rooms/docks.es3").display().to_string()])), 0);
+        assert_eq!(run(args(&["disassemble", &dir.join("objects.txt").display().to_string()])), 2);
+        assert_eq!(run(args(&["disassemble"])), 2);
+
+        assert_eq!(run(args(&["compile", "--unknown", &scripts])), 2);
+        assert_eq!(run(args(&["compile", &dir.join("missing.es3").display().to_string()])), 2);
+        assert_eq!(run(args(&["run"])), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+use std::ops::Range;
+use crate::es3::{ES3Compiler, PositionedToken, Token, ENGINE_OBJECTS};
+use crate::es3_interpreter::BUILTINS;
+use crate::es3_lexer::{COMMANDS, KEYWORDS};
+use crate::es3_symbols::SymbolKind;
+use crate::es3_types::entity_schema;
+
+/// names that can follow `#`
+const CONTEXTS: [&str; 2] = ["player", "dungeon"];
+/// functions inside the builtin namespaces, as (namespace, name)
+const NAMESPACE_MEMBERS: [(&str, &str); 2] = [("random", "choice"), ("random", "range")];
+
+/// What a completion is, in the order they are ranked when they match equally well
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CompletionKind {
+    Parameter,
+    Variable,
+    Member,
+    Macro,
+    Builtin,
+    Context,
+    Object,
+    Keyword
+}
+
+impl CompletionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompletionKind::Parameter => "parameter",
+            CompletionKind::Variable => "variable",
+            CompletionKind::Member => "member",
+            CompletionKind::Macro => "macro",
+            CompletionKind::Builtin => "builtin",
+            CompletionKind::Context => "context",
+            CompletionKind::Object => "object",
+            CompletionKind::Keyword => "keyword"
+        }
+    }
+}
+
+/// A suggestion for the word at the cursor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    /// what to show in the list
+    pub label: String,
+    pub kind: CompletionKind,
+    /// shown next to the label, a member's type or a macro's parameters
+    pub detail: String,
+    /// what to replace `range` with when the completion is picked
+    pub insert_text: String,
+    /// the bytes of the source the completion replaces, what was already typed of the word
+    pub range: Range<usize>
+}
+
+impl Completion {
+    fn new(label: &str, kind: CompletionKind, detail: String) -> Self {
+        Self { label: label.to_string(), kind, detail, insert_text: label.to_string(), range: 0..0 }
+    }
+}
+
+/// Completions for a cursor at byte `index` of the compiler's source, best first.
+/// What is offered depends on what comes before the word being typed:
+/// ```text
+/// <engine:pl|     object ids, the engine's and the project's (see `ES3Compiler::set_objects`)
+/// #player.mo|     attributes of the context's entity
+/// random.ch|      functions in a builtin namespace
+/// #pl|            contexts
+/// cou|            variables and parameters in scope, macros, builtins and keywords
+/// ```
+/// Candidates match when the typed text is a prefix of them (case-sensitive first) or, ranked after those,
+/// when its characters appear in them in order. Nothing is offered inside strings, comments and headers
+pub fn complete(compiler: &ES3Compiler, index: usize) -> Vec<Completion> {
+    let source = &compiler.source;
+    let mut index = index.min(source.len());
+    while !source.is_char_boundary(index) {
+        index -= 1;
+    }
+
+    if compiler.token_at(index).is_some_and(|t| is_inside_text(t, index)) {
+        return Vec::new()
+    }
+
+    let line_start = source[..index].rfind('\n').map_or(0, |i| i + 1);
+    let before = &source[line_start..index];
+
+    // `<namespace:path>` references
+    if let Some(open) = before.rfind('<') {
+        let typed = &before[open + 1..];
+        if !typed.contains(|c: char| c.is_whitespace() || c == '>') {
+            // the rest of the id after the cursor is replaced too
+            let end = source[index..].find(|c: char| c.is_whitespace() || c == '<' || c == '>').map_or(source.len(), |i| index + i);
+            let close = if source[end..].starts_with('>') { "" } else { ">" };
+            let mut ids: Vec<&str> = ENGINE_OBJECTS.to_vec();
+            ids.extend(compiler.objects().into_iter().flatten().map(String::as_str));
+            let candidates = ids.into_iter().map(|id| Completion {
+                insert_text: format!("{}{}", id, close),
+                ..Completion::new(id, CompletionKind::Object, String::new())
+            });
+            return rank(candidates.collect(), typed, line_start + open + 1..end)
+        }
+    }
+
+    let mut word_start = index - before.bytes().rev().take_while(|b| b.is_ascii_alphanumeric() || *b == b'_').count();
+    if source[..word_start].ends_with('$') {
+        word_start -= 1;
+    }
+    let typed = &source[word_start..index];
+    if typed.starts_with(|c: char| c.is_ascii_digit()) {
+        return Vec::new()
+    }
+    let preceding = &source[line_start..word_start];
+
+    let candidates = if let Some(receiver) = preceding.strip_suffix('.').filter(|r| !r.ends_with('.')) {
+        members(receiver.trim_end())
+    } else if preceding.ends_with('#') && !typed.starts_with('$') {
+        CONTEXTS.iter().map(|c| Completion::new(c, CompletionKind::Context, format!("`<engine:{}>` entity", c))).collect()
+    } else {
+        in_scope(compiler, index, word_start)
+    };
+    rank(candidates, typed, word_start..index)
+}
+
+/// whether a cursor at `index` is in the middle of a string, comment or header rather than at its edge
+fn is_inside_text(token: &PositionedToken, index: usize) -> bool {
+    let end = token.index() + token.length();
+    let runs_to_line_end = match token.token() {
+        Token::Context(_) => true,
+        Token::Comment(c) => c.starts_with("//"),
+        Token::String(_) => false,
+        _ => return false
+    };
+    token.index() < index && (index < end || (index == end && runs_to_line_end))
+}
+
+/// attributes of a `#context`, or functions of a builtin namespace, named at the end of `receiver`
+fn members(receiver: &str) -> Vec<Completion> {
+    let name = receiver.rsplit(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '#')).next().unwrap_or("");
+
+    if let Some(context) = name.strip_prefix('#').filter(|c| CONTEXTS.contains(c)) {
+        let schema = entity_schema(&format!("engine:{}", context)).unwrap_or_default();
+        return schema.into_iter().map(|(n, t)| Completion::new(n, CompletionKind::Member, t.to_string())).collect()
+    }
+    NAMESPACE_MEMBERS.iter()
+        .filter(|(namespace, _)| *namespace == name)
+        .map(|(namespace, n)| Completion::new(n, CompletionKind::Member, format!("`{}.{}`", namespace, n)))
+        .collect()
+}
+
+/// variables, parameters and macros visible at `index`, along with the builtins and keywords.
+/// Symbols only seen in the word being typed (starting at `word_start`) are left out
+fn in_scope(compiler: &ES3Compiler, index: usize, word_start: usize) -> Vec<Completion> {
+    let scope = enclosing_macro(&compiler.tokens, index);
+    let symbols = &compiler.symbols.symbols;
+    let mut out = Vec::new();
+
+    for symbol in symbols {
+        if symbol.scope.is_some() && symbol.scope != scope {
+            continue
+        }
+        if symbol.references.iter().all(|r| r.start == word_start) {
+            continue
+        }
+        let completion = match symbol.kind {
+            SymbolKind::Macro if symbol.definition.is_none() => continue,
+            SymbolKind::Macro => {
+                let mut params: Vec<_> = symbols.iter()
+                    .filter(|s| s.kind == SymbolKind::Parameter && s.scope.as_ref() == Some(&symbol.name))
+                    .collect();
+                params.sort_by_key(|s| s.definition.map(|d| d.start));
+                let params: Vec<&str> = params.iter().map(|s| s.name.as_str()).collect();
+                Completion::new(&symbol.name, CompletionKind::Macro, format!("({})", params.join(", ")))
+            }
+            SymbolKind::Parameter => Completion::new(&symbol.name, CompletionKind::Parameter, String::new()),
+            SymbolKind::Variable => Completion::new(&symbol.name, CompletionKind::Variable, String::new())
+        };
+        out.push(completion);
+    }
+
+    out.extend(BUILTINS.iter().map(|b| Completion::new(b, CompletionKind::Builtin, String::new())));
+    out.extend(KEYWORDS.iter().chain(&COMMANDS).chain(&["true", "false"]).map(|k| Completion::new(k, CompletionKind::Keyword, String::new())));
+    out
+}
+
+/// name of the macro whose body byte `index` is in, found from the tokens alone so it works while the script is broken
+fn enclosing_macro(tokens: &[PositionedToken], index: usize) -> Option<String> {
+    let tokens = &tokens[..tokens.partition_point(|t| t.index() < index)];
+    let mut blocks: Vec<Option<String>> = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token.token() {
+            Token::Literal(l) if l == "{" => blocks.push(defined_macro(tokens, i)),
+            Token::Literal(l) if l == "}" => {
+                blocks.pop();
+            }
+            _ => {}
+        }
+    }
+    blocks.into_iter().rev().flatten().next()
+}
+
+/// the macro whose body the `{` at `open` starts, if it is one: `$name(params) {`
+fn defined_macro(tokens: &[PositionedToken], open: usize) -> Option<String> {
+    let is = |i: usize, text: &str| matches!(tokens[i].token(), Token::Literal(l) if l == text);
+    let mut i = open.checked_sub(1)?;
+    if !is(i, ")") {
+        return None
+    }
+    while !is(i, "(") {
+        i = i.checked_sub(1)?;
+    }
+    match tokens[i.checked_sub(1)?].token() {
+        Token::Macro(name) => Some(name.clone()),
+        _ => None
+    }
+}
+
+/// how well `label` matches `typed`, lower is better
+fn match_quality(label: &str, typed: &str) -> Option<u8> {
+    if label.starts_with(typed) {
+        return Some(0)
+    }
+    let (label, typed) = (label.to_lowercase(), typed.to_lowercase());
+    if label.starts_with(&typed) {
+        return Some(1)
+    }
+    let mut chars = label.chars();
+    typed.chars().all(|c| chars.any(|l| l == c)).then_some(2)
+}
+
+/// the candidates matching `typed`, best first and without repeated labels
+fn rank(candidates: Vec<Completion>, typed: &str, range: Range<usize>) -> Vec<Completion> {
+    let mut matched: Vec<(u8, Completion)> = candidates.into_iter()
+        .filter_map(|c| match_quality(&c.label, typed).map(|q| (q, c)))
+        .collect();
+    matched.sort_by(|(a, x), (b, y)| {
+        a.cmp(b).then(x.kind.cmp(&y.kind)).then(x.label.len().cmp(&y.label.len())).then(x.label.cmp(&y.label))
+    });
+
+    let mut out: Vec<Completion> = Vec::new();
+    for (_, completion) in matched {
+        if !out.iter().any(|c| c.label == completion.label) {
+            out.push(Completion { range: range.clone(), ..completion });
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod completion_tests {
+    use crate::es3::ES3Compiler;
+    use crate::es3_completion::CompletionKind;
+
+    const SOURCE: &str = "counter = 0
+$greet($name, $times) {
+    $said = $name
+    output($sa)
+}
+co
+#player.mo
+random.ch
+x = <engine:pl>
+// co";
+
+    fn labels(compiler: &ES3Compiler, after: &str) -> Vec<String> {
+        let index = SOURCE.find(after).unwrap() + after.len();
+        compiler.complete(index).into_iter().map(|c| c.label).collect()
+    }
+
+    #[test]
+    pub fn test_completion() {
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize(SOURCE);
+        let _ = compiler.parse();
+        compiler.set_objects(["emberhollow:rooms/docks"]);
+
+        // macro locals and parameters are only offered inside the macro
+        assert_eq!(labels(&compiler, "output($sa"), ["$said"]);
+        assert_eq!(labels(&compiler, "\nco"), ["counter", "continue"]);
+        let completions = compiler.complete(SOURCE.find("= $na").unwrap() + 5);
+        assert_eq!(completions[0].label, "$name");
+        assert_eq!(completions[0].kind, CompletionKind::Parameter);
+        assert_eq!(completions[0].range.len(), 3);
+
+        assert_eq!(labels(&compiler, "#player.mo"), ["money"]);
+        assert_eq!(compiler.complete(SOURCE.find("#player.").unwrap() + 8)[0].detail, "string");
+        assert_eq!(labels(&compiler, "random.ch"), ["choice"]);
+        assert_eq!(labels(&compiler, "<engine:pl"), ["engine:player"]);
+        assert_eq!(compiler.complete(SOURCE.find("<").unwrap() + 1).last().unwrap().insert_text, "emberhollow:rooms/docks");
+        assert!(labels(&compiler, "// co").is_empty());
+
+        let macros = compiler.complete(SOURCE.find("\nco").unwrap() + 1);
+        let greet = macros.iter().find(|c| c.label == "$greet").unwrap();
+        assert_eq!(greet.detail, "($name, $times)");
+    }
+}
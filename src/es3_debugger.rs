@@ -0,0 +1,282 @@
+//! Debugging for ES3 scripts run by `ES3Interpreter`.
+//!
+//! `ES3Debugger` runs a program from `ES3Compiler::compile_debug`, whose statements carry their line and
+//! whose macro calls are kept as `#expand` statements. Before each statement it checks the breakpoints and
+//! the current step, and it pauses whenever the script starts a `wait`. While paused, `scopes` lists the
+//! values the script can see: the parameters and locals of each macro being run, its variables and `#player`
+
+use std::collections::BTreeSet;
+use serde_json::Value;
+use crate::es3_bytecode::describe_constant;
+use crate::es3_interpreter::{ES3Interpreter, ScriptState, ScriptWorld};
+
+/// how far the script runs before pausing again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepMode {
+    /// until a breakpoint
+    Continue,
+    /// to the next statement, including the first one inside a macro call
+    Into,
+    /// to the next statement no deeper in macro calls than the given depth
+    Over(usize),
+    /// to the next statement after the macro call at the given depth returns
+    Out(usize)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PauseReason {
+    Breakpoint,
+    Step,
+    /// the script started waiting, for this many seconds
+    Wait(f64)
+}
+
+/// a group of values shown while paused, with each name and the value as it would be written in a script
+pub struct Scope {
+    pub name: String,
+    pub values: Vec<(String, String)>
+}
+
+pub struct ES3Debugger {
+    interpreter: ES3Interpreter,
+    /// lines to pause at, counted from 1 like the lines of tokens
+    pub breakpoints: BTreeSet<usize>,
+    mode: StepMode,
+    /// why the script is paused, `None` while it runs
+    pub paused: Option<PauseReason>,
+    /// set when pausing before a statement, so resuming runs it instead of pausing at it again
+    resuming: bool,
+    /// the runtime error that stopped the script
+    pub error: Option<String>
+}
+
+impl ES3Debugger {
+    /// starts debugging `program`, running until the first breakpoint
+    pub fn new(program: &Value, player: impl ToString, owner: Option<String>) -> Result<Self, String> {
+        Ok(Self {
+            interpreter: ES3Interpreter::new(program, player, owner)?,
+            breakpoints: BTreeSet::new(),
+            mode: StepMode::Continue,
+            paused: None,
+            resuming: false,
+            error: None
+        })
+    }
+
+    pub fn interpreter(&self) -> &ES3Interpreter {
+        &self.interpreter
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.interpreter.is_finished()
+    }
+
+    /// the line of the statement the script is paused before
+    pub fn line(&self) -> Option<usize> {
+        self.paused.as_ref().and(self.interpreter.next_line())
+    }
+
+    pub fn toggle_breakpoint(&mut self, line: usize) {
+        if !self.breakpoints.remove(&line) {
+            self.breakpoints.insert(line);
+        }
+    }
+
+    /// how many macro calls deep the script is
+    pub fn depth(&self) -> usize {
+        self.interpreter.expansions().count()
+    }
+
+    pub fn continue_running(&mut self) {
+        self.run(StepMode::Continue)
+    }
+
+    pub fn step_into(&mut self) {
+        self.run(StepMode::Into)
+    }
+
+    pub fn step_over(&mut self) {
+        self.run(StepMode::Over(self.depth()))
+    }
+
+    pub fn step_out(&mut self) {
+        self.run(StepMode::Out(self.depth()))
+    }
+
+    fn run(&mut self, mode: StepMode) {
+        self.mode = mode;
+        self.paused = None;
+    }
+
+    /// Runs the script like `ES3Interpreter::resume`, unless it is paused.
+    /// Stops before a statement on a breakpoint or where the step ends, and right after a `wait` starts
+    pub fn resume(&mut self, world: &mut ScriptWorld, delta: f64) -> Result<&ScriptState, String> {
+        if self.paused.is_some() || self.is_finished() {
+            return Ok(&self.interpreter.state)
+        }
+
+        // a wait that is only counted down doesn't pause again, one that ends and is followed by another does
+        let still_waiting = matches!(self.interpreter.state, ScriptState::Waiting(left) if left - delta > 0.0);
+        let Self { interpreter, breakpoints, mode, paused, resuming, .. } = self;
+        let state = interpreter.resume_until(world, delta, |interpreter| {
+            let Some(line) = interpreter.next_line() else {
+                return false
+            };
+            if std::mem::take(resuming) {
+                return false
+            }
+
+            let depth = interpreter.expansions().count();
+            *paused = match *mode {
+                _ if breakpoints.contains(&line) => Some(PauseReason::Breakpoint),
+                StepMode::Into => Some(PauseReason::Step),
+                StepMode::Over(from) if depth <= from => Some(PauseReason::Step),
+                StepMode::Out(from) if depth < from => Some(PauseReason::Step),
+                _ => None
+            };
+            paused.is_some()
+        }).cloned();
+
+        match state {
+            Ok(ScriptState::Waiting(seconds)) if !still_waiting => self.paused = Some(PauseReason::Wait(seconds)),
+            Err(e) => {
+                self.error = Some(e.clone());
+                return Err(e)
+            }
+            _ => {}
+        }
+        self.resuming = matches!(self.paused, Some(PauseReason::Breakpoint | PauseReason::Step));
+        Ok(&self.interpreter.state)
+    }
+
+    /// The values the script can see, innermost first: the parameters and locals of each macro call being run,
+    /// the script's own variables and `#player`. Parameters are evaluated as they would be now, unless that
+    /// could change the world
+    pub fn scopes(&mut self, world: &mut ScriptWorld) -> Vec<Scope> {
+        let mut scopes = Vec::new();
+
+        let expansions: Vec<Value> = self.interpreter.expansions().cloned().collect();
+        for expansion in expansions.iter().rev() {
+            let mut values = Vec::new();
+            for (param, argument) in expansion["params"].as_object().into_iter().flatten() {
+                let value = self.interpreter.inspect(argument, world).map(|v| describe_constant(&v));
+                values.push((param.clone(), value.unwrap_or_else(|e| e)));
+            }
+            for (local, renamed) in expansion["locals"].as_object().into_iter().flatten() {
+                let value = self.interpreter.variable(renamed.as_str().unwrap_or_default());
+                values.push((local.clone(), value.map_or("not set".to_string(), describe_constant)));
+            }
+            values.sort();
+
+            let name = expansion["#expand"].as_str().unwrap_or_default();
+            let line = expansion["#line"].as_u64().unwrap_or_default();
+            scopes.push(Scope { name: format!("{}, called on line {}", name, line), values });
+        }
+
+        // renamed macro locals are shown with their macro instead
+        let mut values: Vec<(String, String)> = self.interpreter.variables().iter()
+            .filter(|(name, _)| !name.contains('@'))
+            .map(|(name, value)| (name.clone(), describe_constant(value)))
+            .collect();
+        values.sort();
+        scopes.push(Scope { name: "variables".to_string(), values });
+
+        let values = match world.entity(&self.interpreter.player) {
+            Ok(player) => {
+                let mut values = vec![
+                    ("uid".to_string(), player.uid.clone()),
+                    ("room".to_string(), player.room.clone().unwrap_or_else(|| "none".to_string()))
+                ];
+                values.extend(player.attributes.iter().map(|(name, value)| (format!(".{}", name), describe_constant(value))));
+                values.extend(player.tags.iter().map(|(name, value)| (format!("tag$[{}]", name), describe_constant(value))));
+                values
+            }
+            Err(e) => vec![("error".to_string(), e)]
+        };
+        scopes.push(Scope { name: "#player".to_string(), values });
+
+        scopes
+    }
+}
+
+
+#[cfg(test)]
+mod debugger_tests {
+    use crate::es3::ES3Compiler;
+    use crate::es3_debugger::{ES3Debugger, PauseReason};
+    use crate::es3_interpreter::{Entity, ScriptState, ScriptValue, ScriptWorld};
+
+    const SOURCE: &str = "count = 0
+$bump($by) {
+    $next = count + $by
+    count = $next
+}
+$bump(2)
+wait(1)
+$bump(count)
+output(count)
+";
+
+    #[test]
+    pub fn test_debug_script() {
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize(SOURCE);
+        compiler.parse().unwrap();
+
+        let mut world = ScriptWorld::new(0);
+        let mut player = Entity::new("p1", "engine:player");
+        player.attributes.insert("name".to_string(), ScriptValue::String("Mara".to_string()));
+        world.add_entity(player);
+
+        let mut debugger = ES3Debugger::new(&compiler.compile_debug(), "p1", None).unwrap();
+        debugger.toggle_breakpoint(4);
+
+        // stepping into a macro call stops inside it, stepping over one doesn't
+        debugger.step_into();
+        debugger.resume(&mut world, 0.0).unwrap();
+        assert_eq!((debugger.paused.clone(), debugger.line()), (Some(PauseReason::Step), Some(1)));
+        debugger.step_over();
+        debugger.resume(&mut world, 0.0).unwrap();
+        assert_eq!(debugger.line(), Some(6));
+        debugger.step_into();
+        debugger.resume(&mut world, 0.0).unwrap();
+        assert_eq!((debugger.line(), debugger.depth()), (Some(3), 1));
+
+        let scopes = debugger.scopes(&mut world);
+        assert_eq!(scopes[0].name, "$bump, called on line 6");
+        assert_eq!(scopes[0].values, [("$by".to_string(), "2".to_string()), ("$next".to_string(), "not set".to_string())]);
+
+        debugger.continue_running();
+        debugger.resume(&mut world, 0.0).unwrap();
+        assert_eq!((debugger.paused.clone(), debugger.line()), (Some(PauseReason::Breakpoint), Some(4)));
+        assert_eq!(debugger.scopes(&mut world)[0].values[1].1, "2");
+
+        // the step ends after the macro, then the `wait` pauses the script on its own
+        debugger.step_over();
+        debugger.resume(&mut world, 0.0).unwrap();
+        assert_eq!(debugger.line(), Some(7));
+        debugger.step_over();
+        debugger.resume(&mut world, 0.0).unwrap();
+        assert_eq!((debugger.paused.clone(), debugger.line()), (Some(PauseReason::Wait(1.0)), Some(8)));
+
+        debugger.continue_running();
+        assert_eq!(debugger.resume(&mut world, 0.5).unwrap(), &ScriptState::Waiting(0.5));
+        assert_eq!(debugger.paused, None);
+        debugger.resume(&mut world, 0.5).unwrap();
+        assert_eq!((debugger.paused.clone(), debugger.line()), (Some(PauseReason::Breakpoint), Some(4)));
+
+        let scopes = debugger.scopes(&mut world);
+        assert_eq!(scopes[0].values[0], ("$by".to_string(), "2".to_string()));
+        assert_eq!(scopes[1].values, [("count".to_string(), "2".to_string())]);
+        assert_eq!(scopes[2].name, "#player");
+        assert!(scopes[2].values.contains(&(".name".to_string(), "\"Mara\"".to_string())));
+
+        debugger.step_out();
+        debugger.resume(&mut world, 0.0).unwrap();
+        assert_eq!(debugger.line(), Some(9));
+        debugger.continue_running();
+        debugger.resume(&mut world, 0.0).unwrap();
+        assert!(debugger.is_finished());
+        assert_eq!(world.output, ["4"]);
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt::{Display, Formatter};
+use crate::es3::style_flags;
+
+/// Stable diagnostic codes.
+/// `E00xx` come from the lexer, `E01xx`/`W01xx` from the parser, `E02xx` from macro expansion
+/// `E03xx`/`W03xx` from context headers, `E04xx` from name resolution, `E05xx`/`W05xx` from type checking
+/// and `E06xx` from string literals and `format` templates
+pub mod codes {
+    pub const UNKNOWN_CHARACTER: &str       = "E0001";
+    pub const INTEGER_TOO_LARGE: &str       = "E0002";
+
+    pub const EXPECTED_TOKEN: &str          = "E0101";
+    pub const EXPECTED_EXPRESSION: &str     = "E0102";
+    pub const UNCLOSED_DELIMITER: &str      = "E0103";
+    pub const UNEXPECTED_TOKEN: &str        = "E0104";
+    pub const UNSUPPORTED_SYNTAX: &str      = "E0105";
+    pub const INVALID_ARGUMENTS: &str       = "E0106";
+    pub const EXPECTED_NAME: &str           = "E0107";
+
+    pub const UNDEFINED_MACRO: &str         = "E0201";
+    pub const MACRO_ARGUMENTS: &str         = "E0202";
+    pub const RECURSIVE_MACRO: &str         = "E0203";
+
+    pub const UNKNOWN_TRIGGER: &str         = "E0301";
+    pub const DUPLICATE_HEADER: &str        = "E0302";
+
+    pub const UNKNOWN_OBJECT: &str          = "E0401";
+
+    pub const TYPE_MISMATCH: &str           = "E0501";
+    pub const UNKNOWN_ATTRIBUTE: &str       = "E0502";
+
+    pub const INVALID_ESCAPE: &str          = "E0601";
+    pub const INVALID_PLACEHOLDER: &str     = "E0602";
+    pub const UNKNOWN_PLACEHOLDER: &str     = "E0603";
+
+    pub const UNREACHABLE_CODE: &str        = "W0101";
+    pub const UNUSED_EXPRESSION: &str       = "W0102";
+    pub const MISPLACED_HEADER: &str        = "W0301";
+    pub const DISJOINT_COMPARISON: &str     = "W0501";
+    pub const UNDECLARED_ATTRIBUTE: &str    = "W0502";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+impl Severity {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        }
+    }
+
+    /// the token style flag editors should set for this severity
+    pub fn style_flag(&self) -> u8 {
+        match self {
+            Severity::Error => style_flags::ERROR,
+            Severity::Warning => style_flags::WARNING
+        }
+    }
+}
+
+/// Byte range into the source, along with the line/column range it covers.
+/// Lines start at 1 and columns at 0, the same as `PositionedToken`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, start_line: usize, start_column: usize, end_line: usize, end_column: usize) -> Self {
+        Self {
+            start,
+            end,
+            start_line,
+            start_column,
+            end_line,
+            end_column
+        }
+    }
+
+    /// returns a span covering both `self` and `other`
+    pub fn to(&self, other: &Span) -> Span {
+        let first = if self.start <= other.start { self } else { other };
+        let last = if self.end >= other.end { self } else { other };
+        Span::new(first.start, last.end, first.start_line, first.start_column, last.end_line, last.end_column)
+    }
+
+    /// moves the span by `bytes` and `lines`, for text inserted or removed before it
+    pub fn shift(&mut self, bytes: isize, lines: isize) {
+        self.start = (self.start as isize + bytes) as usize;
+        self.end = (self.end as isize + bytes) as usize;
+        self.start_line = (self.start_line as isize + lines) as usize;
+        self.end_line = (self.end_line as isize + lines) as usize;
+    }
+
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start < end && start < self.end || (self.start == self.end && start <= self.start && self.start < end)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub related: Vec<(Span, String)>
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: impl ToString, span: Span) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.to_string(),
+            span,
+            related: Vec::new()
+        }
+    }
+
+    pub fn error(code: &'static str, message: impl ToString, span: Span) -> Self {
+        Self::new(Severity::Error, code, message, span)
+    }
+
+    pub fn warning(code: &'static str, message: impl ToString, span: Span) -> Self {
+        Self::new(Severity::Warning, code, message, span)
+    }
+
+    /// attaches a secondary location, such as where an unclosed block was opened
+    pub fn with_related(mut self, span: Span, label: impl ToString) -> Self {
+        self.related.push((span, label.to_string()));
+        self
+    }
+
+    /// moves the diagnostic and its related spans, see `Span::shift`
+    pub fn shift(&mut self, bytes: isize, lines: isize) {
+        self.span.shift(bytes, lines);
+        for (span, _) in &mut self.related {
+            span.shift(bytes, lines);
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Renders the diagnostic in the style of rustc:
+    /// ```text
+    /// error[E0102]: expected an expression, found `]`
+    ///  --> script.es3:4:9
+    ///   |
+    /// 4 |     c = ]
+    ///   |         ^
+    /// ```
+    pub fn render(&self, source: &str, file_name: &str) -> String {
+        let max_line = self.related.iter().map(|(s, _)| s.end_line).fold(self.span.end_line, usize::max);
+        let width = max_line.to_string().len();
+        let pad = " ".repeat(width);
+
+        let mut out = format!("{}[{}]: {}\n", self.severity.label(), self.code, self.message);
+        out += &format!("{}--> {}:{}:{}\n", pad, file_name, self.span.start_line, self.span.start_column + 1);
+        out += &format!("{} |\n", pad);
+        out += &render_snippet(source, &self.span, '^', "", width);
+
+        for (span, label) in &self.related {
+            if span.start_line != self.span.start_line {
+                out += &format!("{} |\n", pad);
+            }
+            out += &render_snippet(source, span, '-', label, width);
+        }
+
+        out
+    }
+}
+
+/// renders the first line of `span` with the covered columns underlined by `marker`
+fn render_snippet(source: &str, span: &Span, marker: char, label: &str, width: usize) -> String {
+    let line = source.split('\n').nth(span.start_line.saturating_sub(1)).unwrap_or("");
+    let line = line.strip_suffix('\r').unwrap_or(line);
+
+    let start = span.start_column.min(line.len());
+    let end = if span.end_line == span.start_line { span.end_column.clamp(start, line.len()) } else { line.len() };
+
+    // columns are byte offsets, underline by character so multi-byte text lines up
+    let prefix = line.get(..start).map(|s| s.chars().count()).unwrap_or(start);
+    let length = line.get(start..end).map(|s| s.chars().count()).unwrap_or(end - start).max(1);
+
+    let mut out = format!("{:>width$} | {}\n", span.start_line, line, width = width);
+    out += &format!("{} | {}{}", " ".repeat(width), " ".repeat(prefix), marker.to_string().repeat(length));
+    if !label.is_empty() {
+        out += &format!(" {}", label);
+    }
+    out += "\n";
+    out
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}] @ Ln {} Col {}: {}", self.severity.label(), self.code, self.span.start_line, self.span.start_column, self.message)
+    }
+}
+
+
+#[cfg(test)]
+mod diagnostic_tests {
+    use crate::es3_diagnostics::{codes, Diagnostic, Span};
+
+    #[test]
+    pub fn test_render() {
+        let source = "if (b) {\n    c = ]\n";
+
+        let diagnostic = Diagnostic::error(codes::EXPECTED_EXPRESSION, "expected an expression, found `]`", Span::new(17, 18, 2, 8, 2, 9))
+            .with_related(Span::new(7, 8, 1, 7, 1, 8), "block opened here");
+
+        assert_eq!(diagnostic.render(source, "script.es3"), concat!(
+            "error[E0102]: expected an expression, found `]`\n",
+            " --> script.es3:2:9\n",
+            "  |\n",
+            "2 |     c = ]\n",
+            "  |         ^\n",
+            "  |\n",
+            "1 | if (b) {\n",
+            "  |        - block opened here\n",
+        ));
+    }
+
+}
@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use crate::es3::{ES3Compiler, PositionedToken, Token};
+use crate::es3_lexer::Lexer;
+
+const INDENT: &str = "    ";
+/// lines longer than this have their outermost call split into one argument per line
+const MAX_WIDTH: usize = 100;
+
+/// Pretty-prints an ES3 script.
+/// Line breaks are kept as written (runs of blank lines become one), comments and `#!` headers are kept,
+/// indentation follows the open brackets, and spacing between tokens is normalised.
+/// Scripts that don't parse are returned unchanged, since brackets can't be trusted to line up in them.
+/// Formatting is idempotent, `format(format(x)) == format(x)`
+pub fn format(source: &str) -> String {
+    let mut compiler = ES3Compiler::new();
+    compiler.tokenize(source);
+    if compiler.parse().is_err() {
+        return source.to_string()
+    }
+
+    let formatter = Formatter { source, tokens: Lexer::new(source).collect() };
+    formatter.format()
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    tokens: Vec<PositionedToken>
+}
+
+impl Formatter<'_> {
+    /// the token as written in the source
+    fn text(&self, i: usize) -> &str {
+        let token = &self.tokens[i];
+        let text = &self.source[token.index()..token.index() + token.length()];
+        match token.token() {
+            Token::Context(_) | Token::Comment(_) => text.trim_end(),
+            _ => text
+        }
+    }
+
+    fn is(&self, i: usize, text: &str) -> bool {
+        matches!(self.tokens[i].token(), Token::Literal(l) if l == text)
+    }
+
+    fn last_line(&self, i: usize) -> usize {
+        self.tokens[i].line() + self.text(i).matches('\n').count()
+    }
+
+    fn format(&self) -> String {
+        // group the tokens into the lines they were written on, noting which lines had blank lines before them
+        let mut lines: VecDeque<(bool, Vec<usize>)> = VecDeque::new();
+        for i in 0..self.tokens.len() {
+            match lines.back_mut() {
+                Some((_, line)) if self.tokens[i].line() <= self.last_line(*line.last().unwrap()) => line.push(i),
+                Some((_, line)) => {
+                    let gap = self.tokens[i].line() > self.last_line(*line.last().unwrap()) + 1;
+                    lines.push_back((gap, vec![i]));
+                }
+                None => lines.push_back((false, vec![i]))
+            }
+        }
+
+        let mut out = String::new();
+        let mut depth: usize = 0;
+        while let Some((gap, line)) = lines.pop_front() {
+            let closers = line.iter().take_while(|&&i| self.is(i, ")") || self.is(i, "]") || self.is(i, "}")).count();
+            let indent = INDENT.repeat(depth.saturating_sub(closers));
+            let text = self.render(&line);
+
+            if indent.len() + text.chars().count() > MAX_WIDTH && !text.contains('\n') {
+                if let Some(wrapped) = self.wrap(&line) {
+                    for (n, part) in wrapped.into_iter().enumerate().rev() {
+                        lines.push_front((gap && n == 0, part));
+                    }
+                    continue
+                }
+            }
+
+            for &i in &line {
+                match self.text(i) {
+                    "(" | "[" | "{" => depth += 1,
+                    ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+
+            if gap && !out.is_empty() {
+                out.push('\n');
+            }
+            out += &indent;
+            out += &text;
+            out.push('\n');
+        }
+        out
+    }
+
+    /// the tokens of one line with normalised spacing
+    fn render(&self, line: &[usize]) -> String {
+        let mut out = String::new();
+        for (n, &i) in line.iter().enumerate() {
+            if n > 0 && self.space_between(line[n - 1], i, n.checked_sub(2).map(|p| line[p])) {
+                out.push(' ');
+            }
+            out += self.text(i);
+        }
+        out
+    }
+
+    /// whether a `-` or `+` following `before` is a sign rather than an operator
+    fn is_sign(&self, before: Option<usize>) -> bool {
+        let Some(before) = before else {
+            return true
+        };
+        match self.tokens[before].token() {
+            Token::Comparison(_) | Token::Keyword(_) | Token::Command(_) => true,
+            Token::Literal(l) => !matches!(l.as_str(), ")" | "]" | "}"),
+            _ => false
+        }
+    }
+
+    fn space_between(&self, left: usize, right: usize, before_left: Option<usize>) -> bool {
+        let (l, r) = (self.text(left), self.text(right));
+
+        // `->` is lexed as `-` and `>`
+        if l == "-" && r == ">" && self.tokens[left].index() + 1 == self.tokens[right].index() {
+            return false
+        }
+        if matches!(r, ")" | "]" | "," | ";" | ":" | ".") || matches!(l, "(" | "[" | "." | "#" | "$") || r == "$" {
+            return false
+        }
+        if l == "{" {
+            return r != "}"
+        }
+        if (l == "-" || l == "+") && self.is_sign(before_left) {
+            return false
+        }
+        if r == "(" || r == "[" {
+            // calls and indexing stick to what they call or index
+            return !self.is_callee(left) && !matches!(self.tokens[left].token(), Token::String(_))
+        }
+        true
+    }
+
+    /// whether a `(` right after the token at `i` is a call
+    fn is_callee(&self, i: usize) -> bool {
+        matches!(self.tokens[i].token(), Token::Word(_) | Token::Macro(_)) || self.is(i, ")") || self.is(i, "]")
+    }
+
+    /// Splits a line at its first call that opens and closes on it, with each argument on its own line:
+    /// ```text
+    /// output(format(message, captain: captain), 2)
+    /// ```
+    /// becomes
+    /// ```text
+    /// output(
+    ///     format(message, captain: captain),
+    ///     2
+    /// )
+    /// ```
+    fn wrap(&self, line: &[usize]) -> Option<Vec<Vec<usize>>> {
+        let mut nesting = 0;
+        for (n, &i) in line.iter().enumerate() {
+            match self.text(i) {
+                "(" if nesting == 0 && n > 0 && self.is_callee(line[n - 1]) => {
+                    let close = self.matching(line, n)?;
+                    if close == n + 1 {
+                        return None
+                    }
+
+                    let mut parts = vec![line[..=n].to_vec()];
+                    let mut argument = Vec::new();
+                    let mut inner = 0;
+                    for &j in &line[n + 1..close] {
+                        argument.push(j);
+                        match self.text(j) {
+                            "(" | "[" | "{" => inner += 1,
+                            ")" | "]" | "}" => inner -= 1,
+                            "," if inner == 0 => parts.push(std::mem::take(&mut argument)),
+                            _ => {}
+                        }
+                    }
+                    if !argument.is_empty() {
+                        parts.push(argument);
+                    }
+                    parts.push(line[close..].to_vec());
+                    return Some(parts)
+                }
+                "(" | "[" | "{" => nesting += 1,
+                ")" | "]" | "}" => nesting -= 1,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// position in `line` of the bracket closing the one at `open`
+    fn matching(&self, line: &[usize], open: usize) -> Option<usize> {
+        let mut nesting = 0;
+        for (n, &i) in line.iter().enumerate().skip(open) {
+            match self.text(i) {
+                "(" | "[" | "{" => nesting += 1,
+                ")" | "]" | "}" => {
+                    nesting -= 1;
+                    if nesting == 0 {
+                        return Some(n)
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod format_tests {
+    use crate::es3::{format, ES3Compiler};
+
+    #[test]
+    pub fn test_format() {
+        let source = "#!emberhollow/rooms/docks\n\n\n\nx=1+-2*(3)   // one\nif x>=2{\noutput( \"a\"..x,[1 ,2] )\n  /* block */\n        } else { y = {a:1} }\nmove:#player-><emberhollow:rooms/docks>\n";
+        let expected = "#!emberhollow/rooms/docks\n\nx = 1 + -2 * (3) // one\nif x >= 2 {\n    output(\"a\" .. x, [1, 2])\n    /* block */\n} else { y = { a: 1 } }\nmove: #player -> <emberhollow:rooms/docks>\n";
+        assert_eq!(format(source), expected);
+
+        let long = format!("output(format(\"{}\", captain: captain), random.range(1, 2))", "a".repeat(60));
+        let wrapped = format(&long);
+        assert_eq!(wrapped.lines().count(), 4);
+        assert_eq!(wrapped.lines().nth(1).unwrap(), format!("    format(\"{}\", captain: captain),", "a".repeat(60)));
+        assert_eq!(format(&wrapped), wrapped);
+
+        // broken scripts are left alone
+        assert_eq!(format("x = (1 +"), "x = (1 +");
+    }
+
+    #[test]
+    pub fn test_format_fixture() {
+        let formatted = format(crate::es3::es3_tests::SCRIPT);
+        assert_eq!(format(&formatted), formatted);
+
+        // formatting doesn't change what the script does
+        let compile = |source: &str| {
+            let mut compiler = ES3Compiler::new();
+            compiler.tokenize(source);
+            compiler.parse().unwrap();
+            compiler.compile()
+        };
+        assert_eq!(compile(&formatted), compile(crate::es3::es3_tests::SCRIPT));
+        assert!(formatted.contains("// compiling seems to stop here\n"));
+        assert!(formatted.starts_with("#!emberhollow/rooms/boats/spawn_boat\n#!enter-script\n\n"));
+    }
+}
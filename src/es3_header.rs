@@ -0,0 +1,153 @@
+use serde_json::{json, Value};
+use crate::es3::{PositionedToken, Token};
+use crate::es3_diagnostics::{codes, Diagnostic};
+
+/// The event that runs a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// a player entered the owning room
+    Enter,
+    /// a player left the owning room
+    Exit,
+    /// a player interacted with the owning object
+    Interact,
+    /// every game tick
+    Tick
+}
+
+impl Trigger {
+    pub const ALL: [Trigger; 4] = [Trigger::Enter, Trigger::Exit, Trigger::Interact, Trigger::Tick];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Trigger::Enter => "enter",
+            Trigger::Exit => "exit",
+            Trigger::Interact => "interact",
+            Trigger::Tick => "tick"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Trigger> {
+        Trigger::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+/// What a script belongs to, read from the `#!` lines at its top:
+/// ```text
+/// #!emberhollow/rooms/boats/spawn_boat     the owning object, `namespace/path` or `namespace:path`
+/// #!enter-script once                      the trigger, optionally followed by flags
+/// #!hidden                                 any other single word is a flag
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptHeader {
+    /// id of the owning object, as `namespace:path`
+    pub owner: Option<String>,
+    pub trigger: Option<Trigger>,
+    pub flags: Vec<String>
+}
+
+impl ScriptHeader {
+    /// reads the header from the context tokens of a script, along with any problems with it
+    pub fn parse(tokens: &[PositionedToken]) -> (ScriptHeader, Vec<Diagnostic>) {
+        let mut header = ScriptHeader::default();
+        let mut diagnostics = Vec::new();
+        let mut seen_code = false;
+
+        for token in tokens {
+            let text = match token.token() {
+                Token::Context(text) => text,
+                Token::Comment(_) => continue,
+                _ => {
+                    seen_code = true;
+                    continue
+                }
+            };
+            let span = token.span();
+
+            if seen_code {
+                diagnostics.push(Diagnostic::warning(codes::MISPLACED_HEADER, "context headers must come before any code", span));
+            }
+
+            let content = text[2..].trim_end_matches(';').trim();
+            let mut words = content.split_whitespace();
+            let Some(first) = words.next() else {
+                continue
+            };
+
+            if let Some(kind) = first.strip_suffix("-script") {
+                let Some(trigger) = Trigger::from_name(kind) else {
+                    let expected: Vec<&str> = Trigger::ALL.iter().map(|t| t.name()).collect();
+                    let message = format!("unknown trigger `{}`, expected one of {}", kind, expected.join(", "));
+                    diagnostics.push(Diagnostic::error(codes::UNKNOWN_TRIGGER, message, span));
+                    continue
+                };
+                if header.trigger.replace(trigger).is_some() {
+                    diagnostics.push(Diagnostic::error(codes::DUPLICATE_HEADER, "a script can only have one trigger", span));
+                }
+                header.flags.extend(words.map(str::to_string));
+            }
+            else if first.contains('/') || first.contains(':') {
+                let owner = if first.contains(':') { first.to_string() } else { first.replacen('/', ":", 1) };
+                if header.owner.replace(owner).is_some() {
+                    diagnostics.push(Diagnostic::error(codes::DUPLICATE_HEADER, "a script can only have one owner", span));
+                }
+            }
+            else {
+                header.flags.push(first.to_string());
+                header.flags.extend(words.map(str::to_string));
+            }
+        }
+
+        (header, diagnostics)
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "owner": self.owner,
+            "trigger": self.trigger.map(|t| t.name()),
+            "flags": self.flags
+        })
+    }
+
+    /// reads the header embedded in a compiled script
+    pub fn from_json(value: &Value) -> ScriptHeader {
+        ScriptHeader {
+            owner: value["owner"].as_str().map(str::to_string),
+            trigger: value["trigger"].as_str().and_then(Trigger::from_name),
+            flags: value["flags"].as_array().into_iter().flatten().filter_map(|f| f.as_str()).map(str::to_string).collect()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod header_tests {
+    use crate::es3_diagnostics::codes;
+    use crate::es3_header::{ScriptHeader, Trigger};
+    use crate::es3_lexer::Lexer;
+
+    fn parse(source: &str) -> (ScriptHeader, Vec<&'static str>) {
+        let tokens: Vec<_> = Lexer::new(source).collect();
+        let (header, diagnostics) = ScriptHeader::parse(&tokens);
+        (header, diagnostics.iter().map(|d| d.code).collect())
+    }
+
+    #[test]
+    pub fn test_header() {
+        let (header, codes) = parse("#!emberhollow/rooms/boats/spawn_boat\n// spawn\n#!enter-script once\n#!hidden\nx = 1");
+        assert_eq!(header.owner.as_deref(), Some("emberhollow:rooms/boats/spawn_boat"));
+        assert_eq!(header.trigger, Some(Trigger::Enter));
+        assert_eq!(header.flags, vec!["once", "hidden"]);
+        assert!(codes.is_empty());
+        assert_eq!(ScriptHeader::from_json(&header.to_json()), header);
+
+        let (header, found) = parse("#!leave-script\n#!tick-script; #!exit-script\nx = 1\n#!a:b");
+        assert_eq!(header.trigger, Some(Trigger::Exit));
+        assert_eq!(header.owner.as_deref(), Some("a:b"));
+        assert_eq!(found, vec![codes::UNKNOWN_TRIGGER, codes::DUPLICATE_HEADER, codes::MISPLACED_HEADER]);
+    }
+}
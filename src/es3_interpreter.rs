@@ -0,0 +1,1117 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use serde_json::{json, Value};
+use crate::es3_strings::{parse_template, TemplatePiece};
+
+/// builtin functions and namespaces, available unless a variable shadows them
+pub const BUILTINS: [&str; 5] = ["output", "wait", "format", "length", "random"];
+
+/// how many statements a script may run per `resume` before yielding, so a busy loop can't freeze the game
+const STEP_LIMIT: usize = 10_000;
+
+/// Runtime value of an ES3 expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptValue {
+    None,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<ScriptValue>),
+    Map(BTreeMap<String, ScriptValue>),
+    /// created with `new:`, the object type (`engine:currency`) and its fields
+    Instance(String, BTreeMap<String, ScriptValue>),
+    /// a live entity in the world, by uid
+    Entity(String),
+    /// `<namespace:path>`, such as a room
+    Object(String),
+    /// a builtin function or namespace, like `output` or `random.choice`
+    Builtin(String)
+}
+
+impl ScriptValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ScriptValue::None => "none",
+            ScriptValue::Boolean(_) => "boolean",
+            ScriptValue::Integer(_) => "integer",
+            ScriptValue::Float(_) => "float",
+            ScriptValue::String(_) => "string",
+            ScriptValue::List(_) => "list",
+            ScriptValue::Map(_) => "map",
+            ScriptValue::Instance(..) => "instance",
+            ScriptValue::Entity(_) => "entity",
+            ScriptValue::Object(_) => "object",
+            ScriptValue::Builtin(_) => "builtin"
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            ScriptValue::None => false,
+            ScriptValue::Boolean(b) => *b,
+            ScriptValue::Integer(i) => *i != 0,
+            ScriptValue::Float(f) => *f != 0.0,
+            ScriptValue::String(s) => !s.is_empty(),
+            ScriptValue::List(l) => !l.is_empty(),
+            ScriptValue::Map(m) => !m.is_empty(),
+            _ => true
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            ScriptValue::Integer(i) => Some(*i as f64),
+            ScriptValue::Float(f) => Some(*f),
+            _ => None
+        }
+    }
+
+    /// equality where `1 == 1.0`
+    pub fn equals(&self, other: &ScriptValue) -> bool {
+        match (self.as_float(), other.as_float()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self == other
+        }
+    }
+
+    /// Converts to JSON for saving. Plain values map to JSON directly, the rest are tagged the
+    /// same way compiled scripts are (`{"#entity": uid}`, `{"#map": {...}}`, etc...)
+    pub fn to_json(&self) -> Value {
+        let entries = |fields: &BTreeMap<String, ScriptValue>| Value::Object(fields.iter().map(|(k, v)| (k.clone(), v.to_json())).collect());
+        match self {
+            ScriptValue::List(items) => Value::Array(items.iter().map(|v| v.to_json()).collect()),
+            ScriptValue::Map(fields) => json!({ "#map": entries(fields) }),
+            ScriptValue::Instance(object, fields) => json!({ "#new": object, "data": entries(fields) }),
+            ScriptValue::Entity(uid) => json!({ "#entity": uid }),
+            ScriptValue::Object(id) => json!({ "#object": id }),
+            ScriptValue::Builtin(name) => json!({ "#builtin": name }),
+            ScriptValue::Float(f) => json!(f),
+            ScriptValue::Integer(i) => json!(i),
+            ScriptValue::String(s) => json!(s),
+            ScriptValue::Boolean(b) => json!(b),
+            ScriptValue::None => Value::Null
+        }
+    }
+
+    /// reads a value written by `to_json`
+    pub fn from_json(value: &Value) -> Result<ScriptValue, String> {
+        let entries = |fields: &Value| -> Result<BTreeMap<String, ScriptValue>, String> {
+            fields.as_object().ok_or("expected a map")?.iter().map(|(k, v)| Ok((k.clone(), ScriptValue::from_json(v)?))).collect()
+        };
+        let text = |key: &str| value[key].as_str().map(str::to_string).ok_or(format!("`{}` must be a string", key));
+
+        if let Some(value) = ScriptValue::from_literal(value) {
+            return Ok(value)
+        }
+        match value {
+            Value::Array(items) => Ok(ScriptValue::List(items.iter().map(ScriptValue::from_json).collect::<Result<_, _>>()?)),
+            _ if value.get("#map").is_some() => Ok(ScriptValue::Map(entries(&value["#map"])?)),
+            _ if value.get("#new").is_some() => Ok(ScriptValue::Instance(text("#new")?, entries(&value["data"])?)),
+            _ if value.get("#entity").is_some() => Ok(ScriptValue::Entity(text("#entity")?)),
+            _ if value.get("#object").is_some() => Ok(ScriptValue::Object(text("#object")?)),
+            _ if value.get("#builtin").is_some() => Ok(ScriptValue::Builtin(text("#builtin")?)),
+            _ => Err(format!("`{}` is not a saved value", value))
+        }
+    }
+
+    /// the value of a compiled literal
+    pub fn from_literal(value: &Value) -> Option<ScriptValue> {
+        Some(match value {
+            Value::Null => ScriptValue::None,
+            Value::Bool(b) => ScriptValue::Boolean(*b),
+            Value::Number(n) => n.as_i64().map(ScriptValue::Integer).unwrap_or(ScriptValue::Float(n.as_f64()?)),
+            Value::String(s) => ScriptValue::String(s.clone()),
+            _ => return None
+        })
+    }
+}
+
+impl Display for ScriptValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let join = |items: Vec<String>| items.join(", ");
+        match self {
+            ScriptValue::None => write!(f, "none"),
+            ScriptValue::Boolean(b) => write!(f, "{}", b),
+            ScriptValue::Integer(i) => write!(f, "{}", i),
+            ScriptValue::Float(x) => write!(f, "{}", x),
+            ScriptValue::String(s) => write!(f, "{}", s),
+            ScriptValue::List(l) => write!(f, "[{}]", join(l.iter().map(|v| v.to_string()).collect())),
+            ScriptValue::Map(m) => write!(f, "{{{}}}", join(m.iter().map(|(k, v)| format!("{}: {}", k, v)).collect())),
+            ScriptValue::Instance(object, fields) if object == "engine:currency" => {
+                let coins: Vec<String> = ["gold", "silver", "copper"].iter()
+                    .filter_map(|coin| fields.get(*coin).filter(|v| v.is_truthy()).map(|v| format!("{} {}", v, coin)))
+                    .collect();
+                write!(f, "{}", if coins.is_empty() { "nothing".to_string() } else { join(coins) })
+            }
+            ScriptValue::Instance(object, fields) => {
+                write!(f, "<{}> {{{}}}", object, join(fields.iter().map(|(k, v)| format!("{}: {}", k, v)).collect()))
+            }
+            ScriptValue::Entity(uid) => write!(f, "{}", uid),
+            ScriptValue::Object(id) => write!(f, "<{}>", id),
+            ScriptValue::Builtin(name) => write!(f, "{}", name)
+        }
+    }
+}
+
+/// xorshift64* generator, scripts draw from it through `random.*`.
+/// The same seed always gives the same sequence, so a session can be replayed from its seed
+pub struct Random {
+    seed: u64,
+    state: u64
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        // splitmix64 spreads similar seeds apart, and the state must never be zero
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        Self {
+            seed,
+            state: (z ^ (z >> 31)).max(1)
+        }
+    }
+
+    /// continues a generator from a saved `seed` and `state`
+    pub fn restore(seed: u64, state: u64) -> Self {
+        Self {
+            seed,
+            state: state.max(1)
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// uniform float in `0.0..1.0`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// uniform integer in `low..=high`
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        let span = high.abs_diff(low) as u128 + 1;
+        low.min(high).wrapping_add((self.next_u64() as u128 % span) as i64)
+    }
+}
+
+/// Something in the world scripts can refer to, like a player, the dungeon or an item
+pub struct Entity {
+    pub uid: String,
+    /// object type, such as `engine:player`
+    pub object: String,
+    /// id of the room the entity is in
+    pub room: Option<String>,
+    pub tags: BTreeMap<String, ScriptValue>,
+    pub attributes: BTreeMap<String, ScriptValue>
+}
+
+impl Entity {
+    pub fn new(uid: impl ToString, object: impl ToString) -> Self {
+        Self {
+            uid: uid.to_string(),
+            object: object.to_string(),
+            room: None,
+            tags: BTreeMap::new(),
+            attributes: BTreeMap::new()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorldEvent {
+    /// an entity was moved with `move:`
+    Moved { uid: String, from: Option<String>, to: String }
+}
+
+/// The game state scripts run against
+pub struct ScriptWorld {
+    pub entities: HashMap<String, Entity>,
+    /// uid of the entity `#dungeon` refers to
+    pub dungeon: String,
+    /// lines written with `output`, oldest first
+    pub output: Vec<String>,
+    /// things that happened which the game should react to
+    pub events: Vec<WorldEvent>,
+    pub random: Random
+}
+
+impl ScriptWorld {
+    pub fn new(seed: u64) -> Self {
+        let mut dungeon = Entity::new("dungeon", "engine:dungeon");
+        dungeon.attributes.insert("player_ids".to_string(), ScriptValue::List(Vec::new()));
+
+        let mut entities = HashMap::new();
+        entities.insert(dungeon.uid.clone(), dungeon);
+
+        Self {
+            entities,
+            dungeon: "dungeon".to_string(),
+            output: Vec::new(),
+            events: Vec::new(),
+            random: Random::new(seed)
+        }
+    }
+
+    pub fn add_entity(&mut self, entity: Entity) {
+        self.entities.insert(entity.uid.clone(), entity);
+    }
+
+    pub fn entity(&self, uid: &str) -> Result<&Entity, String> {
+        self.entities.get(uid).ok_or_else(|| format!("entity `{}` does not exist", uid))
+    }
+
+    pub fn entity_mut(&mut self, uid: &str) -> Result<&mut Entity, String> {
+        self.entities.get_mut(uid).ok_or_else(|| format!("entity `{}` does not exist", uid))
+    }
+
+    pub fn move_entity(&mut self, uid: &str, room: &str) -> Result<(), String> {
+        let entity = self.entity_mut(uid)?;
+        let from = entity.room.replace(room.to_string());
+        self.events.push(WorldEvent::Moved { uid: uid.to_string(), from, to: room.to_string() });
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptState {
+    Running,
+    /// suspended by `wait`, with the seconds left
+    Waiting(f64),
+    Finished
+}
+
+/// Something running a script, `ES3Interpreter` for JSON and `ES3VirtualMachine` for bytecode
+pub trait ScriptRunner {
+    fn resume(&mut self, world: &mut ScriptWorld, delta: f64) -> Result<&ScriptState, String>;
+    fn is_finished(&self) -> bool;
+}
+
+enum FrameKind {
+    Block,
+    /// the body of a macro call kept by `ES3Compiler::compile_debug`, with its `#expand` statement
+    Macro(Rc<Value>),
+    While(Rc<Value>),
+    For { variable: String, items: Vec<ScriptValue>, next: usize }
+}
+
+/// a block being run, `pc` is the index of the next statement
+struct Frame {
+    statements: Rc<Vec<Value>>,
+    pc: usize,
+    kind: FrameKind
+}
+
+/// where an assignment or mutating method writes to
+pub enum PlaceRoot {
+    Variable(String),
+    /// an attribute of an entity
+    Attribute(String, String)
+}
+
+pub struct Place {
+    pub root: PlaceRoot,
+    pub path: Vec<ScriptValue>
+}
+
+/// Runs a program compiled by `ES3Compiler::compile`, where macros have already been expanded.
+/// Execution is an explicit stack of frames rather than recursion, so `wait` can suspend the script
+/// between statements and `resume` picks it back up on a later frame
+pub struct ES3Interpreter {
+    frames: Vec<Frame>,
+    variables: HashMap<String, ScriptValue>,
+    /// uid of the entity `#player` refers to
+    pub player: String,
+    /// uid of the entity running the script, which bare `tag$[...]` refers to
+    pub owner: Option<String>,
+    pub state: ScriptState,
+    /// set by `wait` during a statement, the script suspends once the statement finishes
+    pending_wait: Option<f64>
+}
+
+impl ES3Interpreter {
+    pub fn new(program: &Value, player: impl ToString, owner: Option<String>) -> Result<Self, String> {
+        let statements = program["body"].as_array().ok_or("a compiled program must have a list of statements as its body")?;
+
+        Ok(Self {
+            frames: vec![Frame { statements: Rc::new(statements.clone()), pc: 0, kind: FrameKind::Block }],
+            variables: HashMap::new(),
+            player: player.to_string(),
+            owner,
+            state: ScriptState::Running,
+            pending_wait: None
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == ScriptState::Finished
+    }
+
+    pub fn variable(&self, name: &str) -> Option<&ScriptValue> {
+        self.variables.get(name)
+    }
+
+    pub fn variables(&self) -> &HashMap<String, ScriptValue> {
+        &self.variables
+    }
+
+    /// the line of the statement that runs next, for programs compiled by `ES3Compiler::compile_debug`.
+    /// `None` between statements, such as when a loop checks its condition
+    pub fn next_line(&self) -> Option<usize> {
+        let frame = self.frames.last()?;
+        frame.statements.get(frame.pc)?.get("#line")?.as_u64().map(|line| line as usize)
+    }
+
+    /// the `#expand` statement of each macro call being run, outermost first
+    pub fn expansions(&self) -> impl Iterator<Item = &Value> {
+        self.frames.iter().filter_map(|frame| match &frame.kind {
+            FrameKind::Macro(statement) => Some(statement.as_ref()),
+            _ => None
+        })
+    }
+
+    /// Evaluates an expression against the script's variables without running the script, for inspecting it.
+    /// Calls and tag assignments could change the world, so those are refused
+    pub fn inspect(&mut self, expr: &Value, world: &mut ScriptWorld) -> Result<ScriptValue, String> {
+        fn has_effects(expr: &Value) -> bool {
+            match expr {
+                Value::Object(map) => map.contains_key("#call") || (map.contains_key("#tag") && map.contains_key("value")) || map.values().any(has_effects),
+                Value::Array(items) => items.iter().any(has_effects),
+                _ => false
+            }
+        }
+
+        if has_effects(expr) {
+            return Err("not evaluated, it could change the world".to_string())
+        }
+        self.eval(expr, world)
+    }
+
+    /// Runs the script until it waits, finishes or hits the step limit.
+    /// `delta` is the time in seconds since the last call, and counts down any active `wait`.
+    /// A runtime error stops the script for good
+    pub fn resume(&mut self, world: &mut ScriptWorld, delta: f64) -> Result<&ScriptState, String> {
+        self.resume_until(world, delta, |_| false)
+    }
+
+    /// Like `resume`, but also stops right before any step where `pause` returns true, leaving the script `Running`
+    pub fn resume_until(&mut self, world: &mut ScriptWorld, delta: f64, mut pause: impl FnMut(&Self) -> bool) -> Result<&ScriptState, String> {
+        if let ScriptState::Waiting(left) = self.state {
+            if left - delta > 0.0 {
+                self.state = ScriptState::Waiting(left - delta);
+                return Ok(&self.state)
+            }
+            self.state = ScriptState::Running;
+        }
+
+        for _ in 0..STEP_LIMIT {
+            if self.state == ScriptState::Finished || pause(self) {
+                break
+            }
+            if let Err(e) = self.step(world) {
+                self.state = ScriptState::Finished;
+                return Err(e)
+            }
+            if let Some(seconds) = self.pending_wait.take() {
+                self.state = ScriptState::Waiting(seconds);
+                break
+            }
+        }
+
+        Ok(&self.state)
+    }
+
+    /// runs a single statement, or moves on from the end of a block
+    fn step(&mut self, world: &mut ScriptWorld) -> Result<(), String> {
+        let Some(frame) = self.frames.last_mut() else {
+            self.state = ScriptState::Finished;
+            return Ok(())
+        };
+
+        if frame.pc < frame.statements.len() {
+            let (statements, pc) = (frame.statements.clone(), frame.pc);
+            frame.pc += 1;
+            return self.execute(&statements[pc], world)
+        }
+
+        match &mut frame.kind {
+            FrameKind::While(condition) => {
+                let condition = condition.clone();
+                if self.eval(&condition, world)?.is_truthy() {
+                    self.frames.last_mut().unwrap().pc = 0;
+                } else {
+                    self.pop_frame();
+                }
+            }
+            FrameKind::For { variable, items, next } => {
+                if let Some(item) = items.get(*next).cloned() {
+                    let variable = variable.clone();
+                    *next += 1;
+                    frame.pc = 0;
+                    self.store_variable(&variable, item);
+                } else {
+                    self.pop_frame();
+                }
+            }
+            FrameKind::Block | FrameKind::Macro(_) => self.pop_frame()
+        }
+        Ok(())
+    }
+
+    fn push_block(&mut self, body: &Value, kind: FrameKind) {
+        let statements = match body {
+            Value::Array(statements) => statements.clone(),
+            Value::Null => Vec::new(),
+            // `elif` compiles to a nested if rather than a block
+            other => vec![other.clone()]
+        };
+        // loops start at the end of their block so the condition or first item is checked before running it
+        let pc = if matches!(kind, FrameKind::Block | FrameKind::Macro(_)) { 0 } else { statements.len() };
+        self.frames.push(Frame { statements: Rc::new(statements), pc, kind });
+    }
+
+    fn pop_frame(&mut self) {
+        self.frames.pop();
+    }
+
+    fn execute(&mut self, statement: &Value, world: &mut ScriptWorld) -> Result<(), String> {
+        let Some(map) = statement.as_object() else {
+            return self.eval(statement, world).map(|_| ())
+        };
+
+        if map.contains_key("#expand") {
+            self.push_block(&statement["body"], FrameKind::Macro(Rc::new(statement.clone())));
+        }
+        else if let Some(condition) = map.get("#check") {
+            let branch = if self.eval(condition, world)?.is_truthy() { "true" } else { "false" };
+            self.push_block(&statement[branch], FrameKind::Block);
+        }
+        else if let Some(condition) = map.get("#while") {
+            self.push_block(&statement["body"], FrameKind::While(Rc::new(condition.clone())));
+        }
+        else if let Some(variable) = map.get("#for") {
+            let items = loop_items(self.eval(&statement["in"], world)?)?;
+            let variable = variable.as_str().unwrap_or_default().to_string();
+            self.push_block(&statement["body"], FrameKind::For { variable, items, next: 0 });
+        }
+        else if let Some(value) = map.get("#match") {
+            let value = self.eval(value, world)?;
+            let mut body = &statement["default"];
+            for case in statement["cases"].as_array().into_iter().flatten() {
+                if self.eval(&case["case"], world)?.equals(&value) {
+                    body = &case["body"];
+                    break
+                }
+            }
+            self.push_block(body, FrameKind::Block);
+        }
+        else if map.contains_key("#break") || map.contains_key("#continue") {
+            self.unwind_loop(map.contains_key("#break"))?;
+        }
+        else if let Some(target) = map.get("#store") {
+            let value = self.eval(&statement["value"], world)?;
+            self.assign(target, value, world)?;
+        }
+        else if let Some(subject) = map.get("#move") {
+            let subject = self.eval(subject, world)?;
+            let destination = self.eval(&statement["to"], world)?;
+            move_value(subject, destination, world)?;
+        }
+        else {
+            self.eval(statement, world)?;
+        }
+        Ok(())
+    }
+
+    /// leaves blocks up to the innermost loop, leaving that too for `break`
+    fn unwind_loop(&mut self, is_break: bool) -> Result<(), String> {
+        let keyword = if is_break { "break" } else { "continue" };
+        loop {
+            match self.frames.last_mut() {
+                Some(Frame { kind: FrameKind::While(_) | FrameKind::For { .. }, pc, statements }) => {
+                    if is_break {
+                        self.pop_frame();
+                    } else {
+                        *pc = statements.len();
+                    }
+                    return Ok(())
+                }
+                Some(Frame { kind: FrameKind::Block | FrameKind::Macro(_), .. }) => {
+                    self.pop_frame();
+                }
+                _ => return Err(format!("`{}` outside of a loop", keyword))
+            }
+        }
+    }
+
+    fn eval_all(&mut self, values: &Value, world: &mut ScriptWorld) -> Result<Vec<ScriptValue>, String> {
+        values.as_array().into_iter().flatten().map(|v| self.eval(v, world)).collect()
+    }
+
+    fn eval_entries(&mut self, entries: &Value, world: &mut ScriptWorld) -> Result<BTreeMap<String, ScriptValue>, String> {
+        let mut out = BTreeMap::new();
+        for (key, value) in entries.as_object().into_iter().flatten() {
+            out.insert(key.clone(), self.eval(value, world)?);
+        }
+        Ok(out)
+    }
+
+    fn eval(&mut self, expr: &Value, world: &mut ScriptWorld) -> Result<ScriptValue, String> {
+        let Some(map) = expr.as_object() else {
+            return ScriptValue::from_literal(expr).ok_or_else(|| "blocks cannot be used as values".to_string())
+        };
+
+        if let Some(name) = map.get("#ref") {
+            return lookup(&self.variables, name.as_str().unwrap_or_default())
+        }
+        if let Some(name) = map.get("#context") {
+            return context(name.as_str().unwrap_or_default(), &self.player, self.owner.as_deref(), world)
+        }
+        if let Some(id) = map.get("#object") {
+            return Ok(ScriptValue::Object(id.as_str().unwrap_or_default().to_string()))
+        }
+        if let Some(items) = map.get("#list") {
+            return Ok(ScriptValue::List(self.eval_all(items, world)?))
+        }
+        if let Some(entries) = map.get("#map") {
+            return Ok(ScriptValue::Map(self.eval_entries(entries, world)?))
+        }
+        if let Some(object) = map.get("#new") {
+            let fields = match &expr["data"] {
+                Value::Null => BTreeMap::new(),
+                data => self.eval_entries(&data["#map"], world)?
+            };
+            return Ok(ScriptValue::Instance(object.as_str().unwrap_or_default().to_string(), fields))
+        }
+        if let Some(name) = map.get("#attr") {
+            let object = self.eval(&expr["of"], world)?;
+            return get_attribute(&object, name.as_str().unwrap_or_default(), world)
+        }
+        if let Some(index) = map.get("#index") {
+            let object = self.eval(&expr["of"], world)?;
+            let index = self.eval(index, world)?;
+            return get_index(&object, &index, world)
+        }
+        if let Some(name) = map.get("#tag") {
+            return self.tag(expr, name.as_str().unwrap_or_default(), world)
+        }
+        if map.contains_key("#call") {
+            return self.call(expr, world)
+        }
+        if let (Some(left), Some(op)) = (map.get("left"), map.get("op").and_then(|op| op.as_str())) {
+            let left = self.eval(left, world)?;
+            // `and` / `or` only evaluate the right side when they need to
+            match op {
+                "and" if !left.is_truthy() => return Ok(ScriptValue::Boolean(false)),
+                "or" if left.is_truthy() => return Ok(ScriptValue::Boolean(true)),
+                "and" | "or" => return Ok(ScriptValue::Boolean(self.eval(&expr["right"], world)?.is_truthy())),
+                _ => {}
+            }
+            let right = self.eval(&expr["right"], world)?;
+            return binary_op(op, left, right)
+        }
+        if let Some(op) = map.get("op").and_then(|op| op.as_str()) {
+            let value = self.eval(&expr["value"], world)?;
+            return unary_op(op, value)
+        }
+
+        Err(format!("unknown expression `{}`", expr))
+    }
+
+    fn store_variable(&mut self, name: &str, value: ScriptValue) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// reads or sets `of.tag$[name]`, a missing tag reads as `none`
+    fn tag(&mut self, expr: &Value, name: &str, world: &mut ScriptWorld) -> Result<ScriptValue, String> {
+        let uid = match &expr["of"] {
+            Value::Null => self.owner.clone().ok_or_else(|| format!("tag `{}` has no owner to read from", name))?,
+            of => match self.eval(of, world)? {
+                ScriptValue::Entity(uid) => uid,
+                other => return Err(format!("a value of type {} has no tags", other.type_name()))
+            }
+        };
+
+        if let Some(value) = expr.get("value") {
+            let value = self.eval(value, world)?;
+            world.entity_mut(&uid)?.tags.insert(name.to_string(), value);
+            return Ok(ScriptValue::None)
+        }
+        Ok(world.entity(&uid)?.tags.get(name).cloned().unwrap_or(ScriptValue::None))
+    }
+
+    /// resolves an assignment target, evaluating any indices in it
+    fn place(&mut self, target: &Value, world: &mut ScriptWorld) -> Result<Place, String> {
+        if let Some(name) = target.get("#ref").and_then(|n| n.as_str()) {
+            return Ok(Place { root: PlaceRoot::Variable(name.to_string()), path: Vec::new() })
+        }
+
+        let (of, key) = if let Some(name) = target.get("#attr").and_then(|n| n.as_str()) {
+            (&target["of"], ScriptValue::String(name.to_string()))
+        } else if let Some(index) = target.get("#index") {
+            (&target["of"], self.eval(index, world)?)
+        } else {
+            return Err("can only assign to variables, attributes and indices".to_string())
+        };
+
+        if let ScriptValue::Entity(uid) = self.eval(of, world)? {
+            let ScriptValue::String(name) = key else {
+                return Err("entity attributes must be named by strings".to_string())
+            };
+            return Ok(Place { root: PlaceRoot::Attribute(uid, name), path: Vec::new() })
+        }
+
+        let mut place = self.place(of, world)?;
+        place.path.push(key);
+        Ok(place)
+    }
+
+    fn assign(&mut self, target: &Value, value: ScriptValue, world: &mut ScriptWorld) -> Result<(), String> {
+        if target.get("#tag").is_some() {
+            return Err("tags are set with `tag$[name = value]`".to_string())
+        }
+        let place = self.place(target, world)?;
+        *place_mut(&place, &mut self.variables, world, true)? = value;
+        Ok(())
+    }
+
+    fn call(&mut self, expr: &Value, world: &mut ScriptWorld) -> Result<ScriptValue, String> {
+        let callee = &expr["#call"];
+        let args = self.eval_all(&expr["args"], world)?;
+        let kwargs = self.eval_entries(&expr["kwargs"], world)?;
+
+        if let Some(method) = callee.get("#attr").and_then(|m| m.as_str()) {
+            let receiver = self.eval(&callee["of"], world)?;
+            if !matches!(receiver, ScriptValue::Builtin(_)) {
+                return self.call_method(&callee["of"], receiver, method, args, world)
+            }
+        }
+
+        match self.eval(callee, world)? {
+            ScriptValue::Builtin(name) => call_builtin(&name, args, kwargs, world, &mut self.pending_wait),
+            other => Err(format!("a value of type {} cannot be called", other.type_name()))
+        }
+    }
+
+    fn call_method(&mut self, target: &Value, receiver: ScriptValue, method: &str, args: Vec<ScriptValue>, world: &mut ScriptWorld) -> Result<ScriptValue, String> {
+        if let ("append", ScriptValue::List(_)) = (method, &receiver) {
+            let place = self.place(target, world)?;
+            return append(place_mut(&place, &mut self.variables, world, false)?, args)
+        }
+        call_method(receiver, method, args, world)
+    }
+}
+
+impl ScriptRunner for ES3Interpreter {
+    fn resume(&mut self, world: &mut ScriptWorld, delta: f64) -> Result<&ScriptState, String> {
+        ES3Interpreter::resume(self, world, delta)
+    }
+
+    fn is_finished(&self) -> bool {
+        ES3Interpreter::is_finished(self)
+    }
+}
+
+/// the variable `name`, or the builtin of that name if no variable shadows it
+pub fn lookup(variables: &HashMap<String, ScriptValue>, name: &str) -> Result<ScriptValue, String> {
+    variables.get(name)
+        .cloned()
+        .or_else(|| BUILTINS.contains(&name).then(|| ScriptValue::Builtin(name.to_string())))
+        .ok_or_else(|| format!("`{}` is not defined", name))
+}
+
+/// the entity `#name` refers to for a script run by `player` and owned by `owner`
+pub fn context(name: &str, player: &str, owner: Option<&str>, world: &ScriptWorld) -> Result<ScriptValue, String> {
+    match name {
+        "player" => Ok(ScriptValue::Entity(player.to_string())),
+        "dungeon" => Ok(ScriptValue::Entity(world.dungeon.clone())),
+        "self" => owner.map(|o| ScriptValue::Entity(o.to_string())).ok_or_else(|| "`#self` is only available in owned scripts".to_string()),
+        other => Err(format!("unknown context `#{}`", other))
+    }
+}
+
+pub fn get_attribute(object: &ScriptValue, name: &str, world: &ScriptWorld) -> Result<ScriptValue, String> {
+    let missing = || format!("{} has no attribute `{}`", object.type_name(), name);
+    match object {
+        ScriptValue::Entity(uid) => {
+            let entity = world.entity(uid)?;
+            match name {
+                "uid" => Ok(ScriptValue::String(entity.uid.clone())),
+                "room" => Ok(entity.room.clone().map(ScriptValue::Object).unwrap_or(ScriptValue::None)),
+                _ => entity.attributes.get(name).cloned().ok_or_else(missing)
+            }
+        }
+        ScriptValue::Map(fields) | ScriptValue::Instance(_, fields) => fields.get(name).cloned().ok_or_else(missing),
+        ScriptValue::Builtin(namespace) if namespace == "random" && (name == "choice" || name == "range") => {
+            Ok(ScriptValue::Builtin(format!("random.{}", name)))
+        }
+        _ => Err(missing())
+    }
+}
+
+pub fn get_index(object: &ScriptValue, index: &ScriptValue, world: &ScriptWorld) -> Result<ScriptValue, String> {
+    match (object, index) {
+        (ScriptValue::List(items), ScriptValue::Integer(i)) => {
+            items.get(*i as usize).cloned().ok_or_else(|| format!("index {} is out of range for a list of length {}", i, items.len()))
+        }
+        (ScriptValue::String(s), ScriptValue::Integer(i)) => {
+            s.chars().nth(*i as usize).map(|c| ScriptValue::String(c.to_string())).ok_or_else(|| format!("index {} is out of range", i))
+        }
+        (_, ScriptValue::String(key)) => get_attribute(object, key, world),
+        _ => Err(format!("cannot index a value of type {} with {}", object.type_name(), index.type_name()))
+    }
+}
+
+/// the items a `for` loop over `value` goes through
+pub fn loop_items(value: ScriptValue) -> Result<Vec<ScriptValue>, String> {
+    match value {
+        ScriptValue::List(items) => Ok(items),
+        ScriptValue::Map(map) => Ok(map.into_keys().map(ScriptValue::String).collect()),
+        ScriptValue::String(s) => Ok(s.chars().map(|c| ScriptValue::String(c.to_string())).collect()),
+        other => Err(format!("cannot loop over a value of type {}", other.type_name()))
+    }
+}
+
+/// `move: subject -> destination`, where the destination is a room or an entity whose room to move to
+pub fn move_value(subject: ScriptValue, destination: ScriptValue, world: &mut ScriptWorld) -> Result<(), String> {
+    let uid = match subject {
+        ScriptValue::Entity(uid) => uid,
+        other => return Err(format!("cannot move a value of type {}", other.type_name()))
+    };
+    let room = match destination {
+        ScriptValue::Object(id) => id,
+        ScriptValue::Entity(uid) => world.entity(&uid)?.room.clone().ok_or_else(|| format!("entity `{}` is not in a room", uid))?,
+        other => return Err(format!("cannot move to a value of type {}", other.type_name()))
+    };
+    world.move_entity(&uid, &room)
+}
+
+/// the value a place refers to, creating map entries and variables when `create` is set
+pub fn place_mut<'a>(place: &Place, variables: &'a mut HashMap<String, ScriptValue>, world: &'a mut ScriptWorld, create: bool) -> Result<&'a mut ScriptValue, String> {
+    let mut value = match &place.root {
+        PlaceRoot::Variable(name) => {
+            if create {
+                variables.entry(name.clone()).or_insert(ScriptValue::None)
+            } else {
+                variables.get_mut(name).ok_or_else(|| format!("`{}` is not defined", name))?
+            }
+        }
+        PlaceRoot::Attribute(uid, name) => {
+            let attributes = &mut world.entity_mut(uid)?.attributes;
+            if create {
+                attributes.entry(name.clone()).or_insert(ScriptValue::None)
+            } else {
+                attributes.get_mut(name).ok_or_else(|| format!("entity has no attribute `{}`", name))?
+            }
+        }
+    };
+
+    for key in &place.path {
+        value = match (value, key) {
+            (ScriptValue::List(items), ScriptValue::Integer(i)) => {
+                let length = items.len();
+                items.get_mut(*i as usize).ok_or_else(|| format!("index {} is out of range for a list of length {}", i, length))?
+            }
+            (ScriptValue::Map(fields) | ScriptValue::Instance(_, fields), key) => {
+                let key = key.to_string();
+                if create {
+                    fields.entry(key).or_insert(ScriptValue::None)
+                } else {
+                    fields.get_mut(&key).ok_or_else(|| format!("map has no key `{}`", key))?
+                }
+            }
+            (value, key) => return Err(format!("cannot index a value of type {} with {}", value.type_name(), key.type_name()))
+        };
+    }
+    Ok(value)
+}
+
+/// `list.append(args...)`, on the list stored at a place
+pub fn append(list: &mut ScriptValue, args: Vec<ScriptValue>) -> Result<ScriptValue, String> {
+    if let ScriptValue::List(items) = list {
+        items.extend(args);
+    }
+    Ok(ScriptValue::None)
+}
+
+/// calls a method that doesn't change its receiver, `append` is done through `append` since it needs to know where the list is stored
+pub fn call_method(receiver: ScriptValue, method: &str, args: Vec<ScriptValue>, world: &mut ScriptWorld) -> Result<ScriptValue, String> {
+    match (method, &receiver) {
+        ("to_string", _) => Ok(ScriptValue::String(receiver.to_string())),
+        ("give_money", ScriptValue::Entity(uid)) => {
+            let Some(ScriptValue::Instance(_, coins)) = args.first() else {
+                return Err("`give_money` takes a `<engine:currency>`".to_string())
+            };
+            let money = world.entity_mut(uid)?.attributes.entry("money".to_string())
+                .or_insert_with(|| ScriptValue::Instance("engine:currency".to_string(), BTreeMap::new()));
+            if let ScriptValue::Instance(_, owned) = money {
+                for (coin, amount) in coins {
+                    let total = binary_op("+", owned.get(coin).cloned().unwrap_or(ScriptValue::Integer(0)), amount.clone())?;
+                    owned.insert(coin.clone(), total);
+                }
+            }
+            Ok(ScriptValue::None)
+        }
+        _ => Err(format!("{} has no method `{}`", receiver.type_name(), method))
+    }
+}
+
+/// calls a builtin function, `wait` sets `pending_wait` to the seconds to wait for
+pub fn call_builtin(name: &str, args: Vec<ScriptValue>, kwargs: BTreeMap<String, ScriptValue>, world: &mut ScriptWorld, pending_wait: &mut Option<f64>) -> Result<ScriptValue, String> {
+    match name {
+        "output" => {
+            world.output.push(args.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(" "));
+            Ok(ScriptValue::None)
+        }
+        "wait" => {
+            let seconds = args.first().and_then(|a| a.as_float()).ok_or("`wait` takes a number of seconds")?;
+            *pending_wait = Some(seconds.max(0.0));
+            Ok(ScriptValue::None)
+        }
+        "format" => {
+            let Some(ScriptValue::String(template)) = args.first() else {
+                return Err("`format` takes a string to format".to_string())
+            };
+            format_string(template, &args[1..], &kwargs).map(ScriptValue::String)
+        }
+        "length" => match args.first() {
+            Some(ScriptValue::List(l)) => Ok(ScriptValue::Integer(l.len() as i64)),
+            Some(ScriptValue::Map(m)) => Ok(ScriptValue::Integer(m.len() as i64)),
+            Some(ScriptValue::String(s)) => Ok(ScriptValue::Integer(s.chars().count() as i64)),
+            other => Err(format!("cannot take the length of {}", other.map_or("nothing", |v| v.type_name())))
+        },
+        "random.choice" => {
+            let options = match args.as_slice() {
+                [ScriptValue::List(items)] => items.clone(),
+                _ => args
+            };
+            if options.is_empty() {
+                return Err("`random.choice` needs at least one option".to_string())
+            }
+            let i = world.random.range(0, options.len() as i64 - 1) as usize;
+            Ok(options[i].clone())
+        }
+        "random.range" => match args.as_slice() {
+            [ScriptValue::Integer(low), ScriptValue::Integer(high)] => Ok(ScriptValue::Integer(world.random.range(*low, *high))),
+            [low, high] if low.as_float().is_some() && high.as_float().is_some() => {
+                let (low, high) = (low.as_float().unwrap(), high.as_float().unwrap());
+                Ok(ScriptValue::Float(low + (high - low) * world.random.next_f64()))
+            }
+            _ => Err("`random.range` takes a low and high number".to_string())
+        },
+        _ => Err(format!("`{}` cannot be called", name))
+    }
+}
+
+pub fn unary_op(op: &str, value: ScriptValue) -> Result<ScriptValue, String> {
+    match (op, value) {
+        ("not", value) => Ok(ScriptValue::Boolean(!value.is_truthy())),
+        ("-", ScriptValue::Integer(i)) => i.checked_neg().map(ScriptValue::Integer).ok_or_else(|| "`-` overflowed".to_string()),
+        ("-", ScriptValue::Float(f)) => Ok(ScriptValue::Float(-f)),
+        (op, value) => Err(format!("cannot apply `{}` to a value of type {}", op, value.type_name()))
+    }
+}
+
+pub fn binary_op(op: &str, left: ScriptValue, right: ScriptValue) -> Result<ScriptValue, String> {
+    use ScriptValue::*;
+
+    let mismatch = |left: &ScriptValue, right: &ScriptValue| {
+        format!("cannot apply `{}` to values of type {} and {}", op, left.type_name(), right.type_name())
+    };
+
+    match op {
+        "==" => return Ok(Boolean(left.equals(&right))),
+        "!=" => return Ok(Boolean(!left.equals(&right))),
+        ".." => return Ok(String(format!("{}{}", left, right))),
+        "::" => return match (left, right) {
+            (List(mut a), List(b)) => {
+                a.extend(b);
+                Ok(List(a))
+            }
+            (left, right) => Err(mismatch(&left, &right))
+        },
+        "<" | ">" | "<=" | ">=" => {
+            let ordering = match (&left, &right) {
+                (String(a), String(b)) => a.partial_cmp(b),
+                _ => match (left.as_float(), right.as_float()) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    _ => return Err(mismatch(&left, &right))
+                }
+            };
+            let Some(ordering) = ordering else {
+                return Ok(Boolean(false))
+            };
+            return Ok(Boolean(match op {
+                "<" => ordering.is_lt(),
+                ">" => ordering.is_gt(),
+                "<=" => ordering.is_le(),
+                _ => ordering.is_ge()
+            }))
+        }
+        _ => {}
+    }
+
+    match (&left, &right) {
+        (Integer(a), Integer(b)) => {
+            let (a, b) = (*a, *b);
+            if (op == "/" || op == "%") && b == 0 {
+                return Err("division by zero".to_string())
+            }
+            let result = match op {
+                "+" => a.checked_add(b),
+                "-" => a.checked_sub(b),
+                "*" => a.checked_mul(b),
+                "/" => a.checked_div(b),
+                "%" => a.checked_rem(b),
+                _ => return Err(mismatch(&left, &right))
+            };
+            result.map(Integer).ok_or_else(|| format!("`{}` overflowed", op))
+        }
+        _ => match (left.as_float(), right.as_float()) {
+            (Some(a), Some(b)) => Ok(Float(match op {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" => a / b,
+                "%" => a % b,
+                _ => return Err(mismatch(&left, &right))
+            })),
+            _ => Err(mismatch(&left, &right))
+        }
+    }
+}
+
+/// replaces `{name}` with keyword arguments and `{0}` with positional ones, laid out by their `{name:spec}`, see `parse_template`
+fn format_string(template: &str, args: &[ScriptValue], kwargs: &BTreeMap<String, ScriptValue>) -> Result<String, String> {
+    let (pieces, errors) = parse_template(template, false);
+    if let Some((_, message)) = errors.into_iter().next() {
+        return Err(message)
+    }
+
+    let mut out = String::new();
+    for piece in pieces {
+        match piece {
+            TemplatePiece::Text(text) => out += &text,
+            TemplatePiece::Placeholder(placeholder) => {
+                let value = match placeholder.name.parse::<usize>() {
+                    Ok(i) => args.get(i),
+                    Err(_) => kwargs.get(&placeholder.name)
+                };
+                let value = value.ok_or_else(|| format!("unknown placeholder `{{{}}}` in format string", placeholder.name))?;
+                let float = match value {
+                    ScriptValue::Float(f) => Some(*f),
+                    _ => None
+                };
+                out += &placeholder.spec.apply(&value.to_string(), float, value.as_float().is_some());
+            }
+        }
+    }
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod interpreter_tests {
+    use crate::es3::ES3Compiler;
+    use crate::es3_interpreter::{ES3Interpreter, Entity, ScriptState, ScriptValue, ScriptWorld, WorldEvent, Random, unary_op};
+
+    fn start(source: &str, world: &mut ScriptWorld) -> ES3Interpreter {
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize(source);
+        compiler.parse().unwrap();
+        world.add_entity(Entity::new("p1", "engine:player"));
+        ES3Interpreter::new(&compiler.compile(), "p1", None).unwrap()
+    }
+
+    #[test]
+    pub fn test_run_script() {
+        let mut world = ScriptWorld::new(7);
+        let mut script = start(r#"
+#dungeon.player_ids.append(#player.uid)
+#player.tag$[listening = true]
+#player.tag$["'quoted'" = 1]
+$listening = #player.tag$[listening]
+
+$out($message, $wait_time) {
+    if ($listening) {
+        output(format($message, who: "the captain"))
+        wait($wait_time)
+    }
+}
+
+total = 0
+for i in [1, 2, 3, 4] {
+    if i == 3 { continue }
+    total = total + i
+}
+$out("{who} says hi", 2)
+output(total, length(#dungeon.player_ids))
+
+money = new: <engine:currency> { gold: random.range(9, 11), silver: 0 }
+#player.give_money(money)
+move: #player -> <emberhollow:rooms/docks/roads/road_4>
+"#, &mut world);
+
+        assert_eq!(script.resume(&mut world, 0.0), Ok(&ScriptState::Waiting(2.0)));
+        assert_eq!(world.output, vec!["the captain says hi"]);
+
+        // the wait counts down across calls
+        assert_eq!(script.resume(&mut world, 1.5), Ok(&ScriptState::Waiting(0.5)));
+        assert_eq!(world.output.len(), 1);
+        assert_eq!(script.resume(&mut world, 0.5), Ok(&ScriptState::Finished));
+
+        assert_eq!(world.output, vec!["the captain says hi", "7 1"]);
+        assert_eq!(script.variable("total"), Some(&ScriptValue::Integer(7)));
+
+        let player = &world.entities["p1"];
+        assert_eq!(player.room.as_deref(), Some("emberhollow:rooms/docks/roads/road_4"));
+        assert_eq!(player.tags["listening"], ScriptValue::Boolean(true));
+        assert_eq!(player.tags["'quoted'"], ScriptValue::Integer(1));
+        let ScriptValue::Instance(_, coins) = &player.attributes["money"] else { panic!() };
+        assert!(matches!(coins["gold"], ScriptValue::Integer(9..=11)));
+        assert_eq!(world.events, vec![WorldEvent::Moved { uid: "p1".to_string(), from: None, to: "emberhollow:rooms/docks/roads/road_4".to_string() }]);
+    }
+
+    #[test]
+    pub fn test_runtime_errors() {
+        let mut world = ScriptWorld::new(0);
+        let mut script = start("x = 1\nwhile true { x = x + 1\nif x > 5 { break } }\ny = x / 0\noutput(x)", &mut world);
+
+        assert_eq!(script.resume(&mut world, 0.0), Err("division by zero".to_string()));
+        assert!(script.is_finished());
+        assert_eq!(script.variable("x"), Some(&ScriptValue::Integer(6)));
+        assert!(world.output.is_empty());
+
+        let mut script = start("output(missing)", &mut world);
+        assert_eq!(script.resume(&mut world, 0.0), Err("`missing` is not defined".to_string()));
+
+        assert_eq!(unary_op("-", ScriptValue::Integer(i64::MIN)), Err("`-` overflowed".to_string()));
+        assert_eq!(unary_op("-", ScriptValue::Integer(i64::MAX)), Ok(ScriptValue::Integer(-i64::MAX)));
+    }
+
+    #[test]
+    pub fn test_random_range() {
+        let mut random = Random::new(5);
+        for _ in 0..100 {
+            assert!(random.range(-1, i64::MAX) >= -1);
+            assert!((9..=11).contains(&random.range(11, 9)));
+        }
+        let _ = random.range(i64::MIN, i64::MAX);
+        assert_eq!(random.range(i64::MIN, i64::MIN), i64::MIN);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::es3::{style_flags, PositionedToken, Token};
+
+pub const KEYWORDS: [&str; 16] = [
+    "if", "elif", "else", "while", "for", "in", "and", "not", "or", "none", "match", "case", "class", "def", "break", "continue"
+];
+
+pub const COMMANDS: [&str; 2] = ["new", "move"];
+
+const LITERALS: &[u8] = b"=-+*/()&[]{},#%:|^.$;~`";
+
+/// Single-pass ES3 lexer.
+/// At every position the first rule that matches wins, in this order:
+/// ```text
+/// COMMENT     //...  and  /* ... */
+/// CONTEXT     #!...  up to a newline or `;` (inclusive)
+/// STRING      "..." or '...', may span lines, `\` escapes the next character
+/// TAG         =>  followed by anything up to whitespace
+/// MACRO       $name
+/// OBJECT      <...>  with no whitespace or angle brackets inside
+/// COMP        <= >= < > == !=
+/// CONCAT      .. ::
+/// WORD        name, turned into BOOLEAN / COMMAND / KEYWORD when it is one of those
+/// NUMBER      1, 1.5, .5
+/// LITERAL     any of =-+*/()&[]{},#%:|^.$;~`
+/// ```
+/// Spaces, tabs and newlines are skipped, anything else becomes a `Token::Error`
+pub struct Lexer<'a> {
+    input: &'a str,
+    bytes: &'a [u8],
+    idx: usize,
+    line: usize,
+    column: usize
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self::starting_at(input, 0, 1, 0)
+    }
+
+    /// starts lexing from byte `idx`, which must be at `line`/`column` of `input`
+    pub fn starting_at(input: &'a str, idx: usize, line: usize, column: usize) -> Self {
+        Self {
+            input,
+            bytes: input.as_bytes(),
+            idx,
+            line,
+            column
+        }
+    }
+
+    fn at(&self, offset: usize) -> u8 {
+        *self.bytes.get(self.idx + offset).unwrap_or(&0)
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.input[self.idx..].starts_with(s)
+    }
+
+    fn is_word_start(b: u8) -> bool {
+        b.is_ascii_alphabetic() || b == b'_'
+    }
+
+    fn is_word_char(b: u8) -> bool {
+        b.is_ascii_alphanumeric() || b == b'_'
+    }
+
+    /// length of the run of bytes from the current position matching `f`, starting `offset` bytes in
+    fn run(&self, offset: usize, f: impl Fn(u8) -> bool) -> usize {
+        self.bytes[(self.idx + offset).min(self.bytes.len())..].iter().take_while(|b| f(**b)).count()
+    }
+
+    /// length of a closed string literal at the current position, if there is one
+    fn string_length(&self) -> Option<usize> {
+        let quote = self.at(0);
+        let mut i = 1;
+        while let Some(&b) = self.bytes.get(self.idx + i) {
+            if b == b'\\' {
+                // the escaped character may be multi-byte, skip all of it
+                i += 1 + self.input[self.idx + i + 1..].chars().next().map(|c| c.len_utf8()).unwrap_or(0);
+                continue
+            }
+            if b == quote {
+                return Some(i + 1)
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// length of a closed block comment at the current position, if there is one
+    fn block_comment_length(&self) -> Option<usize> {
+        self.input[self.idx + 2..].find("*/").map(|end| end + 4)
+    }
+
+    fn object_length(&self) -> Option<usize> {
+        let inner = self.run(1, |b| b != b'<' && b != b'>' && !b.is_ascii_whitespace());
+        (inner > 0 && self.at(inner + 1) == b'>').then_some(inner + 2)
+    }
+
+    /// classifies the token at the current position, returning it with its length in bytes
+    fn classify(&self) -> (Token, usize) {
+        let b = self.at(0);
+        let next = self.at(1);
+        let text = |len: usize| self.input[self.idx..self.idx + len].to_string();
+
+        if self.starts_with("//") {
+            let len = self.run(0, |b| b != b'\n');
+            return (Token::Comment(text(len)), len)
+        }
+        if self.starts_with("/*") && (self.idx == 0 || self.bytes[self.idx - 1] != b'/') {
+            if let Some(len) = self.block_comment_length() {
+                return (Token::Comment(text(len)), len)
+            }
+        }
+        if self.starts_with("#!") {
+            let mut len = self.run(0, |b| b != b'\n' && b != b';');
+            if self.at(len) == b';' {
+                len += 1;
+            }
+            return (Token::Context(text(len)), len)
+        }
+        if b == b'"' || b == b'\'' {
+            if let Some(len) = self.string_length() {
+                return (Token::String(text(len)), len)
+            }
+        }
+        if self.starts_with("=>") {
+            let len = self.run(0, |b| !b.is_ascii_whitespace());
+            return (Token::Tag(text(len)), len)
+        }
+        if b == b'$' && Self::is_word_start(next) {
+            let len = 1 + self.run(1, Self::is_word_char);
+            return (Token::Macro(text(len)), len)
+        }
+        if b == b'<' {
+            if let Some(len) = self.object_length() {
+                return (Token::Object(text(len)), len)
+            }
+        }
+        if matches!(b, b'<' | b'>' | b'=' | b'!') && next == b'=' {
+            return (Token::Comparison(text(2)), 2)
+        }
+        if b == b'<' || b == b'>' {
+            return (Token::Comparison(text(1)), 1)
+        }
+        if (b == b'.' && next == b'.') || (b == b':' && next == b':') {
+            return (Token::Literal(text(2)), 2)
+        }
+        if Self::is_word_start(b) {
+            let len = self.run(0, Self::is_word_char);
+            let word = text(len);
+            let token = match word.as_str() {
+                "true" | "false" => Token::Boolean(word == "true"),
+                w if COMMANDS.contains(&w) => Token::Command(word),
+                w if KEYWORDS.contains(&w) => Token::Keyword(word),
+                _ => Token::Word(word)
+            };
+            return (token, len)
+        }
+        if b.is_ascii_digit() || (b == b'.' && next.is_ascii_digit()) {
+            let mut len = self.run(0, |b| b.is_ascii_digit());
+            if b == b'.' || (self.at(len) == b'.' && self.at(len + 1).is_ascii_digit()) {
+                len += 1 + self.run(len + 1, |b| b.is_ascii_digit());
+                return (Token::Float(text(len).parse::<f64>().unwrap()), len)
+            }
+            // too large for an integer, reported by the compiler
+            return (text(len).parse::<i64>().map_or(Token::Error(text(len)), Token::Integer), len)
+        }
+        if LITERALS.contains(&b) {
+            return (Token::Literal(text(1)), 1)
+        }
+
+        let len = self.input[self.idx..].chars().next().unwrap().len_utf8();
+        (Token::Error(text(len)), len)
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = PositionedToken;
+
+    fn next(&mut self) -> Option<PositionedToken> {
+        loop {
+            match self.at(0) {
+                b' ' | b'\t' => {
+                    self.idx += 1;
+                    self.column += 1;
+                }
+                b'\n' => {
+                    self.idx += 1;
+                    self.line += 1;
+                    self.column = 0;
+                }
+                _ => break
+            }
+        }
+        if self.idx >= self.bytes.len() {
+            return None
+        }
+
+        let (token, len) = self.classify();
+        let (idx, line, column) = (self.idx, self.line, self.column);
+        let is_error = matches!(token, Token::Error(_));
+
+        match &token {
+            Token::String(s) | Token::Comment(s) if s.contains('\n') => {
+                self.line += s.matches('\n').count();
+                self.column = s.rsplit_once('\n').unwrap().1.len();
+            }
+            _ => {
+                self.column += len;
+            }
+        }
+        self.idx += len;
+
+        let mut out = PositionedToken::new(token, idx, line, column, len);
+        if is_error {
+            out.style_mut().set_flag(style_flags::ERROR, true);
+        }
+
+        Some(out)
+    }
+}
+
+
+#[cfg(test)]
+mod lexer_tests {
+    use crate::es3::{PositionedToken, Token};
+    use crate::es3_lexer::Lexer;
+
+    #[test]
+    pub fn test_rules() {
+        let tokens: Vec<Token> = Lexer::new("x<=.5 <engine:currency> 'a\\'b' /* c */ $m(1.25) =>k ::").map(|t| t.token().clone()).collect();
+        let expected = "[Word(\"x\"), Comparison(\"<=\"), Float(0.5), Object(\"<engine:currency>\"), String(\"'a\\\\'b'\"), \
+            Comment(\"/* c */\"), Macro(\"$m\"), Literal(\"(\"), Float(1.25), Literal(\")\"), Tag(\"=>k\"), Literal(\"::\")]";
+        assert_eq!(format!("{:?}", tokens), expected);
+
+        // unterminated strings and comments fall back to single characters
+        let tokens: Vec<Token> = Lexer::new("\"ab /*").map(|t| t.token().clone()).collect();
+        assert_eq!(format!("{:?}", tokens), "[Error(\"\\\"\"), Word(\"ab\"), Literal(\"/\"), Literal(\"*\")]");
+    }
+
+    #[test]
+    pub fn test_positions() {
+        // block comments spanning lines move the tokens after them down, like strings do
+        let tokens: Vec<PositionedToken> = Lexer::new("/* a\nb */ x\ny = ]").collect();
+        let positions: Vec<(usize, usize)> = tokens.iter().map(|t| (t.line(), t.column())).collect();
+        assert_eq!(positions, [(1, 0), (2, 5), (3, 0), (3, 2), (3, 4)]);
+
+        // integers that don't fit become errors instead of panicking
+        let tokens: Vec<Token> = Lexer::new("x = 99999999999999999999 9223372036854775807").map(|t| t.token().clone()).collect();
+        assert_eq!(tokens[2], Token::Error("99999999999999999999".to_string()));
+        assert_eq!(tokens[3], Token::Integer(i64::MAX));
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::es3::{CallNode, MacroCallNode, MacroDefNode, Node, StatementsNode, StringNode, VariableNode};
+use crate::es3_diagnostics::{codes, Diagnostic, Span};
+
+/// Decodes a string literal as written, quotes included, into its value.
+/// Either quote style works and strings may span lines, keeping their line breaks (`\r\n` becomes `\n`).
+/// Escapes:
+/// ```text
+/// \n \t \r \0     newline, tab, carriage return, null
+/// \\ \" \'        backslash and quotes
+/// \u{1F600}       the unicode character with that hex code
+/// \ at line end   joins the lines, dropping the line break and the next line's indentation
+/// ```
+/// Returns the value, with each bad escape replaced by what follows the `\`, and the byte range in `raw`
+/// and message of each bad escape
+pub fn decode_string(raw: &str) -> (String, Vec<(Range<usize>, String)>) {
+    let body = &raw[1..raw.len().saturating_sub(1).max(1)];
+    let mut out = String::new();
+    let mut errors = Vec::new();
+    let mut chars = body.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '\r' && chars.peek().is_some_and(|(_, n)| *n == '\n') {
+            continue
+        }
+        if c != '\\' {
+            out.push(c);
+            continue
+        }
+        let Some((_, escaped)) = chars.next() else {
+            break
+        };
+        match escaped {
+            'n' => out.push('\n'),
+            't' => out.push('\t'),
+            'r' => out.push('\r'),
+            '0' => out.push('\0'),
+            '\\' | '"' | '\'' => out.push(escaped),
+            '\n' | '\r' => {
+                while chars.peek().is_some_and(|(_, c)| matches!(c, ' ' | '\t' | '\n')) {
+                    chars.next();
+                }
+            }
+            'u' => {
+                let end = match body[i..].find('}') {
+                    Some(end) if body[i + 2..].starts_with('{') => i + end + 1,
+                    _ => {
+                        errors.push((i + 1..i + 3, "expected a hex code in braces after `\\u`, like `\\u{1F600}`".to_string()));
+                        out.push('u');
+                        continue
+                    }
+                };
+                let code = &body[i + 3..end - 1];
+                match u32::from_str_radix(code, 16).ok().and_then(char::from_u32) {
+                    Some(c) => out.push(c),
+                    None => errors.push((i + 1..end + 1, format!("`{}` is not a unicode character", code)))
+                }
+                while chars.peek().is_some_and(|(j, _)| *j < end) {
+                    chars.next();
+                }
+            }
+            other => {
+                let length = 1 + other.len_utf8();
+                errors.push((i + 1..i + 1 + length, format!("unknown escape `\\{}`", other)));
+                out.push(other);
+            }
+        }
+    }
+    (out, errors)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Center
+}
+
+/// How a placeholder's value is laid out: `[[fill]align][width][.precision]`, like `{money:>8}` or `{chance:.2}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSpec {
+    pub fill: char,
+    /// defaults to right for numbers and left for everything else
+    pub align: Option<Align>,
+    pub width: usize,
+    /// digits after the point for floats, characters kept for everything else
+    pub precision: Option<usize>
+}
+
+impl Default for FormatSpec {
+    fn default() -> Self {
+        Self { fill: ' ', align: None, width: 0, precision: None }
+    }
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid format spec `{}`, expected `[[fill]align][width][.precision]`", spec);
+        let align = |c: char| match c {
+            '<' => Some(Align::Left),
+            '>' => Some(Align::Right),
+            '^' => Some(Align::Center),
+            _ => None
+        };
+
+        let mut out = FormatSpec::default();
+        let mut chars = spec.chars();
+        let rest = match (chars.next(), chars.next()) {
+            (Some(fill), Some(a)) if align(a).is_some() => {
+                (out.fill, out.align) = (fill, align(a));
+                chars.as_str()
+            }
+            (Some(a), _) if align(a).is_some() => {
+                out.align = align(a);
+                &spec[1..]
+            }
+            _ => spec
+        };
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (rest, None)
+        };
+        if !width.is_empty() {
+            out.width = width.parse().map_err(|_| invalid())?;
+        }
+        if let Some(precision) = precision {
+            out.precision = Some(precision.parse().map_err(|_| invalid())?);
+        }
+        Ok(out)
+    }
+
+    /// lays out `text`, the value as text. `float` is the value if it's a float, `number` whether it's any number
+    pub fn apply(&self, text: &str, float: Option<f64>, number: bool) -> String {
+        let text = match (self.precision, float) {
+            (Some(precision), Some(f)) => format!("{:.*}", precision, f),
+            (Some(precision), None) if !number => text.chars().take(precision).collect(),
+            _ => text.to_string()
+        };
+        let padding = self.width.saturating_sub(text.chars().count());
+        let align = self.align.unwrap_or(if number { Align::Right } else { Align::Left });
+        let (before, after) = match align {
+            Align::Left => (0, padding),
+            Align::Right => (padding, 0),
+            Align::Center => (padding / 2, padding - padding / 2)
+        };
+        let fill = |n: usize| self.fill.to_string().repeat(n);
+        fill(before) + &text + &fill(after)
+    }
+}
+
+/// `{name}` or `{name:spec}` in a format string, `name` being a named argument or the index of a positional one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    pub name: String,
+    pub spec: FormatSpec,
+    /// bytes of the template it covers, braces included
+    pub range: Range<usize>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplatePiece {
+    Text(String),
+    Placeholder(Placeholder)
+}
+
+/// Splits a `format` template into text and placeholders, `{{` and `}}` being literal braces.
+/// With `escaped`, `template` is the inside of a string literal as written and its `\` escapes are stepped over,
+/// so the placeholders found are the same as in the decoded string.
+/// Returns the byte range and message of every malformed placeholder along with the pieces
+pub fn parse_template(template: &str, escaped: bool) -> (Vec<TemplatePiece>, Vec<(Range<usize>, String)>) {
+    let mut pieces = Vec::new();
+    let mut errors = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|(_, n)| *n);
+        match c {
+            '\\' if escaped => {
+                text.push(c);
+                // `\u{...}` braces aren't placeholders
+                let end = if next == Some('u') && template[i + 2..].starts_with('{') {
+                    template[i..].find('}').map_or(i + 2, |e| i + e + 1)
+                } else {
+                    i + 1 + next.map_or(0, char::len_utf8)
+                };
+                while chars.peek().is_some_and(|(j, _)| *j < end) {
+                    text.push(chars.next().unwrap().1);
+                }
+            }
+            '{' | '}' if next == Some(c) => {
+                chars.next();
+                text.push(c);
+            }
+            '{' => {
+                let Some(length) = template[i + 1..].find(['}', '{', '\n']).filter(|&l| template[i + 1 + l..].starts_with('}')) else {
+                    errors.push((i..i + 1, "unclosed placeholder, write `{{` for a literal brace".to_string()));
+                    continue
+                };
+                let range = i..i + length + 2;
+                while chars.peek().is_some_and(|(j, _)| *j < range.end) {
+                    chars.next();
+                }
+
+                let inner = &template[i + 1..i + 1 + length];
+                let (name, spec) = inner.split_once(':').unwrap_or((inner, ""));
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    errors.push((range, format!("invalid placeholder `{{{}}}`, expected a name like `{{captain}}`", inner)));
+                    continue
+                }
+                match FormatSpec::parse(spec) {
+                    Ok(spec) => {
+                        pieces.push(TemplatePiece::Text(std::mem::take(&mut text)));
+                        pieces.push(TemplatePiece::Placeholder(Placeholder { name: name.to_string(), spec, range }));
+                    }
+                    Err(e) => errors.push((range, e))
+                }
+            }
+            '}' => errors.push((i..i + 1, "unmatched `}`, write `}}` for a literal brace".to_string())),
+            c => text.push(c)
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(TemplatePiece::Text(text));
+    }
+    pieces.retain(|p| *p != TemplatePiece::Text(String::new()));
+    (pieces, errors)
+}
+
+/// the part of `span`, which covers `raw`, covering the bytes `range` of `raw`
+fn span_within(span: Span, raw: &str, range: Range<usize>) -> Span {
+    let point = |i: usize| {
+        let before = &raw[..i];
+        match before.rfind('\n') {
+            Some(n) => (span.start_line + before.matches('\n').count(), i - n - 1),
+            None => (span.start_line, span.start_column + i)
+        }
+    };
+    let (start_line, start_column) = point(range.start);
+    let (end_line, end_column) = point(range.end);
+    Span::new(span.start + range.start, span.start + range.end, start_line, start_column, end_line, end_column)
+}
+
+/// Reports bad escapes in string literals, and placeholders in `format` templates that are malformed or name
+/// an argument the call doesn't pass. Templates are checked when they are a literal, either passed straight to
+/// `format` or to a macro parameter that is, the same way macros are expanded
+pub fn check_strings(body: &StatementsNode) -> Vec<Diagnostic> {
+    let mut checker = StringChecker { macros: HashMap::new(), expansions: Vec::new(), diagnostics: Vec::new() };
+    checker.define_macros(body);
+    checker.check(body);
+
+    let mut definitions: Vec<&MacroDefNode> = checker.macros.values().copied().collect();
+    definitions.sort_by_key(|d| d.span.start);
+    for definition in definitions {
+        checker.expansions.push((definition.name.clone(), HashMap::new()));
+        checker.check(definition.body.as_ref());
+        checker.expansions.pop();
+    }
+
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for diagnostic in checker.diagnostics {
+        if !diagnostics.iter().any(|d| d.code == diagnostic.code && d.span == diagnostic.span) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    diagnostics.sort_by_key(|d| d.span.start);
+    diagnostics
+}
+
+struct StringChecker<'a> {
+    macros: HashMap<String, &'a MacroDefNode>,
+    /// macros being checked, innermost last, with the parameters given a string literal
+    expansions: Vec<(String, HashMap<String, &'a StringNode>)>,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl<'a> StringChecker<'a> {
+    fn define_macros(&mut self, node: &'a dyn Node) {
+        if let Some(definition) = node.as_any().downcast_ref::<MacroDefNode>() {
+            self.macros.insert(definition.name.clone(), definition);
+        }
+        node.for_each_child(&mut |child| self.define_macros(child));
+    }
+
+    /// the string literal `node` is, directly or through a macro parameter
+    fn literal(&self, node: &'a dyn Node) -> Option<&'a StringNode> {
+        let any = node.as_any();
+        if let Some(string) = any.downcast_ref::<StringNode>() {
+            return Some(string)
+        }
+        let variable = any.downcast_ref::<VariableNode>()?;
+        self.expansions.last()?.1.get(&variable.name).copied()
+    }
+
+    fn check(&mut self, node: &'a dyn Node) {
+        let any = node.as_any();
+
+        if let Some(string) = any.downcast_ref::<StringNode>() {
+            for (range, message) in decode_string(&string.raw).1 {
+                let span = span_within(string.span, &string.raw, range);
+                self.diagnostics.push(Diagnostic::error(codes::INVALID_ESCAPE, message, span));
+            }
+            return
+        }
+        if any.is::<MacroDefNode>() {
+            return
+        }
+        if let Some(call) = any.downcast_ref::<MacroCallNode>() {
+            return self.check_macro_call(call)
+        }
+        if let Some(call) = any.downcast_ref::<CallNode>() {
+            self.check_format(call);
+        }
+        node.for_each_child(&mut |child| self.check(child));
+    }
+
+    fn check_macro_call(&mut self, call: &'a MacroCallNode) {
+        for arg in &call.args {
+            self.check(arg.as_ref());
+        }
+        let Some(definition) = self.macros.get(&call.name).copied() else {
+            return
+        };
+        if definition.params.len() != call.args.len() || self.expansions.iter().any(|(name, _)| *name == call.name) {
+            return
+        }
+
+        let literals = definition.params.iter().zip(&call.args)
+            .filter_map(|(param, arg)| self.literal(arg.as_ref()).map(|l| (param.clone(), l)))
+            .collect();
+        self.expansions.push((call.name.clone(), literals));
+        self.check(definition.body.as_ref());
+        self.expansions.pop();
+    }
+
+    fn check_format(&mut self, call: &'a CallNode) {
+        let is_format = call.callee.as_any().downcast_ref::<VariableNode>().is_some_and(|v| v.name == "format");
+        let Some(template) = call.args.first().filter(|_| is_format).and_then(|t| self.literal(t.as_ref())) else {
+            return
+        };
+
+        let body = &template.raw[1..template.raw.len() - 1];
+        let (pieces, errors) = parse_template(body, true);
+        let span = |range: Range<usize>| span_within(template.span, &template.raw, range.start + 1..range.end + 1);
+
+        for (range, message) in errors {
+            self.diagnostics.push(Diagnostic::error(codes::INVALID_PLACEHOLDER, message, span(range)));
+        }
+        for piece in pieces {
+            let TemplatePiece::Placeholder(placeholder) = piece else {
+                continue
+            };
+            let known = match placeholder.name.parse::<usize>() {
+                Ok(i) => i + 1 < call.args.len(),
+                Err(_) => call.kwargs.iter().any(|(name, _)| *name == placeholder.name)
+            };
+            if known {
+                continue
+            }
+            let given: Vec<String> = call.kwargs.iter().map(|(name, _)| format!("`{}`", name)).collect();
+            let label = if given.is_empty() {
+                "formatted here without named arguments".to_string()
+            } else {
+                format!("formatted here with {}", given.join(", "))
+            };
+            let message = format!("unknown placeholder `{{{}}}`", placeholder.name);
+            let diagnostic = Diagnostic::error(codes::UNKNOWN_PLACEHOLDER, message, span(placeholder.range)).with_related(call.span, label);
+            self.diagnostics.push(diagnostic);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod string_tests {
+    use crate::es3::ES3Compiler;
+    use crate::es3_diagnostics::codes;
+    use crate::es3_strings::{decode_string, parse_template, Align, TemplatePiece};
+
+    #[test]
+    pub fn test_decode() {
+        assert_eq!(decode_string(r#""a\n\t\"b\"\\""#).0, "a\n\t\"b\"\\");
+        assert_eq!(decode_string(r"'it\'s \u{1F600}'").0, "it's \u{1F600}");
+        assert_eq!(decode_string("\"two\r\nlines\"").0, "two\nlines");
+        assert_eq!(decode_string("\"joined \\\n    here\"").0, "joined here");
+
+        let (value, errors) = decode_string(r#""\q and \u{110000}""#);
+        assert_eq!(value, "q and ");
+        assert_eq!(errors[0], (1..3, "unknown escape `\\q`".to_string()));
+        assert_eq!(errors[1].0, 8..18);
+    }
+
+    #[test]
+    pub fn test_template() {
+        let (pieces, errors) = parse_template("{{{captain}}} owes {0:*^7.2} {money:>5}", false);
+        assert!(errors.is_empty());
+        assert_eq!(pieces[0], TemplatePiece::Text("{".to_string()));
+        let TemplatePiece::Placeholder(owed) = &pieces[3] else { panic!() };
+        assert_eq!((owed.name.as_str(), owed.spec.fill, owed.spec.align, owed.spec.width, owed.spec.precision), ("0", '*', Some(Align::Center), 7, Some(2)));
+        assert_eq!(owed.spec.apply("1.005", Some(1.005), true), "*1.00**");
+        let TemplatePiece::Placeholder(money) = &pieces[5] else { panic!() };
+        assert_eq!(money.spec.apply("ab", None, false), "   ab");
+
+        // escapes are stepped over in literals as written
+        let (pieces, _) = parse_template(r"\u{7B} {a}\\{b}", true);
+        assert_eq!(pieces.len(), 4);
+
+        let errors: Vec<String> = parse_template("{} {a b} {a:x} { }", false).1.into_iter().map(|e| e.1).collect();
+        assert_eq!(errors.len(), 4);
+        assert_eq!(errors[2], "invalid format spec `x`, expected `[[fill]align][width][.precision]`");
+    }
+
+    #[test]
+    pub fn test_check_strings() {
+        let source = "$say($message) {\n    output(format($message, captain: captain))\n}\n$say(\"{captain} pays {money}\\q\")\noutput(format(\"{0} {1}\", 1))\n";
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize(source);
+        let _ = compiler.parse();
+
+        let found: Vec<(&str, &str)> = compiler.diagnostics.iter().map(|d| (d.code, &source[d.span.start..d.span.end])).collect();
+        assert_eq!(found, [(codes::UNKNOWN_PLACEHOLDER, "{money}"), (codes::INVALID_ESCAPE, "\\q"), (codes::UNKNOWN_PLACEHOLDER, "{1}")]);
+        assert_eq!(compiler.diagnostics[0].related[0].1, "formatted here with `captain`");
+        assert_eq!(compiler.diagnostics[0].span.start_line, 4);
+
+        compiler.tokenize("x = \"a\\tb\"");
+        compiler.parse().unwrap();
+        assert_eq!(compiler.compile()["body"][0]["value"], "a\tb");
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::es3::{AssignNode, ForNode, MacroCallNode, MacroDefNode, Node, StatementsNode, VariableNode};
+use crate::es3_diagnostics::Span;
+use crate::es3_lexer::{COMMANDS, KEYWORDS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Macro
+}
+
+impl SymbolKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolKind::Variable => "variable",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Macro => "macro"
+        }
+    }
+}
+
+/// A name and every place it is used
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// the macro the symbol is local to, `None` for globals
+    pub scope: Option<String>,
+    /// the parameter, macro definition, or first assignment. `None` for variables that are only ever read
+    pub definition: Option<Span>,
+    /// every occurrence of the name, including the definition, in source order
+    pub references: Vec<Span>
+}
+
+/// Replace the bytes in `range` of the source with `text`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String
+}
+
+/// Every variable, macro and macro parameter in a script.
+/// Variables are global unless they are a macro's parameter or a `$` variable assigned in its body,
+/// which are scoped to that macro, the same way they are renamed when it is expanded
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    pub symbols: Vec<Symbol>
+}
+
+impl SymbolTable {
+    pub fn build(body: &StatementsNode) -> SymbolTable {
+        let mut builder = Builder { symbols: Vec::new(), ids: HashMap::new() };
+        builder.walk(body, None);
+
+        let mut symbols = builder.symbols;
+        for symbol in &mut symbols {
+            symbol.references.sort_by_key(|s| s.start);
+            symbol.references.dedup();
+        }
+        SymbolTable { symbols }
+    }
+
+    /// Renames the symbol with id `id` to `new_name`, returning the edits to make to the source.
+    /// `$` variables and macros keep their `$`
+    pub fn rename(&self, id: usize, new_name: &str) -> Result<Vec<TextEdit>, String> {
+        let symbol = self.symbols.get(id).ok_or("there is no symbol to rename")?;
+
+        let needs_sigil = symbol.name.starts_with('$');
+        let word = if needs_sigil { new_name.strip_prefix('$') } else { Some(new_name) };
+        let valid = word.is_some_and(|w| {
+            w.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && w.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        if !valid {
+            let expected = if needs_sigil { "a `$` followed by a name" } else { "a name" };
+            return Err(format!("`{}` is not a valid name, expected {}", new_name, expected))
+        }
+        if KEYWORDS.contains(&new_name) || COMMANDS.contains(&new_name) || new_name == "true" || new_name == "false" {
+            return Err(format!("`{}` is a reserved word", new_name))
+        }
+        let taken = self.symbols.iter().enumerate().any(|(i, s)| {
+            i != id && s.name == new_name && (s.kind == SymbolKind::Macro) == (symbol.kind == SymbolKind::Macro) && s.scope == symbol.scope
+        });
+        if taken {
+            return Err(format!("`{}` is already defined", new_name))
+        }
+
+        Ok(symbol.references.iter().map(|r| TextEdit { range: r.start..r.end, text: new_name.to_string() }).collect())
+    }
+}
+
+struct Builder {
+    symbols: Vec<Symbol>,
+    /// symbol ids by whether they are a macro, scope and name
+    ids: HashMap<(bool, Option<String>, String), usize>
+}
+
+impl Builder {
+    /// records an occurrence of `name` at `span`, which defines it if nothing else has yet and `defines` is set
+    fn add(&mut self, kind: SymbolKind, scope: Option<String>, name: &str, span: Span, defines: bool) {
+        let key = (kind == SymbolKind::Macro, scope.clone(), name.to_string());
+        let id = *self.ids.entry(key).or_insert_with(|| {
+            self.symbols.push(Symbol { name: name.to_string(), kind, scope, definition: None, references: Vec::new() });
+            self.symbols.len() - 1
+        });
+
+        let symbol = &mut self.symbols[id];
+        symbol.references.push(span);
+        if defines && symbol.definition.is_none() {
+            symbol.definition = Some(span);
+        }
+    }
+
+    /// records a variable in whichever scope it belongs to
+    fn add_variable(&mut self, name: &str, span: Span, defines: bool, scope: Option<&MacroDefNode>) {
+        match scope {
+            Some(definition) if definition.params.iter().any(|p| p == name) => {
+                self.add(SymbolKind::Parameter, Some(definition.name.clone()), name, span, false)
+            }
+            Some(definition) if definition.locals().iter().any(|l| l == name) => {
+                self.add(SymbolKind::Variable, Some(definition.name.clone()), name, span, defines)
+            }
+            _ => self.add(SymbolKind::Variable, None, name, span, defines)
+        }
+    }
+
+    fn walk(&mut self, node: &dyn Node, scope: Option<&MacroDefNode>) {
+        let any = node.as_any();
+
+        if let Some(definition) = any.downcast_ref::<MacroDefNode>() {
+            self.add(SymbolKind::Macro, None, &definition.name, name_span(definition.span, &definition.name), true);
+            for (param, span) in definition.params.iter().zip(&definition.param_spans) {
+                self.add(SymbolKind::Parameter, Some(definition.name.clone()), param, *span, true);
+            }
+            self.walk(definition.body.as_ref(), Some(definition));
+        }
+        else if let Some(call) = any.downcast_ref::<MacroCallNode>() {
+            self.add(SymbolKind::Macro, None, &call.name, name_span(call.span, &call.name), false);
+            for arg in &call.args {
+                self.walk(arg.as_ref(), scope);
+            }
+        }
+        else if let Some(variable) = any.downcast_ref::<VariableNode>() {
+            self.add_variable(&variable.name, variable.span, false, scope);
+        }
+        else if let Some(assign) = any.downcast_ref::<AssignNode>() {
+            match assign.target.as_any().downcast_ref::<VariableNode>() {
+                Some(variable) => self.add_variable(&variable.name, variable.span, true, scope),
+                None => self.walk(assign.target.as_ref(), scope)
+            }
+            self.walk(assign.value.as_ref(), scope);
+        }
+        else if let Some(for_node) = any.downcast_ref::<ForNode>() {
+            self.add_variable(&for_node.variable, for_node.variable_span, true, scope);
+            self.walk(for_node.iterable.as_ref(), scope);
+            self.walk(for_node.body.as_ref(), scope);
+        }
+        else {
+            node.for_each_child(&mut |child| self.walk(child, scope));
+        }
+    }
+}
+
+/// the part of `span` covering `name`, which it starts with
+fn name_span(span: Span, name: &str) -> Span {
+    Span::new(span.start, span.start + name.len(), span.start_line, span.start_column, span.start_line, span.start_column + name.len())
+}
+
+
+#[cfg(test)]
+mod symbol_tests {
+    use crate::es3::ES3Compiler;
+    use crate::es3_symbols::TextEdit;
+
+    const SOURCE: &str = "count = 0
+$shown = 1
+$say($text) {
+    $shown = $text .. count
+    output($shown)
+}
+for count in [1, 2] { $say(count) }
+$say($shown)";
+
+    /// byte index of the `n`th occurrence of `name`
+    fn at(name: &str, n: usize) -> usize {
+        SOURCE.match_indices(name).nth(n).unwrap().0
+    }
+
+    #[test]
+    pub fn test_symbols() {
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize(SOURCE);
+        compiler.parse().unwrap();
+
+        // `count` is global, even as a loop variable
+        let count: Vec<usize> = compiler.references(at("count", 2)).iter().map(|s| s.start).collect();
+        assert_eq!(count, vec![at("count", 0), at("count", 1), at("count", 2), at("count", 3)]);
+        assert_eq!(compiler.definition(at("count", 3) + 2).unwrap().start, 0);
+
+        // the macro's `$shown` is its own, separate from the global one
+        assert_eq!(compiler.definition(at("$shown", 2)).unwrap().start, at("$shown", 1));
+        assert_eq!(compiler.definition(at("$shown", 3)).unwrap().start, at("$shown", 0));
+        assert_eq!(compiler.references(at("$text", 1)).len(), 2);
+        assert_eq!(compiler.definition(at("$say", 1)).unwrap().start, at("$say", 0));
+        assert!(compiler.definition(at("output", 0)).is_none());
+        assert_eq!(compiler.tokens[0].links()["definition"], "0");
+
+        let edits = compiler.rename(at("$say", 2), "$tell").unwrap();
+        assert_eq!(edits, [0, 1, 2].map(|n| TextEdit { range: at("$say", n)..at("$say", n) + 4, text: "$tell".to_string() }));
+        assert_eq!(compiler.rename(at("count", 0), "$count").unwrap_err(), "`$count` is not a valid name, expected a name");
+        assert_eq!(compiler.rename(at("$shown", 1), "$text").unwrap_err(), "`$text` is already defined");
+        assert_eq!(compiler.rename(at("count", 0), "while").unwrap_err(), "`while` is a reserved word");
+        assert!(compiler.rename(at("=", 0), "x").is_err());
+    }
+}
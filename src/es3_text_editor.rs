@@ -1,19 +1,841 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::time::Instant;
 use crate::app::App;
 use crate::component::Component;
-use crate::es3::ES3Compiler;
-use crate::text_input_handler::TextInputHandler;
+use crate::es3::{format, style_flags, ES3Compiler, PositionedToken, Token};
+use crate::es3_completion::Completion;
+use crate::es3_debugger::{ES3Debugger, PauseReason, Scope};
+use crate::es3_interpreter::{Entity, ScriptState, ScriptWorld};
+use crate::find_bar::FindBar;
+use crate::macros::{collides, font_size, SETTINGS};
+use crate::rectangle::Rectangle;
+use crate::text_box::MULTI_CLICK_SECONDS;
+use crate::text_buffer::TextBuffer;
+use crate::text_input_handler::{IdxSize, TextInputHandler, TAB_WIDTH};
 
-struct ES3TextEditor {
+/// space between the line numbers and the code
+const GUTTER_PADDING: u32 = 10;
+/// lines moved per notch of the mouse wheel
+const SCROLL_LINES: i32 = 3;
+/// rows shown in the completion popup at once
+const COMPLETION_ROWS: usize = 8;
+/// width of the panel left of the code while debugging
+const DEBUG_PANEL_WIDTH: u32 = 260;
+/// uid of the player a script being debugged runs for
+const DEBUG_PLAYER: &str = "player";
+
+/// where the code area starts on screen after scrolling, and the size of a character
+struct Layout {
+    origin: (i32, i32),
+    char_size: (u32, u32)
+}
+
+/// a script being debugged from the editor, in a world of its own
+struct DebugSession {
+    debugger: ES3Debugger,
+    world: ScriptWorld,
+    /// what the script could see when it last paused
+    scopes: Vec<Scope>,
+    last_update: Instant
+}
+
+/// Code editor for ES3 scripts.
+/// Text is edited through a `TextInputHandler`, and every change is fed to the compiler incrementally
+/// so highlighting and diagnostics stay up to date while typing
+pub struct ES3TextEditor {
     compiler: ES3Compiler,
     text_input_handler: TextInputHandler,
     pub position: (i32, i32),
     pub size: (u32, u32),
-    uid: String
+    uid: String,
+    selected: bool,
+    hovered: bool,
+    /// how far the code is scrolled right and down, in pixels
+    scroll: (i32, i32),
+    scale: f32,
+    cursor_blink_delta: Instant,
+    /// when and where the last click was, and how many clicks in a row it was
+    last_click: (Instant, IdxSize, u8),
+    /// where a selection being dragged with the mouse started
+    drag_anchor: Option<IdxSize>,
+    /// the `(line, visual column)` a column selection being dragged with alt+shift started at
+    column_anchor: Option<(IdxSize, IdxSize)>,
+    /// suggestions for the word at the cursor, shown in a popup below it while there are any
+    completions: Vec<Completion>,
+    completion_idx: usize,
+    /// lines to pause at when debugging, counted from 1
+    breakpoints: BTreeSet<usize>,
+    debug: Option<DebugSession>,
+    /// id of the object a ctrl+click on an object reference went to, see `take_followed_object`
+    followed_object: Option<String>,
+    find_bar: FindBar,
+    background: Rectangle,
+    gutter_background: Rectangle,
+    selection_rectangle: Rectangle,
+    cursor_rectangle: Rectangle,
+    error_underline: Rectangle,
+    warning_underline: Rectangle,
+    completion_background: Rectangle,
+    completion_highlight: Rectangle,
+    breakpoint_marker: Rectangle,
+    paused_line: Rectangle,
+    debug_background: Rectangle,
+    z_index: f32
+}
+
+impl ES3TextEditor {
+    pub fn new(position: (i32, i32), size: (u32, u32), content: &str, z_index: f32) -> Self {
+        let mut compiler = ES3Compiler::new();
+        compiler.set_type_checking(true);
+        compiler.tokenize(content);
+        let _ = compiler.parse();
+
+        Self {
+            compiler,
+            text_input_handler: TextInputHandler::new(content.to_string(), true, None, true),
+            position,
+            size,
+            uid: "".to_string(),
+            selected: false,
+            hovered: false,
+            scroll: (0, 0),
+            scale: font_size!(16.0),
+            cursor_blink_delta: Instant::now(),
+            last_click: (Instant::now(), 0, 0),
+            drag_anchor: None,
+            column_anchor: None,
+            completions: Vec::new(),
+            completion_idx: 0,
+            breakpoints: BTreeSet::new(),
+            debug: None,
+            followed_object: None,
+            find_bar: FindBar::new((z_index + 0.015).min(1.0)),
+            background: Rectangle::new(0, 0, 0, 0, SETTINGS!(bg dark 4 u8), z_index - 0.0002),
+            gutter_background: Rectangle::new(0, 0, 0, 0, SETTINGS!(bg medium 4 u8), z_index - 0.0001),
+            selection_rectangle: Rectangle::new(0, 0, 0, 0, SETTINGS!(text highlight 4 u8), z_index - 0.00005),
+            cursor_rectangle: Rectangle::new(0, 0, 1, 16, (255, 255, 255, 255), (z_index + 0.01).min(1.0)),
+            error_underline: Rectangle::new(0, 0, 0, 2, (240, 70, 70, 255), z_index + 0.001),
+            warning_underline: Rectangle::new(0, 0, 0, 2, (230, 190, 60, 255), z_index + 0.001),
+            completion_background: Rectangle::new(0, 0, 0, 0, SETTINGS!(bg medium 4 u8), (z_index + 0.02).min(1.0)),
+            completion_highlight: Rectangle::new(0, 0, 0, 0, SETTINGS!(text highlight 4 u8), (z_index + 0.021).min(1.0)),
+            breakpoint_marker: Rectangle::new(0, 0, 0, 0, (240, 70, 70, 110), z_index - 0.00008),
+            paused_line: Rectangle::new(0, 0, 0, 0, (230, 190, 60, 50), z_index - 0.00006),
+            debug_background: Rectangle::new(0, 0, 0, 0, SETTINGS!(bg medium 4 u8), (z_index + 0.02).min(1.0)),
+            z_index
+        }
+    }
+
+    /// sets the object ids the script's `<namespace:path>` references are checked against
+    pub fn set_objects(&mut self, ids: impl IntoIterator<Item = impl ToString>) {
+        self.compiler.set_objects(ids);
+    }
+
+    /// the object the last followed object reference links to, for whatever holds the project's objects to go to
+    pub fn take_followed_object(&mut self) -> Option<String> {
+        self.followed_object.take()
+    }
+
+    pub fn content(&self) -> &str {
+        self.text_input_handler.content.as_str()
+    }
+
+    /// replaces the whole script, resetting cursors and scrolling
+    pub fn set_content(&mut self, content: &str) {
+        self.text_input_handler.set_content(content);
+        self.scroll = (0, 0);
+        self.breakpoints.clear();
+        self.debug = None;
+        self.compiler.tokenize(content);
+        let _ = self.compiler.parse();
+    }
+
+    /// pretty-prints the script with `es3::format`, keeping the cursor on the same line and column
+    fn format_content(&mut self, app: &mut App) {
+        let handler = &mut self.text_input_handler;
+        let formatted = TextBuffer::from(format(handler.content.as_str()));
+        if handler.content == formatted {
+            return
+        }
+        let (line, column) = handler.get_text_pos(handler.cursor.idx).unwrap_or((0, 0));
+        let idx = formatted.line_to_char(line).map_or(0, |start| start + column.min(formatted.line_len(line).unwrap_or(0)));
+        handler.replace_range(app, 0..handler.content.len_chars(), formatted.as_str(), idx);
+    }
+
+    /// width of a character and height of a line, the font is monospaced
+    fn char_size(&self, app: &App) -> (u32, u32) {
+        let font = app.font_handler.style_flagged(0);
+        let (mut w, mut h) = (0, 0);
+        font.skip_char(&mut w, &mut 0, " ", self.scale);
+        font.skip_char(&mut 0, &mut h, "\n", self.scale);
+        (w.max(1), h.max(1))
+    }
+
+    fn line_count(&self) -> usize {
+        self.text_input_handler.content.len_lines()
+    }
+
+    fn gutter_width(&self, char_width: u32) -> u32 {
+        self.line_count().to_string().len() as u32 * char_width + GUTTER_PADDING * 2
+    }
+
+    /// The `(line, visual column)` nearest to `position` on screen, which can be past the end of a line.
+    /// Tabs are drawn to the handler's tab stops, see `TextInputHandler::visual_column`
+    fn column_at(&self, app: &App, position: (i32, i32)) -> (IdxSize, IdxSize) {
+        let (char_width, line_height) = self.char_size(app);
+        let origin = (self.position.0 + self.gutter_width(char_width) as i32 - self.scroll.0, self.position.1 - self.scroll.1);
+        let line = ((position.1 - origin.1) / line_height as i32).max(0) as usize;
+        let column = ((position.0 - origin.0 + char_width as i32 / 2) / char_width as i32).max(0) as usize;
+        (line, column)
+    }
+
+    /// index of the character nearest to `position` on screen
+    fn index_at(&self, app: &App, position: (i32, i32)) -> IdxSize {
+        let (line, column) = self.column_at(app, position);
+        self.text_input_handler.index_at_column(line.min(self.line_count() - 1), column).unwrap_or(0)
+    }
+
+    /// Places the cursor at the mouse and starts dragging a selection from there. A double click selects a word,
+    /// a triple click the line, shift+click selects up to the mouse and alt+click adds or removes a cursor.
+    /// Alt+shift starts dragging a column selection instead
+    fn click(&mut self, app: &App) {
+        let idx = self.index_at(app, app.mouse.position);
+        let (time, last_idx, count) = self.last_click;
+        let count = if time.elapsed().as_secs_f64() < MULTI_CLICK_SECONDS && last_idx == idx { count % 3 + 1 } else { 1 };
+        self.last_click = (Instant::now(), idx, count);
+        self.drag_anchor = None;
+        self.column_anchor = None;
+
+        let column = self.column_at(app, app.mouse.position);
+        let handler = &mut self.text_input_handler;
+        if app.keyboard.alt_held && app.keyboard.shift_held {
+            handler.select_columns(column, column);
+            self.column_anchor = Some(column);
+        }
+        else if app.keyboard.alt_held {
+            handler.add_cursor(idx);
+        }
+        else if count == 2 {
+            handler.select_word(idx);
+        }
+        else if count == 3 {
+            handler.select_line(idx);
+        }
+        else {
+            let anchor = if app.keyboard.shift_held { handler.cursor.selection_idx.unwrap_or(handler.cursor.idx) } else { idx };
+            handler.select_to(anchor, idx);
+            self.drag_anchor = Some(anchor);
+        }
+    }
+
+    /// Moves the cursor to where the variable, macro or parameter under `position` is defined.
+    /// An object reference is followed to its object instead, see `take_followed_object`
+    fn go_to_definition(&mut self, app: &App, position: (i32, i32)) {
+        let content = &self.text_input_handler.content;
+        let byte = content.char_to_byte(self.index_at(app, position));
+        if let Some(id) = self.compiler.token_at(byte).and_then(|t| t.links().get("object")) {
+            self.followed_object = Some(id.clone());
+        }
+        else if let Some(definition) = self.compiler.definition(byte) {
+            let idx = content.byte_to_char(definition.start);
+            self.text_input_handler.set_cursor_index(idx);
+            self.focus_cursor(app);
+        }
+    }
+
+    /// byte index of the primary cursor in the content
+    fn cursor_byte(&self) -> usize {
+        self.text_input_handler.content.char_to_byte(self.text_input_handler.cursor.idx)
+    }
+
+    /// asks the compiler for suggestions at the cursor, only offered with a single cursor and nothing selected
+    fn refresh_completions(&mut self) {
+        let handler = &self.text_input_handler;
+        self.completion_idx = 0;
+        self.completions = if handler.cursors.is_empty() && handler.cursor.selection_idx.is_none() {
+            self.compiler.complete(self.cursor_byte())
+        } else {
+            Vec::new()
+        };
+    }
+
+    /// replaces the word at the cursor with the highlighted completion and closes the popup
+    fn accept_completion(&mut self, app: &mut App) {
+        let completion = self.completions.swap_remove(self.completion_idx);
+        self.completions.clear();
+        let handler = &mut self.text_input_handler;
+        let range = handler.content.byte_to_char(completion.range.start)..handler.content.byte_to_char(completion.range.end);
+        let cursor = range.start + completion.insert_text.chars().count();
+        handler.replace_range(app, range, &completion.insert_text, cursor);
+    }
+
+    /// Handles the keys the completion popup takes over while it's open: Up/Down pick a completion,
+    /// Tab/Return accept it and Escape closes the popup. Handled keys are taken out of `triggered_keys`
+    /// so the text input handler doesn't also act on them. Returns whether a completion was accepted
+    fn process_completion_keys(&mut self, app: &mut App) -> bool {
+        let mut accept = false;
+        app.keyboard.triggered_keys.retain(|key| match key.as_str() {
+            "Up" => {
+                self.completion_idx = self.completion_idx.checked_sub(1).unwrap_or(self.completions.len() - 1);
+                false
+            }
+            "Down" => {
+                self.completion_idx = (self.completion_idx + 1) % self.completions.len();
+                false
+            }
+            "Tab" | "Return" | "Keypad Enter" => {
+                accept = true;
+                false
+            }
+            "Escape" => {
+                self.completions.clear();
+                false
+            }
+            _ => true
+        });
+        if accept && !self.completions.is_empty() {
+            self.accept_completion(app);
+            return true
+        }
+        false
+    }
+
+    /// the popup listing the completions, below the start of the word being completed
+    fn render_completions(&mut self, app: &mut App, layout: &Layout) {
+        let (char_width, line_height) = layout.char_size;
+        let start = self.text_input_handler.content.byte_to_char(self.completions[0].range.start);
+        let Some((line, _)) = self.text_input_handler.get_text_pos(start) else {
+            return
+        };
+        let column = self.text_input_handler.visual_column(start);
+
+        let first = self.completion_idx.saturating_sub(COMPLETION_ROWS - 1);
+        let shown = &self.completions[first..self.completions.len().min(first + COMPLETION_ROWS)];
+        let label_width = shown.iter().map(|c| c.label.chars().count()).max().unwrap_or(0) + 2;
+        let width = shown.iter().map(|c| label_width + c.kind.name().len().max(c.detail.chars().count())).max().unwrap_or(0) + 1;
+
+        let x = layout.origin.0 + (column as u32 * char_width) as i32 - char_width as i32 / 2;
+        let y = layout.origin.1 + ((line as u32 + 1) * line_height) as i32;
+        self.completion_background.position = (x, y);
+        self.completion_background.size = (width as u32 * char_width, shown.len() as u32 * line_height);
+        self.completion_background.update(app);
+        self.completion_highlight.position = (x, y + ((self.completion_idx - first) as u32 * line_height) as i32);
+        self.completion_highlight.size = (width as u32 * char_width, line_height);
+        self.completion_highlight.update(app);
+
+        let z = (self.z_index + 0.022).min(1.0);
+        for (row, completion) in shown.iter().enumerate() {
+            let y = y + (row as u32 * line_height) as i32;
+            let detail = if completion.detail.is_empty() { completion.kind.name() } else { &completion.detail };
+            let font = app.font_handler.style_flagged(0);
+            font.draw_text(app, x + char_width as i32 / 2, y, &completion.label, self.scale, (None, None, None, None), z, SETTINGS!(text color 4 u8), 0);
+            let detail_x = x + (label_width as u32 * char_width) as i32;
+            font.draw_text(app, detail_x, y, detail, self.scale, (None, None, None, None), z, SETTINGS!(bg light 4 u8), 0);
+        }
+    }
+
+    /// Hands the handler's latest edit to the compiler, returning the indices of the tokens that changed.
+    /// Breakpoints below the edit move with their lines, and any debugging stops as the script no longer matches
+    fn sync_compiler(&mut self) -> Range<usize> {
+        let content = self.text_input_handler.content.as_str();
+        let (old, new) = changed_ranges(&self.compiler.source, content);
+        let text = content[new].to_string();
+
+        let line = self.compiler.source[..old.start].matches('\n').count() + 1;
+        let removed = self.compiler.source[old.clone()].matches('\n').count();
+        let added = text.matches('\n').count();
+        self.breakpoints = self.breakpoints.iter()
+            .filter(|&&b| b <= line || b > line + removed)
+            .map(|&b| if b > line { b + added - removed } else { b })
+            .collect();
+        self.debug = None;
+
+        self.compiler.apply_edit(old, &text)
+    }
+
+    /// line under `position` on screen, counted from 1
+    fn line_at(&self, app: &App, position: (i32, i32)) -> usize {
+        let line_height = self.char_size(app).1 as i32;
+        ((position.1 - self.position.1 + self.scroll.1) / line_height).clamp(0, self.line_count() as i32 - 1) as usize + 1
+    }
+
+    fn toggle_breakpoint(&mut self, line: usize) {
+        if !self.breakpoints.remove(&line) {
+            self.breakpoints.insert(line);
+        }
+        if let Some(session) = &mut self.debug {
+            session.debugger.toggle_breakpoint(line);
+        }
+    }
+
+    /// Starts debugging the script in a world with just a player and the script's owner, or continues it when paused.
+    /// Scripts with errors aren't started
+    fn debug_continue(&mut self) {
+        if let Some(session) = &mut self.debug {
+            session.debugger.continue_running();
+            return
+        }
+        if self.compiler.diagnostics.iter().any(|d| d.is_error()) {
+            eprintln!("Error: cannot debug a script with errors");
+            return
+        }
+
+        let owner = self.compiler.header.owner.clone();
+        let mut world = ScriptWorld::new(0);
+        world.add_entity(Entity::new(DEBUG_PLAYER, "engine:player"));
+        if let Some(owner) = &owner {
+            world.add_entity(Entity::new(owner, owner));
+        }
+        match ES3Debugger::new(&self.compiler.compile_debug(), DEBUG_PLAYER, owner) {
+            Ok(mut debugger) => {
+                debugger.breakpoints = self.breakpoints.clone();
+                self.debug = Some(DebugSession { debugger, world, scopes: Vec::new(), last_update: Instant::now() });
+            }
+            Err(e) => eprintln!("Error: could not debug script: {}", e)
+        }
+    }
+
+    /// handles the debugging keybinds, returning whether one was pressed. Stepping only applies while paused
+    fn process_debug_keys(&mut self, app: &mut App) -> bool {
+        let binding = ["Debug", "Debug-Stop", "Breakpoint", "Step-Over", "Step-Into", "Step-Out"].into_iter()
+            .find(|name| app.keybinds.check_binding(name));
+        let Some(binding) = binding else {
+            return false
+        };
+        app.keybinds.accept(&app.keybinds.last(binding).unwrap().clone());
+
+        match binding {
+            "Debug" => self.debug_continue(),
+            "Debug-Stop" => self.debug = None,
+            "Breakpoint" => {
+                let line = self.text_input_handler.get_text_pos(self.text_input_handler.cursor.idx).map_or(0, |p| p.0);
+                self.toggle_breakpoint(line + 1);
+            }
+            _ => {
+                if let Some(debugger) = self.debug.as_mut().map(|s| &mut s.debugger).filter(|d| d.paused.is_some()) {
+                    match binding {
+                        "Step-Over" => debugger.step_over(),
+                        "Step-Into" => debugger.step_into(),
+                        _ => debugger.step_out()
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// runs the script being debugged for the time since the last frame, showing where it pauses
+    fn run_debugger(&mut self, app: &App) {
+        let Some(session) = &mut self.debug else {
+            return
+        };
+        let delta = session.last_update.elapsed().as_secs_f64();
+        session.last_update = Instant::now();
+
+        let was_paused = session.debugger.paused.is_some();
+        if session.debugger.resume(&mut session.world, delta).is_err() || was_paused || session.debugger.paused.is_none() {
+            return
+        }
+        session.scopes = session.debugger.scopes(&mut session.world);
+        if let Some(line) = session.debugger.line() {
+            self.focus_position(app, (line - 1, 0));
+        }
+    }
+
+    /// what the debugged script is doing, followed by what it can see while paused and its latest output
+    fn debug_rows(session: &DebugSession) -> Vec<(String, (u8, u8, u8, u8))> {
+        let debugger = &session.debugger;
+        let line = debugger.line().map_or(String::new(), |l| format!(", line {}", l));
+        let status = match (&debugger.error, &debugger.paused, &debugger.interpreter().state) {
+            (Some(e), _, _) => format!("stopped: {}", e),
+            (_, _, ScriptState::Finished) => "finished".to_string(),
+            (_, Some(PauseReason::Breakpoint), _) => format!("paused on breakpoint{}", line),
+            (_, Some(PauseReason::Step), _) => format!("paused{}", line),
+            (_, Some(PauseReason::Wait(seconds)), _) => format!("paused at wait({}){}", seconds, line),
+            (_, None, ScriptState::Waiting(left)) => format!("waiting, {:.1}s left", left),
+            (_, None, ScriptState::Running) => "running".to_string()
+        };
+
+        let mut rows = vec![(status, SETTINGS!(text color 4 u8))];
+        if debugger.paused.is_some() {
+            for scope in &session.scopes {
+                rows.push((scope.name.clone(), SETTINGS!(bg light 4 u8)));
+                rows.extend(scope.values.iter().map(|(name, value)| (format!("  {} = {}", name, value), SETTINGS!(text color 4 u8))));
+            }
+        }
+        rows.push(("output".to_string(), SETTINGS!(bg light 4 u8)));
+        rows.extend(session.world.output.iter().rev().take(COMPLETION_ROWS).rev().map(|l| (format!("  {}", l), SETTINGS!(text color 4 u8))));
+        rows
+    }
+
+    /// the panel left of the code listing the state of the script being debugged
+    fn render_debug_panel(&mut self, app: &mut App, layout: &Layout) {
+        let Some(session) = &self.debug else {
+            return
+        };
+        let rows = Self::debug_rows(session);
+        let x = self.position.0 - DEBUG_PANEL_WIDTH as i32;
+        self.debug_background.position = (x, self.position.1);
+        self.debug_background.size = (DEBUG_PANEL_WIDTH, self.size.1);
+        self.debug_background.update(app);
+
+        let line_height = layout.char_size.1;
+        let z = (self.z_index + 0.022).min(1.0);
+        let bounds = (None, None, Some(DEBUG_PANEL_WIDTH - GUTTER_PADDING * 2), None);
+        for (row, (text, color)) in rows.iter().take((self.size.1 / line_height) as usize).enumerate() {
+            let y = self.position.1 + (row as u32 * line_height) as i32;
+            app.font_handler.style_flagged(0).draw_text(app, x + GUTTER_PADDING as i32, y, text, self.scale, bounds, z, *color, 0);
+        }
+    }
+
+    /// scrolls just far enough to show the primary cursor
+    fn focus_cursor(&mut self, app: &App) {
+        let handler = &self.text_input_handler;
+        let line = handler.get_text_pos(handler.cursor.idx).map_or(0, |p| p.0);
+        self.focus_position(app, (line, handler.visual_column(handler.cursor.idx)));
+    }
+
+    /// scrolls just far enough to show a 0-based line and column
+    fn focus_position(&mut self, app: &App, (line, column): (usize, usize)) {
+        let (char_width, line_height) = self.char_size(app);
+
+        let x = column as i32 * char_width as i32;
+        let y = line as i32 * line_height as i32;
+        let code_width = self.size.0 as i32 - self.gutter_width(char_width) as i32;
+
+        self.scroll.0 = self.scroll.0.clamp((x - code_width + char_width as i32 * 2).max(0), x);
+        self.scroll.1 = self.scroll.1.clamp((y - self.size.1 as i32 + line_height as i32).max(0), y);
+    }
+
+    fn scroll_by(&mut self, app: &App, dx: i32, dy: i32) {
+        let (char_width, line_height) = self.char_size(app);
+        let max_y = (self.line_count() as i32 - 1) * line_height as i32;
+        self.scroll.0 = (self.scroll.0 + dx * char_width as i32 * SCROLL_LINES).max(0);
+        self.scroll.1 = (self.scroll.1 - dy * line_height as i32 * SCROLL_LINES).clamp(0, max_y.max(0));
+    }
+
+    /// draws `text` (a single line) at the given character column and 0-based line of the code area
+    fn draw_segment(&self, app: &App, text: &str, (line, column): (usize, usize), flags: u8, color: (u8, u8, u8, u8), layout: &Layout) {
+        let (origin, char_size) = (layout.origin, layout.char_size);
+        let x = origin.0 + (column as u32 * char_size.0) as i32;
+        let y = origin.1 + (line as u32 * char_size.1) as i32;
+        let right = self.position.0 + self.size.0 as i32 - x;
+        if right <= 0 {
+            return
+        }
+        let bounds = (Some(self.gutter_width(char_size.0) as i32 + self.position.0 - x), None, Some(right as u32), None);
+        app.font_handler.style_flagged(flags).draw_text(app, x, y, text, self.scale, bounds, self.z_index, color, flags);
+    }
+
+    fn draw_underline(&mut self, app: &mut App, (line, column): (usize, usize), length: usize, flags: u8, layout: &Layout) {
+        let (origin, char_size) = (layout.origin, layout.char_size);
+        let underline = if flags & style_flags::ERROR != 0 {
+            &mut self.error_underline
+        } else if flags & style_flags::WARNING != 0 {
+            &mut self.warning_underline
+        } else {
+            return
+        };
+        underline.position = (origin.0 + (column as u32 * char_size.0) as i32, origin.1 + ((line as u32 + 1) * char_size.1) as i32 - 2);
+        underline.size.0 = length.max(1) as u32 * char_size.0;
+        underline.update(app);
+    }
+
+    fn render(&mut self, app: &mut App) {
+        let char_size = self.char_size(app);
+        let (char_width, line_height) = char_size;
+        let gutter_width = self.gutter_width(char_width);
+        let origin = (self.position.0 + gutter_width as i32 - self.scroll.0, self.position.1 - self.scroll.1);
+        let layout = Layout { origin, char_size };
+
+        let first_line = (self.scroll.1 / line_height as i32).max(0) as usize;
+        let last_line = first_line + (self.size.1 / line_height) as usize + 1;
+
+        self.background.position = self.position;
+        self.background.size = self.size;
+        self.background.update(app);
+        self.gutter_background.position = self.position;
+        self.gutter_background.size = (gutter_width - GUTTER_PADDING / 2, self.size.1);
+        self.gutter_background.update(app);
+
+        // selections, split into one rectangle per line, in visual columns
+        let handler = &self.text_input_handler;
+        for (start, end) in handler.get_selections() {
+            let (Some(from), Some(to)) = (handler.get_text_pos(start), handler.get_text_pos(end)) else {
+                continue
+            };
+            for line in from.0.max(first_line)..=to.0.min(last_line) {
+                let start_column = if line == from.0 { handler.visual_column(start) } else { 0 };
+                let end_column = if line == to.0 {
+                    handler.visual_column(end)
+                } else {
+                    handler.index_at_column(line, IdxSize::MAX).map_or(0, |idx| handler.visual_column(idx)) + 1
+                };
+                self.selection_rectangle.position = (origin.0 + (start_column as u32 * char_width) as i32, origin.1 + (line as u32 * line_height) as i32);
+                self.selection_rectangle.size = ((end_column.saturating_sub(start_column) as u32 * char_width).max(1), line_height);
+                self.selection_rectangle.update(app);
+            }
+        }
+
+        // tokens, skipping straight to the first one that can be on screen
+        let source = &self.compiler.source;
+        let tokens = &self.compiler.tokens;
+        let first_token = tokens.partition_point(|t| t.line() + token_text(source, t).matches('\n').count() < first_line + 1);
+        let mut underlines = Vec::new();
+
+        for token in &tokens[first_token..] {
+            if token.line() > last_line + 1 {
+                break
+            }
+            let flags = token.style().get_flags();
+            let color = token_color(token.token(), flags);
+            let line_start = token.index() - token.column();
+
+            for (i, segment) in token_text(source, token).split('\n').enumerate() {
+                let line = token.line() - 1 + i;
+                let column = if i == 0 { visual_width(&source[line_start..token.index()]) } else { 0 };
+                if line < first_line || line > last_line {
+                    continue
+                }
+                self.draw_segment(app, segment, (line, column), flags, color, &layout);
+                if flags & (style_flags::ERROR | style_flags::WARNING) != 0 {
+                    underlines.push((line, column, segment.chars().count(), flags));
+                }
+            }
+        }
+
+        for (line, column, length, flags) in underlines {
+            self.draw_underline(app, (line, column), length, flags, &layout);
+        }
+
+        // breakpoints behind their line numbers, and the line a debugged script is paused at
+        for &line in self.breakpoints.range(first_line + 1..=last_line + 1) {
+            self.breakpoint_marker.position = (self.position.0, origin.1 + ((line as u32 - 1) * line_height) as i32);
+            self.breakpoint_marker.size = (gutter_width - GUTTER_PADDING / 2, line_height);
+            self.breakpoint_marker.update(app);
+        }
+        if let Some(line) = self.debug.as_ref().and_then(|s| s.debugger.line()) {
+            self.paused_line.position = (self.position.0 + gutter_width as i32 - GUTTER_PADDING as i32 / 2, origin.1 + ((line as u32 - 1) * line_height) as i32);
+            self.paused_line.size = (self.size.0 - gutter_width + GUTTER_PADDING / 2, line_height);
+            self.paused_line.update(app);
+        }
+
+        // line numbers, right aligned in the gutter
+        let cursor_line = self.text_input_handler.get_text_pos(self.text_input_handler.cursor.idx).map_or(0, |p| p.0);
+        for line in first_line..=last_line.min(self.line_count() - 1) {
+            let number = (line + 1).to_string();
+            let x = self.position.0 + (gutter_width - GUTTER_PADDING - number.len() as u32 * char_width) as i32 - GUTTER_PADDING as i32 / 2;
+            let y = origin.1 + (line as u32 * line_height) as i32;
+            let color = if line == cursor_line && self.selected { SETTINGS!(text color 4 u8) } else { SETTINGS!(bg light 4 u8) };
+            app.font_handler.style_flagged(0).draw_text(app, x, y, &number, self.scale, (None, None, None, None), self.z_index, color, 0);
+        }
+
+        if self.selected && self.cursor_blink_delta.elapsed().as_secs_f64() % 1.0 <= 0.5 {
+            let mut cursors: Vec<IdxSize> = vec![self.text_input_handler.cursor.idx];
+            cursors.extend(self.text_input_handler.cursors.iter().map(|c| c.idx));
+
+            for idx in cursors {
+                if let Some((line, _)) = self.text_input_handler.get_text_pos(idx) {
+                    if line < first_line || line > last_line {
+                        continue
+                    }
+                    let column = self.text_input_handler.visual_column(idx);
+                    self.cursor_rectangle.position = (origin.0 + (column as u32 * char_width) as i32, origin.1 + (line as u32 * line_height) as i32 + 2);
+                    self.cursor_rectangle.size.1 = line_height - 4;
+                    self.cursor_rectangle.update(app);
+                }
+            }
+        }
+
+        if self.selected && !self.completions.is_empty() {
+            self.render_completions(app, &layout);
+        }
+        if self.find_bar.open {
+            self.find_bar.render(app, (self.position.0 + gutter_width as i32, self.position.1), self.size.0 - gutter_width, char_size, self.scale);
+        }
+        self.render_debug_panel(app, &layout);
+    }
 }
 
 impl Component for ES3TextEditor {
     fn update(&mut self, app: &mut App) {
-        todo!()
+        self.hovered = collides!(app, self, app.mouse.position);
+
+        if self.hovered {
+            app.set_cursor("IBeam".to_string());
+            if app.mouse.scroll_x != 0 || app.mouse.scroll_y != 0 {
+                self.scroll_by(app, app.mouse.scroll_x, app.mouse.scroll_y);
+            }
+        }
+
+        if app.mouse.left_down {
+            self.selected = self.hovered;
+            self.completions.clear();
+            if self.hovered {
+                self.cursor_blink_delta = Instant::now();
+                let in_gutter = app.mouse.position.0 < self.position.0 + self.gutter_width(self.char_size(app).0) as i32;
+                if in_gutter {
+                    self.toggle_breakpoint(self.line_at(app, app.mouse.position));
+                }
+                else if app.keyboard.ctrl_held {
+                    self.go_to_definition(app, app.mouse.position);
+                }
+                else {
+                    self.click(app);
+                }
+            }
+        }
+        else if !app.mouse.left_held {
+            self.drag_anchor = None;
+            self.column_anchor = None;
+        }
+        else if let Some(anchor) = self.column_anchor {
+            let head = self.column_at(app, app.mouse.position);
+            self.text_input_handler.select_columns(anchor, head);
+        }
+        else if let Some(anchor) = self.drag_anchor {
+            let idx = self.index_at(app, app.mouse.position);
+            if idx != self.text_input_handler.cursor.idx {
+                self.text_input_handler.select_to(anchor, idx);
+                self.cursor_blink_delta = Instant::now();
+            }
+        }
+
+        // undos of this script's edits can also come from elsewhere in the app
+        if !self.selected && self.text_input_handler.apply_history() {
+            self.sync_compiler();
+        }
+
+        if self.selected {
+            let accepted = !self.completions.is_empty() && self.process_completion_keys(app);
+            let cursor = self.text_input_handler.cursor.idx;
+            let mut typed = false;
+            let mut complete = false;
+
+            if app.keybinds.check_binding("Find") || app.keybinds.check_binding("Replace") {
+                let replacing = !app.keybinds.check_binding("Find");
+                let binding = if replacing { "Replace" } else { "Find" };
+                app.keybinds.accept(&app.keybinds.last(binding).unwrap().clone());
+                self.find_bar.show(&self.text_input_handler, replacing);
+                self.completions.clear();
+            }
+            else if self.find_bar.open {
+                if self.find_bar.process(app, &mut self.text_input_handler) {
+                    self.cursor_blink_delta = Instant::now();
+                }
+            }
+            else if app.keybinds.check_binding("Format") {
+                app.keybinds.accept(&app.keybinds.last("Format").unwrap().clone());
+                self.format_content(app);
+                self.completions.clear();
+                self.cursor_blink_delta = Instant::now();
+            }
+            else if app.keybinds.check_binding("Complete") {
+                app.keybinds.accept(&app.keybinds.last("Complete").unwrap().clone());
+                complete = true;
+            }
+            else if self.process_debug_keys(app) {
+                self.cursor_blink_delta = Instant::now();
+            }
+            else if self.text_input_handler.process(app) {
+                typed = true;
+                self.cursor_blink_delta = Instant::now();
+            }
+
+            if self.text_input_handler.content != self.compiler.source {
+                self.sync_compiler();
+            }
+
+            // the popup opens on its keybind or when typing a name, and follows the cursor until it leaves the word
+            let idx = self.text_input_handler.cursor.idx;
+            let previous = idx.checked_sub(1).and_then(|i| self.text_input_handler.content.char_at(i));
+            let moved = idx != cursor;
+            let opens = typed && previous.is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '.' | '#' | '<'));
+            let follows = moved && !self.completions.is_empty() && previous.is_some_and(|c| !c.is_whitespace());
+            if !accepted && (complete || opens || follows) {
+                self.refresh_completions();
+            }
+            else if moved {
+                self.completions.clear();
+            }
+
+            if self.text_input_handler.should_focus_cursor() {
+                self.focus_cursor(app);
+            }
+        }
+
+        self.run_debugger(app);
+        self.render(app);
+    }
+
+}
+
+/// columns `text` takes up from the start of a line, with tabs going to the next tab stop
+fn visual_width(text: &str) -> usize {
+    text.chars().fold(0, |visual, c| if c == '\t' { (visual / TAB_WIDTH + 1) * TAB_WIDTH } else { visual + 1 })
+}
+
+fn token_text<'a>(source: &'a str, token: &PositionedToken) -> &'a str {
+    &source[token.index()..token.index() + token.length()]
+}
+
+/// highlight colour of a token, dimmed when it is styled as faded
+fn token_color(token: &Token, flags: u8) -> (u8, u8, u8, u8) {
+    let color = match token {
+        Token::Keyword(_) | Token::Command(_) => (198, 120, 221, 255),
+        Token::String(_) => (152, 195, 121, 255),
+        Token::Integer(_) | Token::Float(_) | Token::Boolean(_) => (209, 154, 102, 255),
+        Token::Comment(_) => (127, 132, 142, 255),
+        Token::Context(_) => (86, 182, 194, 255),
+        Token::Macro(_) => (229, 192, 123, 255),
+        Token::Object(_) | Token::Tag(_) => (97, 175, 239, 255),
+        Token::Comparison(_) | Token::Literal(_) => (171, 178, 191, 255),
+        Token::Error(_) => (240, 70, 70, 255),
+        _ => SETTINGS!(text color 4 u8)
+    };
+    if flags & style_flags::FADED != 0 {
+        (color.0, color.1, color.2, color.3 / 2)
+    } else {
+        color
+    }
+}
+
+/// Finds the part of `old` that was replaced to get `new`, by trimming their common prefix and suffix.
+/// Returns the byte range in `old` and the byte range of its replacement in `new`
+fn changed_ranges(old: &str, new: &str) -> (Range<usize>, Range<usize>) {
+    let mut prefix = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(prefix) || !new.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let mut suffix = old.bytes().rev().zip(new.bytes().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(old.len() - suffix) || !new.is_char_boundary(new.len() - suffix) {
+        suffix -= 1;
+    }
+
+    (prefix..old.len() - suffix, prefix..new.len() - suffix)
+}
+
+
+#[cfg(test)]
+mod es3_text_editor_tests {
+    use crate::es3_text_editor::{changed_ranges, visual_width};
+
+    #[test]
+    pub fn test_changed_ranges() {
+        assert_eq!(changed_ranges("abcdef", "abXYef"), (2..4, 2..4));
+        assert_eq!(changed_ranges("aaa", "aaaa"), (3..3, 3..4));
+        assert_eq!(changed_ranges("a\nb", "b"), (0..2, 0..0));
+        // never splits a multi-byte character
+        assert_eq!(changed_ranges("xé", "xè"), (1..3, 1..3));
+    }
+
+    #[test]
+    pub fn test_visual_width() {
+        assert_eq!(visual_width("abc"), 3);
+        assert_eq!(visual_width("\tx"), 5);
+        assert_eq!(visual_width("ab\t"), 4);
+        assert_eq!(visual_width("abcd\t\t"), 12);
     }
-    
 }
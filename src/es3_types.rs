@@ -0,0 +1,600 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::es3::{
+    AssignNode, BinaryOpNode, BooleanNode, CallNode, CompareNode, ContextRefNode, FloatNode, ForNode, IndexNode, IntegerNode,
+    ListNode, LogicNode, MacroCallNode, MacroDefNode, MapNode, MemberNode, NewNode, Node, NoneNode, NotNode, ObjectNode,
+    StatementsNode, StringNode, TagNode, UnaryOpNode, VariableNode
+};
+use crate::es3_diagnostics::{codes, Diagnostic, Span};
+
+/// What the type checker knows about a value, `Any` when it can't tell
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptType {
+    Any,
+    None,
+    Boolean,
+    Integer,
+    Float,
+    String,
+    List(Box<ScriptType>),
+    Map,
+    /// created with `new:`, by object type
+    Instance(String),
+    /// an entity in the world, by object type
+    Entity(String),
+    /// a `<namespace:path>` reference
+    Object,
+    /// a builtin function or namespace, such as `wait` or `random.range`
+    Builtin(String)
+}
+
+impl ScriptType {
+    fn is_number(&self) -> bool {
+        matches!(self, ScriptType::Integer | ScriptType::Float)
+    }
+
+    /// the type of a value that is either `self` or `other`
+    fn join(self, other: ScriptType) -> ScriptType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (a, b) if a.is_number() && b.is_number() => ScriptType::Float,
+            (ScriptType::List(a), ScriptType::List(b)) => ScriptType::List(Box::new(a.join(*b))),
+            _ => ScriptType::Any
+        }
+    }
+
+    /// whether a value of type `found` can be used where `self` is expected
+    fn accepts(&self, found: &ScriptType) -> bool {
+        match (self, found) {
+            (ScriptType::Any, _) | (_, ScriptType::Any) => true,
+            (ScriptType::Float, ScriptType::Integer) => true,
+            (ScriptType::List(a), ScriptType::List(b)) => a.accepts(b),
+            (a, b) => a == b
+        }
+    }
+
+    /// whether values of the two types can never be equal, `none` is left out since comparing to it is a null check
+    fn is_disjoint(&self, other: &ScriptType) -> bool {
+        match (self, other) {
+            (ScriptType::Any, _) | (_, ScriptType::Any) | (ScriptType::None, _) | (_, ScriptType::None) => false,
+            (a, b) if a.is_number() && b.is_number() => false,
+            (ScriptType::List(_), ScriptType::List(_)) => false,
+            (a, b) => a != b
+        }
+    }
+}
+
+impl Display for ScriptType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptType::Any => write!(f, "any"),
+            ScriptType::None => write!(f, "none"),
+            ScriptType::Boolean => write!(f, "boolean"),
+            ScriptType::Integer => write!(f, "integer"),
+            ScriptType::Float => write!(f, "float"),
+            ScriptType::String => write!(f, "string"),
+            ScriptType::List(item) if **item == ScriptType::Any => write!(f, "list"),
+            ScriptType::List(item) => write!(f, "list of {}", item),
+            ScriptType::Map => write!(f, "map"),
+            ScriptType::Instance(object) => write!(f, "`<{}>`", object),
+            ScriptType::Entity(object) => write!(f, "`<{}>` entity", object),
+            ScriptType::Object => write!(f, "object"),
+            ScriptType::Builtin(name) => write!(f, "`{}`", name)
+        }
+    }
+}
+
+/// Attributes of the `<engine:...>` object types.
+/// Entities also always have a `uid` and a `room`, see `entity_attribute`
+pub fn engine_schema(object: &str) -> Option<Vec<(&'static str, ScriptType)>> {
+    let currency = || ScriptType::Instance("engine:currency".to_string());
+    Some(match object {
+        "engine:currency" => vec![("gold", ScriptType::Integer), ("silver", ScriptType::Integer), ("copper", ScriptType::Integer)],
+        "engine:dungeon" => vec![("player_ids", ScriptType::List(Box::new(ScriptType::String)))],
+        "engine:item" => vec![("name", ScriptType::String), ("description", ScriptType::String), ("value", currency())],
+        "engine:npc" => vec![("name", ScriptType::String), ("description", ScriptType::String)],
+        "engine:player" => vec![("name", ScriptType::String), ("money", currency())],
+        "engine:room" => vec![("name", ScriptType::String), ("description", ScriptType::String)],
+        _ => return None
+    })
+}
+
+/// attributes of an entity of type `object`, its schema's along with `uid` and `room`
+pub fn entity_schema(object: &str) -> Option<Vec<(&'static str, ScriptType)>> {
+    let mut schema = vec![("uid", ScriptType::String), ("room", ScriptType::Object)];
+    schema.extend(engine_schema(object)?);
+    Some(schema)
+}
+
+/// type of `name` on an entity of type `object`, `None` if the engine doesn't know of it
+fn entity_attribute(object: &str, name: &str) -> Option<ScriptType> {
+    match name {
+        "uid" => Some(ScriptType::String),
+        "room" => Some(ScriptType::Object),
+        _ => engine_schema(object)?.into_iter().find(|(n, _)| *n == name).map(|(_, t)| t)
+    }
+}
+
+/// Infers the types of the values in a parsed script and reports operations that can't work on them.
+/// Macros are checked at every call with the types of its arguments, and once on their own with
+/// parameters of any type so problems that don't depend on the arguments show up even in unused macros
+pub fn check_types(body: &StatementsNode) -> Vec<Diagnostic> {
+    let mut checker = TypeChecker {
+        macros: HashMap::new(),
+        globals: HashMap::new(),
+        expansions: Vec::new(),
+        diagnostics: Vec::new()
+    };
+    checker.define_macros(body);
+    checker.check(body);
+
+    let mut definitions: Vec<&MacroDefNode> = checker.macros.values().copied().collect();
+    definitions.sort_by_key(|d| d.span.start);
+    for definition in definitions {
+        let params = definition.params.iter().map(|p| (p.clone(), ScriptType::Any)).collect();
+        checker.expansions.push((definition.name.clone(), params));
+        checker.check(definition.body.as_ref());
+        checker.expansions.pop();
+    }
+
+    // macro bodies are checked once per call, only report each problem once
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for diagnostic in checker.diagnostics {
+        if !diagnostics.iter().any(|d| d.code == diagnostic.code && d.span == diagnostic.span) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    diagnostics.sort_by_key(|d| d.span.start);
+    diagnostics
+}
+
+struct TypeChecker<'a> {
+    macros: HashMap<String, &'a MacroDefNode>,
+    globals: HashMap<String, ScriptType>,
+    /// macros being checked, innermost last, with the types of their parameters and `$` locals
+    expansions: Vec<(String, HashMap<String, ScriptType>)>,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl<'a> TypeChecker<'a> {
+    fn define_macros(&mut self, node: &'a dyn Node) {
+        if let Some(definition) = node.as_any().downcast_ref::<MacroDefNode>() {
+            self.macros.insert(definition.name.clone(), definition);
+        }
+        node.for_each_child(&mut |child| self.define_macros(child));
+    }
+
+    fn error(&mut self, code: &'static str, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::error(code, message, span));
+    }
+
+    fn lookup(&self, name: &str) -> ScriptType {
+        if let Some(t) = self.expansions.last().and_then(|(_, scope)| scope.get(name)) {
+            return t.clone()
+        }
+        match name {
+            "output" | "wait" | "format" | "length" | "random" => ScriptType::Builtin(name.to_string()),
+            _ => self.globals.get(name).cloned().unwrap_or(ScriptType::Any)
+        }
+    }
+
+    /// `$` variables assigned inside a macro are local to the expansion, everything else is global
+    fn assign(&mut self, name: &str, value: ScriptType) {
+        let scope = match self.expansions.last_mut() {
+            Some((_, scope)) if name.starts_with('$') => scope,
+            _ => &mut self.globals
+        };
+        let joined = match scope.remove(name) {
+            Some(old) => old.join(value),
+            None => value
+        };
+        scope.insert(name.to_string(), joined);
+    }
+
+    /// checks `node` and returns the type of its value, statements are `none`
+    fn check(&mut self, node: &'a dyn Node) -> ScriptType {
+        let any = node.as_any();
+
+        if any.is::<IntegerNode>() {
+            return ScriptType::Integer
+        }
+        if any.is::<FloatNode>() {
+            return ScriptType::Float
+        }
+        if any.is::<StringNode>() {
+            return ScriptType::String
+        }
+        if any.is::<BooleanNode>() {
+            return ScriptType::Boolean
+        }
+        if any.is::<NoneNode>() {
+            return ScriptType::None
+        }
+        if any.is::<ObjectNode>() {
+            return ScriptType::Object
+        }
+        if any.is::<MacroDefNode>() {
+            return ScriptType::None
+        }
+        if let Some(n) = any.downcast_ref::<VariableNode>() {
+            return self.lookup(&n.name)
+        }
+        if let Some(n) = any.downcast_ref::<ContextRefNode>() {
+            return match n.name.as_str() {
+                "player" | "dungeon" => ScriptType::Entity(format!("engine:{}", n.name)),
+                _ => ScriptType::Any
+            }
+        }
+        if let Some(n) = any.downcast_ref::<ListNode>() {
+            let items: Vec<ScriptType> = n.items.iter().map(|i| self.check(i.as_ref())).collect();
+            let item = items.into_iter().reduce(ScriptType::join).unwrap_or(ScriptType::Any);
+            return ScriptType::List(Box::new(item))
+        }
+        if let Some(n) = any.downcast_ref::<AssignNode>() {
+            return self.check_assign(n)
+        }
+        if let Some(n) = any.downcast_ref::<CompareNode>() {
+            return self.check_compare(n)
+        }
+        if let Some(n) = any.downcast_ref::<BinaryOpNode>() {
+            return self.check_binary_op(n)
+        }
+        if let Some(n) = any.downcast_ref::<UnaryOpNode>() {
+            let value = self.check(n.value.as_ref());
+            if value.is_number() || value == ScriptType::Any {
+                return value
+            }
+            self.error(codes::TYPE_MISMATCH, format!("cannot negate {}", value), n.span);
+            return ScriptType::Any
+        }
+        if let Some(n) = any.downcast_ref::<LogicNode>() {
+            let left = self.check(n.left.as_ref());
+            let right = self.check(n.right.as_ref());
+            return if left == right { left } else { ScriptType::Any }
+        }
+        if let Some(n) = any.downcast_ref::<NotNode>() {
+            self.check(n.value.as_ref());
+            return ScriptType::Boolean
+        }
+        if let Some(n) = any.downcast_ref::<MemberNode>() {
+            let object = self.check(n.object.as_ref());
+            return self.attribute(&object, &n.name, n.span)
+        }
+        if let Some(n) = any.downcast_ref::<IndexNode>() {
+            return self.check_index(n)
+        }
+        if let Some(n) = any.downcast_ref::<CallNode>() {
+            return self.check_call(n)
+        }
+        if let Some(n) = any.downcast_ref::<NewNode>() {
+            return self.check_new(n)
+        }
+        if let Some(n) = any.downcast_ref::<ForNode>() {
+            let item = match self.check(n.iterable.as_ref()) {
+                ScriptType::List(item) => *item,
+                ScriptType::String => ScriptType::String,
+                ScriptType::Any | ScriptType::Map => ScriptType::Any,
+                other => {
+                    self.error(codes::TYPE_MISMATCH, format!("cannot iterate over {}", other), n.span);
+                    ScriptType::Any
+                }
+            };
+            self.assign(&n.variable, item);
+            self.check(n.body.as_ref());
+            return ScriptType::None
+        }
+        if let Some(n) = any.downcast_ref::<MacroCallNode>() {
+            self.check_macro_call(n);
+            return ScriptType::None
+        }
+
+        // statements and tags, which only need their children checked
+        node.for_each_child(&mut |child| {
+            self.check(child);
+        });
+        if any.is::<TagNode>() || any.is::<MapNode>() {
+            return if any.is::<MapNode>() { ScriptType::Map } else { ScriptType::Any }
+        }
+        ScriptType::None
+    }
+
+    fn check_assign(&mut self, n: &'a AssignNode) -> ScriptType {
+        let value = self.check(n.value.as_ref());
+        let target = n.target.as_any();
+
+        if let Some(variable) = target.downcast_ref::<VariableNode>() {
+            self.assign(&variable.name, value);
+        }
+        else if let Some(member) = target.downcast_ref::<MemberNode>() {
+            let object = self.check(member.object.as_ref());
+            let expected = self.attribute(&object, &member.name, member.span);
+            if !expected.accepts(&value) {
+                let message = format!("`{}` of {} is {}, found {}", member.name, object, expected, value);
+                self.error(codes::TYPE_MISMATCH, message, n.span);
+            }
+        }
+        else {
+            self.check(n.target.as_ref());
+        }
+        ScriptType::None
+    }
+
+    fn check_compare(&mut self, n: &'a CompareNode) -> ScriptType {
+        let left = self.check(n.left.as_ref());
+        let right = self.check(n.right.as_ref());
+
+        match n.op.as_str() {
+            "==" | "!=" => {
+                if left.is_disjoint(&right) {
+                    let result = if n.op == "==" { "false" } else { "true" };
+                    let message = format!("`{}` between {} and {} is always {}", n.op, left, right, result);
+                    self.diagnostics.push(Diagnostic::warning(codes::DISJOINT_COMPARISON, message, n.span));
+                }
+            }
+            _ => {
+                let ordered = match (&left, &right) {
+                    (ScriptType::Any, _) | (_, ScriptType::Any) | (ScriptType::String, ScriptType::String) => true,
+                    (a, b) => a.is_number() && b.is_number()
+                };
+                if !ordered {
+                    self.error(codes::TYPE_MISMATCH, format!("cannot compare {} with {} using `{}`", left, right, n.op), n.span);
+                }
+            }
+        }
+        ScriptType::Boolean
+    }
+
+    fn check_binary_op(&mut self, n: &'a BinaryOpNode) -> ScriptType {
+        let left = self.check(n.left.as_ref());
+        let right = self.check(n.right.as_ref());
+
+        let result = match (n.op.as_str(), &left, &right) {
+            ("..", _, _) => Some(ScriptType::String),
+            ("::", ScriptType::List(_) | ScriptType::Any, ScriptType::List(_) | ScriptType::Any) => Some(left.clone().join(right.clone())),
+            ("::", _, _) => None,
+            (_, ScriptType::Integer, ScriptType::Integer) => Some(ScriptType::Integer),
+            (_, a, b) if a.is_number() && b.is_number() => Some(ScriptType::Float),
+            (_, ScriptType::Any, b) if b.is_number() || *b == ScriptType::Any => Some(ScriptType::Any),
+            (_, a, ScriptType::Any) if a.is_number() => Some(ScriptType::Any),
+            _ => None
+        };
+
+        result.unwrap_or_else(|| {
+            self.error(codes::TYPE_MISMATCH, format!("cannot apply `{}` to {} and {}", n.op, left, right), n.span);
+            ScriptType::Any
+        })
+    }
+
+    fn check_index(&mut self, n: &'a IndexNode) -> ScriptType {
+        let object = self.check(n.object.as_ref());
+        let index = self.check(n.index.as_ref());
+
+        match (&object, &index) {
+            (ScriptType::List(item), ScriptType::Integer | ScriptType::Any) => *item.clone(),
+            (ScriptType::String, ScriptType::Integer | ScriptType::Any) => ScriptType::String,
+            (ScriptType::Any | ScriptType::Map | ScriptType::Instance(_) | ScriptType::Entity(_), _) => ScriptType::Any,
+            (_, ScriptType::String) => ScriptType::Any,
+            _ => {
+                self.error(codes::TYPE_MISMATCH, format!("cannot index {} with {}", object, index), n.span);
+                ScriptType::Any
+            }
+        }
+    }
+
+    /// type of `object.name`
+    fn attribute(&mut self, object: &ScriptType, name: &str, span: Span) -> ScriptType {
+        match object {
+            ScriptType::Any | ScriptType::Map => ScriptType::Any,
+            ScriptType::Entity(t) => entity_attribute(t, name).unwrap_or_else(|| {
+                // entities can carry attributes the engine doesn't know about, so this might still work
+                if engine_schema(t).is_some() {
+                    let message = format!("{} has no attribute `{}`", object, name);
+                    self.diagnostics.push(Diagnostic::warning(codes::UNDECLARED_ATTRIBUTE, message, span));
+                }
+                ScriptType::Any
+            }),
+            ScriptType::Instance(t) => match engine_schema(t) {
+                Some(fields) => fields.into_iter().find(|(n, _)| *n == name).map(|(_, t)| t).unwrap_or_else(|| {
+                    self.error(codes::UNKNOWN_ATTRIBUTE, format!("{} has no field `{}`", object, name), span);
+                    ScriptType::Any
+                }),
+                None => ScriptType::Any
+            },
+            ScriptType::Builtin(namespace) if namespace == "random" && (name == "choice" || name == "range") => {
+                ScriptType::Builtin(format!("random.{}", name))
+            }
+            _ => {
+                self.error(codes::UNKNOWN_ATTRIBUTE, format!("{} has no attribute `{}`", object, name), span);
+                ScriptType::Any
+            }
+        }
+    }
+
+    fn check_call(&mut self, n: &'a CallNode) -> ScriptType {
+        let callee = match n.callee.as_any().downcast_ref::<MemberNode>() {
+            Some(member) => {
+                let receiver = self.check(member.object.as_ref());
+                if !matches!(receiver, ScriptType::Builtin(_)) {
+                    let args: Vec<ScriptType> = n.args.iter().map(|a| self.check(a.as_ref())).collect();
+                    return self.check_method(&receiver, &member.name, &args, member.span)
+                }
+                self.attribute(&receiver, &member.name, member.span)
+            }
+            None => self.check(n.callee.as_ref())
+        };
+
+        let args: Vec<ScriptType> = n.args.iter().map(|a| self.check(a.as_ref())).collect();
+        for (_, value) in &n.kwargs {
+            self.check(value.as_ref());
+        }
+
+        let name = match callee {
+            ScriptType::Builtin(name) => name,
+            ScriptType::Any => return ScriptType::Any,
+            other => {
+                self.error(codes::TYPE_MISMATCH, format!("{} cannot be called", other), n.span);
+                return ScriptType::Any
+            }
+        };
+
+        let first = args.first().cloned().unwrap_or(ScriptType::None);
+        let expect = |checker: &mut Self, ok: bool, what: &str, found: &ScriptType| {
+            if !ok {
+                checker.error(codes::TYPE_MISMATCH, format!("`{}` expects {}, found {}", name, what, found), n.span);
+            }
+        };
+
+        match name.as_str() {
+            "wait" => {
+                expect(self, first.is_number() || first == ScriptType::Any, "a number of seconds", &first);
+                ScriptType::None
+            }
+            "format" => {
+                expect(self, ScriptType::String.accepts(&first), "a string to format", &first);
+                ScriptType::String
+            }
+            "length" => {
+                let ok = matches!(first, ScriptType::List(_) | ScriptType::Map | ScriptType::String | ScriptType::Any);
+                expect(self, ok, "a list, map or string", &first);
+                ScriptType::Integer
+            }
+            "random.range" => {
+                let bad = args.iter().find(|a| !a.is_number() && **a != ScriptType::Any).cloned();
+                if let Some(bad) = bad {
+                    expect(self, false, "a low and high number", &bad);
+                }
+                if args.iter().all(|a| *a == ScriptType::Integer) {
+                    ScriptType::Integer
+                } else if args.contains(&ScriptType::Any) {
+                    ScriptType::Any
+                } else {
+                    ScriptType::Float
+                }
+            }
+            "random.choice" => match args.as_slice() {
+                [ScriptType::List(item)] => *item.clone(),
+                _ => args.into_iter().reduce(ScriptType::join).unwrap_or(ScriptType::Any)
+            },
+            _ => ScriptType::None
+        }
+    }
+
+    fn check_method(&mut self, receiver: &ScriptType, method: &str, args: &[ScriptType], span: Span) -> ScriptType {
+        match (method, receiver) {
+            ("to_string", _) => ScriptType::String,
+            (_, ScriptType::Any | ScriptType::Map) => ScriptType::Any,
+            ("append", ScriptType::List(_)) => ScriptType::None,
+            ("give_money", ScriptType::Entity(_)) => {
+                let currency = ScriptType::Instance("engine:currency".to_string());
+                let found = args.first().cloned().unwrap_or(ScriptType::None);
+                if !currency.accepts(&found) {
+                    self.error(codes::TYPE_MISMATCH, format!("`give_money` expects {}, found {}", currency, found), span);
+                }
+                ScriptType::None
+            }
+            _ => {
+                self.error(codes::UNKNOWN_ATTRIBUTE, format!("{} has no method `{}`", receiver, method), span);
+                ScriptType::Any
+            }
+        }
+    }
+
+    fn check_new(&mut self, n: &'a NewNode) -> ScriptType {
+        let schema = engine_schema(&n.object);
+        if let Some(data) = &n.data {
+            match (data.as_any().downcast_ref::<MapNode>(), &schema) {
+                (Some(map), Some(fields)) => {
+                    for (name, value) in &map.entries {
+                        let found = self.check(value.as_ref());
+                        match fields.iter().find(|(n, _)| n == name) {
+                            Some((_, expected)) if !expected.accepts(&found) => {
+                                let message = format!("`{}` of `<{}>` is {}, found {}", name, n.object, expected, found);
+                                self.error(codes::TYPE_MISMATCH, message, n.span);
+                            }
+                            Some(_) => {}
+                            None => self.error(codes::UNKNOWN_ATTRIBUTE, format!("`<{}>` has no field `{}`", n.object, name), n.span)
+                        }
+                    }
+                }
+                _ => {
+                    self.check(data.as_ref());
+                }
+            }
+        }
+        ScriptType::Instance(n.object.clone())
+    }
+
+    fn check_macro_call(&mut self, n: &'a MacroCallNode) {
+        let args: Vec<ScriptType> = n.args.iter().map(|a| self.check(a.as_ref())).collect();
+
+        // undefined, recursive and mismatched calls are reported by the expansion
+        let Some(definition) = self.macros.get(&n.name).copied() else {
+            return
+        };
+        if definition.params.len() != args.len() || self.expansions.iter().any(|(name, _)| *name == n.name) {
+            return
+        }
+
+        let params = definition.params.iter().cloned().zip(args).collect();
+        self.expansions.push((n.name.clone(), params));
+        self.check(definition.body.as_ref());
+        self.expansions.pop();
+    }
+}
+
+
+#[cfg(test)]
+mod type_tests {
+    use crate::es3::{ES3Compiler, ENGINE_OBJECTS};
+    use crate::es3_diagnostics::{codes, Severity};
+    use crate::es3_types::engine_schema;
+
+    /// code and message of every type problem in `source`
+    fn check(source: &str) -> Vec<(&'static str, String)> {
+        let mut compiler = ES3Compiler::new();
+        compiler.set_type_checking(true);
+        compiler.tokenize(source);
+        let _ = compiler.parse();
+        compiler.diagnostics.iter().filter(|d| d.code.starts_with("E05") || d.code.starts_with("W05")).map(|d| (d.code, d.message.clone())).collect()
+    }
+
+    #[test]
+    pub fn test_type_checking() {
+        assert!(ENGINE_OBJECTS.iter().all(|o| engine_schema(o).is_some()));
+
+        let found = check(r#"
+money = new: <engine:currency> { gold: 1, silver: "2" }
+if money == "10 gold" { wait("soon") }
+count = length(#dungeon.player_ids) + "1"
+#player.money = 5
+#player.give_money(money)
+#player.nmae
+names = ["a", "b"]
+for n in 3 { names.push(n) }
+$say($x) { output($x - 1) }
+$say("hi")
+$say(2)
+"#);
+        let expected = [
+            (codes::TYPE_MISMATCH, "`silver` of `<engine:currency>` is integer, found string"),
+            (codes::DISJOINT_COMPARISON, "`==` between `<engine:currency>` and string is always false"),
+            (codes::TYPE_MISMATCH, "`wait` expects a number of seconds, found string"),
+            (codes::TYPE_MISMATCH, "cannot apply `+` to integer and string"),
+            (codes::TYPE_MISMATCH, "`money` of `<engine:player>` entity is `<engine:currency>`, found integer"),
+            (codes::UNDECLARED_ATTRIBUTE, "`<engine:player>` entity has no attribute `nmae`"),
+            (codes::TYPE_MISMATCH, "cannot iterate over integer"),
+            (codes::UNKNOWN_ATTRIBUTE, "list of string has no method `push`"),
+            (codes::TYPE_MISMATCH, "cannot apply `-` to string and integer")
+        ];
+        let found: Vec<(&str, &str)> = found.iter().map(|(c, m)| (*c, m.as_str())).collect();
+        assert_eq!(found, expected);
+
+        // the checker is off unless asked for
+        let mut compiler = ES3Compiler::new();
+        compiler.tokenize("x = 1 + \"a\"");
+        assert!(compiler.parse().is_ok());
+        compiler.set_type_checking(true);
+        assert_eq!(compiler.diagnostics[0].severity, Severity::Error);
+        assert!(compiler.tokens[2].style().is_error());
+    }
+}
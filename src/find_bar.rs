@@ -0,0 +1,174 @@
+use crate::app::App;
+use crate::component::Component;
+use crate::macros::SETTINGS;
+use crate::rectangle::Rectangle;
+use crate::text_input_handler::{Cursor, Search, SearchOptions, TextInputHandler};
+
+/// characters before the text of each field, so both fields line up
+const LABEL_WIDTH: usize = 9;
+
+/// Find and replace for a text editor, drawn over its top while open.
+/// Return finds the next match of the query, or replaces it while typing in the replacement,
+/// and the toggle keybinds change how the query matches
+pub struct FindBar {
+    pub open: bool,
+    query: TextInputHandler,
+    replacement: TextInputHandler,
+    /// whether typing goes to the replacement instead of the query
+    replacing: bool,
+    options: SearchOptions,
+    /// how many matches the query has, or why it can't be searched for
+    status: String,
+    background: Rectangle,
+    selection_rectangle: Rectangle,
+    cursor_rectangle: Rectangle,
+    z_index: f32
+}
+
+impl FindBar {
+    pub fn new(z_index: f32) -> Self {
+        Self {
+            open: false,
+            query: TextInputHandler::new(String::new(), false, None, true),
+            replacement: TextInputHandler::new(String::new(), false, None, true),
+            replacing: false,
+            options: SearchOptions::default(),
+            status: String::new(),
+            background: Rectangle::new(0, 0, 0, 0, SETTINGS!(bg medium 4 u8), z_index),
+            selection_rectangle: Rectangle::new(0, 0, 0, 0, SETTINGS!(text highlight 4 u8), z_index + 0.0001),
+            cursor_rectangle: Rectangle::new(0, 0, 1, 16, (255, 255, 255, 255), z_index + 0.0002),
+            z_index
+        }
+    }
+
+    /// Opens the bar with the query field selected, or the replacement field when `replacing`.
+    /// A selection on a single line of `text` becomes the query
+    pub fn show(&mut self, text: &TextInputHandler, replacing: bool) {
+        let (start, end) = text.cursor.get_range();
+        let selected = text.content.slice(start..end);
+        if start != end && !selected.contains('\n') {
+            self.query.set_content(selected);
+        }
+        self.open = true;
+        self.replacing = replacing;
+        let field = self.field();
+        field.cursor = Cursor::selection(field.content.len_chars(), 0);
+        self.refresh(text);
+    }
+
+    fn field(&mut self) -> &mut TextInputHandler {
+        if self.replacing { &mut self.replacement } else { &mut self.query }
+    }
+
+    fn search(&self) -> Result<Search, String> {
+        Search::new(self.query.content.as_str(), self.options)
+    }
+
+    /// counts the matches in `text` again, after the query, the options or the text changed
+    pub fn refresh(&mut self, text: &TextInputHandler) {
+        self.status = match self.search() {
+            Ok(search) => match text.find_all(&search).len() {
+                0 => "no matches".to_string(),
+                1 => "1 match".to_string(),
+                n => format!("{} matches", n)
+            },
+            Err(_) if self.query.content.len_chars() == 0 => String::new(),
+            Err(e) => e
+        };
+    }
+
+    /// Handles the keys while the bar is open, in place of `text`'s own typing.
+    /// Returns whether `text` was changed or its cursor moved
+    pub fn process(&mut self, app: &mut App, text: &mut TextInputHandler) -> bool {
+        let toggle = ["Toggle-Case", "Toggle-Word", "Toggle-Regex", "Replace-All", "Find-All"].into_iter()
+            .find(|name| app.keybinds.check_binding(name));
+        if let Some(binding) = toggle {
+            app.keybinds.accept(&app.keybinds.last(binding).unwrap().clone());
+            let changed = match binding {
+                "Toggle-Case" => { self.options.case_insensitive = !self.options.case_insensitive; false }
+                "Toggle-Word" => { self.options.whole_word = !self.options.whole_word; false }
+                "Toggle-Regex" => { self.options.regex = !self.options.regex; false }
+                "Replace-All" => self.search().is_ok_and(|search| text.replace_all(app, &search, self.replacement.content.as_str()) > 0),
+                _ => self.search().is_ok_and(|search| text.select_all_occurrences(&search) > 0)
+            };
+            self.refresh(text);
+            return changed
+        }
+
+        let mut next = false;
+        app.keyboard.triggered_keys.retain(|key| match key.as_str() {
+            "Escape" => {
+                self.open = false;
+                false
+            }
+            "Tab" => {
+                self.replacing = !self.replacing;
+                false
+            }
+            "Return" | "Keypad Enter" => {
+                next = true;
+                false
+            }
+            _ => true
+        });
+
+        if next {
+            let changed = match self.search() {
+                Ok(search) if self.replacing => text.replace_next(app, &search, self.replacement.content.as_str()),
+                Ok(search) => text.find_next(&search),
+                Err(_) => false
+            };
+            self.refresh(text);
+            return changed
+        }
+
+        let query = self.query.content.clone();
+        self.field().process(app);
+        if self.query.content != query {
+            self.refresh(text);
+        }
+        false
+    }
+
+    /// draws the bar across the top of an editor at `position`, `width` wide
+    pub fn render(&mut self, app: &mut App, position: (i32, i32), width: u32, (char_width, line_height): (u32, u32), scale: f32) {
+        self.background.position = position;
+        self.background.size = (width, line_height * 2);
+        self.background.update(app);
+
+        let z = self.z_index + 0.0003;
+        let x = position.0 + (LABEL_WIDTH as u32 * char_width) as i32;
+        let labels = [("find", &self.query, false), ("replace", &self.replacement, true)];
+        for (row, (label, field, replacing)) in labels.into_iter().enumerate() {
+            let y = position.1 + (row as u32 * line_height) as i32;
+            let font = app.font_handler.style_flagged(0);
+            font.draw_text(app, position.0 + char_width as i32, y, label, scale, (None, None, None, None), z, SETTINGS!(bg light 4 u8), 0);
+            font.draw_text(app, x, y, field.content.as_str(), scale, (None, None, None, None), z, SETTINGS!(text color 4 u8), 0);
+
+            if replacing != self.replacing {
+                continue
+            }
+            let (start, end) = field.cursor.get_range();
+            if start != end {
+                self.selection_rectangle.position = (x + (start as u32 * char_width) as i32, y);
+                self.selection_rectangle.size = ((end - start) as u32 * char_width, line_height);
+                self.selection_rectangle.update(app);
+            }
+            self.cursor_rectangle.position = (x + (field.cursor.idx as u32 * char_width) as i32, y + 2);
+            self.cursor_rectangle.size.1 = line_height - 4;
+            self.cursor_rectangle.update(app);
+        }
+
+        // the options on the right of the query, lit while on, then the match count below them
+        let options = [("a=A", self.options.case_insensitive), ("W", self.options.whole_word), (".*", self.options.regex)];
+        let mut option_x = position.0 + width as i32 - (10 * char_width) as i32;
+        for (label, on) in options {
+            let color = if on { SETTINGS!(text color 4 u8) } else { SETTINGS!(bg light 4 u8) };
+            app.font_handler.style_flagged(0).draw_text(app, option_x, position.1, label, scale, (None, None, None, None), z, color, 0);
+            option_x += ((label.len() + 1) as u32 * char_width) as i32;
+        }
+        let status_x = position.0 + width as i32 - ((self.status.chars().count() + 1) as u32 * char_width) as i32;
+        let y = position.1 + line_height as i32;
+        app.font_handler.style_flagged(0).draw_text(app, status_x, y, &self.status, scale, (None, None, None, None), z, SETTINGS!(bg light 4 u8), 0);
+    }
+}
@@ -1,23 +1,231 @@
+use std::time::Instant;
+use serde_json::{Map, Value};
 use crate::component::Component;
+use crate::dungeon_session::DungeonSession;
+use crate::es3::ES3Compiler;
+use crate::es3_bytecode::CompiledScript;
+use crate::es3_header::{ScriptHeader, Trigger};
+use crate::es3_interpreter::{ScriptRunner, WorldEvent};
+use crate::project::{self, PROJECT_PATH};
 
+/// where Save and Load keep the dungeon session
+const SAVE_PATH: &str = "data/saves/session.json";
 
+/// uid of the player entity the game is played as
+const PLAYER: &str = "player";
 
-pub struct GameApp {
 
+pub struct GameApp {
+    pub session: DungeonSession,
+    /// compiled scripts with a trigger, started whenever their event happens to their owner
+    triggered_scripts: Vec<(ScriptHeader, CompiledScript)>,
+    /// scripts that are still running, each is resumed once per frame,
+    /// with the index in `triggered_scripts` of the tick script it was started from
+    scripts: Vec<(Option<usize>, Box<dyn ScriptRunner>)>,
+    last_update: Instant
 }
 
 impl GameApp {
-    pub fn new() -> Self {
+    /// `seed` fixes the session's randomness, see `--seed`
+    pub fn new(seed: Option<u64>) -> Self {
+        let project = project::load(PROJECT_PATH).unwrap_or_else(|e| {
+            eprintln!("Error: could not load the project: {}", e);
+            Map::new()
+        });
+        Self::with_project(seed, &project)
+    }
+
+    /// starts a session of `project` with the player in its starting room
+    fn with_project(seed: Option<u64>, project: &Map<String, Value>) -> Self {
+        let mut game = Self {
+            session: DungeonSession::new(seed),
+            triggered_scripts: Vec::new(),
+            scripts: Vec::new(),
+            last_update: Instant::now()
+        };
+        game.add_project_scripts(project);
+
+        match project::starting_room(project) {
+            Some(room) => {
+                if let Err(e) = game.session.spawn_player(PLAYER, room) {
+                    eprintln!("Error: could not spawn the player: {}", e);
+                }
+            }
+            None => eprintln!("Error: the project has no room to start in")
+        }
+        game
+    }
+
+    /// Compiles the scripts in the project and registers the ones with a trigger, see `add_script`.
+    /// Scripts with errors are left out
+    fn add_project_scripts(&mut self, project: &Map<String, Value>) {
+        for (id, source) in project::scripts(project) {
+            let mut compiler = ES3Compiler::new();
+            compiler.tokenize(source);
+            if let Err(errors) = compiler.parse() {
+                eprintln!("Error: script {} has {} error(s) and won't run", id, errors.len());
+                continue
+            }
+            let program = CompiledScript::Json(compiler.compile());
+            if program.header().trigger.is_some() {
+                if let Err(e) = self.add_script(program) {
+                    eprintln!("Error: could not add script {}: {}", id, e);
+                }
+            }
+        }
+    }
 
-        Self {
+    /// starts running a compiled script for `player`, it first runs on the next update
+    pub fn run_script(&mut self, program: &CompiledScript, player: &str, owner: Option<String>) -> Result<(), String> {
+        self.scripts.push((None, program.start(player, owner)?));
+        Ok(())
+    }
+
+    /// Registers a compiled script to be started by the event in its header.
+    /// Scripts without both an owner and a trigger can only be started with `run_script`
+    pub fn add_script(&mut self, program: CompiledScript) -> Result<(), String> {
+        let header = program.header();
+        if header.owner.is_none() || header.trigger.is_none() {
+            return Err("a triggered script needs both an owner and a trigger header".to_string())
+        }
+        self.triggered_scripts.push((header, program));
+        Ok(())
+    }
+
+    /// starts every script owned by `owner` that runs on `trigger`
+    fn trigger(&mut self, trigger: Trigger, owner: &str, player: &str) {
+        let programs: Vec<CompiledScript> = self.triggered_scripts.iter()
+            .filter(|(header, _)| header.trigger == Some(trigger) && header.owner.as_deref() == Some(owner))
+            .map(|(_, program)| program.clone())
+            .collect();
 
+        for program in programs {
+            if let Err(e) = self.run_script(&program, player, Some(owner.to_string())) {
+                eprintln!("Error: could not start {} script of {}: {}", trigger.name(), owner, e);
+            }
         }
     }
+
+    /// starts every tick script that isn't still running from an earlier tick
+    fn tick(&mut self) {
+        for (index, (header, program)) in self.triggered_scripts.iter().enumerate() {
+            if header.trigger != Some(Trigger::Tick) || self.scripts.iter().any(|(tick, _)| *tick == Some(index)) {
+                continue
+            }
+            match program.start(PLAYER, header.owner.clone()) {
+                Ok(script) => self.scripts.push((Some(index), script)),
+                Err(e) => eprintln!("Error: could not start tick script of {}: {}", header.owner.as_deref().unwrap_or_default(), e)
+            }
+        }
+    }
+
+    /// runs the interact scripts of the player's room and of everything in it
+    fn interact(&mut self) {
+        let Some(room) = self.session.world.entities.get(PLAYER).and_then(|player| player.room.clone()) else {
+            return
+        };
+        let mut owners = vec![room.clone()];
+        for entity in self.session.world.entities.values() {
+            if entity.uid != PLAYER && entity.room.as_ref() == Some(&room) && !owners.contains(&entity.object) {
+                owners.push(entity.object.clone());
+            }
+        }
+        for owner in owners {
+            self.trigger(Trigger::Interact, &owner, PLAYER);
+        }
+    }
+
+    /// reacts to what happened in the world since the last step, then runs the scripts for `delta` seconds
+    fn step(&mut self, delta: f64) {
+        let events: Vec<WorldEvent> = self.session.world.events.drain(..).collect();
+        for event in events {
+            match event {
+                WorldEvent::Moved { uid, from, to } => {
+                    if let Some(from) = from {
+                        self.trigger(Trigger::Exit, &from, &uid);
+                    }
+                    self.trigger(Trigger::Enter, &to, &uid);
+                }
+            }
+        }
+        self.tick();
+
+        for (_, script) in &mut self.scripts {
+            if let Err(e) = script.resume(&mut self.session.world, delta) {
+                eprintln!("Error: script stopped: {}", e);
+            }
+        }
+        self.scripts.retain(|(_, s)| !s.is_finished());
+    }
 }
 
 impl Component for GameApp {
     fn update(&mut self, app: &mut crate::app::App) {
+        let delta = self.last_update.elapsed().as_secs_f64();
+        self.last_update = Instant::now();
+
+        if app.keybinds.check_binding("Save") {
+            app.keybinds.accept(&app.keybinds.last("Save").unwrap().clone());
+            match self.session.save(SAVE_PATH) {
+                Ok(()) => app.toast(format!("Saved the session, its seed is {}", self.session.seed())),
+                Err(e) => eprintln!("Error: could not save the session: {}", e)
+            }
+        }
+        else if app.keybinds.check_binding("Load") {
+            app.keybinds.accept(&app.keybinds.last("Load").unwrap().clone());
+            match DungeonSession::load(SAVE_PATH) {
+                // running scripts belong to the session being replaced
+                Ok(session) => {
+                    self.session = session;
+                    self.scripts.clear();
+                }
+                Err(e) => eprintln!("Error: could not load the session: {}", e)
+            }
+        }
+        else if app.keybinds.check_binding("Interact") {
+            app.keybinds.accept(&app.keybinds.last("Interact").unwrap().clone());
+            self.interact();
+        }
+
+        self.step(delta);
     }
 
 }
 
+
+#[cfg(test)]
+mod game_tests {
+    use serde_json::json;
+    use crate::game_app::{GameApp, PLAYER};
+
+    #[test]
+    pub fn test_project_scripts_run() {
+        let project = json!({
+            "rooms": [
+                {"id": "emberhollow:rooms/docks/roads/road_4"},
+                {"id": "emberhollow:rooms/docks", "start": true}
+            ],
+            "scripts": [
+                {"id": "emberhollow:scripts/welcome", "source": "#!emberhollow/rooms/docks\n#!enter-script\noutput(\"welcome\")"},
+                {"id": "emberhollow:scripts/bell", "source": "#!emberhollow/rooms/docks\n#!tick-script\noutput(\"ding\")\nwait(1)"},
+                {"id": "emberhollow:scripts/rope", "source": "#!emberhollow/rooms/docks\n#!interact-script\noutput(\"the rope holds\")"}
+            ]
+        });
+        let mut game = GameApp::with_project(Some(1), project.as_object().unwrap());
+        assert_eq!(game.session.world.entities[PLAYER].room.as_deref(), Some("emberhollow:rooms/docks"));
+
+        game.step(0.0);
+        assert_eq!(game.session.world.output, ["welcome", "ding"]);
+
+        // the bell is still waiting, so it isn't started again
+        game.step(0.5);
+        assert_eq!(game.session.world.output.len(), 2);
+        game.step(0.5);
+        game.step(0.0);
+        assert_eq!(game.session.world.output, ["welcome", "ding", "ding"]);
+
+        game.interact();
+        game.step(0.0);
+        assert_eq!(game.session.world.output.last().map(String::as_str), Some("the rope holds"));
+    }
+}
@@ -1,8 +1,25 @@
+use std::any::Any;
 use crate::app::App;
 
-pub trait HistoryEvent {
+pub trait HistoryToAny: 'static {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: 'static + HistoryEvent> HistoryToAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub trait HistoryEvent: HistoryToAny {
     fn redo(&mut self, app: &mut App);
     fn undo(&mut self, app: &mut App);
+
+    /// folds `next`, which happened right after this event, into this one so they undo together.
+    /// Returns whether it did, by default events are never merged
+    fn merge(&mut self, _next: &dyn HistoryEvent) -> bool {
+        false
+    }
 }
 
 
@@ -30,8 +47,13 @@ impl HistoryManager {
         }
     }
     
+    /// adds an event to undo later, merging it into the latest one when that allows it and nothing was undone since
     pub fn add_history(&mut self, hist: impl WrapHistory) {
-        self.history.push(hist.wrap());
+        let hist = hist.wrap();
+        let merged = self.future.is_empty() && self.history.last_mut().is_some_and(|last| last.merge(hist.as_ref()));
+        if !merged {
+            self.history.push(hist);
+        }
         self.future.clear();
     }
     
@@ -8,10 +8,24 @@ mod camera;
 mod canvas;
 mod collider;
 mod component;
+mod dungeon_session;
 mod easing;
 mod editor_app;
 mod es3;
+mod es3_bytecode;
+mod es3_cli;
+mod es3_completion;
+mod es3_debugger;
+mod es3_diagnostics;
+mod es3_format;
+mod es3_header;
+mod es3_interpreter;
+mod es3_lexer;
+mod es3_strings;
+mod es3_symbols;
 mod es3_text_editor;
+mod es3_types;
+mod find_bar;
 mod game_app;
 mod history_manager;
 mod image;
@@ -19,6 +33,7 @@ mod keybinds;
 mod macros;
 mod monitor_info;
 mod object_tree;
+mod project;
 mod rectangle;
 mod settings;
 mod shaders;
@@ -27,6 +42,7 @@ mod storage_component;
 mod text;
 mod texture_atlas;
 mod text_box;
+mod text_buffer;
 mod text_input_handler;
 mod toast_system;
 mod window_frame;
@@ -48,12 +64,18 @@ use crate::text::FontHandler;
 
 fn main() -> Result<(), String> {
 
+    // `IDNHRust es3 ...` runs the script tools without a window
+    if env::args().nth(1).as_deref() == Some("es3") {
+        std::process::exit(es3_cli::run(env::args().skip(2).collect()));
+    }
+
     if is_wsl() {
         println!("Windows subsystem for linux is not supported. Both windows and Linux are individually supported however. (Use either of those instead)");
         return Ok(());
     }
 
     let mut args: VecDeque<String> = env::args().collect();
+    let mut seed = None;
 
     while !args.is_empty() {
         let arg = args.pop_front().unwrap();
@@ -74,10 +96,16 @@ fn main() -> Result<(), String> {
             ghost(pos_x, pos_y, width as i32, height as i32, side);
             return Ok(());
         }
+
+        if arg == "--seed" {
+            const ERR_MSG: &str = "argument after --seed must be in form 'u64'";
+            seed = Some(args.pop_front().expect(ERR_MSG)
+                .parse::<u64>().expect(ERR_MSG));
+        }
     }
 
 
-    main_app();
+    main_app(seed);
 
     Ok(())
 
@@ -176,7 +204,7 @@ fn ghost(pos_x: i32, pos_y: i32, width: i32, height: i32, side: u8) {
 
 }
 
-fn main_app() {
+fn main_app(seed: Option<u64>) {
 
     let sdl = sdl2::init().unwrap();
     let video_subsystem = sdl.video().unwrap();
@@ -219,7 +247,7 @@ fn main_app() {
 
     let shader = Shaders::new();
 
-    let mut app = App::new(shader, font_handler, window_width, window_height, &mut window, monitors);
+    let mut app = App::new(shader, font_handler, window_width, window_height, &mut window, monitors, seed);
 
     unsafe {
         gl::Enable(gl::BLEND);
@@ -0,0 +1,50 @@
+use std::fs;
+use std::path::Path;
+use serde_json::{Map, Value};
+
+/// where the editor keeps the project's objects, and the game finds its scripts
+pub const PROJECT_PATH: &str = "data/project.json";
+
+/// group of the objects whose `source` attribute is an ES3 script
+pub const SCRIPTS_GROUP: &str = "scripts";
+
+pub const ROOMS_GROUP: &str = "rooms";
+
+/// Reads a project: a JSON object with the objects of each group ("rooms", "scripts", ...) in an array under its name.
+/// A project that was never saved is empty
+pub fn load(path: impl AsRef<Path>) -> Result<Map<String, Value>, String> {
+    if !path.as_ref().exists() {
+        return Ok(Map::new())
+    }
+    let project = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    match serde_json::from_str(&project).map_err(|e| e.to_string())? {
+        Value::Object(groups) => Ok(groups),
+        _ => Err("project is not a JSON object".to_string())
+    }
+}
+
+pub fn save(path: impl AsRef<Path>, project: Map<String, Value>) -> Result<(), String> {
+    if let Some(parent) = path.as_ref().parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let project = serde_json::to_string_pretty(&Value::Object(project)).map_err(|e| e.to_string())?;
+    fs::write(path, project).map_err(|e| e.to_string())
+}
+
+/// every object in the project with the group it is in
+pub fn objects(project: &Map<String, Value>) -> impl Iterator<Item = (&String, &Value)> {
+    project.iter().flat_map(|(group, objects)| objects.as_array().into_iter().flatten().map(move |object| (group, object)))
+}
+
+/// id and source of every object in the scripts group
+pub fn scripts(project: &Map<String, Value>) -> impl Iterator<Item = (&str, &str)> {
+    project.get(SCRIPTS_GROUP).and_then(Value::as_array).into_iter().flatten()
+        .filter_map(|object| Some((object["id"].as_str()?, object["source"].as_str()?)))
+}
+
+/// id of the room players start in: the first room whose `start` attribute is true, or else the first room
+pub fn starting_room(project: &Map<String, Value>) -> Option<&str> {
+    let rooms: Vec<&Value> = project.get(ROOMS_GROUP).and_then(Value::as_array).into_iter().flatten().collect();
+    rooms.iter().find(|room| room["start"] == Value::Bool(true)).or(rooms.first())
+        .and_then(|room| room["id"].as_str())
+}
@@ -5,7 +5,7 @@ use rusttype::{point, Font, GlyphId, Scale};
 use crate::{app::App, component::Component, texture_atlas::convert_tex_to_gl, macros::CONST};
 use crate::component::setup_gl_pos_tex;
 use crate::es3::style_flags;
-use crate::text_input_handler::IdxSize;
+use crate::text_input_handler::{IdxSize, TAB_WIDTH};
 
 pub struct CharAtlas {
     chars: HashMap<String, ((u32, u32, u32, u32), i32)>,
@@ -138,6 +138,10 @@ impl CharAtlas {
             *draw_y += ((HEIGHT as f32 * scale) + (4.0 * scale)) as u32;
             *draw_x = 0;
         }
+        else if character == "\t" {
+            let stop = ((HEIGHT as f32 / 2.0 * scale) + (4.0 * scale)) as u32 * TAB_WIDTH as u32;
+            *draw_x = (*draw_x / stop.max(1) + 1) * stop;
+        }
         else if character == " " || self.chars.contains_key(character) {
             *draw_x += ((HEIGHT as f32 / 2.0 * scale) + (4.0 * scale)) as u32;
         }
@@ -153,6 +157,9 @@ impl CharAtlas {
         else if character == " " {
             *draw_x += ((HEIGHT as f32 / 2.0 * scale) + (4.0 * scale)) as u32;
         }
+        else if character == "\t" {
+            self.skip_char(draw_x, draw_y, character, scale);
+        }
         else if self.chars.contains_key(character) {
             let rect = self.chars.get(character).unwrap();
 
@@ -372,6 +379,34 @@ impl Text {
         
         None
     }
+
+    /// Inverse of `get_draw_offset`: the index whose draw offset is nearest to `offset`, on the line `offset` is in.
+    /// Offsets past the last line pick from the last line
+    pub fn get_index_at(&self, app: &App, offset: (i32, i32)) -> IdxSize {
+        let font = app.font_handler.style_flagged(self.styles);
+        let mut line_height = 0;
+        font.skip_char(&mut 0, &mut line_height, "\n", self.scale);
+        let line = (offset.1.max(0) as u32 / line_height.max(1)).min(self.content.matches('\n').count() as u32);
+        let line_y = line * line_height;
+
+        let target_x = offset.0.max(0) as u32;
+        let (mut x, mut y): (u32, u32) = (0, 0);
+        let mut nearest = (0, u32::MAX);
+        let mut chars = self.content.chars();
+        for idx in 0.. {
+            if y > line_y {
+                break
+            }
+            if y == line_y && x.abs_diff(target_x) < nearest.1 {
+                nearest = (idx, x.abs_diff(target_x));
+            }
+            match chars.next() {
+                Some(c) => font.skip_char(&mut x, &mut y, &c.to_string(), self.scale),
+                None => break
+            }
+        }
+        nearest.0
+    }
     
 }
 
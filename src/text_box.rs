@@ -1,46 +1,14 @@
-use std::mem;
 use std::time::Instant;
 use crate::app::App;
-use crate::component::{Component, ComponentToAny};
+use crate::component::Component;
 use crate::component_system::{CompRef, SystematicComponent};
-use crate::history_manager::HistoryEvent;
-use crate::macros::{cast_component, collides, font_size};
+use crate::macros::{collides, font_size, SETTINGS};
 use crate::rectangle::Rectangle;
 use crate::text::Text;
 use crate::text_input_handler::{IdxSize, TextInputHandler};
 
-pub struct TextTypeHistory {
-    uuid: String,
-    data: String,
-}
-
-impl TextTypeHistory {
-    pub fn new(uid: impl ToString, data: impl ToString) -> Self {
-        Self {
-            uuid: uid.to_string(),
-            data: data.to_string()
-        }
-    }
-}
-
-impl HistoryEvent for TextTypeHistory {
-    fn redo(&mut self, app: &mut App) {
-        
-        let tb = app.component_system.take(&self.uuid);
-        
-        if let Some(text_box) = tb {
-            let mut text_box: Box<Textbox> = cast_component!(text_box => owned Textbox);
-            mem::swap(&mut text_box.handler.content, &mut self.data);
-            
-            app.component_system.add(&self.uuid, text_box);
-        }
-        
-    }
-
-    fn undo(&mut self, app: &mut App) {
-        self.redo(app)
-    }
-}
+/// clicks on the same spot closer together than this count as a double or triple click
+pub const MULTI_CLICK_SECONDS: f64 = 0.4;
 
 pub struct Textbox {
     handler: TextInputHandler,
@@ -54,6 +22,13 @@ pub struct Textbox {
     z_index: f32,
     cursor_blink_delta: Instant,
     cursor_rectangle: Rectangle,
+    selection_rectangle: Rectangle,
+    /// when and where the last click was, and how many clicks in a row it made
+    last_click: (Instant, IdxSize, u8),
+    /// where the selection being dragged with the mouse started
+    drag_anchor: Option<IdxSize>,
+    /// the `(line, visual column)` a column selection being dragged with alt+shift started at
+    column_anchor: Option<(IdxSize, IdxSize)>,
     pub uid: String,
     offset: (i32, i32),
 }
@@ -74,6 +49,10 @@ impl Textbox {
             z_index,
             cursor_blink_delta: Instant::now(),
             cursor_rectangle: Rectangle::new(0, 0, 1, 16, (255, 255, 255, 255), (z_index + 0.01).min(1.0)),
+            selection_rectangle: Rectangle::new(0, 0, 0, 0, SETTINGS!(text highlight 4 u8), z_index - 0.00005),
+            last_click: (Instant::now(), 0, 0),
+            drag_anchor: None,
+            column_anchor: None,
             uid: "".to_string(),
             offset: (0, 0),
         }.systemize(&mut app.component_system);
@@ -109,6 +88,84 @@ impl Textbox {
     pub fn encapsulate_region(&mut self, region: (i32, i32, u32, u32), padding: u32) {
         
     }
+
+    /// index of the character nearest to the mouse
+    fn index_at_mouse(&self, app: &App) -> IdxSize {
+        let (x, y) = app.mouse.position;
+        self.text.get_index_at(app, (x - self.text.position.0 - self.offset.0, y - self.text.position.1 - self.offset.1))
+    }
+
+    /// The `(line, visual column)` nearest to the mouse, which can be past the end of a line. The font is monospaced
+    fn column_at_mouse(&self, app: &App) -> (IdxSize, IdxSize) {
+        let font = app.font_handler.style_flagged(self.text.styles);
+        let (mut char_width, mut line_height) = (0, 0);
+        font.skip_char(&mut char_width, &mut 0, " ", self.text.scale);
+        font.skip_char(&mut 0, &mut line_height, "\n", self.text.scale);
+
+        let (x, y) = (app.mouse.position.0 - self.text.position.0 - self.offset.0, app.mouse.position.1 - self.text.position.1 - self.offset.1);
+        let column = (x + char_width as i32 / 2).max(0) as u32 / char_width.max(1);
+        ((y.max(0) as u32 / line_height.max(1)) as IdxSize, column as IdxSize)
+    }
+
+    /// Places the cursor at the mouse and starts dragging a selection from there. A double click selects a word,
+    /// a triple click the line, shift+click selects up to the mouse and alt+click adds or removes a cursor.
+    /// Alt+shift starts dragging a column selection instead
+    fn click(&mut self, app: &App) {
+        let idx = self.index_at_mouse(app);
+        let (time, last_idx, count) = self.last_click;
+        let count = if time.elapsed().as_secs_f64() < MULTI_CLICK_SECONDS && last_idx == idx { count % 3 + 1 } else { 1 };
+        self.last_click = (Instant::now(), idx, count);
+        self.drag_anchor = None;
+        self.column_anchor = None;
+
+        if app.keyboard.alt_held && app.keyboard.shift_held {
+            let anchor = self.column_at_mouse(app);
+            self.handler.select_columns(anchor, anchor);
+            self.column_anchor = Some(anchor);
+        }
+        else if app.keyboard.alt_held {
+            self.handler.add_cursor(idx);
+        }
+        else if count == 2 {
+            self.handler.select_word(idx);
+        }
+        else if count == 3 {
+            self.handler.select_line(idx);
+        }
+        else {
+            let cursor = &self.handler.cursor;
+            let anchor = if app.keyboard.shift_held { cursor.selection_idx.unwrap_or(cursor.idx) } else { idx };
+            self.handler.select_to(anchor, idx);
+            self.drag_anchor = Some(anchor);
+        }
+    }
+
+    /// highlights each line of the selected text, a selected newline shows as a space
+    fn render_selections(&mut self, app: &mut App) {
+        let font = app.font_handler.style_flagged(self.text.styles);
+        let (mut space_width, mut line_height) = (0, 0);
+        font.skip_char(&mut space_width, &mut 0, " ", self.text.scale);
+        font.skip_char(&mut 0, &mut line_height, "\n", self.text.scale);
+
+        for (start, end) in self.handler.get_selections() {
+            let mut from = start;
+            while from < end {
+                let (line, _) = self.handler.get_text_pos(from).unwrap();
+                let next_line = self.handler.content.line_to_char(line + 1).unwrap_or(self.handler.content.len_chars());
+                let to = end.min(next_line);
+                let newline = to == next_line && self.handler.content.char_at(to - 1) == Some('\n');
+
+                let (Some(p0), Some(p1)) = (self.text.get_draw_offset(app, from), self.text.get_draw_offset(app, if newline { to - 1 } else { to })) else {
+                    break
+                };
+                let width = p1.0.saturating_sub(p0.0) + if newline { space_width } else { 0 };
+                self.selection_rectangle.position = (p0.0 as i32 + 3 + self.position.0, p0.1 as i32 + self.position.1);
+                self.selection_rectangle.size = (width.max(1), line_height);
+                self.selection_rectangle.update(app);
+                from = to;
+            }
+        }
+    }
     
 }
 
@@ -129,9 +186,22 @@ impl Component for Textbox {
             self.selected = self.hovered;
             if self.hovered {
                 self.cursor_blink_delta = Instant::now();
-                
-                let dx = self.position.0;
-                
+                self.click(app);
+            }
+        }
+        else if !app.mouse.left_held {
+            self.drag_anchor = None;
+            self.column_anchor = None;
+        }
+        else if let Some(anchor) = self.column_anchor {
+            let head = self.column_at_mouse(app);
+            self.handler.select_columns(anchor, head);
+        }
+        else if let Some(anchor) = self.drag_anchor {
+            let idx = self.index_at_mouse(app);
+            if idx != self.handler.cursor.idx {
+                self.handler.select_to(anchor, idx);
+                self.cursor_blink_delta = Instant::now();
             }
         }
 
@@ -153,12 +223,9 @@ impl Component for Textbox {
                 dy *= h as IdxSize;
             }
             
-            if self.handler.should_update_history() {
-                // let hist = TextTypeHistory::new(app.get_child_path(), &self.handler.content);
-                // 
-                // app.history.add_history(hist);
-            }
-            
+        }
+        else if self.handler.apply_history() {
+            self.cursor_blink_delta = Instant::now();
         }
 
         self.text.content = self.handler.content.to_string();
@@ -194,6 +261,8 @@ impl Component for Textbox {
         
         
         if self.selected {
+            self.render_selections(app);
+
             let d = self.cursor_blink_delta.elapsed().as_secs_f64();
             if d % 1.0 <= 0.5 {
                 let p = self.text.get_draw_offset(app, self.handler.cursor.idx).unwrap();
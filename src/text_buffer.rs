@@ -0,0 +1,518 @@
+use std::cell::OnceCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
+
+/// longest a chunk of text is allowed to grow to in place, in bytes
+const MAX_CHUNK: usize = 1024;
+/// size of the chunks new text is cut into, in bytes
+const CHUNK: usize = 512;
+
+/// A leaf of the rope with its place in the tree.
+/// The tree is a treap: in order it spells out the text, and each node's priority is at least its children's,
+/// which keeps it balanced with high probability
+#[derive(Clone)]
+struct Node {
+    text: String,
+    priority: u64,
+    left: Option<Box<Node>>,
+    right: Option<Box<Node>>,
+    /// totals for the whole subtree, this node's text included
+    chars: usize,
+    bytes: usize,
+    newlines: usize
+}
+
+impl Node {
+    fn new(text: String, priority: u64) -> Box<Self> {
+        let mut node = Box::new(Self { text, priority, left: None, right: None, chars: 0, bytes: 0, newlines: 0 });
+        node.update();
+        node
+    }
+
+    /// recomputes the totals after the text or a child changed
+    fn update(&mut self) {
+        self.chars = self.text.chars().count() + chars(&self.left) + chars(&self.right);
+        self.bytes = self.text.len() + bytes(&self.left) + bytes(&self.right);
+        self.newlines = self.text.matches('\n').count() + newlines(&self.left) + newlines(&self.right);
+    }
+}
+
+fn chars(node: &Option<Box<Node>>) -> usize {
+    node.as_ref().map_or(0, |n| n.chars)
+}
+
+fn bytes(node: &Option<Box<Node>>) -> usize {
+    node.as_ref().map_or(0, |n| n.bytes)
+}
+
+fn newlines(node: &Option<Box<Node>>) -> usize {
+    node.as_ref().map_or(0, |n| n.newlines)
+}
+
+/// byte index of the `idx`th character of `text`, or its length past the end
+fn byte_of(text: &str, idx: usize) -> usize {
+    text.char_indices().nth(idx).map_or(text.len(), |(b, _)| b)
+}
+
+/// splits a tree into the first `idx` characters and the rest, cutting a chunk in two if needed
+fn split(node: Option<Box<Node>>, idx: usize) -> (Option<Box<Node>>, Option<Box<Node>>) {
+    let Some(mut node) = node else {
+        return (None, None)
+    };
+    let left_chars = chars(&node.left);
+    let own_chars = node.chars - left_chars - chars(&node.right);
+
+    if idx <= left_chars {
+        let (a, b) = split(node.left.take(), idx);
+        node.left = b;
+        node.update();
+        (a, Some(node))
+    } else if idx >= left_chars + own_chars {
+        let (a, b) = split(node.right.take(), idx - left_chars - own_chars);
+        node.right = a;
+        node.update();
+        (Some(node), b)
+    } else {
+        // the tail keeps the node's priority, so the right subtree can hang below it
+        let tail = node.text.split_off(byte_of(&node.text, idx - left_chars));
+        let mut tail = Node::new(tail, node.priority);
+        tail.right = node.right.take();
+        tail.update();
+        node.update();
+        (Some(node), Some(tail))
+    }
+}
+
+/// joins two trees, every character of `a` coming before those of `b`
+fn merge(a: Option<Box<Node>>, b: Option<Box<Node>>) -> Option<Box<Node>> {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(mut a), Some(mut b)) => {
+            if a.priority > b.priority {
+                a.right = merge(a.right.take(), Some(b));
+                a.update();
+                Some(a)
+            } else {
+                b.left = merge(Some(a), b.left.take());
+                b.update();
+                Some(b)
+            }
+        }
+    }
+}
+
+/// inserts into the chunk the position falls in if it has room, returning whether it did
+fn insert_in_place(node: &mut Option<Box<Node>>, idx: usize, text: &str) -> bool {
+    let Some(node) = node else {
+        return false
+    };
+    let left_chars = chars(&node.left);
+    let own_chars = node.chars - left_chars - chars(&node.right);
+
+    let inserted = if idx < left_chars {
+        insert_in_place(&mut node.left, idx, text)
+    } else if idx <= left_chars + own_chars {
+        if node.text.len() + text.len() > MAX_CHUNK {
+            return false
+        }
+        let at = byte_of(&node.text, idx - left_chars);
+        node.text.insert_str(at, text);
+        true
+    } else {
+        insert_in_place(&mut node.right, idx - left_chars - own_chars, text)
+    };
+    if inserted {
+        node.update();
+    }
+    inserted
+}
+
+/// removes characters that all fall in a single chunk, returning whether they did
+fn remove_in_place(node: &mut Option<Box<Node>>, range: Range<usize>) -> bool {
+    let Some(node) = node else {
+        return false
+    };
+    let left_chars = chars(&node.left);
+    let own_chars = node.chars - left_chars - chars(&node.right);
+
+    let removed = if range.end <= left_chars {
+        remove_in_place(&mut node.left, range)
+    } else if range.start >= left_chars + own_chars {
+        let offset = left_chars + own_chars;
+        remove_in_place(&mut node.right, range.start - offset..range.end - offset)
+    } else if range.start >= left_chars && range.end <= left_chars + own_chars {
+        let start = byte_of(&node.text, range.start - left_chars);
+        let end = byte_of(&node.text, range.end - left_chars);
+        node.text.replace_range(start..end, "");
+        true
+    } else {
+        false
+    };
+    if removed {
+        node.update();
+    }
+    removed
+}
+
+/// appends the characters of `range` in the tree to `out`, skipping subtrees outside of it
+fn collect(node: &Option<Box<Node>>, range: &Range<usize>, offset: usize, out: &mut String) {
+    let Some(node) = node else {
+        return
+    };
+    if range.end <= offset || offset + node.chars <= range.start {
+        return
+    }
+    collect(&node.left, range, offset, out);
+
+    let start = offset + chars(&node.left);
+    let own_chars = node.text.chars().count();
+    let from = range.start.max(start) - start;
+    let to = range.end.min(start + own_chars).saturating_sub(start);
+    if from < to {
+        out.push_str(&node.text[byte_of(&node.text, from)..byte_of(&node.text, to)]);
+    }
+    collect(&node.right, range, start + own_chars, out);
+}
+
+/// Text stored as a rope, so characters and lines can be found and edited in logarithmic time however long it gets.
+/// Positions are counted in characters, like the cursors of `TextInputHandler`.
+/// `as_str` flattens the text once after each change, for the code that needs all of it at once
+#[derive(Clone)]
+pub struct TextBuffer {
+    root: Option<Box<Node>>,
+    /// state of the xorshift generator giving new nodes their priority
+    seed: u64,
+    flat: OnceCell<String>
+}
+
+impl TextBuffer {
+    pub fn new(text: &str) -> Self {
+        let mut buffer = Self { root: None, seed: 0x2545_f491_4f6c_dd1d, flat: OnceCell::new() };
+        buffer.insert(0, text);
+        buffer
+    }
+
+    fn priority(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    pub fn len_chars(&self) -> usize {
+        chars(&self.root)
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        bytes(&self.root)
+    }
+
+    /// number of lines, an empty buffer or one ending in a newline counts the empty last line
+    pub fn len_lines(&self) -> usize {
+        newlines(&self.root) + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none() || self.len_chars() == 0
+    }
+
+    /// the whole text, built once after each change
+    pub fn as_str(&self) -> &str {
+        self.flat.get_or_init(|| self.slice(0..self.len_chars()))
+    }
+
+    /// inserts `text` before character `idx`, which is clamped to the end
+    pub fn insert(&mut self, idx: usize, text: &str) {
+        if text.is_empty() {
+            return
+        }
+        self.flat = OnceCell::new();
+        let idx = idx.min(self.len_chars());
+        if insert_in_place(&mut self.root, idx, text) {
+            return
+        }
+
+        let mut middle = None;
+        let mut rest = text;
+        while !rest.is_empty() {
+            let mut end = rest.len().min(CHUNK);
+            while !rest.is_char_boundary(end) {
+                end += 1;
+            }
+            let priority = self.priority();
+            middle = merge(middle, Some(Node::new(rest[..end].to_string(), priority)));
+            rest = &rest[end..];
+        }
+
+        let (before, after) = split(self.root.take(), idx);
+        self.root = merge(merge(before, middle), after);
+    }
+
+    /// removes the characters in `range`, which is clamped to the text
+    pub fn remove(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.len_chars());
+        if range.start >= end {
+            return
+        }
+        self.flat = OnceCell::new();
+        if remove_in_place(&mut self.root, range.start..end) {
+            return
+        }
+
+        let (before, rest) = split(self.root.take(), range.start);
+        let (_, after) = split(rest, end - range.start);
+        self.root = merge(before, after);
+    }
+
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        let start = range.start;
+        self.remove(range);
+        self.insert(start, text);
+    }
+
+    /// the characters in `range`, clamped to the text
+    pub fn slice(&self, range: Range<usize>) -> String {
+        let mut out = String::new();
+        collect(&self.root, &range, 0, &mut out);
+        out
+    }
+
+    pub fn char_at(&self, idx: usize) -> Option<char> {
+        let mut node = self.root.as_ref()?;
+        let mut idx = idx;
+        loop {
+            let left_chars = chars(&node.left);
+            let own_chars = node.chars - left_chars - chars(&node.right);
+            if idx < left_chars {
+                node = node.left.as_ref()?;
+            } else if idx < left_chars + own_chars {
+                return node.text.chars().nth(idx - left_chars)
+            } else {
+                idx -= left_chars + own_chars;
+                node = node.right.as_ref()?;
+            }
+        }
+    }
+
+    /// the 0-based line character `idx` is on, past the end is the last line
+    pub fn char_to_line(&self, idx: usize) -> usize {
+        let mut line = 0;
+        let mut idx = idx;
+        let mut current = &self.root;
+        while let Some(node) = current {
+            let left_chars = chars(&node.left);
+            let own_chars = node.chars - left_chars - chars(&node.right);
+            if idx <= left_chars {
+                current = &node.left;
+                continue
+            }
+            line += newlines(&node.left);
+            idx -= left_chars;
+            if idx <= own_chars {
+                return line + node.text.chars().take(idx).filter(|&c| c == '\n').count()
+            }
+            line += node.text.matches('\n').count();
+            idx -= own_chars;
+            current = &node.right;
+        }
+        line
+    }
+
+    /// the character index line `line` starts at, or `None` past the last line
+    pub fn line_to_char(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            return Some(0)
+        }
+        // the line starts right after its `line`th newline
+        let mut remaining = line;
+        let mut idx = 0;
+        let mut current = &self.root;
+        while let Some(node) = current {
+            let left_newlines = newlines(&node.left);
+            if remaining <= left_newlines {
+                current = &node.left;
+                continue
+            }
+            remaining -= left_newlines;
+            idx += chars(&node.left);
+
+            let own_newlines = node.text.matches('\n').count();
+            if remaining <= own_newlines {
+                let (at, _) = node.text.chars().enumerate().filter(|&(_, c)| c == '\n').nth(remaining - 1)?;
+                return Some(idx + at + 1)
+            }
+            remaining -= own_newlines;
+            idx += node.text.chars().count();
+            current = &node.right;
+        }
+        None
+    }
+
+    /// the text of line `line` without its newline
+    pub fn line(&self, line: usize) -> Option<String> {
+        let start = self.line_to_char(line)?;
+        Some(self.slice(start..start + self.line_len(line)?))
+    }
+
+    /// length of line `line` in characters, without its newline
+    pub fn line_len(&self, line: usize) -> Option<usize> {
+        let start = self.line_to_char(line)?;
+        let end = self.line_to_char(line + 1).map_or(self.len_chars(), |next| next - 1);
+        Some(end - start)
+    }
+
+    /// byte index of character `idx` in `as_str`, past the end is the length in bytes
+    pub fn char_to_byte(&self, idx: usize) -> usize {
+        let mut byte = 0;
+        let mut idx = idx;
+        let mut current = &self.root;
+        while let Some(node) = current {
+            let left_chars = chars(&node.left);
+            let own_chars = node.chars - left_chars - chars(&node.right);
+            if idx <= left_chars {
+                current = &node.left;
+                continue
+            }
+            byte += bytes(&node.left);
+            idx -= left_chars;
+            if idx <= own_chars {
+                return byte + byte_of(&node.text, idx)
+            }
+            byte += node.text.len();
+            idx -= own_chars;
+            current = &node.right;
+        }
+        byte
+    }
+
+    /// inverse of `char_to_byte`, a byte inside a character counts as that character
+    pub fn byte_to_char(&self, byte: usize) -> usize {
+        let mut idx = 0;
+        let mut byte = byte;
+        let mut current = &self.root;
+        while let Some(node) = current {
+            let left_bytes = bytes(&node.left);
+            if byte <= left_bytes {
+                current = &node.left;
+                continue
+            }
+            idx += chars(&node.left);
+            byte -= left_bytes;
+            if byte <= node.text.len() {
+                return idx + node.text.char_indices().take_while(|&(b, _)| b < byte).count()
+            }
+            idx += node.text.chars().count();
+            byte -= node.text.len();
+            current = &node.right;
+        }
+        idx
+    }
+}
+
+impl Default for TextBuffer {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl From<&str> for TextBuffer {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<String> for TextBuffer {
+    fn from(text: String) -> Self {
+        Self::new(&text)
+    }
+}
+
+impl Display for TextBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Debug for TextBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl PartialEq for TextBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialEq<str> for TextBuffer {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for TextBuffer {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for TextBuffer {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+
+#[cfg(test)]
+mod text_buffer_tests {
+    use crate::text_buffer::TextBuffer;
+
+    #[test]
+    pub fn test_edits_match_string() {
+        let mut buffer = TextBuffer::new("");
+        let mut expected: Vec<char> = Vec::new();
+        let mut seed: u64 = 7;
+        let long = "x".repeat(700);
+
+        for step in 0..2000 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let at = (seed >> 33) as usize % (expected.len() + 1);
+            if step % 3 == 2 && !expected.is_empty() {
+                let end = (at + (seed >> 20) as usize % 40).min(expected.len());
+                buffer.remove(at..end);
+                expected.drain(at..end);
+            } else {
+                let text = ["é", "ab\ncd", "\n", "line of text ", &long][(seed >> 40) as usize % 5];
+                buffer.insert(at, text);
+                expected.splice(at..at, text.chars());
+            }
+        }
+
+        let expected: String = expected.into_iter().collect();
+        assert_eq!(buffer, expected);
+        assert_eq!((buffer.len_chars(), buffer.len_bytes()), (expected.chars().count(), expected.len()));
+        assert_eq!(buffer.len_lines(), expected.split('\n').count());
+        for (i, line) in expected.split('\n').enumerate() {
+            assert_eq!(buffer.line(i).as_deref(), Some(line));
+        }
+        assert_eq!(buffer.line(buffer.len_lines()), None);
+    }
+
+    #[test]
+    pub fn test_positions() {
+        let mut buffer = TextBuffer::new("ab\nçd\n\nef");
+        assert_eq!(buffer.char_to_line(2), 0);
+        assert_eq!(buffer.char_to_line(3), 1);
+        assert_eq!(buffer.char_to_line(10), 3);
+        assert_eq!(buffer.line_to_char(3), Some(7));
+        assert_eq!(buffer.line_len(2), Some(0));
+        assert_eq!((buffer.char_to_byte(4), buffer.byte_to_char(5)), (5, 4));
+        assert_eq!(buffer.char_at(3), Some('ç'));
+
+        buffer.replace(3..5, "xyz");
+        assert_eq!(buffer, "ab\nxyz\n\nef");
+        assert_eq!(buffer.slice(1..5), "b\nxy");
+    }
+}
@@ -1,14 +1,21 @@
+use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::mem;
-use std::str::FromStr;
+use std::ops::Range;
+use std::rc::{Rc, Weak};
 use clipboard::{ClipboardContext, ClipboardProvider};
 use fancy_regex::Regex;
 use crate::app::App;
+use crate::history_manager::{HistoryEvent, HistoryManager};
+use crate::text_buffer::TextBuffer;
 
 // Using this ensures that all text edit indexing uses the same type
 pub type IdxSize = usize;
 
-#[derive(Eq)]
+/// columns between tab stops, for tab characters and the Tab key
+pub const TAB_WIDTH: IdxSize = 4;
+
+#[derive(Eq, Debug)]
 pub struct Cursor {
     pub idx: IdxSize,
     pub selection_idx: Option<IdxSize>,
@@ -97,9 +104,137 @@ impl Clone for Cursor {
 }
 
 
+/// One change to the content: `removed` starting at character `start` was replaced with `inserted`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub start: IdxSize,
+    pub removed: String,
+    pub inserted: String
+}
+
+/// what made an edit record, consecutive typing or backspacing of the same kind of character undoes as one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditKind {
+    /// typing, with the A/N/S bits of the handler's flags
+    Typing(u8),
+    /// backspacing, with the A/N/S bits of the handler's flags
+    Backspace(u8),
+    Other
+}
+
+/// The changes made by one operation, in the order they were made, along with the primary cursor and
+/// the other cursors before and after, so undoing it restores the text and every cursor
+#[derive(Clone, Debug, PartialEq)]
+pub struct EditRecord {
+    pub edits: Vec<Edit>,
+    pub before: (Cursor, Vec<Cursor>),
+    pub after: (Cursor, Vec<Cursor>),
+    pub kind: EditKind
+}
+
+enum HistoryAction {
+    Undo(EditRecord),
+    Redo(EditRecord)
+}
+
+/// An `EditRecord` in the app's `HistoryManager`.
+/// The handler it came from is usually the one asking for an undo, so it can't be looked up while history runs.
+/// Instead the record is handed back through a queue the handler shares with it, see `TextInputHandler::apply_history`
+pub struct TextEditHistory {
+    record: EditRecord,
+    queue: Weak<RefCell<Vec<HistoryAction>>>
+}
+
+impl HistoryEvent for TextEditHistory {
+    fn redo(&mut self, _app: &mut App) {
+        if let Some(queue) = self.queue.upgrade() {
+            queue.borrow_mut().push(HistoryAction::Redo(self.record.clone()));
+        }
+    }
+
+    fn undo(&mut self, _app: &mut App) {
+        if let Some(queue) = self.queue.upgrade() {
+            queue.borrow_mut().push(HistoryAction::Undo(self.record.clone()));
+        }
+    }
+
+    fn merge(&mut self, next: &dyn HistoryEvent) -> bool {
+        let Some(next) = next.as_any().downcast_ref::<TextEditHistory>() else {
+            return false
+        };
+        let (record, next_record) = (&mut self.record, &next.record);
+        if !self.queue.ptr_eq(&next.queue) || record.kind == EditKind::Other || record.kind != next_record.kind || record.after != next_record.before {
+            return false
+        }
+        record.edits.extend(next_record.edits.iter().cloned());
+        record.after = next_record.after.clone();
+        true
+    }
+}
+
+/// how a `Search` matches its query, the options combine
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchOptions {
+    /// only matches that aren't part of a longer word
+    pub whole_word: bool,
+    pub case_insensitive: bool,
+    /// the query is a regular expression, and replacements can use its groups as `$1` or `${name}`
+    pub regex: bool
+}
+
+/// A find query, compiled once for `TextInputHandler::find_all` and the replace functions
+pub struct Search {
+    regex: Regex,
+    expand: bool
+}
+
+impl Search {
+    pub fn new(query: &str, options: SearchOptions) -> Result<Self, String> {
+        if query.is_empty() {
+            return Err("nothing to search for".to_string())
+        }
+        let mut pattern = if options.regex { query.to_string() } else { fancy_regex::escape(query).to_string() };
+        if options.whole_word {
+            pattern = format!(r"(?<!\w)(?:{})(?!\w)", pattern);
+        }
+        if options.case_insensitive {
+            pattern = format!("(?i){}", pattern);
+        }
+        let regex = Regex::new(&pattern).map_err(|e| format!("invalid search: {}", e))?;
+        Ok(Self { regex, expand: options.regex })
+    }
+
+    /// Byte ranges of every match in `text`, each with what it's replaced by when `replacement` is given.
+    /// Stops early if the regex gives up on a match
+    fn matches(&self, text: &str, replacement: Option<&str>) -> Vec<(Range<usize>, String)> {
+        self.regex.captures_iter(text).map_while(Result::ok).map(|captures| {
+            let range = captures.get(0).unwrap().range();
+            let replaced = match replacement {
+                Some(replacement) if self.expand => {
+                    let mut out = String::new();
+                    captures.expand(replacement, &mut out);
+                    out
+                }
+                Some(replacement) => replacement.to_string(),
+                None => String::new()
+            };
+            (range, replaced)
+        }).collect()
+    }
+}
+
+/// A rectangular selection between two `(line, visual column)` corners. It's kept along with the cursors it made,
+/// so growing it again keeps its columns even where short lines clamped the cursors
+struct ColumnSelection {
+    anchor: (IdxSize, IdxSize),
+    head: (IdxSize, IdxSize),
+    cursors: (Cursor, Vec<Cursor>)
+}
+
+
 // Base for all text boxes
 pub struct TextInputHandler {
-    pub content: String,
+    pub content: TextBuffer,
     allow_newlines: bool,
     max_length: IdxSize,
     enforce_max_length: bool,
@@ -109,6 +244,11 @@ pub struct TextInputHandler {
     pub flags: u8,
     // flags are: ANS- --HC
     // A: alpha   N: numeric   S: special chars   H: should push to history   C: should focus cursor
+    /// changes made by the operation in progress, collected into an `EditRecord` once it finishes
+    edits: Vec<Edit>,
+    /// undos and redos of this handler's records, waiting to be applied
+    history: Rc<RefCell<Vec<HistoryAction>>>,
+    column_selection: Option<ColumnSelection>
 }
 
 
@@ -122,14 +262,17 @@ impl TextInputHandler {
     pub fn new(content: String, allow_newlines: bool, max_length: Option<IdxSize>, allow_editing: bool) -> Self {
 
         Self {
-            content,
+            content: content.into(),
             allow_newlines,
             max_length: max_length.unwrap_or(0),
             enforce_max_length: max_length.is_some(),
             allow_editing,
             cursor: Cursor::new(0),
             cursors: Vec::new(),
-            flags: 0b_0000_0000
+            flags: 0b_0000_0000,
+            edits: Vec::new(),
+            history: Rc::new(RefCell::new(Vec::new())),
+            column_selection: None
         }
     }
     
@@ -188,31 +331,17 @@ impl TextInputHandler {
     
 
     /// returns the specified line if it exists. Trailing newline is not included
-    pub fn get_line(&self, line: IdxSize) -> Option<&str> {
-
-        let mut lines = self.content.split("\n");
+    pub fn get_line(&self, line: IdxSize) -> Option<String> {
+        self.content.line(line)
+    }
 
-        lines.nth(line)
+    /// returns the length of the specified line in characters if it exists. Trailing newline is not included
+    pub fn get_line_length(&self, line: IdxSize) -> Option<IdxSize> {
+        self.content.line_len(line)
     }
 
     pub fn get_line_start_index(&self, line: IdxSize) -> Option<IdxSize> {
-
-        let mut lines = self.content.split_inclusive('\n');
-
-        let mut l = line;
-
-        let mut idx = 0;
-
-        while l > 0 {
-            if let Some(s) = lines.next() {
-                l -= 1;
-                idx += s.chars().count();
-            } else {
-                return None
-            }
-        }
-
-        Some(idx)
+        self.content.line_to_char(line)
     }
     
     fn merge_groups(ranges: &mut Vec<(IdxSize, IdxSize)>) {
@@ -311,7 +440,7 @@ impl TextInputHandler {
             }
             
         } else {
-            let l = self.content.chars().count();
+            let l = self.content.len_chars();
             if self.cursor.selection_idx.is_some() {
                 self.cursor.idx = self.cursor.idx.min(self.cursor.selection_idx.unwrap());
             } else if self.cursor.idx < l && move_cursors {
@@ -342,90 +471,29 @@ impl TextInputHandler {
     }
 
     pub fn ctrl_move(&mut self, left: bool) {
-        println!("Ctrl move");
-        if left {
-            let pattern = Regex::new(r"(\w+ *|. *)").unwrap();
-            let (l, c) = self.get_text_pos(self.cursor.idx).unwrap();
-            let mut line = self.get_line(l).unwrap().to_string();
-            let mut offset = 0;
-            
-            for mat in pattern.find_iter(&line) {
-                println!("{:?}", mat);
-                if let Ok(m) = mat {
-                    println!("match left: {:?}", m);
-                    if c <= m.end() {
-                        offset = m.start();
-                        self.cursor.idx -= c-offset;
-                        break
-                    }
-                }
-            }
-            
-            let mut cursors = Vec::new();
-            
-            mem::swap(&mut cursors, &mut self.cursors);
-            
-            for cursor in &mut cursors {
-                let (l, c) = self.get_text_pos(cursor.idx).unwrap();
-                line = self.get_line(l).unwrap().to_string();
-                let offset;
-
-                for mat in pattern.find_iter(&line) {
-                    println!("{:?}", mat);
-                    if let Ok(m) = mat {
-                        if c <= m.end() {
-                            offset = m.start();
-                            cursor.idx -= c-offset;
-                            break
-                        }
-                    }
-                }
-            }
-            
-            mem::swap(&mut cursors, &mut self.cursors);
-            
-            
+        self.cursor.idx = self.word_boundary(self.cursor.idx, left);
+        for i in 0..self.cursors.len() {
+            self.cursors[i].idx = self.word_boundary(self.cursors[i].idx, left);
+        }
+    }
 
-        } else {
-            let pattern = Regex::new(r"( *\w+| *.)").unwrap();
-            let (l, c) = self.get_text_pos(self.cursor.idx).unwrap();
-            let mut line = self.get_line(l).unwrap().to_string();
-           
-            let mut offset = 0;
-
-            for mat in pattern.find_iter(&line) {
-                println!("{:?}", mat);
-                if let Ok(m) = mat {
-                    if m.start() <= c && c < m.end() {
-                        offset = m.end();
-                        self.cursor.idx += offset-c;
-                    }
-                }
+    /// Where a ctrl+arrow move from `idx` lands, without leaving its line: going left, the start of the word
+    /// (or other character) and the spaces after it that `idx` is in, going right, the end of the spaces and word after `idx`
+    fn word_boundary(&self, idx: IdxSize, left: bool) -> IdxSize {
+        let pattern = Regex::new(if left { r"(\w+ *|. *)" } else { r"( *\w+| *.)" }).unwrap();
+        let (l, c) = self.get_text_pos(idx).unwrap();
+        let line = self.get_line(l).unwrap();
+        let column = line.char_indices().nth(c).map_or(line.len(), |(i, _)| i);
+
+        for m in pattern.find_iter(&line).map_while(Result::ok) {
+            if left && column <= m.end() {
+                return idx - line[m.start()..column].chars().count()
             }
-
-            let mut cursors = Vec::new();
-
-            mem::swap(&mut cursors, &mut self.cursors);
-
-            for cursor in &mut cursors {
-                let (l, c) = self.get_text_pos(cursor.idx).unwrap();
-                line = self.get_line(l).unwrap().to_string();
-                let mut offset = 0;
-
-                for mat in pattern.find_iter(&line) {
-                    println!("{:?}", mat);
-                    if let Ok(m) = mat {
-                        if m.start() <= c && c < m.end() {
-                            offset = m.end();
-                            self.cursor.idx += offset-c;
-                        }
-                    }
-                }
+            if !left && m.start() <= column && column < m.end() {
+                return idx + line[column..m.end()].chars().count()
             }
-
-            mem::swap(&mut cursors, &mut self.cursors);
-            
         }
+        idx
     }
 
     /// Simply moves all cursors up or down. selection index is untouched and nothing is force-deselected
@@ -437,8 +505,7 @@ impl TextInputHandler {
                 self.cursor.idx = 0;
                 self.cursor.preferred_column = 0;
             } else {
-                let ln = self.get_line(line - 1).unwrap();
-                let col = ln.chars().count().min(self.cursor.preferred_column);
+                let col = self.get_line_length(line - 1).unwrap().min(self.cursor.preferred_column);
                 self.cursor.idx = self.get_index(line-1, col).unwrap();
             }
 
@@ -451,8 +518,7 @@ impl TextInputHandler {
                     cursor.idx = 0;
                     cursor.preferred_column = 0;
                 } else {
-                    let ln = self.get_line(line - 1).unwrap();
-                    let col = ln.chars().count().min(cursor.preferred_column);
+                    let col = self.get_line_length(line - 1).unwrap().min(cursor.preferred_column);
                     cursor.idx = self.get_index(line-1, col).unwrap();
                 }
             }
@@ -463,13 +529,13 @@ impl TextInputHandler {
         else {
             let (mut line, mut column) = self.get_text_pos(self.cursor.idx).unwrap();
 
-            let next_line = self.get_line(line + 1);
+            let next_line = self.get_line_length(line + 1);
 
             if next_line.is_none() {
-                self.cursor.idx = self.content.chars().count();
-                self.cursor.preferred_column = self.get_line(line).unwrap().chars().count();
+                self.cursor.idx = self.content.len_chars();
+                self.cursor.preferred_column = self.get_line_length(line).unwrap();
             } else {
-                let col = next_line.unwrap().chars().count().min(self.cursor.preferred_column);
+                let col = next_line.unwrap().min(self.cursor.preferred_column);
                 self.cursor.idx = self.get_index(line+1, col).unwrap();
             }
 
@@ -478,13 +544,13 @@ impl TextInputHandler {
 
             for cursor in &mut cursors {
                 (line, column) = self.get_text_pos(cursor.idx).unwrap();
-                let next_line = self.get_line(line + 1);
+                let next_line = self.get_line_length(line + 1);
 
                 if next_line.is_none() {
-                    cursor.idx = self.content.chars().count();
-                    cursor.preferred_column = self.get_line(line).unwrap().chars().count();
+                    cursor.idx = self.content.len_chars();
+                    cursor.preferred_column = self.get_line_length(line).unwrap();
                 } else {
-                    let col = next_line.unwrap().chars().count().min(cursor.preferred_column);
+                    let col = next_line.unwrap().min(cursor.preferred_column);
                     cursor.idx = self.get_index(line+1, col).unwrap();
                 }
             }
@@ -496,7 +562,7 @@ impl TextInputHandler {
     
     /// clamps all cursors to be within the bounds of the content.
     pub fn clamp_cursors(&mut self) {
-        let c = self.content.chars().count();
+        let c = self.content.len_chars();
         self.cursor.idx = self.cursor.idx.min(c);
         if self.cursor.selection_idx.is_some() && self.cursor.selection_idx.unwrap() > c {
             self.cursor.selection_idx = None;
@@ -515,8 +581,20 @@ impl TextInputHandler {
         if !self.allow_editing {
             return false;
         }
-        let mut out = false;
+        let mut out = self.apply_history();
+
+        if app.keybinds.check_binding("Undo") {
+            app.keybinds.accept(&app.keybinds.last("Undo").unwrap().clone());
+            self.step_history(app, true);
+            return true
+        }
+        else if app.keybinds.check_binding("Redo") {
+            app.keybinds.accept(&app.keybinds.last("Redo").unwrap().clone());
+            self.step_history(app, false);
+            return true
+        }
 
+        let before = (self.cursor.clone(), self.cursors.clone());
         if app.keybinds.check_binding("Copy") {
             app.keybinds.accept(&app.keybinds.last("Copy").unwrap().clone());
             self.copy_at_cursor();
@@ -532,11 +610,11 @@ impl TextInputHandler {
         }
         else if app.keybinds.check_binding("Paste") {
             app.keybinds.accept(&app.keybinds.last("Paste").unwrap().clone());
-            if !(self.enforce_max_length && self.content.chars().count() >= self.max_length) {
+            if !(self.enforce_max_length && self.content.len_chars() >= self.max_length) {
                 self.paste_at_cursor(app);
-                if self.enforce_max_length && self.content.chars().count() > self.max_length {
-                    // self.content = self.content[0..self.max_length].to_owned();
-                    self.content = self.content.chars().take(self.max_length).collect::<String>();
+                if self.enforce_max_length && self.content.len_chars() > self.max_length {
+                    let length = self.content.len_chars();
+                    self.replace(self.max_length..length, "");
                     self.clamp_cursors();
                 }
                 self.set_cursor_preference();
@@ -546,19 +624,34 @@ impl TextInputHandler {
         }
         else if app.keybinds.check_binding("Select-All") {
             app.keybinds.accept(&Vec::<String>::new());
-            self.cursor.idx = self.content.chars().count();
+            self.cursor.idx = self.content.len_chars();
             self.cursor.selection_idx = Some(0);
             self.cursors.clear();
         }
+        else if app.keybinds.check_binding("Find-All") {
+            app.keybinds.accept(&app.keybinds.last("Find-All").unwrap().clone());
+            let (start, end) = self.cursor.get_range();
+            if let Ok(search) = Search::new(&self.content.slice(start..end), SearchOptions::default()) {
+                self.select_all_occurrences(&search);
+            }
+        }
+        self.push_edits(app, EditKind::Other, before);
         
-        for key in &app.keyboard.triggered_keys {
-            
-            if app.keybinds.matches_any() || app.keyboard.alt_held {
+        for key in &app.keyboard.triggered_keys.clone() {
+            let before = (self.cursor.clone(), self.cursors.clone());
+            let mut kind = EditKind::Other;
+
+            if app.keyboard.alt_held && app.keyboard.shift_held && matches!(key.as_str(), "Up" | "Down" | "Left" | "Right") {
+                self.move_column_selection(key);
+                out = true;
+                self.set_focus_cursor(true);
+            }
+            else if app.keybinds.matches_any() || app.keyboard.alt_held {
                 // println!("Keybind");
                 // do nothing because keybinds
             }
             else if key.chars().count() == 1 {
-                if self.enforce_max_length && self.content.chars().count() >= self.max_length { continue }
+                if self.enforce_max_length && self.content.len_chars() >= self.max_length { continue }
                 // println!("Type '{}'", key);
                 self.insert_at_cursor(app, key.to_string());
                 out = true;
@@ -567,9 +660,10 @@ impl TextInputHandler {
                 if let Ok(c) = key.parse::<char>() {
                     self.set_typing_flags(c);
                 }
+                kind = EditKind::Typing(self.flags & 0b_1110_0000);
             }
             else if key == "Tab" {
-                if self.enforce_max_length && self.content.chars().count() >= self.max_length { continue }
+                if self.enforce_max_length && self.content.len_chars() >= self.max_length { continue }
                 
                 self.tab_at_cursor();
                 
@@ -577,9 +671,10 @@ impl TextInputHandler {
                 self.set_cursor_preference();
                 self.set_focus_cursor(true);
                 self.set_typing_flags(' ');
+                kind = EditKind::Typing(self.flags & 0b_1110_0000);
             }
             // else if key == "Space" {
-            //     if self.enforce_max_length && self.content.chars().count() >= self.max_length { continue }
+            //     if self.enforce_max_length && self.content.len_chars() >= self.max_length { continue }
             //     self.insert_at_cursor(app, " ".to_string());
             //     out = true;
             //     self.set_cursor_preference();
@@ -587,6 +682,7 @@ impl TextInputHandler {
             // }
             else if key == "Backspace" {
                 self.backspace_at_cursor();
+                kind = EditKind::Backspace(self.flags & 0b_1110_0000);
                 out = true;
                 self.set_cursor_preference();
                 self.set_focus_cursor(true);
@@ -599,7 +695,7 @@ impl TextInputHandler {
             }
             else if key == "Return" || key == "Keypad Enter" {
                 if self.allow_newlines {
-                    if self.enforce_max_length && self.content.chars().count() >= self.max_length { continue; }
+                    if self.enforce_max_length && self.content.len_chars() >= self.max_length { continue; }
                     self.insert_at_cursor(app, "\n".to_string());
                     out = true;
                     self.set_cursor_preference();
@@ -608,7 +704,7 @@ impl TextInputHandler {
                 self.set_update_history(true);
             }
             // else if key.starts_with("Keypad") {
-            //     if self.enforce_max_length && self.content.chars().count() >= self.max_length { continue }
+            //     if self.enforce_max_length && self.content.len_chars() >= self.max_length { continue }
             //     if key.rsplit_once(" ").is_some_and(|x| { x.1.chars().count() == 1 }) {
             //         self.insert_at_cursor(app, key[key.chars().count()-1..].to_string());
             //         out = true;
@@ -660,7 +756,7 @@ impl TextInputHandler {
                         self.cursor.selection_idx = Some(self.cursor.idx);
                     }
                     if !app.keyboard.ctrl_held {
-                        self.cursor.idx = self.content.chars().count().min(self.cursor.idx + 1);
+                        self.cursor.idx = self.content.len_chars().min(self.cursor.idx + 1);
                     }
                     
                     for cursor in &mut self.cursors {
@@ -668,7 +764,7 @@ impl TextInputHandler {
                             cursor.selection_idx = Some(cursor.idx);
                         }
                         if !app.keyboard.ctrl_held {
-                            cursor.idx = self.content.chars().count().min(cursor.idx + 1);
+                            cursor.idx = self.content.len_chars().min(cursor.idx + 1);
                         }
                     }
                     
@@ -730,6 +826,7 @@ impl TextInputHandler {
             else {
                 println!("Unprocessed event: {}", key);
             }
+            self.push_edits(app, kind, before);
         }
 
         out
@@ -739,53 +836,156 @@ impl TextInputHandler {
     /// The index of self.content.len() is considered valid
     /// line and column start at 0
     pub fn get_text_pos(&self, idx: IdxSize) -> Option<(IdxSize, IdxSize)> {
-        if self.content.chars().count() == 0 {
-            return Some((0, 0))
-        }
-        if idx <= self.content.chars().count() {
-            let mut line = 0;
-            let mut dx = idx;
-            let mut lines = self.content.split_inclusive('\n').peekable();
-            println!("Searching for: {}", idx);
-            while let Some(l) = lines.next() {
-                if dx < l.chars().count() {
-                    println!("Found {}, {}", line, dx);
-                    return Some((line, dx));
-                } else if dx == l.chars().count() && lines.peek().is_none() {
-                    return if l.ends_with('\n') {
-                        Some((line + 1, 0))
-                    } else {
-                        Some((line, dx.min(l.chars().count())))
-                    }
-                } else {
-                    line += 1;
-                    dx -= l.chars().count();
-                }
-            }
+        if idx > self.content.len_chars() {
+            return None
         }
-        None
+        let line = self.content.char_to_line(idx);
+        Some((line, idx - self.content.line_to_char(line)?))
     }
 
     /// Inverse of `get_text_pos`
     pub fn get_index(&self, line: IdxSize, column: IdxSize) -> Option<IdxSize> {
-        let mut idx = 0;
-        let mut i = 0;
-        for ln in self.content.split('\n') {
-            if i == line {
-                idx += column.min(ln.chars().count());
-                return Some(idx + i)
+        Some(self.content.line_to_char(line)? + column.min(self.content.line_len(line)?))
+    }
+    
+    /// Selects from `anchor` to `idx` with only the primary cursor, which ends up at `idx`. Used when dragging the mouse
+    pub fn select_to(&mut self, anchor: IdxSize, idx: IdxSize) {
+        let (anchor, idx) = (anchor.min(self.content.len_chars()), idx.min(self.content.len_chars()));
+        self.cursors.clear();
+        self.cursor = if anchor == idx { Cursor::new(idx) } else { Cursor::selection(idx, anchor) };
+        self.set_cursor_preference();
+    }
+
+    /// Selects the word at `idx`, or the run of whitespace or the single other character there.
+    /// Between a word and anything else, the word is selected
+    pub fn select_word(&mut self, idx: IdxSize) {
+        let idx = idx.min(self.content.len_chars());
+        let Some((line, column)) = self.get_text_pos(idx) else {
+            return
+        };
+        let text: Vec<char> = self.get_line(line).unwrap_or_default().chars().collect();
+        let kind = |c: char| if c.is_alphanumeric() || c == '_' { 0 } else if c.is_whitespace() { 1 } else { 2 };
+
+        let at = match (column.checked_sub(1).map(|i| text[i]), text.get(column)) {
+            (Some(before), Some(&after)) if kind(after) != 0 && kind(before) == 0 => column - 1,
+            (_, Some(_)) => column,
+            (Some(_), None) => column - 1,
+            (None, None) => return self.select_to(idx, idx)
+        };
+        let (start, end) = if kind(text[at]) == 2 {
+            (at, at + 1)
+        } else {
+            let same = |i: &IdxSize| kind(text[*i]) == kind(text[at]);
+            ((0..at).rev().take_while(same).last().unwrap_or(at), (at..text.len()).take_while(same).last().unwrap_or(at) + 1)
+        };
+        self.select_to(idx - column + start, idx - column + end);
+    }
+
+    /// selects the line `idx` is on, along with its newline
+    pub fn select_line(&mut self, idx: IdxSize) {
+        let Some((line, _)) = self.get_text_pos(idx) else {
+            return
+        };
+        let start = self.content.line_to_char(line).unwrap_or(0);
+        let end = self.content.line_to_char(line + 1).unwrap_or(self.content.len_chars());
+        self.select_to(start, end);
+    }
+
+    /// Adds a cursor at `idx`, or removes the additional cursor that is already there
+    pub fn add_cursor(&mut self, idx: IdxSize) {
+        let idx = idx.min(self.content.len_chars());
+        if let Some(i) = self.cursors.iter().position(|c| c.idx == idx) {
+            self.cursors.remove(i);
+        }
+        else if self.cursor.idx != idx {
+            let mut cursor = Cursor::new(idx);
+            cursor.preferred_column = self.get_text_pos(idx).map_or(0, |(_, c)| c);
+            self.cursors.push(cursor);
+            self.cursors.sort();
+        }
+    }
+
+    /// the column `idx` is drawn at on its line, with tabs reaching to the next tab stop
+    pub fn visual_column(&self, idx: IdxSize) -> IdxSize {
+        let Some((line, column)) = self.get_text_pos(idx) else {
+            return 0
+        };
+        let text = self.get_line(line).unwrap_or_default();
+        text.chars().take(column).fold(0, |visual, c| if c == '\t' { (visual / TAB_WIDTH + 1) * TAB_WIDTH } else { visual + 1 })
+    }
+
+    /// Inverse of `visual_column`: the index on `line` nearest to the visual `column`, clamped to the end of the line.
+    /// None if there is no such line
+    pub fn index_at_column(&self, line: IdxSize, column: IdxSize) -> Option<IdxSize> {
+        let start = self.content.line_to_char(line)?;
+        let mut visual = 0;
+        for (i, c) in self.get_line(line)?.chars().enumerate() {
+            let next = if c == '\t' { (visual / TAB_WIDTH + 1) * TAB_WIDTH } else { visual + 1 };
+            if column < next {
+                // inside a tab, the nearer side of it
+                return Some(start + i + usize::from(next - column < column - visual))
             }
-            idx += ln.chars().count();
-            i += 1;
+            visual = next;
+        }
+        Some(start + self.get_line_length(line)?)
+    }
+
+    /// Selects the box between two `(line, visual column)` corners with one cursor per line, the primary one
+    /// on the `head` line. Lines ending before the box starts are skipped, unless the box has no width
+    pub fn select_columns(&mut self, anchor: (IdxSize, IdxSize), head: (IdxSize, IdxSize)) {
+        let last_line = self.content.len_lines() - 1;
+        let (anchor, head) = ((anchor.0.min(last_line), anchor.1), (head.0.min(last_line), head.1));
+        let left = anchor.1.min(head.1);
+
+        let lines: Vec<IdxSize> = if anchor.0 <= head.0 { (anchor.0..=head.0).collect() } else { (head.0..=anchor.0).rev().collect() };
+        let mut cursors: Vec<Cursor> = Vec::new();
+        for line in lines {
+            let length = self.visual_column(self.index_at_column(line, IdxSize::MAX).unwrap());
+            if length < left && anchor.1 != head.1 {
+                continue
+            }
+            let (from, to) = (self.index_at_column(line, anchor.1).unwrap(), self.index_at_column(line, head.1).unwrap());
+            let mut cursor = if from == to { Cursor::new(to) } else { Cursor::selection(to, from) };
+            cursor.preferred_column = head.1;
+            cursors.push(cursor);
         }
 
-        None
+        self.cursor = cursors.pop().unwrap_or_else(|| Cursor::new(self.index_at_column(head.0, head.1).unwrap()));
+        cursors.sort();
+        self.cursors = cursors;
+        self.column_selection = Some(ColumnSelection { anchor, head, cursors: (self.cursor.clone(), self.cursors.clone()) });
     }
-    
+
+    /// Grows or shrinks a column selection by moving its head with an arrow key. Starts a new one from the primary
+    /// cursor, and its selection, if the cursors changed since the last one
+    fn move_column_selection(&mut self, key: &str) {
+        let current = (self.cursor.clone(), self.cursors.clone());
+        let (anchor, mut head) = match &self.column_selection {
+            Some(selection) if selection.cursors == current => (selection.anchor, selection.head),
+            _ => {
+                let position = |idx| (self.get_text_pos(idx).unwrap().0, self.visual_column(idx));
+                (position(self.cursor.selection_idx.unwrap_or(self.cursor.idx)), position(self.cursor.idx))
+            }
+        };
+
+        match key {
+            "Up" => head.0 = head.0.saturating_sub(1),
+            "Down" => head.0 = (head.0 + 1).min(self.content.len_lines() - 1),
+            "Left" => head.1 = head.1.saturating_sub(1),
+            // right stops at the end of the longest line in the box
+            _ => {
+                let (top, bottom) = (anchor.0.min(head.0), anchor.0.max(head.0));
+                let longest = (top..=bottom).map(|line| self.visual_column(self.index_at_column(line, IdxSize::MAX).unwrap())).max().unwrap_or(0);
+                head.1 = (head.1 + 1).min(longest.max(head.1));
+            }
+        }
+        self.select_columns(anchor, head);
+    }
+
     /// Removes additional cursors and deselects all text, moves the cursor to the specified position (clamped to the length of the text)
     pub fn set_cursor_index(&mut self, idx: IdxSize) {
         self.cursors.clear();
-        self.cursor.idx = idx.min(self.content.chars().count());
+        self.cursor.idx = idx.min(self.content.len_chars());
         self.cursor.selection_idx = None;
 
     }
@@ -821,67 +1021,161 @@ impl TextInputHandler {
         }
     }
 
-    /// Used for typing, automatically accounts for selected text and multiple cursors
-    pub fn insert_at_cursor(&mut self, app: &App, content: String) {
-        self.collapse_selections();
+    /// replaces the characters in `range` with `text`, recording the change for the edit in progress
+    fn replace(&mut self, range: Range<IdxSize>, text: &str) {
+        if range.is_empty() && text.is_empty() {
+            return
+        }
+        let removed = self.content.slice(range.clone());
+        self.content.replace(range.clone(), text);
+        self.edits.push(Edit { start: range.start, removed, inserted: text.to_string() });
+    }
 
-        let mut offset = 0;
-        let mut cursors = Vec::new();
+    /// finishes the edit in progress, `before` being the cursors from when it started. None if nothing changed
+    fn take_record(&mut self, kind: EditKind, before: (Cursor, Vec<Cursor>)) -> Option<EditRecord> {
+        if self.edits.is_empty() {
+            return None
+        }
+        Some(EditRecord {
+            edits: mem::take(&mut self.edits),
+            before,
+            after: (self.cursor.clone(), self.cursors.clone()),
+            kind
+        })
+    }
 
-        let mod_c = &self.mod_char(app, &content);
-        
-        cursors.push(&mut self.cursor.idx);
+    /// finishes the edit in progress and adds it to the app's history
+    fn push_edits(&mut self, app: &mut App, kind: EditKind, before: (Cursor, Vec<Cursor>)) {
+        if let Some(record) = self.take_record(kind, before) {
+            app.history.add_history(TextEditHistory { record, queue: Rc::downgrade(&self.history) });
+        }
+    }
 
-        for idx in &mut self.cursors {
-            if !cursors.contains(&&mut idx.idx) {
-                cursors.push(&mut idx.idx);
+    /// Replaces the characters in `range` with `text` as one undoable edit, leaving a single cursor at `cursor`
+    /// in the new content. For edits made outside of typing, like formatting or accepting a completion
+    pub fn replace_range(&mut self, app: &mut App, range: Range<IdxSize>, text: &str, cursor: IdxSize) {
+        let before = (self.cursor.clone(), self.cursors.clone());
+        let end = range.end.min(self.content.len_chars());
+        self.replace(range.start.min(end)..end, text);
+        self.set_cursor_index(cursor);
+        self.set_cursor_preference();
+        self.push_edits(app, EditKind::Other, before);
+    }
+
+    /// Replaces all the content without recording it. The handler's earlier edits can't be undone anymore
+    pub fn set_content(&mut self, content: impl Into<TextBuffer>) {
+        self.content = content.into();
+        self.edits.clear();
+        self.history = Rc::new(RefCell::new(Vec::new()));
+        self.set_cursor_index(0);
+    }
+
+    /// Applies undos and redos of this handler's records that the app's history has run, see `TextEditHistory`.
+    /// Stops at a record that no longer matches the content. Returns whether anything changed
+    pub fn apply_history(&mut self) -> bool {
+        let actions = mem::take(&mut *self.history.borrow_mut());
+        let mut changed = false;
+        for action in actions {
+            let applied = match action {
+                HistoryAction::Undo(record) => self.undo_record(&record),
+                HistoryAction::Redo(record) => self.redo_record(&record)
+            };
+            if !applied {
+                break
             }
+            changed = true;
+            self.set_focus_cursor(true);
         }
-        cursors.sort();
+        changed
+    }
 
-        for cursor in &mut cursors {
-            // self.content = self.content[0..cursor].to_string() + &self.mod_char(app, &content) + &self.content[cursor..];
-            self.content = self.content.chars().take(**cursor - offset).collect::<String>()
-                + mod_c
-                + &self.content.chars().skip(**cursor - offset).collect::<String>();
-            
-            **cursor += content.chars().count();
-            offset += content.chars().count();
+    /// reverts the edits of `record`, last first, and restores the cursors from before it
+    pub fn undo_record(&mut self, record: &EditRecord) -> bool {
+        let steps: Vec<_> = record.edits.iter().rev().map(|e| (e.start, &e.inserted, &e.removed)).collect();
+        if !self.apply_steps(&steps) {
+            return false
         }
+        (self.cursor, self.cursors) = record.before.clone();
+        true
     }
-    
-    pub fn tab_at_cursor(&mut self) {
-        self.collapse_selections();
-        
-        let mut offset = 0;
-        let mut cursors = Vec::new();
-        
-        let mut curs = Cursor::new(0);
-        
-        mem::swap(&mut curs, &mut self.cursor);
-        let mut crs = mem::take(&mut self.cursors);
-        
-        cursors.push(&mut curs.idx);
-        for idx in &mut crs {
-            if !cursors.contains(&&mut idx.idx) {
-                cursors.push(&mut idx.idx);
+
+    /// makes the edits of `record` again and restores the cursors from after it
+    pub fn redo_record(&mut self, record: &EditRecord) -> bool {
+        let steps: Vec<_> = record.edits.iter().map(|e| (e.start, &e.removed, &e.inserted)).collect();
+        if !self.apply_steps(&steps) {
+            return false
+        }
+        (self.cursor, self.cursors) = record.after.clone();
+        true
+    }
+
+    /// replaces `from` at each start with `to`, in order. If some `from` isn't there, the steps already made are
+    /// taken back and the content is left as it was
+    fn apply_steps(&mut self, steps: &[(IdxSize, &String, &String)]) -> bool {
+        for (i, &(start, from, to)) in steps.iter().enumerate() {
+            let end = start + from.chars().count();
+            if end > self.content.len_chars() || self.content.slice(start..end) != *from {
+                for &(start, from, to) in steps[..i].iter().rev() {
+                    self.content.replace(start..start + to.chars().count(), from);
+                }
+                return false
             }
+            self.content.replace(start..end, to);
         }
-        cursors.sort();
-        
-        for cursor in &mut cursors {
-            let (_, c) = self.get_text_pos(**cursor).unwrap();
-            let spaces = (3 - (((c as isize % 4) - 1) % 4)) as usize;
-            self.content = self.content.chars().take(**cursor - offset).collect::<String>()
-                + " ".repeat(spaces).as_str()
-                + &self.content.chars().skip(**cursor - offset).collect::<String>();
-            **cursor += spaces;
-            offset += spaces;
+        true
+    }
+
+    /// runs the app's undo or redo, then applies it if it was one of this handler's edits
+    fn step_history(&mut self, app: &mut App, undo: bool) {
+        let mut history = mem::replace(&mut app.history, HistoryManager::new());
+        if undo {
+            history.undo(app);
+        } else {
+            history.redo(app);
         }
-        
-        self.cursors = crs;
-        self.cursor = curs;
-        
+        app.history = history;
+        self.apply_history();
+    }
+
+    /// Inserts text at every cursor, `text_at` gives the text for a cursor index.
+    /// Inserts from the last cursor back so earlier indices stay valid, then moves each cursor past its text
+    fn insert_at_cursors(&mut self, mut text_at: impl FnMut(&Self, IdxSize) -> String) {
+        let mut positions: Vec<IdxSize> = self.cursors.iter().map(|c| c.idx).collect();
+        positions.push(self.cursor.idx);
+        positions.sort();
+        positions.dedup();
+
+        let texts: Vec<String> = positions.iter().map(|&idx| text_at(self, idx)).collect();
+        for (&idx, text) in positions.iter().zip(&texts).rev() {
+            self.replace(idx..idx, text);
+        }
+
+        // a cursor moves by the length of everything inserted at or before it
+        let mut inserted = vec![0];
+        for text in &texts {
+            inserted.push(inserted.last().unwrap() + text.chars().count());
+        }
+        let shift = |idx: IdxSize| idx + inserted[positions.partition_point(|&p| p <= idx)];
+        self.cursor.idx = shift(self.cursor.idx);
+        for cursor in &mut self.cursors {
+            cursor.idx = shift(cursor.idx);
+        }
+    }
+
+    /// Used for typing, automatically accounts for selected text and multiple cursors
+    pub fn insert_at_cursor(&mut self, app: &App, content: String) {
+        self.collapse_selections();
+
+        let mod_c = self.mod_char(app, &content);
+        self.insert_at_cursors(|_, _| mod_c.clone());
+    }
+    
+    pub fn tab_at_cursor(&mut self) {
+        self.collapse_selections();
+
+        self.insert_at_cursors(|handler, idx| {
+            " ".repeat(TAB_WIDTH - handler.visual_column(idx) % TAB_WIDTH)
+        });
     }
 
     /// Copies selected text. if no text is selected then copies the line the cursor is on. if there are multiple cursors, the selections are joined with a newline
@@ -910,7 +1204,7 @@ impl TextInputHandler {
         TextInputHandler::merge_groups(&mut ranges);
         
         for r in ranges {
-            clip.push(self.content[r.0..r.1].to_string())
+            clip.push(self.content.slice(r.0..r.1))
         }
         
         if !clip.is_empty() {
@@ -942,34 +1236,11 @@ impl TextInputHandler {
 
     /// Removes selected text. Any cursors that end up in the same place collapse into one cursor.
     pub fn collapse_selections(&mut self) {
-        let mut regions: Vec<(IdxSize, IdxSize)> = Vec::new();
+        let regions = self.remove_regions(|cursor, _| cursor.get_range());
 
         let mut offset = 0;
-        let r = self.cursor.get_range();
-        regions.push(r);
-
-        offset += r.1-r.0;
-        self.cursor.idx = r.0;
-        self.cursor.selection_idx = None;
-
-        for cursor in &mut self.cursors {
-            let r= cursor.get_range();
-            regions.push(r);
-            offset += r.1-r.0;
-            cursor.idx = r.0 - offset;
-            cursor.selection_idx = None;
-        }
-
-        regions.sort();
-        regions.dedup();
-
-        offset = 0;
-
-        TextInputHandler::merge_groups(&mut regions);
-
         for region in regions {
-            self.content = self.content.chars().take(region.0 - offset).collect::<String>()
-                + &self.content.chars().skip(region.1 - offset).collect::<String>();
+            self.replace(region.0 - offset..region.1 - offset, "");
 
             offset += region.1-region.0;
         }
@@ -978,76 +1249,147 @@ impl TextInputHandler {
 
     /// does a backspace, accounting for selected text and multiple cursors
     pub fn backspace_at_cursor(&mut self) {
-
-
-        let mut regions: Vec<(IdxSize, IdxSize)> = Vec::new();
+        let regions = self.remove_regions(|cursor, _| cursor.get_backspace_range());
 
         let mut offset = 0;
-        let r = self.cursor.get_backspace_range();
-        regions.push(r);
-        offset += r.1-r.0;
-        self.cursor.idx = r.0;
-        self.cursor.selection_idx = None;
-
-        for cursor in &mut self.cursors {
-            let r = cursor.get_backspace_range();
-            regions.push(r);
-            offset += r.1-r.0;
-            cursor.idx = r.0 - offset;
-            cursor.selection_idx = None;
+        for region in regions {
+            
+            if let Some(c) = self.content.char_at(region.0 - offset) {
+                self.set_typing_flags(c);
+            }
+            self.replace(region.0 - offset..region.1 - offset, "");
+            offset += region.1-region.0;
         }
 
-        regions.sort();
-        regions.dedup();
+    }
 
-        offset = 0;
+    /// Gets the region each cursor removes from `region_of`, given the content length, and sorts and merges them.
+    /// Every cursor is deselected and moved to where its region starts once everything before it is removed.
+    /// Returns the merged regions, which the caller removes
+    fn remove_regions(&mut self, region_of: impl Fn(&Cursor, IdxSize) -> (IdxSize, IdxSize)) -> Vec<(IdxSize, IdxSize)> {
+        let length = self.content.len_chars();
+        let mut regions: Vec<(IdxSize, IdxSize)> = self.cursors.iter().map(|c| region_of(c, length)).collect();
+        regions.push(region_of(&self.cursor, length));
 
+        regions.sort();
+        regions.dedup();
         TextInputHandler::merge_groups(&mut regions);
 
-
-        for region in regions {
-            
-            if self.content.len() > 0 {
-                if let Ok(c) = char::from_str(&self.content.chars().skip(region.0 - offset).take(1).collect::<String>()[0..1]) {
-                    self.set_typing_flags(c);
+        let collapsed = |idx: IdxSize| {
+            let mut removed = 0;
+            for &(start, end) in &regions {
+                if end <= idx {
+                    removed += end - start;
+                } else if start <= idx {
+                    return start - removed
+                } else {
+                    break
                 }
             }
-            self.content = self.content.chars().take(region.0 - offset).collect::<String>()
-                + &self.content.chars().skip(region.1 - offset).collect::<String>();
-            offset += region.1-region.0;
+            idx - removed
+        };
+        for cursor in self.cursors.iter_mut().chain([&mut self.cursor]) {
+            cursor.idx = collapsed(region_of(cursor, length).0);
+            cursor.selection_idx = None;
         }
-
+        regions
     }
 
-    /// same as backspace_at_cursor, but with delete behavior
-    pub fn delete_at_cursor(&mut self) {
+    /// matches of `search` as character ranges, each with its replacement if one is given
+    fn search_matches(&self, search: &Search, replacement: Option<&str>) -> Vec<(Range<IdxSize>, String)> {
+        search.matches(self.content.as_str(), replacement).into_iter()
+            .map(|(range, text)| (self.content.byte_to_char(range.start)..self.content.byte_to_char(range.end), text))
+            .collect()
+    }
 
-        let mut regions: Vec<(IdxSize, IdxSize)> = Vec::new();
-        let mut offset = 0;
+    /// every match of `search` in the content, in order
+    pub fn find_all(&self, search: &Search) -> Vec<Range<IdxSize>> {
+        self.search_matches(search, None).into_iter().map(|(range, _)| range).collect()
+    }
 
-        let r = self.cursor.get_delete_range(self.content.chars().count());
-        regions.push(r);
-        offset += r.1-r.0;
-        self.cursor.selection_idx = None;
+    /// Selects the first match after the primary cursor, wrapping around to the start, and removes the other cursors.
+    /// Returns whether anything matched
+    pub fn find_next(&mut self, search: &Search) -> bool {
+        let (start, end) = self.cursor.get_range();
+        let matches = self.find_all(search);
+        let Some(found) = matches.iter().find(|m| m.start >= end && **m != (start..end)).or(matches.first()) else {
+            return false
+        };
+        self.cursors.clear();
+        self.cursor = if found.is_empty() { Cursor::new(found.end) } else { Cursor::selection(found.end, found.start) };
+        self.set_cursor_preference();
+        self.set_focus_cursor(true);
+        true
+    }
 
-        for cursor in &mut self.cursors {
-            regions.push(cursor.get_delete_range(self.content.chars().count()));
-            cursor.idx = r.0 - offset;
-            offset += r.1-r.0;
-            cursor.selection_idx = None;
+    /// Turns every match of `search` into a selection, the first one being the primary cursor.
+    /// Returns how many matches there were, the cursors are left alone if there were none
+    pub fn select_all_occurrences(&mut self, search: &Search) -> usize {
+        let mut cursors: Vec<Cursor> = self.find_all(search).into_iter()
+            .map(|m| if m.is_empty() { Cursor::new(m.end) } else { Cursor::selection(m.end, m.start) })
+            .collect();
+        let count = cursors.len();
+        if count == 0 {
+            return 0
         }
+        self.cursor = cursors.remove(0);
+        self.cursors = cursors;
+        self.set_cursor_preference();
+        self.set_focus_cursor(true);
+        count
+    }
 
-        regions.sort();
-        regions.dedup();
+    /// Replaces the matches of `search`, or only the one exactly covering `only`, leaving a single cursor after the
+    /// last replacement. Returns how many were replaced
+    fn replace_matches(&mut self, search: &Search, replacement: &str, only: Option<Range<IdxSize>>) -> usize {
+        let matches: Vec<(Range<IdxSize>, String)> = self.search_matches(search, Some(replacement)).into_iter()
+            .filter(|(m, _)| only.as_ref().is_none_or(|only| m == only))
+            .collect();
+
+        // each match moves by what the replacements before it added or removed
+        let mut offset = 0isize;
+        let mut end = None;
+        for (range, text) in &matches {
+            let start = (range.start as isize + offset) as IdxSize;
+            self.replace(start..start + range.len(), text);
+            let length = text.chars().count();
+            offset += length as isize - range.len() as isize;
+            end = Some(start + length);
+        }
+        if let Some(end) = end {
+            self.set_cursor_index(end);
+            self.set_cursor_preference();
+            self.set_focus_cursor(true);
+        }
+        matches.len()
+    }
 
+    /// If the primary cursor selects a match of `search`, replaces it as one undoable edit. Then selects the next match.
+    /// Returns whether a match was replaced
+    pub fn replace_next(&mut self, app: &mut App, search: &Search, replacement: &str) -> bool {
+        let before = (self.cursor.clone(), self.cursors.clone());
+        let (start, end) = self.cursor.get_range();
+        let replaced = self.replace_matches(search, replacement, Some(start..end)) > 0;
+        self.push_edits(app, EditKind::Other, before);
+        self.find_next(search);
+        replaced
+    }
 
-        TextInputHandler::merge_groups(&mut regions);
+    /// replaces every match of `search` as one undoable edit, returning how many were replaced
+    pub fn replace_all(&mut self, app: &mut App, search: &Search, replacement: &str) -> usize {
+        let before = (self.cursor.clone(), self.cursors.clone());
+        let count = self.replace_matches(search, replacement, None);
+        self.push_edits(app, EditKind::Other, before);
+        count
+    }
 
-        offset = 0;
+    /// same as backspace_at_cursor, but with delete behavior
+    pub fn delete_at_cursor(&mut self) {
+        let regions = self.remove_regions(|cursor, length| cursor.get_delete_range(length));
 
+        let mut offset = 0;
         for region in regions {
-            self.content = self.content.chars().take(region.0 - offset).collect::<String>()
-                + &self.content.chars().skip(region.1 - offset).collect::<String>();
+            self.replace(region.0 - offset..region.1 - offset, "");
             offset += region.1-region.0;
         }
 
@@ -1058,7 +1400,9 @@ impl TextInputHandler {
 
 #[cfg(test)]
 mod handler_tests {
-    use crate::text_input_handler::{Cursor, TextInputHandler};
+    use std::rc::Rc;
+    use crate::history_manager::HistoryEvent;
+    use crate::text_input_handler::{Cursor, EditKind, IdxSize, Search, SearchOptions, TextEditHistory, TextInputHandler};
 
     #[test]
     pub fn test_sorting() {
@@ -1133,6 +1477,215 @@ mod handler_tests {
         assert_eq!(handler.content, "This is test #4");
     }
 
+    #[test]
+    pub fn test_tab_2_cursors() {
+        let mut handler: TextInputHandler = TextInputHandler::new("ab\ncdef".to_string(), true, None, true);
+        //                                                          ^   ^
+
+        handler.cursor.idx = 5;
+        handler.cursors.push(Cursor::new(1));
+
+        handler.tab_at_cursor();
+
+        assert_eq!(handler.content, "a   b\ncd  ef");
+        assert_eq!((handler.cursor.idx, handler.cursors[0].idx), (10, 4));
+        assert_eq!(handler.get_text_pos(10), Some((1, 4)));
+    }
+
+    #[test]
+    pub fn test_undo_redo_records() {
+        let mut handler: TextInputHandler = TextInputHandler::new("one\ntwo".to_string(), true, None, true);
+        //                                                          [ ]    ^
+
+        handler.cursor = Cursor::selection(3, 1);
+        handler.cursors.push(Cursor::new(7));
+        let start = (handler.cursor.clone(), handler.cursors.clone());
+
+        handler.collapse_selections();
+        handler.insert_at_cursors(|_, _| "x".to_string());
+        let typed = handler.take_record(EditKind::Typing(0b_1000_0000), start.clone()).unwrap();
+        assert_eq!(handler.content, "ox\ntwox");
+
+        let before = (handler.cursor.clone(), handler.cursors.clone());
+        handler.insert_at_cursors(|_, _| "y".to_string());
+        let more = handler.take_record(EditKind::Typing(0b_1000_0000), before.clone()).unwrap();
+        assert_eq!(handler.take_record(EditKind::Other, before), None);
+
+        // typing the same kind of character right after merges, anything else doesn't
+        let queue = Rc::downgrade(&handler.history);
+        let mut history = TextEditHistory { record: typed, queue: queue.clone() };
+        let mut other = TextEditHistory { record: more.clone(), queue: queue.clone() };
+        other.record.kind = EditKind::Typing(0b_0010_0000);
+        assert!(!history.merge(&other));
+        assert!(history.merge(&TextEditHistory { record: more, queue }));
+
+        let after = (handler.cursor.clone(), handler.cursors.clone());
+        assert!(handler.undo_record(&history.record));
+        assert_eq!(handler.content, "one\ntwo");
+        assert_eq!((handler.cursor.clone(), handler.cursors.clone()), start);
+
+        assert!(handler.redo_record(&history.record));
+        assert_eq!(handler.content, "oxy\ntwoxy");
+        assert_eq!((handler.cursor.clone(), handler.cursors.clone()), after);
+
+        // a record that no longer matches the content is left alone
+        handler.backspace_at_cursor();
+        assert!(!handler.redo_record(&history.record));
+        assert_eq!(handler.content, "ox\ntwox");
+    }
+
+    #[test]
+    pub fn test_mouse_selection() {
+        let mut handler: TextInputHandler = TextInputHandler::new("let  hp = max_hp\nnext line".to_string(), true, None, true);
+
+        handler.select_word(1);
+        assert_eq!(handler.cursor.get_range(), (0, 3));
+        handler.select_word(12);
+        assert_eq!(handler.cursor.get_range(), (10, 16));
+        handler.select_word(16);
+        assert_eq!(handler.cursor.get_range(), (10, 16));
+
+        // whitespace selects just the whitespace, other characters only themselves
+        handler.select_word(4);
+        assert_eq!(handler.cursor.get_range(), (3, 5));
+        handler.select_word(8);
+        assert_eq!(handler.cursor.get_range(), (8, 9));
+        handler.select_word(17);
+        assert_eq!(handler.cursor.get_range(), (17, 21));
+        handler.select_line(3);
+        assert_eq!(handler.cursor.get_range(), (0, 17));
+        handler.select_line(20);
+        assert_eq!(handler.cursor.get_range(), (17, 26));
+
+        handler.select_to(5, 2);
+        assert_eq!((handler.cursor.idx, handler.cursor.selection_idx), (2, Some(5)));
+
+        // alt+clicking a cursor again removes it, the primary cursor stays
+        handler.set_cursor_index(12);
+        handler.add_cursor(22);
+        handler.add_cursor(4);
+        handler.add_cursor(12);
+        assert_eq!(handler.cursors.iter().map(|c| c.idx).collect::<Vec<_>>(), [4, 22]);
+        handler.add_cursor(4);
+        assert_eq!(handler.cursors.len(), 1);
+
+        // every cursor moves by words
+        handler.ctrl_move(false);
+        assert_eq!((handler.cursor.idx, handler.cursors[0].idx), (16, 26));
+        handler.ctrl_move(true);
+        assert_eq!((handler.cursor.idx, handler.cursors[0].idx), (10, 22));
+    }
+
+    #[test]
+    pub fn test_remove_at_cursors() {
+        // cursors before the primary one move back by what is removed before them, not after
+        let mut handler: TextInputHandler = TextInputHandler::new("ab cd ef".to_string(), true, None, true);
+        handler.cursor.idx = 8;
+        handler.cursors.push(Cursor::new(2));
+        handler.backspace_at_cursor();
+        assert_eq!(handler.content, "a cd e");
+        assert_eq!((handler.cursor.idx, handler.cursors[0].idx), (6, 1));
+
+        let mut handler: TextInputHandler = TextInputHandler::new("one two three".to_string(), true, None, true);
+        handler.cursor = Cursor::selection(13, 8);
+        handler.cursors.push(Cursor::selection(3, 0));
+        handler.collapse_selections();
+        assert_eq!(handler.content, " two ");
+        assert_eq!((handler.cursor.idx, handler.cursors[0].idx), (5, 0));
+
+        // deleting a selection leaves the cursor where it started, whichever end the cursor was at
+        let mut handler: TextInputHandler = TextInputHandler::new("hello world".to_string(), true, None, true);
+        handler.cursor = Cursor::selection(5, 0);
+        handler.delete_at_cursor();
+        assert_eq!(handler.content, " world");
+        assert_eq!((handler.cursor.idx, handler.cursor.selection_idx), (0, None));
+
+        // overlapping regions are removed once, and their cursors end up together
+        let mut handler: TextInputHandler = TextInputHandler::new("abcdef".to_string(), true, None, true);
+        handler.cursor = Cursor::selection(4, 1);
+        handler.cursors.push(Cursor::new(3));
+        handler.delete_at_cursor();
+        assert_eq!(handler.content, "aef");
+        assert_eq!((handler.cursor.idx, handler.cursors[0].idx), (1, 1));
+    }
+
+    #[test]
+    pub fn test_column_selection() {
+        let mut handler: TextInputHandler = TextInputHandler::new("sword\t12\t3\nax\nbow\t8\t15".to_string(), true, None, true);
+        let ranges = |handler: &TextInputHandler| {
+            let mut ranges: Vec<(IdxSize, IdxSize)> = handler.cursors.iter().map(|c| c.get_range()).collect();
+            ranges.push(handler.cursor.get_range());
+            ranges
+        };
+
+        // tabs reach to the next tab stop
+        assert_eq!((handler.visual_column(6), handler.visual_column(18), handler.visual_column(20)), (8, 4, 8));
+        assert_eq!((handler.index_at_column(2, 6), handler.index_at_column(2, 7)), (Some(19), Some(20)));
+        assert_eq!(handler.index_at_column(1, 8), Some(13));
+
+        // the short line is skipped, the primary cursor is on the last line
+        handler.select_columns((0, 8), (2, 10));
+        assert_eq!(ranges(&handler), [(6, 8), (20, 22)]);
+        handler.collapse_selections();
+        handler.insert_at_cursors(|_, _| "x".to_string());
+        assert_eq!(handler.content, "sword\tx\t3\nax\nbow\t8\tx");
+        assert_eq!(ranges(&handler), [(7, 7), (20, 20)]);
+
+        // moving the head keeps the box's columns, with a short line in it only while it has no width
+        handler.set_cursor_index(3);
+        handler.move_column_selection("Down");
+        handler.move_column_selection("Down");
+        assert_eq!(ranges(&handler), [(3, 3), (12, 12), (16, 16)]);
+        handler.move_column_selection("Right");
+        assert_eq!(ranges(&handler), [(3, 4), (16, 17)]);
+        handler.move_column_selection("Left");
+        handler.move_column_selection("Up");
+        assert_eq!(ranges(&handler), [(3, 3), (12, 12)]);
+    }
+
+    #[test]
+    pub fn test_find_replace() {
+        let mut handler: TextInputHandler = TextInputHandler::new("cat Cat cat_dog\ncat".to_string(), true, None, true);
+        let word = SearchOptions { whole_word: true, ..Default::default() };
+        let any_case = SearchOptions { case_insensitive: true, ..word };
+        let regex = SearchOptions { regex: true, ..Default::default() };
+
+        assert_eq!(handler.find_all(&Search::new("cat", SearchOptions::default()).unwrap()), [0..3, 8..11, 16..19]);
+        assert_eq!(handler.find_all(&Search::new("cat", word).unwrap()), [0..3, 16..19]);
+        assert_eq!(handler.find_all(&Search::new("CAT", any_case).unwrap()), [0..3, 4..7, 16..19]);
+        assert!(handler.find_all(&Search::new("c.t", SearchOptions::default()).unwrap()).is_empty());
+        assert!(Search::new("(", regex).is_err());
+
+        // find next wraps around
+        let search = Search::new("cat", SearchOptions::default()).unwrap();
+        for range in [0..3, 8..11, 16..19, 0..3] {
+            assert!(handler.find_next(&search));
+            assert_eq!(handler.cursor.get_range(), (range.start, range.end));
+        }
+
+        let search = Search::new("cat", any_case).unwrap();
+        assert_eq!(handler.select_all_occurrences(&search), 3);
+        let selections: Vec<(IdxSize, IdxSize)> = handler.cursors.iter().map(|c| c.get_range()).collect();
+        assert_eq!((handler.cursor.get_range(), selections), ((0, 3), vec![(4, 7), (16, 19)]));
+
+        // replacing one match, then all of them as a single edit
+        let before = (handler.cursor.clone(), handler.cursors.clone());
+        assert_eq!(handler.replace_matches(&search, "$1", Some(4..7)), 1);
+        assert_eq!(handler.content, "cat $1 cat_dog\ncat");
+        assert_eq!(handler.replace_matches(&search, "dog", None), 2);
+        assert_eq!(handler.content, "dog $1 cat_dog\ndog");
+        assert_eq!((handler.cursor.idx, handler.cursor.selection_idx), (18, None));
+
+        let search = Search::new(r"(\w+)_(?<last>\w+)", regex).unwrap();
+        assert_eq!(handler.replace_matches(&search, "${last}_$1", None), 1);
+        assert_eq!(handler.content, "dog $1 dog_cat\ndog");
+
+        let record = handler.take_record(EditKind::Other, before.clone()).unwrap();
+        assert!(handler.undo_record(&record));
+        assert_eq!(handler.content, "cat Cat cat_dog\ncat");
+        assert_eq!((handler.cursor.clone(), handler.cursors.clone()), before);
+    }
+
     
 }
 
@@ -0,0 +1,139 @@
+//! Drives the `es3_lsp` binary through its stdin and checks what it writes back
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use serde_json::{json, Value};
+
+const URI: &str = "file:///scripts/docks.es3";
+const SCRIPT: &str = "#!emberhollow:rooms/docks
+#!enter-script
+count = 1
+$greet($name) {
+    output(\"hi \" .. $name)
+}
+$greet(#player.name)
+x = [count,<emberhollow:rooms/missing>]
+";
+
+/// frames a message the way LSP clients send them
+fn frame(message: Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn request(id: u64, method: &str, params: Value) -> String {
+    frame(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+}
+
+fn notification(method: &str, params: Value) -> String {
+    frame(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+}
+
+fn at(line: u64, character: u64) -> Value {
+    json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+}
+
+/// sends `input` to a fresh server and returns its exit code and every message it sent
+fn run(input: &str) -> (i32, Vec<Value>) {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_es3_lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start es3_lsp");
+    server.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = server.wait_with_output().unwrap();
+
+    let mut stdout = String::from_utf8(output.stdout).unwrap();
+    let mut messages = Vec::new();
+    while let Some(header_end) = stdout.find("\r\n\r\n") {
+        let length: usize = stdout[..header_end].trim_start_matches("Content-Length: ").parse().unwrap();
+        let body_start = header_end + 4;
+        messages.push(serde_json::from_str(&stdout[body_start..body_start + length]).unwrap());
+        stdout = stdout[body_start + length..].to_string();
+    }
+    (output.status.code().unwrap(), messages)
+}
+
+fn response(messages: &[Value], id: u64) -> &Value {
+    messages.iter().find(|m| m["id"] == id).unwrap_or_else(|| panic!("no response to request {}", id))
+}
+
+#[test]
+pub fn test_session() {
+    let input = [
+        request(1, "initialize", json!({ "capabilities": {}, "initializationOptions": { "objects": ["emberhollow:rooms/docks"] } })),
+        notification("initialized", json!({})),
+        notification("textDocument/didOpen", json!({ "textDocument": { "uri": URI, "languageId": "es3", "version": 1, "text": SCRIPT } })),
+        request(2, "textDocument/hover", at(7, 5)),
+        request(3, "textDocument/completion", at(6, 16)),
+        request(4, "textDocument/definition", at(4, 24)),
+        request(5, "textDocument/formatting", json!({ "textDocument": { "uri": URI }, "options": { "tabSize": 4, "insertSpaces": true } })),
+        request(6, "textDocument/semanticTokens/full", json!({ "textDocument": { "uri": URI } })),
+        request(7, "textDocument/rename", at(2, 0)),
+        request(8, "shutdown", Value::Null),
+        notification("exit", Value::Null)
+    ].concat();
+    let (code, messages) = run(&input);
+    assert_eq!(code, 0);
+
+    let capabilities = &response(&messages, 1)["result"]["capabilities"];
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert_eq!(capabilities["semanticTokensProvider"]["legend"]["tokenModifiers"][4], "error");
+
+    let diagnostics = &messages.iter().find(|m| m["method"] == "textDocument/publishDiagnostics").unwrap()["params"];
+    assert_eq!(diagnostics["uri"], URI);
+    assert_eq!(diagnostics["diagnostics"].as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics["diagnostics"][0]["code"], "E0401");
+    assert_eq!(diagnostics["diagnostics"][0]["range"]["start"], json!({ "line": 7, "character": 11 }));
+
+    let hover = &response(&messages, 2)["result"];
+    assert_eq!(hover["contents"]["value"], "variable `count`, defined on line 3");
+
+    let completions = response(&messages, 3)["result"]["items"].as_array().unwrap();
+    assert_eq!(completions[0]["label"], "name");
+    assert_eq!(completions[0]["detail"], "string");
+    assert_eq!(completions[0]["textEdit"]["range"]["start"], json!({ "line": 6, "character": 15 }));
+
+    let definition = &response(&messages, 4)["result"];
+    assert_eq!(definition["range"]["start"], json!({ "line": 3, "character": 7 }));
+
+    let formatting = &response(&messages, 5)["result"];
+    assert!(formatting[0]["newText"].as_str().unwrap().contains("x = [count, <emberhollow:rooms/missing>]"));
+
+    // every token is five numbers, the first being a header on line 0
+    let data = response(&messages, 6)["result"]["data"].as_array().unwrap();
+    assert_eq!(data.len() % 5, 0);
+    assert_eq!(data[..3], [json!(0), json!(0), json!(SCRIPT.lines().next().unwrap().len())]);
+
+    assert_eq!(response(&messages, 7)["error"]["code"], -32601);
+    assert_eq!(response(&messages, 8)["result"], Value::Null);
+}
+
+#[test]
+pub fn test_lifecycle() {
+    // requests before `initialize` are refused
+    let (code, messages) = run(&request(1, "textDocument/hover", at(0, 0)));
+    assert_eq!(response(&messages, 1)["error"]["code"], -32002);
+    // the input ending without a shutdown is an error
+    assert_eq!(code, 1);
+
+    let input = [
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification("textDocument/didOpen", json!({ "textDocument": { "uri": URI, "languageId": "es3", "version": 1, "text": "x = (" } })),
+        notification("textDocument/didChange", json!({ "textDocument": { "uri": URI, "version": 2 }, "contentChanges": [{ "text": "x = (1)" }] })),
+        notification("textDocument/didClose", json!({ "textDocument": { "uri": URI } })),
+        frame(json!("not a message")),
+        "Content-Length: 3\r\n\r\n{{{".to_string(),
+        notification("exit", Value::Null)
+    ].concat();
+    let (code, messages) = run(&input);
+    assert_eq!(code, 1);
+
+    let published: Vec<usize> = messages.iter()
+        .filter(|m| m["method"] == "textDocument/publishDiagnostics")
+        .map(|m| m["params"]["diagnostics"].as_array().unwrap().len())
+        .collect();
+    assert_eq!(published[1..], [0, 0]);
+    assert!(published[0] > 0);
+    assert_eq!(messages.last().unwrap()["error"]["code"], -32700);
+}